@@ -18,6 +18,11 @@ pub struct Model {
     #[sea_orm(column_type = "Float")]
     pub confidence: f32,
     pub heuristics_used: bool,
+    pub model_id: Option<String>,
+    pub shadow_model_id: Option<String>,
+    pub shadow_answer: Option<String>,
+    #[sea_orm(column_type = "Float", nullable)]
+    pub shadow_confidence: Option<f32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -11,6 +11,8 @@ pub struct Model {
     pub category: String,
     pub skip_inbox: bool,
     pub mark_spam: bool,
+    pub created_at: DateTimeWithTimeZone,
+    pub updated_at: DateTimeWithTimeZone,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -2,10 +2,28 @@
 
 pub use super::auto_cleanup_setting::Entity as AutoCleanupSetting;
 pub use super::custom_email_rule::Entity as CustomEmailRule;
+pub use super::dead_letter::Entity as DeadLetter;
 pub use super::default_email_rule_override::Entity as DefaultEmailRuleOverride;
+pub use super::deterministic_email_rule::Entity as DeterministicEmailRule;
+pub use super::email_correction::Entity as EmailCorrection;
+pub use super::email_embedding::Entity as EmailEmbedding;
+pub use super::email_example::Entity as EmailExample;
+pub use super::email_failure::Entity as EmailFailure;
 pub use super::email_training::Entity as EmailTraining;
+pub use super::heuristic_rule::Entity as HeuristicRule;
+pub use super::inbox_settings::Entity as InboxSettings;
+pub use super::label_migration::Entity as LabelMigration;
+pub use super::label_setting::Entity as LabelSetting;
+pub use super::leader_lease::Entity as LeaderLease;
+pub use super::mailbox_backfill::Entity as MailboxBackfill;
+pub use super::mailbox_sync_state::Entity as MailboxSyncState;
 pub use super::processed_daily_summary::Entity as ProcessedDailySummary;
 pub use super::processed_email::Entity as ProcessedEmail;
+pub use super::processor_state::Entity as ProcessorState;
+pub use super::prompt_queue_entry::Entity as PromptQueueEntry;
+pub use super::rate_limit_bucket::Entity as RateLimitBucket;
+pub use super::reclassification_job::Entity as ReclassificationJob;
+pub use super::server_instance::Entity as ServerInstance;
 pub use super::user::Entity as User;
 pub use super::user_account_access::Entity as UserAccountAccess;
 pub use super::user_token_usage_stat::Entity as UserTokenUsageStat;
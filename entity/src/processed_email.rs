@@ -13,6 +13,7 @@ pub struct Model {
     pub labels_removed: Option<Vec<String>>,
     pub ai_answer: String,
     pub category: String,
+    pub matched_rule: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -18,6 +18,16 @@ pub enum AssociatedEmailClientCategory {
     #[sea_orm(string_value = "CATEGORY_UPDATES")]
     CategoryUpdates,
 }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "backfill_status")]
+pub enum BackfillStatus {
+    #[sea_orm(string_value = "COMPLETED")]
+    Completed,
+    #[sea_orm(string_value = "PAUSED")]
+    Paused,
+    #[sea_orm(string_value = "RUNNING")]
+    Running,
+}
 #[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
 #[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "cleanup_action")]
 pub enum CleanupAction {
@@ -28,6 +38,48 @@ pub enum CleanupAction {
     #[sea_orm(string_value = "NOTHING")]
     Nothing,
 }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "Enum",
+    enum_name = "processor_failure_reason"
+)]
+pub enum ProcessorFailureReason {
+    #[sea_orm(string_value = "AUTH")]
+    Auth,
+    #[sea_orm(string_value = "INTERNAL")]
+    Internal,
+    #[sea_orm(string_value = "LABELS")]
+    Labels,
+    #[sea_orm(string_value = "PROVIDER")]
+    Provider,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "prompt_priority")]
+pub enum PromptPriority {
+    #[sea_orm(string_value = "HIGH")]
+    High,
+    #[sea_orm(string_value = "LOW")]
+    Low,
+}
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "Enum",
+    enum_name = "reclassification_status"
+)]
+pub enum ReclassificationStatus {
+    #[sea_orm(string_value = "CANCELLED")]
+    Cancelled,
+    #[sea_orm(string_value = "COMPLETED")]
+    Completed,
+    #[sea_orm(string_value = "FAILED")]
+    Failed,
+    #[sea_orm(string_value = "PENDING")]
+    Pending,
+    #[sea_orm(string_value = "RUNNING")]
+    Running,
+}
 #[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
 #[sea_orm(
     rs_type = "String",
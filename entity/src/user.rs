@@ -11,6 +11,7 @@ pub struct Model {
     #[sea_orm(unique)]
     pub email: String,
     pub subscription_status: SubscriptionStatus,
+    pub plan: String,
     pub last_successful_payment_at: Option<DateTimeWithTimeZone>,
     pub last_payment_attempt_at: Option<DateTimeWithTimeZone>,
     pub created_at: DateTimeWithTimeZone,
@@ -23,12 +24,40 @@ pub enum Relation {
     AutoCleanupSetting,
     #[sea_orm(has_many = "super::custom_email_rule::Entity")]
     CustomEmailRule,
+    #[sea_orm(has_many = "super::dead_letter::Entity")]
+    DeadLetter,
     #[sea_orm(has_many = "super::default_email_rule_override::Entity")]
     DefaultEmailRuleOverride,
+    #[sea_orm(has_many = "super::deterministic_email_rule::Entity")]
+    DeterministicEmailRule,
+    #[sea_orm(has_many = "super::email_correction::Entity")]
+    EmailCorrection,
+    #[sea_orm(has_many = "super::email_embedding::Entity")]
+    EmailEmbedding,
+    #[sea_orm(has_many = "super::email_example::Entity")]
+    EmailExample,
+    #[sea_orm(has_many = "super::email_failure::Entity")]
+    EmailFailure,
+    #[sea_orm(has_many = "super::heuristic_rule::Entity")]
+    HeuristicRule,
+    #[sea_orm(has_many = "super::inbox_settings::Entity")]
+    InboxSettings,
+    #[sea_orm(has_many = "super::label_migration::Entity")]
+    LabelMigration,
+    #[sea_orm(has_many = "super::label_setting::Entity")]
+    LabelSetting,
+    #[sea_orm(has_one = "super::mailbox_backfill::Entity")]
+    MailboxBackfill,
+    #[sea_orm(has_one = "super::mailbox_sync_state::Entity")]
+    MailboxSyncState,
     #[sea_orm(has_many = "super::processed_daily_summary::Entity")]
     ProcessedDailySummary,
     #[sea_orm(has_many = "super::processed_email::Entity")]
     ProcessedEmail,
+    #[sea_orm(has_one = "super::processor_state::Entity")]
+    ProcessorState,
+    #[sea_orm(has_many = "super::reclassification_job::Entity")]
+    ReclassificationJob,
     #[sea_orm(has_one = "super::user_account_access::Entity")]
     UserAccountAccess,
     #[sea_orm(has_many = "super::user_token_usage_stat::Entity")]
@@ -47,12 +76,84 @@ impl Related<super::custom_email_rule::Entity> for Entity {
     }
 }
 
+impl Related<super::dead_letter::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::DeadLetter.def()
+    }
+}
+
 impl Related<super::default_email_rule_override::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::DefaultEmailRuleOverride.def()
     }
 }
 
+impl Related<super::deterministic_email_rule::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::DeterministicEmailRule.def()
+    }
+}
+
+impl Related<super::email_correction::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::EmailCorrection.def()
+    }
+}
+
+impl Related<super::email_embedding::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::EmailEmbedding.def()
+    }
+}
+
+impl Related<super::email_example::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::EmailExample.def()
+    }
+}
+
+impl Related<super::email_failure::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::EmailFailure.def()
+    }
+}
+
+impl Related<super::heuristic_rule::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::HeuristicRule.def()
+    }
+}
+
+impl Related<super::inbox_settings::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::InboxSettings.def()
+    }
+}
+
+impl Related<super::label_migration::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::LabelMigration.def()
+    }
+}
+
+impl Related<super::label_setting::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::LabelSetting.def()
+    }
+}
+
+impl Related<super::mailbox_backfill::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MailboxBackfill.def()
+    }
+}
+
+impl Related<super::mailbox_sync_state::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MailboxSyncState.def()
+    }
+}
+
 impl Related<super::processed_daily_summary::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::ProcessedDailySummary.def()
@@ -65,6 +166,18 @@ impl Related<super::processed_email::Entity> for Entity {
     }
 }
 
+impl Related<super::processor_state::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ProcessorState.def()
+    }
+}
+
+impl Related<super::reclassification_job::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ReclassificationJob.def()
+    }
+}
+
 impl Related<super::user_account_access::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::UserAccountAccess.def()
@@ -11,6 +11,7 @@ pub struct Model {
     pub month: i32,
     pub year: i32,
     pub tokens_consumed: i64,
+    pub preview_tokens_consumed: i64,
     pub created_at: DateTimeWithTimeZone,
     pub updated_at: DateTimeWithTimeZone,
     pub user_email: String,
@@ -14,6 +14,7 @@ pub struct GmailApiQuota {
     pub labels_update: usize,
     pub messages_attachments_get: usize,
     pub messages_batch_delete: usize,
+    pub messages_batch_modify: usize,
     pub messages_delete: usize,
     pub messages_get: usize,
     pub messages_import: usize,
@@ -77,6 +78,7 @@ pub const GMAIL_API_QUOTA: GmailApiQuota = GmailApiQuota {
     labels_update: 5,
     messages_attachments_get: 5,
     messages_batch_delete: 50,
+    messages_batch_modify: 50,
     messages_delete: 10,
     messages_get: 5,
     messages_import: 25,
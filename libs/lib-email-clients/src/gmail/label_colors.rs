@@ -177,6 +177,17 @@ impl Default for GmailLabelColorMap {
     }
 }
 
+/// Looks up one of the colours Gmail allows for labels by its name
+pub fn color_by_name(name: &str) -> Option<LabelColor> {
+    GMAIL_LABEL_COLORS
+        .iter()
+        .find(|c| c.0 == name)
+        .map(|c| LabelColor {
+            background_color: Some(c.1.to_string()),
+            text_color: Some(c.2.to_string()),
+        })
+}
+
 fn get_color(key: &str) -> LabelColor {
     let map: Lazy<HashMap<String, LabelColor>> = Lazy::new(|| {
         let mut map = HashMap::new();
@@ -246,4 +257,13 @@ mod tests {
         assert_ne!(color1.background_color, color2.background_color);
         assert_eq!(color1.background_color, color3.background_color);
     }
+
+    #[test]
+    fn test_color_by_name() {
+        let color = color_by_name("yellow-500").unwrap();
+        assert_eq!(color.background_color, Some("#f2c960".to_string()));
+        assert_eq!(color.text_color, Some("#000000".to_string()));
+
+        assert!(color_by_name("not-a-color").is_none());
+    }
 }
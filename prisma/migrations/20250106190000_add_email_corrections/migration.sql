@@ -0,0 +1,45 @@
+-- CreateTable
+CREATE TABLE "email_correction" (
+    "id" SERIAL NOT NULL,
+    "user_id" INTEGER NOT NULL,
+    "email_id" VARCHAR NOT NULL,
+    "original_category" VARCHAR NOT NULL,
+    "corrected_category" VARCHAR,
+    "ai_answer" VARCHAR NOT NULL,
+    "from" VARCHAR,
+    "subject" VARCHAR,
+    "body_snippet" TEXT,
+    "created_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updated_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "email_correction_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateTable
+CREATE TABLE "mailbox_sync_state" (
+    "id" SERIAL NOT NULL,
+    "user_id" INTEGER NOT NULL,
+    "last_history_id" BIGINT NOT NULL,
+    "created_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updated_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "mailbox_sync_state_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE INDEX "email_correction_user_id_idx" ON "email_correction"("user_id");
+
+-- CreateIndex
+CREATE INDEX "email_correction_user_id_from_idx" ON "email_correction"("user_id", "from");
+
+-- CreateIndex
+CREATE UNIQUE INDEX "email_correction_user_id_email_id_key" ON "email_correction"("user_id", "email_id");
+
+-- CreateIndex
+CREATE UNIQUE INDEX "mailbox_sync_state_user_id_key" ON "mailbox_sync_state"("user_id");
+
+-- AddForeignKey
+ALTER TABLE "email_correction" ADD CONSTRAINT "email_correction_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "user"("id") ON DELETE CASCADE ON UPDATE CASCADE;
+
+-- AddForeignKey
+ALTER TABLE "mailbox_sync_state" ADD CONSTRAINT "mailbox_sync_state_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "user"("id") ON DELETE CASCADE ON UPDATE CASCADE;
@@ -0,0 +1,22 @@
+-- CreateTable
+CREATE TABLE "email_example" (
+    "id" SERIAL NOT NULL,
+    "user_id" INTEGER NOT NULL,
+    "category" VARCHAR NOT NULL,
+    "from" VARCHAR,
+    "subject" VARCHAR,
+    "body_snippet" TEXT,
+    "created_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updated_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "email_example_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE INDEX "email_example_user_id_idx" ON "email_example"("user_id");
+
+-- CreateIndex
+CREATE INDEX "email_example_user_id_category_idx" ON "email_example"("user_id", "category");
+
+-- AddForeignKey
+ALTER TABLE "email_example" ADD CONSTRAINT "email_example_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "user"("id") ON DELETE CASCADE ON UPDATE CASCADE;
@@ -0,0 +1,25 @@
+-- CreateTable
+CREATE TABLE "email_embedding" (
+    "id" SERIAL NOT NULL,
+    "user_id" INTEGER NOT NULL,
+    "email_id" VARCHAR NOT NULL,
+    "category" VARCHAR NOT NULL,
+    "model" VARCHAR NOT NULL,
+    "embedding" REAL[],
+    "created_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updated_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "email_embedding_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE INDEX "email_embedding_user_id_idx" ON "email_embedding"("user_id");
+
+-- CreateIndex
+CREATE INDEX "email_embedding_user_id_created_at_idx" ON "email_embedding"("user_id", "created_at" DESC);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "email_embedding_user_id_email_id_key" ON "email_embedding"("user_id", "email_id");
+
+-- AddForeignKey
+ALTER TABLE "email_embedding" ADD CONSTRAINT "email_embedding_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "user"("id") ON DELETE CASCADE ON UPDATE CASCADE;
@@ -0,0 +1,8 @@
+-- AlterTable
+ALTER TABLE "email_training" ADD COLUMN     "model_id" VARCHAR,
+ADD COLUMN     "shadow_answer" VARCHAR,
+ADD COLUMN     "shadow_confidence" REAL,
+ADD COLUMN     "shadow_model_id" VARCHAR;
+
+-- CreateIndex
+CREATE INDEX "email_training_shadow_model_id_idx" ON "email_training"("shadow_model_id");
@@ -0,0 +1,19 @@
+-- CreateTable
+CREATE TABLE "heuristic_rule" (
+    "id" SERIAL NOT NULL,
+    "user_id" INTEGER,
+    "name" VARCHAR NOT NULL,
+    "priority" INTEGER NOT NULL DEFAULT 0,
+    "definition" JSONB NOT NULL,
+    "is_disabled" BOOLEAN NOT NULL DEFAULT false,
+    "created_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updated_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "heuristic_rule_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE INDEX "heuristic_rule_user_id_idx" ON "heuristic_rule"("user_id");
+
+-- AddForeignKey
+ALTER TABLE "heuristic_rule" ADD CONSTRAINT "heuristic_rule_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "user"("id") ON DELETE CASCADE ON UPDATE CASCADE;
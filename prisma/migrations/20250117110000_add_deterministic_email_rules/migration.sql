@@ -0,0 +1,23 @@
+-- AlterTable
+ALTER TABLE "processed_email" ADD COLUMN     "matched_rule" VARCHAR;
+
+-- CreateTable
+CREATE TABLE "deterministic_email_rule" (
+    "id" SERIAL NOT NULL,
+    "user_id" INTEGER NOT NULL,
+    "name" VARCHAR NOT NULL,
+    "priority" INTEGER NOT NULL DEFAULT 0,
+    "condition" JSONB NOT NULL,
+    "mail_label" VARCHAR NOT NULL,
+    "is_disabled" BOOLEAN NOT NULL DEFAULT false,
+    "created_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updated_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "deterministic_email_rule_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "deterministic_email_rule_user_id_name_key" ON "deterministic_email_rule"("user_id", "name");
+
+-- AddForeignKey
+ALTER TABLE "deterministic_email_rule" ADD CONSTRAINT "deterministic_email_rule_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "user"("id") ON DELETE CASCADE ON UPDATE CASCADE;
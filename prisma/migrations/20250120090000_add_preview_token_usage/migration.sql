@@ -0,0 +1,2 @@
+-- AlterTable
+ALTER TABLE "user_token_usage_stat" ADD COLUMN     "preview_tokens_consumed" BIGINT NOT NULL DEFAULT 0;
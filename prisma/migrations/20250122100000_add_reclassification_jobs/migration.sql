@@ -0,0 +1,31 @@
+-- CreateEnum
+CREATE TYPE "reclassification_status" AS ENUM ('PENDING', 'RUNNING', 'COMPLETED', 'CANCELLED', 'FAILED');
+
+-- CreateTable
+CREATE TABLE "reclassification_job" (
+    "id" SERIAL NOT NULL,
+    "user_id" INTEGER NOT NULL,
+    "status" "reclassification_status" NOT NULL DEFAULT 'PENDING',
+    "categories" TEXT[],
+    "since" TIMESTAMPTZ(6) NOT NULL,
+    "cursor" VARCHAR,
+    "total" INTEGER NOT NULL DEFAULT 0,
+    "processed" INTEGER NOT NULL DEFAULT 0,
+    "changed" INTEGER NOT NULL DEFAULT 0,
+    "failed" INTEGER NOT NULL DEFAULT 0,
+    "error" VARCHAR,
+    "created_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updated_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "finished_at" TIMESTAMPTZ(6),
+
+    CONSTRAINT "reclassification_job_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE INDEX "reclassification_job_user_id_idx" ON "reclassification_job"("user_id");
+
+-- CreateIndex
+CREATE INDEX "reclassification_job_status_idx" ON "reclassification_job"("status");
+
+-- AddForeignKey
+ALTER TABLE "reclassification_job" ADD CONSTRAINT "reclassification_job_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "user"("id") ON DELETE CASCADE ON UPDATE CASCADE;
@@ -0,0 +1,17 @@
+-- CreateTable
+CREATE TABLE "label_migration" (
+    "id" SERIAL NOT NULL,
+    "user_id" INTEGER NOT NULL,
+    "from_label" VARCHAR NOT NULL,
+    "to_label" VARCHAR NOT NULL,
+    "messages_moved" INTEGER NOT NULL DEFAULT 0,
+    "created_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "label_migration_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "label_migration_user_id_from_label_key" ON "label_migration"("user_id", "from_label");
+
+-- AddForeignKey
+ALTER TABLE "label_migration" ADD CONSTRAINT "label_migration_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "user"("id") ON DELETE CASCADE ON UPDATE CASCADE;
@@ -0,0 +1,19 @@
+-- CreateTable
+CREATE TABLE "label_setting" (
+    "id" SERIAL NOT NULL,
+    "user_id" INTEGER NOT NULL,
+    "category" VARCHAR NOT NULL,
+    "color" VARCHAR,
+    "label_list_visibility" VARCHAR,
+    "message_list_visibility" VARCHAR,
+    "created_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updated_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "label_setting_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "label_setting_user_id_category_key" ON "label_setting"("user_id", "category");
+
+-- AddForeignKey
+ALTER TABLE "label_setting" ADD CONSTRAINT "label_setting_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "user"("id") ON DELETE CASCADE ON UPDATE CASCADE;
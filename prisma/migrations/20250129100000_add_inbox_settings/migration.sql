@@ -0,0 +1,21 @@
+-- CreateTable
+CREATE TABLE "inbox_settings" (
+    "id" SERIAL NOT NULL,
+    "user_id" INTEGER NOT NULL,
+    "category" VARCHAR NOT NULL,
+    "skip_inbox" BOOLEAN NOT NULL DEFAULT false,
+    "mark_spam" BOOLEAN NOT NULL DEFAULT false,
+    "created_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updated_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "inbox_settings_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE INDEX "inbox_settings_user_id_idx" ON "inbox_settings"("user_id");
+
+-- CreateIndex
+CREATE UNIQUE INDEX "inbox_settings_category_user_id_key" ON "inbox_settings"("category", "user_id");
+
+-- AddForeignKey
+ALTER TABLE "inbox_settings" ADD CONSTRAINT "inbox_settings_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "user"("id") ON DELETE CASCADE ON UPDATE CASCADE;
@@ -0,0 +1,2 @@
+-- AlterTable
+ALTER TABLE "default_email_rule_override" ADD COLUMN     "is_important" BOOLEAN;
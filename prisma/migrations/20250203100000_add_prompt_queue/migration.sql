@@ -0,0 +1,24 @@
+-- CreateEnum
+CREATE TYPE "prompt_priority" AS ENUM ('HIGH', 'LOW');
+
+-- CreateTable
+CREATE TABLE "prompt_queue_entry" (
+    "id" SERIAL NOT NULL,
+    "user_email" VARCHAR NOT NULL,
+    "email_id" VARCHAR NOT NULL,
+    "priority" "prompt_priority" NOT NULL,
+    "attempts" INTEGER NOT NULL DEFAULT 0,
+    "visible_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "created_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "prompt_queue_entry_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE INDEX "prompt_queue_entry_priority_visible_at_idx" ON "prompt_queue_entry"("priority", "visible_at");
+
+-- CreateIndex
+CREATE INDEX "prompt_queue_entry_user_email_idx" ON "prompt_queue_entry"("user_email");
+
+-- CreateIndex
+CREATE UNIQUE INDEX "prompt_queue_entry_email_id_user_email_key" ON "prompt_queue_entry"("email_id", "user_email");
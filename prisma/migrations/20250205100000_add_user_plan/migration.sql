@@ -0,0 +1,2 @@
+-- AlterTable
+ALTER TABLE "user" ADD COLUMN     "plan" VARCHAR NOT NULL DEFAULT 'standard';
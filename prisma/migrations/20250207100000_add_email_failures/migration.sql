@@ -0,0 +1,37 @@
+-- CreateTable
+CREATE TABLE "email_failure" (
+    "id" SERIAL NOT NULL,
+    "user_id" INTEGER NOT NULL,
+    "email_id" VARCHAR NOT NULL,
+    "attempts" INTEGER NOT NULL DEFAULT 0,
+    "last_error" VARCHAR NOT NULL,
+    "next_retry_at" TIMESTAMPTZ(6) NOT NULL,
+    "created_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updated_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "email_failure_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateTable
+CREATE TABLE "dead_letter" (
+    "id" SERIAL NOT NULL,
+    "user_id" INTEGER NOT NULL,
+    "email_id" VARCHAR NOT NULL,
+    "attempts" INTEGER NOT NULL,
+    "last_error" VARCHAR NOT NULL,
+    "created_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "dead_letter_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "email_failure_user_id_email_id_key" ON "email_failure"("user_id", "email_id");
+
+-- CreateIndex
+CREATE UNIQUE INDEX "dead_letter_user_id_email_id_key" ON "dead_letter"("user_id", "email_id");
+
+-- AddForeignKey
+ALTER TABLE "email_failure" ADD CONSTRAINT "email_failure_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "user"("id") ON DELETE CASCADE ON UPDATE CASCADE;
+
+-- AddForeignKey
+ALTER TABLE "dead_letter" ADD CONSTRAINT "dead_letter_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "user"("id") ON DELETE CASCADE ON UPDATE CASCADE;
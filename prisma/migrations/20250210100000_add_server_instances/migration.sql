@@ -0,0 +1,12 @@
+-- CreateTable
+CREATE TABLE "server_instance" (
+    "id" VARCHAR NOT NULL,
+    "hostname" VARCHAR NOT NULL,
+    "started_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "last_heartbeat_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "server_instance_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE INDEX "server_instance_last_heartbeat_at_idx" ON "server_instance"("last_heartbeat_at");
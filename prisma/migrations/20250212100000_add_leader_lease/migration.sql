@@ -0,0 +1,10 @@
+-- CreateTable
+CREATE TABLE "leader_lease" (
+    "name" VARCHAR NOT NULL,
+    "instance_id" VARCHAR NOT NULL,
+    "acquired_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "renewed_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "expires_at" TIMESTAMPTZ(6) NOT NULL,
+
+    CONSTRAINT "leader_lease_pkey" PRIMARY KEY ("name")
+);
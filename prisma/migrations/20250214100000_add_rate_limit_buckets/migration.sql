@@ -0,0 +1,9 @@
+-- CreateTable
+CREATE TABLE "rate_limit_bucket" (
+    "key" VARCHAR NOT NULL,
+    "tokens" DOUBLE PRECISION NOT NULL,
+    "updated_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "backoff_until" TIMESTAMPTZ(6),
+
+    CONSTRAINT "rate_limit_bucket_pkey" PRIMARY KEY ("key")
+);
@@ -0,0 +1,26 @@
+-- CreateEnum
+CREATE TYPE "processor_failure_reason" AS ENUM ('AUTH', 'LABELS', 'PROVIDER', 'INTERNAL');
+
+-- CreateTable
+CREATE TABLE "processor_state" (
+    "user_id" INTEGER NOT NULL,
+    "status" VARCHAR NOT NULL,
+    "instance_id" VARCHAR,
+    "emails_processed" BIGINT NOT NULL DEFAULT 0,
+    "emails_failed" BIGINT NOT NULL DEFAULT 0,
+    "consecutive_failures" INTEGER NOT NULL DEFAULT 0,
+    "failure_reason" "processor_failure_reason",
+    "failure_message" VARCHAR,
+    "last_failed_at" TIMESTAMPTZ(6),
+    "next_restart_at" TIMESTAMPTZ(6),
+    "started_at" TIMESTAMPTZ(6),
+    "updated_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "processor_state_pkey" PRIMARY KEY ("user_id")
+);
+
+-- CreateIndex
+CREATE INDEX "processor_state_next_restart_at_idx" ON "processor_state"("next_restart_at");
+
+-- AddForeignKey
+ALTER TABLE "processor_state" ADD CONSTRAINT "processor_state_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "user"("id") ON DELETE CASCADE ON UPDATE CASCADE;
@@ -0,0 +1,21 @@
+-- CreateEnum
+CREATE TYPE "backfill_status" AS ENUM ('RUNNING', 'PAUSED', 'COMPLETED');
+
+-- CreateTable
+CREATE TABLE "mailbox_backfill" (
+    "user_id" INTEGER NOT NULL,
+    "status" "backfill_status" NOT NULL DEFAULT 'RUNNING',
+    "page_token" VARCHAR,
+    "not_before" TIMESTAMPTZ(6) NOT NULL,
+    "estimated_total" BIGINT,
+    "scanned" BIGINT NOT NULL DEFAULT 0,
+    "queued" BIGINT NOT NULL DEFAULT 0,
+    "started_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "completed_at" TIMESTAMPTZ(6),
+    "updated_at" TIMESTAMPTZ(6) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "mailbox_backfill_pkey" PRIMARY KEY ("user_id")
+);
+
+-- AddForeignKey
+ALTER TABLE "mailbox_backfill" ADD CONSTRAINT "mailbox_backfill_user_id_fkey" FOREIGN KEY ("user_id") REFERENCES "user"("id") ON DELETE CASCADE ON UPDATE CASCADE;
@@ -0,0 +1,2 @@
+-- AlterTable
+ALTER TABLE "email_embedding" ADD COLUMN     "confirmed" BOOLEAN NOT NULL DEFAULT true;
@@ -0,0 +1,108 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+/// FNV-1a, so every instance hashes the same way whatever build it runs. FNV barely mixes
+/// the high bits of short keys that differ in one character, so the result goes through
+/// MurmurHash3's finalizer to spread the points around the ring.
+pub fn stable_hash(value: &str) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    let mut hash = value.bytes().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(PRIME)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+/// Consistent hash ring of instances. When an instance joins or leaves, only the keys next to
+/// its points on the ring move.
+#[derive(Debug, Clone, Default)]
+pub struct HashRing {
+    points: BTreeMap<u64, String>,
+    instances: BTreeSet<String>,
+}
+
+impl HashRing {
+    pub fn new(instances: impl IntoIterator<Item = String>, virtual_nodes: usize) -> Self {
+        let instances = instances.into_iter().collect::<BTreeSet<_>>();
+        let points = instances
+            .iter()
+            .flat_map(|instance| {
+                (0..virtual_nodes.max(1))
+                    .map(move |i| (stable_hash(&format!("{instance}#{i}")), instance.clone()))
+            })
+            .collect();
+
+        Self { points, instances }
+    }
+
+    /// The instance a key belongs to, the first point at or after the key's hash
+    pub fn owner(&self, key: &str) -> Option<&str> {
+        let hash = stable_hash(key);
+        self.points
+            .range(hash..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map(|(_, instance)| instance.as_str())
+    }
+
+    pub fn instances(&self) -> &BTreeSet<String> {
+        &self.instances
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn users() -> Vec<String> {
+        (0..3000).map(|i| format!("user{i}@example.com")).collect()
+    }
+
+    fn ring(instances: &[&str]) -> HashRing {
+        HashRing::new(instances.iter().map(|i| i.to_string()), 100)
+    }
+
+    #[test]
+    fn test_users_are_spread_across_instances() {
+        let ring = ring(&["a", "b", "c"]);
+        let mut counts = HashMap::<&str, usize>::new();
+        for user in users() {
+            *counts.entry(ring.owner(&user).unwrap()).or_default() += 1;
+        }
+
+        assert_eq!(counts.len(), 3);
+        for count in counts.values() {
+            assert!(*count > 600 && *count < 1400, "unbalanced: {:?}", counts);
+        }
+    }
+
+    #[test]
+    fn test_only_the_leaving_instances_users_move() {
+        let before = ring(&["a", "b", "c"]);
+        let after = ring(&["a", "c"]);
+
+        for user in users() {
+            let old_owner = before.owner(&user).unwrap();
+            let new_owner = after.owner(&user).unwrap();
+            if old_owner != "b" {
+                assert_eq!(old_owner, new_owner);
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_ring() {
+        assert_eq!(HashRing::default().owner("user@example.com"), None);
+        assert_eq!(ring(&["a"]).owner("user@example.com"), Some("a"));
+    }
+}
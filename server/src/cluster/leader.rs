@@ -0,0 +1,116 @@
+use std::sync::{Arc, RwLock};
+
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::DatabaseConnection;
+use tokio::sync::Mutex;
+
+use crate::{
+    db_core::prelude::sqlx::{self, PgConnection},
+    error::AppResult,
+    model::leader_lease::LeaderLeaseCtrl,
+    server_config::cfg,
+};
+
+/// Key of the advisory lock the leader holds, the same on every instance
+const LEADER_LOCK_KEY: i64 = 0x006d_6169_6c63_6c6b;
+const LEASE_NAME: &str = "scheduler";
+
+/// Elects the one instance that runs singleton jobs.
+///
+/// The leader holds a Postgres advisory lock on a connection it keeps out of the pool, so the
+/// lock is released as soon as that connection drops. It also renews a lease row, which shows
+/// the other instances who leads, and only counts itself as leader until the lease it last
+/// renewed runs out. A leader that loses its connection steps down before anyone else takes
+/// over.
+#[derive(Debug, Clone)]
+pub struct LeaderElection {
+    instance_id: String,
+    lock_conn: Arc<Mutex<Option<PgConnection>>>,
+    lease_expires_at: Arc<RwLock<Option<DateTime<Utc>>>>,
+}
+
+impl LeaderElection {
+    pub fn new(instance_id: String) -> Self {
+        Self {
+            instance_id,
+            lock_conn: Arc::new(Mutex::new(None)),
+            lease_expires_at: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.lease_expires_at
+            .read()
+            .unwrap()
+            .is_some_and(|expires_at| expires_at > Utc::now())
+    }
+
+    /// Takes or keeps leadership. Returns whether this instance is the leader afterwards.
+    pub async fn renew(&self, conn: &DatabaseConnection) -> AppResult<bool> {
+        let mut lock_conn = self.lock_conn.lock().await;
+
+        let holds_lock = match lock_conn.as_mut() {
+            Some(held) => sqlx::query("SELECT 1").execute(&mut *held).await.is_ok(),
+            None => false,
+        };
+        if !holds_lock {
+            // The lock went with the connection, if there was one
+            *lock_conn = None;
+            *self.lease_expires_at.write().unwrap() = None;
+            *lock_conn = Self::try_lock(conn).await?;
+        }
+        if lock_conn.is_none() {
+            return Ok(false);
+        }
+
+        // Taken before the write, so the lease never looks longer here than in the database
+        let expires_at = Utc::now() + Duration::seconds(cfg.cluster.leader_lease_secs);
+        let renewed =
+            LeaderLeaseCtrl::renew(conn, LEASE_NAME, &self.instance_id, expires_at).await?;
+        if renewed {
+            *self.lease_expires_at.write().unwrap() = Some(expires_at);
+        }
+
+        Ok(self.is_leader())
+    }
+
+    async fn try_lock(conn: &DatabaseConnection) -> AppResult<Option<PgConnection>> {
+        let mut pooled = conn
+            .get_postgres_connection_pool()
+            .acquire()
+            .await
+            .context("Failed to get a connection for the leader lock")?;
+        let locked = sqlx::query_scalar::<_, bool>("SELECT pg_try_advisory_lock($1)")
+            .bind(LEADER_LOCK_KEY)
+            .fetch_one(&mut *pooled)
+            .await
+            .context("Failed to try the leader lock")?;
+
+        // Kept out of the pool, otherwise the lock would be handed out with the connection
+        Ok(locked.then(|| pooled.detach()))
+    }
+
+    /// The instance holding the lease, if it is still valid
+    pub async fn current_leader(
+        &self,
+        conn: &DatabaseConnection,
+    ) -> AppResult<Option<(String, DateTime<Utc>)>> {
+        let lease = LeaderLeaseCtrl::get(conn, LEASE_NAME).await?;
+
+        Ok(lease
+            .filter(|lease| lease.expires_at.to_utc() > Utc::now())
+            .map(|lease| (lease.instance_id, lease.expires_at.to_utc())))
+    }
+
+    /// Steps down, e.g. on shutdown, so another instance takes over right away
+    pub async fn resign(&self, conn: &DatabaseConnection) -> AppResult<()> {
+        let mut lock_conn = self.lock_conn.lock().await;
+        *self.lease_expires_at.write().unwrap() = None;
+        if lock_conn.take().is_some() {
+            LeaderLeaseCtrl::release(conn, LEASE_NAME, &self.instance_id).await?;
+        }
+
+        Ok(())
+    }
+}
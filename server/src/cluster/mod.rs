@@ -0,0 +1,100 @@
+pub(crate) mod hash_ring;
+pub(crate) mod leader;
+
+use std::{
+    env,
+    sync::{Arc, RwLock},
+};
+
+use chrono::Duration;
+use sea_orm::DatabaseConnection;
+
+use crate::{
+    db_core::prelude::Uuid, error::AppResult, model::server_instance::ServerInstanceCtrl,
+    server_config::cfg,
+};
+
+use hash_ring::HashRing;
+use leader::LeaderElection;
+
+/// This instance's place among the running server instances. Users are assigned to live
+/// instances on a consistent hash ring, and each instance only processes the users it owns.
+#[derive(Debug, Clone)]
+pub struct ClusterMembership {
+    pub instance_id: String,
+    pub hostname: String,
+    pub leader: LeaderElection,
+    ring: Arc<RwLock<HashRing>>,
+}
+
+impl ClusterMembership {
+    pub fn new() -> Self {
+        let instance_id = Uuid::new_v4().to_string();
+        Self {
+            leader: LeaderElection::new(instance_id.clone()),
+            instance_id,
+            hostname: env::var("HOSTNAME").unwrap_or("unknown".to_string()),
+            // Owns nobody until the first heartbeat
+            ring: Arc::new(RwLock::new(HashRing::default())),
+        }
+    }
+
+    pub fn owns(&self, user_email: &str) -> bool {
+        self.ring.read().unwrap().owner(user_email) == Some(self.instance_id.as_str())
+    }
+
+    pub fn owner(&self, user_email: &str) -> Option<String> {
+        self.ring
+            .read()
+            .unwrap()
+            .owner(user_email)
+            .map(|o| o.to_string())
+    }
+
+    pub fn instances(&self) -> Vec<String> {
+        self.ring
+            .read()
+            .unwrap()
+            .instances()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Marks this instance alive and rebuilds the ring from the live instances. Returns true if
+    /// the instances changed, in which case users may have moved.
+    pub async fn heartbeat(&self, conn: &DatabaseConnection) -> AppResult<bool> {
+        ServerInstanceCtrl::heartbeat(conn, &self.instance_id, &self.hostname).await?;
+
+        let ttl = Duration::seconds(cfg.cluster.instance_ttl_secs);
+        ServerInstanceCtrl::delete_stale(conn, ttl * 10).await?;
+        let live = ServerInstanceCtrl::live(conn, ttl)
+            .await?
+            .into_iter()
+            .map(|i| i.id)
+            .collect::<Vec<_>>();
+
+        let ring = HashRing::new(live, cfg.cluster.virtual_nodes);
+        let changed = ring.instances() != self.ring.read().unwrap().instances();
+        if changed {
+            tracing::info!(
+                "Server instances changed, {} is now one of {:?}",
+                self.instance_id,
+                ring.instances()
+            );
+        }
+        *self.ring.write().unwrap() = ring;
+
+        Ok(changed)
+    }
+
+    /// Removes this instance so the others take over its users and leadership without waiting
+    /// for it to expire
+    pub async fn leave(&self, conn: &DatabaseConnection) -> AppResult<()> {
+        self.leader.resign(conn).await?;
+        ServerInstanceCtrl::remove(conn, &self.instance_id).await?;
+        *self.ring.write().unwrap() = HashRing::default();
+
+        Ok(())
+    }
+}
@@ -6,6 +6,8 @@ use anyhow::anyhow;
 use chrono::{DateTime, Utc};
 
 use crate::email::processor::EmailProcessor;
+use crate::email::reclassification;
+use crate::model::processor_state::ProcessorStateCtrl;
 use crate::model::user::UserWithAccountAccessAndUsage;
 use crate::ServerState;
 
@@ -36,6 +38,7 @@ impl ActiveEmailProcessorMap {
             .last_rule_update_time
             .unwrap_or(DateTime::<Utc>::MIN_UTC.into());
 
+        let mut replaced_for_rule_change = None;
         if let Some(processor) = self.active_processors.read().unwrap().get(&user_email) {
             match processor.status() {
                 ProcessorStatus::Cancelled
@@ -56,6 +59,7 @@ impl ActiveEmailProcessorMap {
                         user_email
                     );
                     processor.cancel();
+                    replaced_for_rule_change = Some(processor.clone());
                 }
                 _ => {
                     tracing::info!("Processor for {} already exists", user_email);
@@ -64,11 +68,46 @@ impl ActiveEmailProcessorMap {
             };
         }
 
-        let proc = Arc::new(
-            EmailProcessor::new(self.server_state.clone(), user)
-                .await
-                .map_err(|e| anyhow!("Could not create email processor {:?}", e))?,
-        );
+        let user_id = user.id;
+        let proc = match EmailProcessor::new(self.server_state.clone(), user).await {
+            Ok(proc) => Arc::new(proc),
+            Err(failure) => {
+                if let Err(e) =
+                    ProcessorStateCtrl::record_failure(&self.server_state.conn, user_id, &failure)
+                        .await
+                {
+                    tracing::error!(
+                        "Error recording processor failure for {}: {:?}",
+                        user_email,
+                        e
+                    );
+                }
+                return Err(anyhow!("Could not create email processor {:?}", failure));
+            }
+        };
+
+        if let Some(old_processor) = replaced_for_rule_change {
+            match reclassification::queue_if_affected(
+                &self.server_state.conn,
+                proc.user_id,
+                old_processor.email_rules(),
+                proc.email_rules(),
+            )
+            .await
+            {
+                Ok(Some(job)) => {
+                    tracing::info!("Queued reclassification job {} for {}", job.id, user_email);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::error!(
+                        "Error queueing reclassification for {}: {:?}",
+                        user_email,
+                        e
+                    );
+                }
+            }
+        }
 
         self.active_processors
             .write()
@@ -0,0 +1,580 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::Context;
+use entity::email_correction;
+use sea_orm::DatabaseConnection;
+
+use crate::{
+    email::{
+        knn::{KnnDecision, KnnIndex, LabeledEmbedding},
+        label_template,
+        parsed_message::ParsedMessage,
+        rule_engine::{RuleCategory, RuleDefinition, RuleEngine},
+        rules::{EmailRule, UserEmailRules},
+        sender_memory::SenderMemory,
+    },
+    error::{AppError, AppResult},
+    model::{
+        deterministic_email_rule::DeterministicEmailRuleCtrl,
+        email_correction::EmailCorrectionCtrl,
+        email_embedding::EmailEmbeddingCtrl,
+        email_example::{EmailExampleCtrl, EmailExampleWatermark},
+        heuristic_rule::HeuristicRuleCtrl,
+        labels::UtilityLabels,
+    },
+    prompt::{
+        embeddings::{self, EmailEmbedding},
+        few_shot::FewShotPool,
+        mistral::{self, CategoryPromptResponse},
+        shadow::{self, ShadowAnswer},
+    },
+    rate_limiters::RateLimiters,
+    server_config::{cfg, ClassifierConfig, ClassifierMode, FewShotConfig, ModelConfig},
+    HttpClient,
+};
+
+lazy_static::lazy_static!(
+    pub static ref UNKNOWN_RULE: EmailRule = EmailRule {
+            prompt_content: "Unknown".to_string(),
+            mail_label: UtilityLabels::Uncategorized.as_str().to_string(),
+            associated_email_client_category: None,
+        };
+);
+
+/// Picks the category for a single user's emails. The processor keeps one for its lifetime,
+/// and rule previews build one from a proposed rule set.
+#[derive(Clone)]
+pub struct EmailClassifier {
+    http_client: HttpClient,
+    rate_limiters: RateLimiters,
+    settings: Arc<ClassifierSettings>,
+    user_email_rules: Arc<UserEmailRules>,
+    rule_engine: Arc<RuleEngine>,
+    deterministic_rules: Arc<RwLock<RuleEngine>>,
+    sender_memory: Arc<RwLock<SenderMemory>>,
+    few_shot_pool: Arc<RwLock<FewShotPool>>,
+    pinned_examples_watermark: Arc<RwLock<Option<EmailExampleWatermark>>>,
+    knn_index: Arc<RwLock<KnnIndex>>,
+}
+
+/// The config the classifier reads for every email. The server takes it from its config
+/// file, tools replaying emails can pass their own.
+#[derive(Debug, Clone)]
+pub struct ClassifierSettings {
+    pub model: ModelConfig,
+    pub few_shot: FewShotConfig,
+    pub classifier: ClassifierConfig,
+}
+
+impl ClassifierSettings {
+    pub fn from_config() -> Self {
+        Self {
+            model: cfg.model.clone(),
+            few_shot: cfg.few_shot.clone(),
+            classifier: cfg.classifier.clone(),
+        }
+    }
+}
+
+impl EmailClassifier {
+    /// A classifier that only knows the user's categories, with no rules or learned
+    /// categories. Doesn't read the server config or the database.
+    pub fn new(
+        http_client: HttpClient,
+        rate_limiters: RateLimiters,
+        user_email_rules: UserEmailRules,
+        settings: ClassifierSettings,
+    ) -> Self {
+        Self {
+            http_client,
+            rate_limiters,
+            settings: Arc::new(settings),
+            user_email_rules: Arc::new(user_email_rules),
+            rule_engine: Arc::new(RuleEngine::default()),
+            deterministic_rules: Arc::new(RwLock::new(RuleEngine::default())),
+            sender_memory: Arc::new(RwLock::new(SenderMemory::default())),
+            few_shot_pool: Arc::new(RwLock::new(FewShotPool::default())),
+            pinned_examples_watermark: Arc::new(RwLock::new(None)),
+            knn_index: Arc::new(RwLock::new(KnnIndex::default())),
+        }
+    }
+
+    pub async fn load(
+        conn: &DatabaseConnection,
+        http_client: HttpClient,
+        rate_limiters: RateLimiters,
+        user_id: i32,
+        user_email_rules: UserEmailRules,
+    ) -> AppResult<Self> {
+        Self::load_without_emails(
+            conn,
+            http_client,
+            rate_limiters,
+            user_id,
+            user_email_rules,
+            &HashSet::new(),
+        )
+        .await
+    }
+
+    /// Loads the classifier as it would have been without what it learned from the given
+    /// emails, so replaying them can't read the answer from their own corrections or embeddings
+    pub async fn load_without_emails(
+        conn: &DatabaseConnection,
+        http_client: HttpClient,
+        rate_limiters: RateLimiters,
+        user_id: i32,
+        user_email_rules: UserEmailRules,
+        held_out_email_ids: &HashSet<String>,
+    ) -> AppResult<Self> {
+        let rule_engine = Self::load_rule_engine(conn, user_id, &user_email_rules).await?;
+        let deterministic_rules = Self::load_deterministic_rules(conn, user_id).await?;
+        let corrections = EmailCorrectionCtrl::all_by_user(conn, user_id)
+            .await?
+            .into_iter()
+            .filter(|c| !held_out_email_ids.contains(&c.email_id))
+            .collect::<Vec<_>>();
+        let sender_memory = SenderMemory::from_corrections(&corrections);
+        let pinned_examples_watermark = EmailExampleCtrl::watermark(conn, user_id).await?;
+        let pinned_examples = EmailExampleCtrl::all_by_user(conn, user_id).await?;
+        let few_shot_pool = FewShotPool::from_models(&pinned_examples, &corrections);
+        let knn_index = if cfg.classifier.mode == ClassifierMode::Knn {
+            let max_size = cfg.classifier.max_index_size;
+            let embeddings =
+                EmailEmbeddingCtrl::recent_by_user(conn, user_id, max_size as u64).await?;
+            KnnIndex::new(
+                embeddings
+                    .into_iter()
+                    .filter(|e| !held_out_email_ids.contains(&e.email_id))
+                    .filter_map(LabeledEmbedding::from_model)
+                    .collect(),
+                max_size,
+            )
+        } else {
+            KnnIndex::default()
+        };
+
+        Ok(Self {
+            http_client,
+            rate_limiters,
+            settings: Arc::new(ClassifierSettings::from_config()),
+            user_email_rules: Arc::new(user_email_rules),
+            rule_engine: Arc::new(rule_engine),
+            deterministic_rules: Arc::new(RwLock::new(deterministic_rules)),
+            sender_memory: Arc::new(RwLock::new(sender_memory)),
+            few_shot_pool: Arc::new(RwLock::new(few_shot_pool)),
+            pinned_examples_watermark: Arc::new(RwLock::new(Some(pinned_examples_watermark))),
+            knn_index: Arc::new(RwLock::new(knn_index)),
+        })
+    }
+
+    /// Replaces the model and classifier config the server loaded with
+    pub fn with_settings(mut self, settings: ClassifierSettings) -> Self {
+        self.settings = Arc::new(settings);
+        self
+    }
+
+    pub fn user_email_rules(&self) -> &UserEmailRules {
+        &self.user_email_rules
+    }
+
+    /// Learns from corrections already saved to the database
+    pub fn apply_corrections(&self, corrections: &[email_correction::Model]) {
+        if self.settings.classifier.mode == ClassifierMode::Knn {
+            let mut knn_index = self.knn_index.write().unwrap();
+            for correction in corrections {
+                knn_index.relabel(
+                    &correction.email_id,
+                    correction.corrected_category.as_deref(),
+                );
+            }
+        }
+
+        self.few_shot_pool
+            .write()
+            .unwrap()
+            .apply_corrections(corrections);
+
+        let mut sender_memory = self.sender_memory.write().unwrap();
+        for correction in corrections {
+            if let (Some(from), Some(category)) = (&correction.from, &correction.corrected_category)
+            {
+                sender_memory.record(from, category);
+            }
+        }
+    }
+
+    pub fn insert_embedding(&self, embedding: LabeledEmbedding) {
+        self.knn_index.write().unwrap().insert(embedding);
+    }
+
+    async fn embed_for_knn(&self, email_message: &ParsedMessage) -> Option<EmailEmbedding> {
+        if self.settings.classifier.mode != ClassifierMode::Knn {
+            return None;
+        }
+
+        match embeddings::embed_email(&self.http_client, email_message).await {
+            Ok(embedding) => Some(embedding),
+            Err(e) => {
+                // The LLM can still categorize the email without an embedding
+                tracing::warn!("Error embedding email {}: {:?}", email_message.id, e);
+                None
+            }
+        }
+    }
+
+    /// Categorizes by the user's nearest labeled emails, if they agree
+    fn classify_with_knn(&self, embedding: &EmailEmbedding) -> Option<(EmailRule, f32)> {
+        let decision = self
+            .knn_index
+            .read()
+            .unwrap()
+            .classify(&embedding.vector, &self.settings.classifier);
+
+        match decision {
+            KnnDecision::Agreed {
+                category,
+                agreement,
+            } => self
+                .user_email_rules
+                .data()
+                .iter()
+                .find(|r| label_template::label_matches(&r.mail_label, &category))
+                .map(|r| (r.clone(), agreement)),
+            KnnDecision::Undecided => None,
+        }
+    }
+
+    /// Pinned examples can change through the API at any time, so they are reloaded
+    /// whenever their watermark moves. Corrections reach the pool through `apply_corrections`.
+    pub async fn refresh_few_shot_pool(
+        &self,
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> anyhow::Result<()> {
+        let watermark = EmailExampleCtrl::watermark(conn, user_id).await?;
+        if *self.pinned_examples_watermark.read().unwrap() == Some(watermark) {
+            return Ok(());
+        }
+
+        let pinned_examples = EmailExampleCtrl::all_by_user(conn, user_id).await?;
+        self.few_shot_pool
+            .write()
+            .unwrap()
+            .replace_pinned(&pinned_examples);
+        *self.pinned_examples_watermark.write().unwrap() = Some(watermark);
+
+        Ok(())
+    }
+
+    /// Rules that fail to compile are skipped so the user's other rules keep applying
+    async fn load_deterministic_rules(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<RuleEngine> {
+        let definitions = DeterministicEmailRuleCtrl::enabled_definitions(conn, user_id).await?;
+        let valid_definitions = definitions
+            .into_iter()
+            .filter(|d| match RuleEngine::compile(std::slice::from_ref(d)) {
+                Ok(_) => true,
+                Err(e) => {
+                    tracing::warn!("Skipping deterministic rule {}: {:?}", d.name, e);
+                    false
+                }
+            })
+            .collect::<Vec<_>>();
+
+        RuleEngine::compile(&valid_definitions).map_err(AppError::Internal)
+    }
+
+    /// Picks up rules the user created, changed or deleted since the last tick
+    pub async fn refresh_deterministic_rules(
+        &self,
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> anyhow::Result<()> {
+        let rules = Self::load_deterministic_rules(conn, user_id).await?;
+        *self.deterministic_rules.write().unwrap() = rules;
+
+        Ok(())
+    }
+
+    /// Compiles the configured rules with the global and user rules from the database. Database
+    /// rules may only apply labels the processor creates.
+    async fn load_rule_engine(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        user_email_rules: &UserEmailRules,
+    ) -> AppResult<RuleEngine> {
+        let known_labels = user_email_rules
+            .get_custom_labels()
+            .into_iter()
+            .chain(cfg.heuristic_labels().map(|l| l.to_string()))
+            .collect::<HashSet<_>>();
+        let db_rules = HeuristicRuleCtrl::definitions_for_user(conn, user_id)
+            .await?
+            .into_iter()
+            .filter(|rule| match rule.action.mail_label() {
+                Some(label) if !known_labels.contains(label) => {
+                    tracing::warn!(
+                        "Skipping heuristic rule {} with unknown label {}",
+                        rule.name,
+                        label
+                    );
+                    false
+                }
+                _ => true,
+            })
+            // A rule saved with a bad pattern is skipped, global rules would otherwise
+            // break every processor
+            .filter(
+                |rule| match RuleEngine::compile(std::slice::from_ref(rule)) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        tracing::warn!("Skipping heuristic rule {}: {:?}", rule.name, e);
+                        false
+                    }
+                },
+            );
+
+        let definitions = cfg
+            .heuristic_rules
+            .iter()
+            .cloned()
+            .chain(cfg.heuristics.iter().map(RuleDefinition::from_legacy))
+            .chain(db_rules)
+            .collect::<Vec<_>>();
+
+        RuleEngine::compile(&definitions).map_err(AppError::Internal)
+    }
+
+    fn rule_category_to_email_rule(&self, category: &RuleCategory) -> EmailRule {
+        let user_rule = self
+            .user_email_rules
+            .data()
+            .iter()
+            .find(|r| r.mail_label == category.mail_label);
+
+        EmailRule {
+            prompt_content: user_rule
+                .map_or(category.rule_name.clone(), |r| r.prompt_content.clone()),
+            mail_label: category.mail_label.clone(),
+            associated_email_client_category: category
+                .gmail_category
+                .clone()
+                .or(user_rule.and_then(|r| r.associated_email_client_category.clone())),
+        }
+    }
+
+    /// Uses the category a user keeps moving a sender's emails to instead of prompting
+    fn remembered_sender_rule(&self, email_message: &ParsedMessage) -> Option<EmailRule> {
+        let from = email_message.from.as_ref()?;
+        let sender_memory = self.sender_memory.read().unwrap();
+        let category = sender_memory.lookup(from)?;
+
+        self.user_email_rules
+            .data()
+            .iter()
+            .find(|r| label_template::label_matches(&r.mail_label, category))
+            .cloned()
+    }
+
+    pub async fn classify(
+        &self,
+        email_message: &ParsedMessage,
+    ) -> anyhow::Result<PromptReturnData> {
+        // Deterministic rules run before anything that costs tokens
+        let deterministic_match = self
+            .deterministic_rules
+            .read()
+            .unwrap()
+            .evaluate(email_message)
+            .skip_llm()
+            .cloned();
+        if let Some(category) = deterministic_match {
+            let email_rule = self.rule_category_to_email_rule(&category);
+            return Ok(PromptReturnData {
+                ai_answer: email_rule.prompt_content.clone(),
+                email_rule,
+                ai_confidence: 1.0,
+                heuristics_used: true,
+                token_usage: 0,
+                embedding: None,
+                knn_decided: false,
+                llm_prompted: false,
+                matched_rule: Some(category.rule_name),
+            });
+        }
+
+        if let Some(email_rule) = self.remembered_sender_rule(email_message) {
+            return Ok(PromptReturnData {
+                ai_answer: email_rule.prompt_content.clone(),
+                email_rule,
+                ai_confidence: 1.0,
+                heuristics_used: true,
+                token_usage: 0,
+                embedding: None,
+                knn_decided: false,
+                llm_prompted: false,
+                matched_rule: None,
+            });
+        }
+
+        let rule_matches = self.rule_engine.evaluate(email_message);
+        if let Some(category) = rule_matches.skip_llm() {
+            let email_rule = self.rule_category_to_email_rule(category);
+            return Ok(PromptReturnData {
+                ai_answer: email_rule.prompt_content.clone(),
+                email_rule,
+                ai_confidence: 1.0,
+                heuristics_used: true,
+                token_usage: 0,
+                embedding: None,
+                knn_decided: false,
+                llm_prompted: false,
+                matched_rule: Some(category.rule_name.clone()),
+            });
+        }
+
+        // Embedding is a paid call, so it only happens once the cheaper checks have passed
+        let embedding = self.embed_for_knn(email_message).await;
+        let embedding_tokens = embedding.as_ref().map_or(0, |e| e.token_usage);
+
+        if let Some((email_rule, agreement)) =
+            embedding.as_ref().and_then(|e| self.classify_with_knn(e))
+        {
+            return Ok(PromptReturnData {
+                ai_answer: email_rule.prompt_content.clone(),
+                email_rule,
+                ai_confidence: agreement,
+                heuristics_used: false,
+                token_usage: embedding_tokens,
+                embedding,
+                knn_decided: true,
+                llm_prompted: false,
+                matched_rule: None,
+            });
+        }
+
+        let examples = self.few_shot_pool.read().unwrap().select(
+            email_message,
+            &self.user_email_rules,
+            &self.settings.few_shot,
+        );
+
+        let CategoryPromptResponse {
+            category: ai_answer,
+            confidence,
+            token_usage,
+        } = mistral::send_category_prompt(
+            &self.http_client,
+            &self.rate_limiters,
+            email_message,
+            &self.user_email_rules,
+            &examples,
+            &rule_matches.hints(),
+            &self.settings.model,
+        )
+        .await
+        .context("Error sending prompt")?;
+
+        // Nested categories are prompted with their path. The rule is picked by that path, as
+        // categories under different parents can share a name, and the answer is kept without it.
+        let answered_rule = self.user_email_rules.rule_for_answer(&ai_answer);
+        let ai_answer = answered_rule.map_or(ai_answer, |r| r.prompt_content.clone());
+        let answered_rule = answered_rule.unwrap_or(&UNKNOWN_RULE).clone();
+
+        let rule_override = rule_matches.override_for(&ai_answer);
+        let mut selected_email_rule = match rule_override {
+            Some(category) => self.rule_category_to_email_rule(category),
+            None => answered_rule.clone(),
+        };
+
+        let heuristics_used = selected_email_rule.mail_label != answered_rule.mail_label;
+
+        if confidence < self.settings.model.email_confidence_threshold && !heuristics_used {
+            selected_email_rule = UNKNOWN_RULE.clone();
+        }
+
+        Ok(PromptReturnData {
+            email_rule: selected_email_rule,
+            ai_answer,
+            ai_confidence: confidence,
+            heuristics_used,
+            token_usage: token_usage + embedding_tokens,
+            embedding,
+            knn_decided: false,
+            llm_prompted: true,
+            matched_rule: rule_override
+                .filter(|_| heuristics_used)
+                .map(|c| c.rule_name.clone()),
+        })
+    }
+
+    /// Asks the candidate model for its answer on a sample of emails. The answer is only
+    /// recorded, and its tokens do not count against the user's quota.
+    pub async fn shadow_answer(&self, email_message: &ParsedMessage) -> Option<ShadowAnswer> {
+        let model = cfg.shadow.candidate()?;
+        if !shadow::in_sample(&email_message.id, cfg.shadow.percentage) {
+            return None;
+        }
+
+        self.rate_limiters.acquire_one().await;
+
+        let examples = self.few_shot_pool.read().unwrap().select(
+            email_message,
+            &self.user_email_rules,
+            &self.settings.few_shot,
+        );
+
+        match mistral::send_category_prompt(
+            &self.http_client,
+            &self.rate_limiters,
+            email_message,
+            &self.user_email_rules,
+            &examples,
+            &self.rule_engine.evaluate(email_message).hints(),
+            model,
+        )
+        .await
+        {
+            // Kept the way the primary answer is, without the path of nested categories
+            Ok(resp) => Some(ShadowAnswer {
+                model_id: model.id.clone(),
+                answer: self
+                    .user_email_rules
+                    .rule_for_answer(&resp.category)
+                    .map_or(resp.category, |r| r.prompt_content.clone()),
+                confidence: resp.confidence,
+            }),
+            Err(e) => {
+                tracing::warn!(
+                    "Error sending shadow prompt for {}: {:?}",
+                    email_message.id,
+                    e
+                );
+                None
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PromptReturnData {
+    pub email_rule: EmailRule,
+    pub ai_answer: String,
+    pub ai_confidence: f32,
+    pub heuristics_used: bool,
+    pub token_usage: i64,
+    pub embedding: Option<EmailEmbedding>,
+    /// The category came from the email's nearest neighbours, so its embedding must not
+    /// vote until the user confirms it
+    pub knn_decided: bool,
+    /// The LLM was asked, so a shadow model's answer can be compared with this one
+    pub llm_prompted: bool,
+    /// Name of the rule that decided the category, if any
+    pub matched_rule: Option<String>,
+}
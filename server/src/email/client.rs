@@ -1,35 +1,40 @@
 extern crate google_gmail1 as gmail1;
 
 use anyhow::{anyhow, Context};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use derive_more::Display;
 use futures::future::join_all;
 use google_gmail1::api::{
-    Label, ListLabelsResponse, ListMessagesResponse, Message, Profile, WatchResponse,
+    Label, ListHistoryResponse, ListLabelsResponse, ListMessagesResponse, Message, Profile,
+    WatchResponse,
 };
 use lazy_static::lazy_static;
-use leaky_bucket::RateLimiter;
-use lib_email_clients::gmail::api_quota::{GMAIL_API_QUOTA, GMAIL_QUOTA_PER_SECOND};
+use lib_email_clients::gmail::api_quota::GMAIL_API_QUOTA;
 use lib_email_clients::gmail::label_colors::GmailLabelColorMap;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use serde_json::json;
-use std::sync::Arc;
-use std::{collections::HashSet, time::Duration};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use strum::IntoEnumIterator;
 
 use crate::error::AppError;
-use crate::model::labels::UtilityLabels;
 use crate::{
     db_core::prelude::*,
     model::response::LabelUpdate,
     model::{
+        default_email_rule_override::{DefaultEmailRuleOverrideCtrl, ImportantCategories},
+        inbox_settings::{CategoryInboxSettings, InboxSetting, UserInboxSettingsCtrl},
         labels,
         user::{self, AccountAccess, EmailAddress, Id},
     },
+    rate_limiters::RateLimiters,
     server_config::{cfg, DAILY_SUMMARY_CATEGORY},
     HttpClient,
 };
 
+use super::label_appearance::LabelAppearance;
+use super::label_template;
 use super::parsed_message::ParsedMessage;
 use super::rules::EmailRule;
 
@@ -45,6 +50,76 @@ macro_rules! gmail_url {
 }
 
 const MAX_RESULTS_DEFAULT: u32 = 500;
+pub const MAX_BATCH_MODIFY_IDS: usize = 1000;
+
+/// An error response from the Gmail API, kept typed so callers can act on the status
+#[derive(Debug, Display)]
+#[display("Gmail API error {status}: {body}")]
+pub struct GmailApiError {
+    pub status: reqwest::StatusCode,
+    pub body: String,
+    /// Gmail reports rate limits as 403 with a reason in the body
+    pub rate_limited: bool,
+}
+
+impl std::error::Error for GmailApiError {}
+
+/// The user's Google authorization was revoked, they have to reconnect their account
+#[derive(Debug, Display)]
+#[display("Gmail authorization failed: {_0}")]
+pub struct GmailAuthError(pub String);
+
+impl std::error::Error for GmailAuthError {}
+
+/// Whether the error means the user has to reconnect their account, either because the token
+/// couldn't be refreshed or because Gmail rejected it
+pub fn is_auth_error(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.is::<GmailAuthError>()
+            || cause
+                .downcast_ref::<GmailApiError>()
+                .is_some_and(|e| e.status == reqwest::StatusCode::UNAUTHORIZED)
+    })
+}
+
+impl GmailApiError {
+    pub fn new(status: reqwest::StatusCode, body: String) -> Self {
+        Self {
+            rate_limited: status == reqwest::StatusCode::FORBIDDEN
+                && is_rate_limit_error(body.as_bytes()),
+            status,
+            body,
+        }
+    }
+
+    /// Passes successful responses through, and reads the error out of the others
+    async fn check(resp: reqwest::Response) -> Result<reqwest::Response, GmailApiError> {
+        if resp.status().is_success() {
+            return Ok(resp);
+        }
+
+        let status = resp.status();
+        Err(GmailApiError::new(
+            status,
+            resp.text().await.unwrap_or_default(),
+        ))
+    }
+}
+
+/// Whether a 403 response is Gmail asking us to slow down rather than a missing permission
+fn is_rate_limit_error(body: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|body| body["error"]["errors"].as_array().cloned())
+        .unwrap_or_default()
+        .iter()
+        .any(|e| {
+            matches!(
+                e["reason"].as_str(),
+                Some("rateLimitExceeded" | "userRateLimitExceeded")
+            )
+        })
+}
 
 lazy_static! {
     static ref COLOR_MAP: Lazy<GmailLabelColorMap> = Lazy::new(GmailLabelColorMap::new);
@@ -54,7 +129,13 @@ lazy_static! {
 pub struct EmailClient {
     http_client: HttpClient,
     access_token: String,
-    rate_limiter: Arc<RateLimiter>,
+    rate_limiters: RateLimiters,
+    /// Templated labels known to exist, so they are only looked up once
+    template_labels: Arc<Mutex<HashSet<String>>>,
+    /// Templates that reached their label limit, so their new values go straight to the parent
+    full_templates: Arc<Mutex<HashSet<String>>>,
+    inbox_settings: CategoryInboxSettings,
+    important_categories: ImportantCategories,
     pub email_address: String,
 }
 
@@ -63,16 +144,9 @@ impl EmailClient {
     pub async fn new(
         http_client: reqwest::Client,
         conn: DatabaseConnection,
+        rate_limiters: RateLimiters,
         mut user: impl AccountAccess + Id + EmailAddress,
     ) -> anyhow::Result<EmailClient> {
-        let rate_limiter = Arc::new(
-            RateLimiter::builder()
-                .initial(GMAIL_QUOTA_PER_SECOND)
-                .interval(Duration::from_secs(1))
-                .refill(GMAIL_QUOTA_PER_SECOND)
-                .build(),
-        );
-
         let access_token = match user::get_new_token(&http_client, &conn, &mut user).await {
             Ok(token) => token,
             Err(AppError::Oauth2) => {
@@ -86,7 +160,7 @@ impl EmailClient {
                 .await
                 .context("Could not update user account access")?;
 
-                return Err(anyhow!("User needs to reauthenticate"));
+                return Err(GmailAuthError("User needs to reauthenticate".to_string()).into());
             }
             Err(e) => {
                 tracing::error!("Error getting token: {:?}", e);
@@ -94,47 +168,82 @@ impl EmailClient {
             }
         };
 
-        // let user_configured_settings = UserInboxSettingsCtrl::get(&conn, user.id())
-        //     .await
-        //     .context("Could not retrieve inbox settings")?;
-
-        // let mut category_inbox_settings = UserInboxSettingsCtrl::default();
-        // category_inbox_settings.extend(user_configured_settings);
-
-        // This will be a map from Mailclerk/* -> inbox_settings
-        // let category_inbox_settings = category_inbox_settings
-        //     .into_iter()
-        //     .map(|(category, setting)| (format!("Mailclerk/{}", category), setting))
-        //     .collect::<HashMap<_, _>>();
+        let inbox_settings = UserInboxSettingsCtrl::for_user(&conn, user.id())
+            .await
+            .context("Could not retrieve inbox settings")?;
+        let important_categories =
+            DefaultEmailRuleOverrideCtrl::important_categories(&conn, user.id())
+                .await
+                .context("Could not retrieve important categories")?;
 
         Ok(EmailClient {
             http_client,
             access_token,
-            rate_limiter,
+            rate_limiters,
+            template_labels: Arc::new(Mutex::new(HashSet::new())),
+            full_templates: Arc::new(Mutex::new(HashSet::new())),
+            inbox_settings,
+            important_categories,
             email_address: user.email().to_string(),
         })
     }
 
     // This is only used to test a new client on authentication
-    pub fn from_access_code(http_client: reqwest::Client, access_token: String) -> EmailClient {
-        let rate_limiter = Arc::new(
-            RateLimiter::builder()
-                .initial(GMAIL_QUOTA_PER_SECOND)
-                .interval(Duration::from_secs(1))
-                .refill(GMAIL_QUOTA_PER_SECOND)
-                .build(),
-        );
-
+    pub fn from_access_code(
+        http_client: reqwest::Client,
+        rate_limiters: RateLimiters,
+        access_token: String,
+    ) -> EmailClient {
         EmailClient {
             http_client,
             access_token,
-            rate_limiter,
+            rate_limiters,
+            template_labels: Arc::new(Mutex::new(HashSet::new())),
+            full_templates: Arc::new(Mutex::new(HashSet::new())),
+            inbox_settings: CategoryInboxSettings::default(),
+            important_categories: ImportantCategories::default(),
             email_address: "test".to_string(),
         }
     }
 
+    /// Waits for the Gmail quota the request will use
+    async fn acquire(&self, units: usize) {
+        self.rate_limiters
+            .acquire_gmail(&self.email_address, units)
+            .await;
+    }
+
+    /// Sends a Gmail request, pausing the user's requests on every instance when Gmail asks
+    /// us to slow down
+    async fn send(&self, request: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+        let resp = request.send().await?;
+        match resp.status() {
+            reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                self.rate_limiters
+                    .trigger_gmail_backoff(&self.email_address);
+                Ok(resp)
+            }
+            // Gmail also reports rate limits as 403s, which only the body tells apart
+            reqwest::StatusCode::FORBIDDEN => {
+                let status = resp.status();
+                let headers = resp.headers().clone();
+                let body = resp.bytes().await?;
+                if is_rate_limit_error(&body) {
+                    self.rate_limiters
+                        .trigger_gmail_backoff(&self.email_address);
+                }
+
+                let mut resp = http::Response::new(body);
+                *resp.status_mut() = status;
+                *resp.headers_mut() = headers;
+                Ok(resp.into())
+            }
+            _ => Ok(resp),
+        }
+    }
+
     pub async fn watch_mailbox(&self) -> anyhow::Result<WatchResponse> {
-        self.rate_limiter.acquire(GMAIL_API_QUOTA.watch).await;
+        self.acquire(GMAIL_API_QUOTA.watch).await;
         const TOPIC_NAME: &str = "projects/mail-assist-434915/topics/mailclerk-user-inboxes";
         let resp = self
             .http_client
@@ -146,7 +255,7 @@ impl EmailClient {
                 "labelFilterBehavior": "INCLUDE",
             }));
 
-        let data = resp.send().await?;
+        let data = self.send(resp).await?;
 
         if !data.status().is_success() {
             let json = data.json::<serde_json::Value>().await?;
@@ -162,9 +271,7 @@ impl EmailClient {
         &self,
         options: MessageListOptions,
     ) -> anyhow::Result<ListMessagesResponse> {
-        self.rate_limiter
-            .acquire(GMAIL_API_QUOTA.messages_list)
-            .await;
+        self.acquire(GMAIL_API_QUOTA.messages_list).await;
 
         let time_filter = options
             .more_recent_than
@@ -178,6 +285,9 @@ impl EmailClient {
         if let Some(time_filter) = time_filter {
             filters.push(time_filter);
         }
+        if let Some(received_after) = options.received_after {
+            filters.push(format!("after:{}", received_after.timestamp()));
+        }
         let max_results = options.max_results.unwrap_or(MAX_RESULTS_DEFAULT);
 
         let mut query = vec![
@@ -189,12 +299,14 @@ impl EmailClient {
             query.push(("pageToken".to_string(), token));
         }
         let resp = self
-            .http_client
-            .get(gmail_url!("messages"))
-            .query(&query)
-            .bearer_auth(&self.access_token)
-            .send()
+            .send(
+                self.http_client
+                    .get(gmail_url!("messages"))
+                    .query(&query)
+                    .bearer_auth(&self.access_token),
+            )
             .await?;
+        let resp = GmailApiError::check(resp).await?;
 
         let data = resp.json::<ListMessagesResponse>().await?;
 
@@ -202,17 +314,17 @@ impl EmailClient {
     }
 
     pub async fn get_message_by_id(&self, message_id: &str) -> anyhow::Result<Message> {
-        self.rate_limiter
-            .acquire(GMAIL_API_QUOTA.messages_get)
-            .await;
+        self.acquire(GMAIL_API_QUOTA.messages_get).await;
         let id = message_id;
         let req = self
-            .http_client
-            .get(gmail_url!("messages", id))
-            .bearer_auth(&self.access_token)
-            .query(&[("format", "RAW")])
-            .send()
+            .send(
+                self.http_client
+                    .get(gmail_url!("messages", id))
+                    .bearer_auth(&self.access_token)
+                    .query(&[("format", "RAW")]),
+            )
             .await?;
+        let req = GmailApiError::check(req).await?;
 
         req.json::<Message>().await.context("Error getting message")
     }
@@ -222,18 +334,60 @@ impl EmailClient {
         ParsedMessage::from_gmail_message(message)
     }
 
+    /// Lists label changes since `start_history_id`, returns `None` if the history id
+    /// is too old for Gmail to serve and syncing needs to start over
+    pub async fn get_label_history(
+        &self,
+        start_history_id: u64,
+        page_token: Option<String>,
+    ) -> anyhow::Result<Option<ListHistoryResponse>> {
+        self.acquire(GMAIL_API_QUOTA.history_list).await;
+
+        let mut query = vec![
+            ("startHistoryId".to_string(), start_history_id.to_string()),
+            ("historyTypes".to_string(), "labelAdded".to_string()),
+            ("historyTypes".to_string(), "labelRemoved".to_string()),
+            ("maxResults".to_string(), MAX_RESULTS_DEFAULT.to_string()),
+        ];
+
+        if let Some(token) = page_token {
+            query.push(("pageToken".to_string(), token));
+        }
+
+        let resp = self
+            .send(
+                self.http_client
+                    .get(gmail_url!("history"))
+                    .query(&query)
+                    .bearer_auth(&self.access_token),
+            )
+            .await?;
+
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let data = resp.json::<serde_json::Value>().await?;
+        if data.get("error").is_some() {
+            return Err(anyhow!("Error listing history: {:?}", data));
+        }
+
+        Ok(Some(serde_json::from_value(data)?))
+    }
+
     // pub async fn get_threads(&self) -> anyhow::Result<Vec<Thread>> {
     //     let (_, resp) = self.hub.users().threads_list("me").doit().await?;
     //     Ok(resp.threads.unwrap_or_default())
     // }
 
     pub async fn get_labels(&self) -> anyhow::Result<Vec<Label>> {
-        self.rate_limiter.acquire(GMAIL_API_QUOTA.labels_list).await;
+        self.acquire(GMAIL_API_QUOTA.labels_list).await;
         let resp = self
-            .http_client
-            .get(gmail_url!("labels"))
-            .bearer_auth(&self.access_token)
-            .send()
+            .send(
+                self.http_client
+                    .get(gmail_url!("labels"))
+                    .bearer_auth(&self.access_token),
+            )
             .await?;
         let data = resp.json::<ListLabelsResponse>().await?;
 
@@ -241,16 +395,15 @@ impl EmailClient {
     }
 
     pub async fn create_label(&self, label: Label) -> anyhow::Result<Label> {
-        self.rate_limiter
-            .acquire(GMAIL_API_QUOTA.labels_create)
-            .await;
+        self.acquire(GMAIL_API_QUOTA.labels_create).await;
 
         let resp = self
-            .http_client
-            .post(gmail_url!("labels"))
-            .bearer_auth(&self.access_token)
-            .json(&label)
-            .send()
+            .send(
+                self.http_client
+                    .post(gmail_url!("labels"))
+                    .bearer_auth(&self.access_token)
+                    .json(&label),
+            )
             .await?;
         let data = resp.json::<serde_json::Value>().await?;
         if let Some(error) = data.get("error") {
@@ -268,15 +421,38 @@ impl EmailClient {
         Ok(serde_json::from_value(data)?)
     }
 
+    pub async fn update_label(&self, label: Label) -> anyhow::Result<Label> {
+        let label_id = label.id.clone().context("Label id not provided")?;
+        self.acquire(GMAIL_API_QUOTA.labels_update).await;
+
+        let resp = self
+            .send(
+                self.http_client
+                    .put(gmail_url!("labels", &label_id))
+                    .bearer_auth(&self.access_token)
+                    .json(&label),
+            )
+            .await?;
+        let data = resp.json::<serde_json::Value>().await?;
+        if data.get("error").is_some() {
+            return Err(anyhow::anyhow!(
+                "Error updating label: {:?} Error: {:?}",
+                label,
+                data
+            ));
+        }
+
+        Ok(serde_json::from_value(data)?)
+    }
+
     pub async fn delete_label(&self, label_id: String) -> anyhow::Result<()> {
-        self.rate_limiter
-            .acquire(GMAIL_API_QUOTA.labels_delete)
-            .await;
+        self.acquire(GMAIL_API_QUOTA.labels_delete).await;
         let resp = self
-            .http_client
-            .delete(gmail_url!("labels", &label_id))
-            .bearer_auth(&self.access_token)
-            .send()
+            .send(
+                self.http_client
+                    .delete(gmail_url!("labels", &label_id))
+                    .bearer_auth(&self.access_token),
+            )
             .await?;
         match resp.json::<serde_json::Value>().await {
             Ok(data) if data.get("error").is_some() => {
@@ -293,16 +469,24 @@ impl EmailClient {
     pub async fn configure_labels_if_needed(
         &self,
         user_custom_labels: Vec<String>,
+        label_settings: &[label_setting::Model],
     ) -> anyhow::Result<bool> {
+        // Templated labels may have been deleted as well, so they are looked up again
+        self.template_labels.lock().unwrap().clear();
+        self.full_templates.lock().unwrap().clear();
         let current_labels = self.get_labels().await?;
 
         let parent_label_exists = current_labels
             .iter()
-            .any(|l| l.name.as_ref().map_or(false, |n| n == "Mailclerk"));
+            .any(|l| l.name.as_ref() == Some(&cfg.settings.label_root));
 
         let existing_labels = current_labels
             .iter()
-            .filter(|l| l.name.as_ref().map_or(false, |n| n.contains("Mailclerk/")))
+            .filter(|l| {
+                l.name
+                    .as_ref()
+                    .is_some_and(|n| cfg.mail_label_of(n).is_some())
+            })
             .cloned()
             .collect::<Vec<_>>();
 
@@ -311,15 +495,7 @@ impl EmailClient {
         // -- DEBUG
 
         // Configure labels if they need it
-        let required_labels = cfg
-            .categories
-            .iter()
-            .map(|c| c.mail_label.as_str())
-            .chain(cfg.heuristics.iter().map(|c| c.mail_label.as_str()))
-            .chain(labels::UtilityLabels::iter().map(|c| c.as_str()))
-            .chain(user_custom_labels.iter().map(|s| s.as_str()))
-            .map(|mail_label| format!("Mailclerk/{}", mail_label))
-            .collect::<HashSet<_>>();
+        let required_labels = required_label_names(&user_custom_labels);
 
         let existing_label_names = existing_labels
             .iter()
@@ -348,7 +524,28 @@ impl EmailClient {
                 .collect::<Vec<_>>()
         };
 
-        if parent_label_exists && missing_labels.is_empty() && unneeded_labels.is_empty() {
+        let settings_by_category = label_settings
+            .iter()
+            .map(|s| (s.category.as_str(), s))
+            .collect::<HashMap<_, _>>();
+
+        // Only labels the user has settings for are updated, so changes made in Gmail to other
+        // labels are kept
+        let labels_to_update = existing_labels
+            .iter()
+            .filter_map(|l| {
+                let name = cfg.mail_label_of(l.name.as_ref()?)?;
+                let setting = settings_by_category.get(name).copied()?;
+                let appearance = LabelAppearance::resolve(name, Some(setting));
+                (!appearance.matches(l)).then(|| appearance.apply_to(l))
+            })
+            .collect::<Vec<_>>();
+
+        if parent_label_exists
+            && missing_labels.is_empty()
+            && unneeded_labels.is_empty()
+            && labels_to_update.is_empty()
+        {
             // Labels are already configured
             return Ok(false);
         }
@@ -358,7 +555,7 @@ impl EmailClient {
                 id: None,
                 type_: Some("user".to_string()),
                 color: Some(COLOR_MAP.get("blue-600")),
-                name: Some("Mailclerk".to_string()),
+                name: Some(cfg.settings.label_root.clone()),
                 messages_total: None,
                 messages_unread: None,
                 threads_total: None,
@@ -371,68 +568,129 @@ impl EmailClient {
                 .context("Could not create parent label")?;
         }
 
-        let labels_to_add = missing_labels.into_iter().collect::<Vec<_>>();
+        // Parents must exist before their children for Gmail to nest them, so labels are
+        // created one depth level at a time
+        let mut labels_by_depth = BTreeMap::<usize, Vec<String>>::new();
+        for label in missing_labels {
+            labels_by_depth
+                .entry(label.matches('/').count())
+                .or_default()
+                .push(label);
+        }
 
         // Add mailclerk labels
-        let add_label_tasks = labels_to_add.into_iter().map(|label| {
-            let (message_list_visibility, label_list_visibility) =
-                if label == UtilityLabels::Uncategorized.as_str() {
-                    (Some("hide".to_string()), Some("labelHide".to_string()))
-                } else {
-                    (
-                        Some("show".to_string()),
-                        Some("labelShowIfUnread".to_string()),
-                    )
-                };
-            let label_name = label.split("Mailclerk/").nth(1).unwrap_or("");
-            let color = COLOR_MAP.get(label_name);
-            let label = Label {
-                id: None,
-                type_: Some("user".to_string()),
-                color: Some(color),
-                name: Some(label.clone()),
-                messages_total: None,
-                messages_unread: None,
-                threads_total: None,
-                threads_unread: None,
-                message_list_visibility,
-                label_list_visibility,
-            };
-            async { self.create_label(label).await }
-        });
-
-        // Reset mailclerk labels
-        //? Maybe remove this in the future?
-        //? Probably needs to migrate existing mails to new labels
-        // let remove_label_tasks = existing_labels.into_iter().map(|label| async {
-        //     let id = label.id.context("Label id not provided")?;
-        //     self.delete_label(id).await
-        // });
-
-        // let results = join_all(remove_label_tasks).await;
-        // for result in results {
-        //     match result {
-        //         Ok(_) => {}
-        //         Err(e) => {
-        //             tracing::error!("{e}");
-        //             return Err(e);
-        //         }
-        //     }
-        // }
-
-        let results = join_all(add_label_tasks).await;
+        for labels in labels_by_depth.into_values() {
+            let add_label_tasks = labels.into_iter().map(|label| {
+                let label_name = cfg.mail_label_of(&label).unwrap_or_default();
+                let appearance = LabelAppearance::resolve(
+                    label_name,
+                    settings_by_category.get(label_name).copied(),
+                );
+                let label = appearance.to_label(label);
+                async { self.create_label(label).await }
+            });
+
+            let results = join_all(add_label_tasks).await;
+            for result in results {
+                result.context("Could not create label")?;
+            }
+        }
+
+        let update_label_tasks = labels_to_update
+            .into_iter()
+            .map(|label| async { self.update_label(label).await });
+
+        // Obsolete labels are moved and deleted by label_migration once replacements exist
+
+        let results = join_all(update_label_tasks).await;
         for result in results {
-            result.context("Could not create label")?;
+            result.context("Could not update label")?;
         }
 
         Ok(true)
     }
 
+    /// The label an email gets from `mail_label`. Templated labels are created the first time
+    /// a value is seen, and once a template has reached its label limit new values go to its
+    /// parent label instead.
+    pub async fn resolve_label(
+        &self,
+        mail_label: &str,
+        email_message: &ParsedMessage,
+    ) -> anyhow::Result<String> {
+        if !label_template::is_template(mail_label) {
+            return Ok(mail_label.to_string());
+        }
+
+        let parent = label_template::static_label(mail_label);
+        let Some(rendered) = label_template::render(mail_label, email_message)
+            .filter(|l| label_template::fits_label_name(&cfg.label_name(l)))
+        else {
+            return Ok(parent.to_string());
+        };
+        if self.template_labels.lock().unwrap().contains(&rendered) {
+            return Ok(rendered);
+        }
+        if self.full_templates.lock().unwrap().contains(mail_label) {
+            return Ok(parent.to_string());
+        }
+
+        let existing_labels = self.template_label_names().await?;
+
+        if !existing_labels.contains(&rendered) {
+            let template_label_count = existing_labels
+                .iter()
+                .filter(|l| l.as_str() != parent && label_template::label_matches(mail_label, l))
+                .count();
+            if template_label_count >= cfg.label_templates.max_labels_per_template {
+                tracing::warn!(
+                    "Label limit reached for {} on {}, using {}",
+                    mail_label,
+                    self.email_address,
+                    parent
+                );
+                self.full_templates
+                    .lock()
+                    .unwrap()
+                    .insert(mail_label.to_string());
+                return Ok(parent.to_string());
+            }
+
+            for label in with_parents(&rendered).filter(|l| !existing_labels.contains(*l)) {
+                let appearance = LabelAppearance::default_for(label);
+                if let Err(e) = self
+                    .create_label(appearance.to_label(cfg.label_name(label)))
+                    .await
+                {
+                    // Another processor may have created the label in the meantime
+                    if !self.template_label_names().await?.contains(label) {
+                        return Err(e.context("Could not create templated label"));
+                    }
+                }
+            }
+        }
+
+        self.template_labels
+            .lock()
+            .unwrap()
+            .insert(rendered.clone());
+        Ok(rendered)
+    }
+
+    /// Mail labels of the user's labels under the root label
+    async fn template_label_names(&self) -> anyhow::Result<HashSet<String>> {
+        Ok(self
+            .get_labels()
+            .await?
+            .into_iter()
+            .filter_map(|l| Some(cfg.mail_label_of(&l.name?)?.to_string()))
+            .collect())
+    }
+
     /// Gets the label id for the mailclerk daily summary label, if doesn't exist, creates it
     pub async fn get_daily_summary_label_id(&self) -> anyhow::Result<String> {
         let existing_labels = self.get_labels().await?;
-        let daily_summary_label_name =
-            format!("Mailclerk/{}", DAILY_SUMMARY_CATEGORY.mail_label.clone());
+        let daily_summary_label_name = cfg.label_name(&DAILY_SUMMARY_CATEGORY.mail_label);
         if let Some(label) = existing_labels.iter().find(|l| {
             l.name
                 .as_ref()
@@ -459,6 +717,14 @@ impl EmailClient {
         }
     }
 
+    pub fn is_important(&self, mail_label: &str) -> bool {
+        self.important_categories.contains(mail_label)
+    }
+
+    pub fn important_categories(&self) -> &ImportantCategories {
+        &self.important_categories
+    }
+
     pub async fn label_email(
         &self,
         email_id: String,
@@ -466,17 +732,25 @@ impl EmailClient {
         email_rule: EmailRule,
     ) -> anyhow::Result<LabelUpdate> {
         let user_labels = self.get_labels().await?;
-        self.rate_limiter
-            .acquire(GMAIL_API_QUOTA.messages_modify)
-            .await;
-        let (json_body, update) = build_label_update(user_labels, current_labels, email_rule)?;
+        self.acquire(GMAIL_API_QUOTA.messages_modify).await;
+        let inbox_setting = self.inbox_settings.get(&email_rule.mail_label);
+        let is_important = self.is_important(&email_rule.mail_label);
+        let (json_body, update) = build_label_update(
+            user_labels,
+            current_labels,
+            email_rule,
+            inbox_setting,
+            is_important,
+        )?;
         let resp = self
-            .http_client
-            .post(gmail_url!("messages", &email_id, "modify"))
-            .bearer_auth(&self.access_token)
-            .json(&json_body)
-            .send()
+            .send(
+                self.http_client
+                    .post(gmail_url!("messages", &email_id, "modify"))
+                    .bearer_auth(&self.access_token)
+                    .json(&json_body),
+            )
             .await?;
+        let resp = GmailApiError::check(resp).await?;
         let data = resp.json::<serde_json::Value>().await?;
 
         if data.get("error").is_some() {
@@ -486,58 +760,145 @@ impl EmailClient {
         Ok(update)
     }
 
+    /// Moves an email from one Mailclerk label to the label of `email_rule`
+    pub async fn move_email_label(
+        &self,
+        email_id: String,
+        current_labels: Vec<String>,
+        from_mail_label: &str,
+        email_rule: EmailRule,
+    ) -> anyhow::Result<LabelUpdate> {
+        let user_labels = self.get_labels().await?;
+        self.acquire(GMAIL_API_QUOTA.messages_modify).await;
+        let (json_body, update) =
+            build_label_move(user_labels, current_labels, from_mail_label, email_rule)?;
+        let resp = self
+            .send(
+                self.http_client
+                    .post(gmail_url!("messages", &email_id, "modify"))
+                    .bearer_auth(&self.access_token)
+                    .json(&json_body),
+            )
+            .await?;
+        let resp = GmailApiError::check(resp).await?;
+        let data = resp.json::<serde_json::Value>().await?;
+
+        if data.get("error").is_some() {
+            return Err(anyhow::anyhow!("Error moving email label: {:?}", data));
+        }
+
+        Ok(update)
+    }
+
+    /// Lists the first page of the messages that have the label, by label id
+    pub async fn list_message_ids_with_label(
+        &self,
+        label_id: &str,
+    ) -> anyhow::Result<ListMessagesResponse> {
+        self.acquire(GMAIL_API_QUOTA.messages_list).await;
+
+        let resp = self
+            .send(
+                self.http_client
+                    .get(gmail_url!("messages"))
+                    .query(&[
+                        ("labelIds", label_id.to_string()),
+                        ("maxResults", MAX_RESULTS_DEFAULT.to_string()),
+                    ])
+                    .bearer_auth(&self.access_token),
+            )
+            .await?;
+        let resp = GmailApiError::check(resp).await?;
+
+        Ok(resp.json::<ListMessagesResponse>().await?)
+    }
+
+    /// Adds and removes labels on up to 1000 messages in a single request
+    pub async fn batch_modify_labels(
+        &self,
+        message_ids: Vec<String>,
+        add_label_ids: Vec<String>,
+        remove_label_ids: Vec<String>,
+    ) -> anyhow::Result<()> {
+        if message_ids.len() > MAX_BATCH_MODIFY_IDS {
+            return Err(anyhow!(
+                "Cannot modify more than {} messages at once",
+                MAX_BATCH_MODIFY_IDS
+            ));
+        }
+
+        self.acquire(GMAIL_API_QUOTA.messages_batch_modify).await;
+        let resp = self
+            .send(
+                self.http_client
+                    .post(gmail_url!("messages", "batchModify"))
+                    .bearer_auth(&self.access_token)
+                    .json(&json!({
+                        "ids": message_ids,
+                        "addLabelIds": add_label_ids,
+                        "removeLabelIds": remove_label_ids,
+                    })),
+            )
+            .await?;
+        match resp.json::<serde_json::Value>().await {
+            Ok(data) if data.get("error").is_some() => {
+                Err(anyhow::anyhow!("Error modifying labels: {:?}", data))
+            }
+            // An empty response is expected if the labels were modified successfully
+            _ => Ok(()),
+        }
+    }
+
     pub async fn get_profile(&self) -> anyhow::Result<Profile> {
-        self.rate_limiter.acquire(GMAIL_API_QUOTA.get_profile).await;
+        self.acquire(GMAIL_API_QUOTA.get_profile).await;
         let resp = self
-            .http_client
-            .get("https://www.googleapis.com/gmail/v1/users/me/profile")
-            .bearer_auth(&self.access_token)
-            .send()
+            .send(
+                self.http_client
+                    .get("https://www.googleapis.com/gmail/v1/users/me/profile")
+                    .bearer_auth(&self.access_token),
+            )
             .await?;
 
         Ok(resp.json::<Profile>().await?)
     }
 
     pub async fn insert_email(&self, message: Message) -> anyhow::Result<()> {
-        self.rate_limiter
-            .acquire(GMAIL_API_QUOTA.messages_insert)
-            .await;
-        self.http_client
-            .post(gmail_url!("messages"))
-            .bearer_auth(&self.access_token)
-            .json(&message)
-            .send()
-            .await?;
+        self.acquire(GMAIL_API_QUOTA.messages_insert).await;
+        self.send(
+            self.http_client
+                .post(gmail_url!("messages"))
+                .bearer_auth(&self.access_token)
+                .json(&message),
+        )
+        .await?;
 
         Ok(())
     }
 
     pub async fn trash_email(&self, message_id: &str) -> anyhow::Result<()> {
-        self.rate_limiter
-            .acquire(GMAIL_API_QUOTA.messages_trash)
-            .await;
-        self.http_client
-            .post(gmail_url!("messages", message_id, "trash"))
-            .bearer_auth(&self.access_token)
-            .send()
-            .await?;
+        self.acquire(GMAIL_API_QUOTA.messages_trash).await;
+        self.send(
+            self.http_client
+                .post(gmail_url!("messages", message_id, "trash"))
+                .bearer_auth(&self.access_token),
+        )
+        .await?;
 
         Ok(())
     }
 
     pub async fn archive_email(&self, message_id: &str) -> anyhow::Result<()> {
-        self.rate_limiter
-            .acquire(GMAIL_API_QUOTA.messages_modify)
-            .await;
-        self.http_client
-            .post(gmail_url!("messages", message_id, "modify"))
-            .bearer_auth(&self.access_token)
-            .json(&json!({
-                "removeLabelIds": ["INBOX", "UNREAD"],
-                "addLabelIds": []
-            }))
-            .send()
-            .await?;
+        self.acquire(GMAIL_API_QUOTA.messages_modify).await;
+        self.send(
+            self.http_client
+                .post(gmail_url!("messages", message_id, "modify"))
+                .bearer_auth(&self.access_token)
+                .json(&json!({
+                    "removeLabelIds": ["INBOX", "UNREAD"],
+                    "addLabelIds": []
+                })),
+        )
+        .await?;
 
         Ok(())
     }
@@ -547,6 +908,8 @@ fn build_label_update(
     user_labels: Vec<Label>,
     current_labels: Vec<String>,
     email_rule: EmailRule,
+    inbox_setting: InboxSetting,
+    is_important: bool,
 ) -> anyhow::Result<(serde_json::Value, LabelUpdate)> {
     static RE_CATEGORY_LABEL: Lazy<Regex> = Lazy::new(|| Regex::new(r"CATEGORY_+").unwrap());
 
@@ -562,7 +925,7 @@ fn build_label_update(
         .map_or(Vec::new(), |c| vec![c.to_value()]);
 
     // Only remove categories if you have a different category to add
-    let categories_to_remove = if categories_to_add.is_empty() {
+    let mut categories_to_remove = if categories_to_add.is_empty() {
         None
     } else {
         Some(
@@ -574,9 +937,24 @@ fn build_label_update(
         )
     };
 
+    // Important emails always stay in the inbox
+    let inbox_setting = if is_important {
+        InboxSetting::default()
+    } else {
+        inbox_setting
+    };
+
+    // System labels use their name as their id
+    let leaves_inbox = inbox_setting.skip_inbox || inbox_setting.mark_spam;
+    if leaves_inbox && current_labels.iter().any(|l| l == "INBOX") {
+        categories_to_remove
+            .get_or_insert_with(Vec::new)
+            .push("INBOX".to_string());
+    }
+
     let label_id = user_labels
         .iter()
-        .find(|l| l.name.as_ref() == Some(&format!("Mailclerk/{}", email_rule.mail_label)))
+        .find(|l| l.name.as_ref() == Some(&cfg.label_name(&email_rule.mail_label)))
         .map(|l| l.id.clone().unwrap_or_default())
         .context(format!("Could not find {}!", email_rule.mail_label))?;
 
@@ -585,6 +963,18 @@ fn build_label_update(
         label_ids.push(label_id);
         let mut label_names = categories_to_add;
         label_names.push(email_rule.mail_label);
+        if inbox_setting.mark_spam {
+            label_ids.push("SPAM".to_string());
+            label_names.push("SPAM".to_string());
+        }
+        if is_important {
+            for system_label in ["IMPORTANT", "STARRED"] {
+                if !current_labels.iter().any(|l| l == system_label) {
+                    label_ids.push(system_label.to_string());
+                    label_names.push(system_label.to_string());
+                }
+            }
+        }
 
         (
             label_ids.into_iter().collect::<Vec<_>>(),
@@ -606,19 +996,80 @@ fn build_label_update(
     ))
 }
 
+fn build_label_move(
+    user_labels: Vec<Label>,
+    current_labels: Vec<String>,
+    from_mail_label: &str,
+    email_rule: EmailRule,
+) -> anyhow::Result<(serde_json::Value, LabelUpdate)> {
+    let from_label_id = user_labels
+        .iter()
+        .find(|l| l.name.as_ref() == Some(&cfg.label_name(from_mail_label)))
+        .and_then(|l| l.id.clone());
+    // Past emails are only relabeled, they stay where they are in the mailbox
+    let (mut json_body, mut update) = build_label_update(
+        user_labels,
+        current_labels,
+        email_rule,
+        InboxSetting::default(),
+        false,
+    )?;
+
+    // The old label may have been deleted already, in which case there is nothing to remove
+    if let Some(from_label_id) = from_label_id {
+        json_body["removeLabelIds"]
+            .as_array_mut()
+            .context("removeLabelIds is not an array")?
+            .push(json!(from_label_id));
+        update
+            .removed
+            .get_or_insert_with(Vec::new)
+            .push(from_mail_label.to_string());
+    }
+
+    Ok((json_body, update))
+}
+
+/// Names of every label under the root label the user should have, including the parents of
+/// nested categories
+pub fn required_label_names(user_custom_labels: &[String]) -> HashSet<String> {
+    get_required_labels()
+        .into_iter()
+        .chain(
+            user_custom_labels
+                .iter()
+                .flat_map(|mail_label| with_parents(label_template::static_label(mail_label)))
+                .map(|mail_label| cfg.label_name(mail_label)),
+        )
+        .collect()
+}
+
 fn get_required_labels() -> HashSet<String> {
     cfg.categories
         .iter()
-        .map(|c| c.mail_label.as_str())
-        .chain(cfg.heuristics.iter().map(|c| c.mail_label.as_str()))
+        .map(|c| label_template::static_label(&c.mail_label))
+        .chain(cfg.heuristic_labels())
         .chain(labels::UtilityLabels::iter().map(|c| c.as_str()))
-        .map(|mail_label| format!("Mailclerk/{}", mail_label))
+        .flat_map(with_parents)
+        .map(|mail_label| cfg.label_name(mail_label))
         .collect::<HashSet<_>>()
 }
 
-fn format_filter(label: &str) -> String {
-    let label = label.replace(" ", "-");
-    format!("label:Mailclerk/{}", label)
+/// The parents a nested label sits under followed by the label itself, so `Finance/Receipts`
+/// gives `Finance` and `Finance/Receipts`
+pub fn with_parents(mail_label: &str) -> impl Iterator<Item = &str> {
+    mail_label
+        .match_indices('/')
+        .map(move |(i, _)| &mail_label[..i])
+        .chain(std::iter::once(mail_label))
+}
+
+/// Gmail search filter for a mail label, templates filter on their parent label
+pub fn format_filter(label: &str) -> String {
+    let label = cfg
+        .label_name(label_template::static_label(label))
+        .replace(" ", "-");
+    format!("label:{}", label)
 }
 
 fn default_mailclerk_label_filter() -> String {
@@ -627,7 +1078,7 @@ fn default_mailclerk_label_filter() -> String {
         .categories
         .iter()
         .map(|c| c.mail_label.as_str())
-        .chain(cfg.heuristics.iter().map(|c| c.mail_label.as_str()))
+        .chain(cfg.heuristic_labels())
         .chain(labels::UtilityLabels::iter().map(|l| l.as_str()))
         .map(format_filter)
         .collect::<HashSet<String>>();
@@ -648,6 +1099,8 @@ pub struct MessageListOptions {
     /// Messages more recent than this duration will be returned
     pub more_recent_than: Option<chrono::Duration>,
     pub older_than: Option<chrono::Duration>,
+    /// Unlike `more_recent_than` this doesn't move, so page tokens stay valid between calls
+    pub received_after: Option<DateTime<Utc>>,
     pub categories: Option<Vec<String>>,
     pub page_token: Option<String>,
     pub max_results: Option<u32>,
@@ -688,6 +1141,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_is_rate_limit_error() {
+        let body = |reason: &str| {
+            json!({
+                "error": {
+                    "code": 403,
+                    "errors": [{ "domain": "usageLimits", "reason": reason }],
+                }
+            })
+            .to_string()
+        };
+
+        assert!(is_rate_limit_error(body("rateLimitExceeded").as_bytes()));
+        assert!(is_rate_limit_error(
+            body("userRateLimitExceeded").as_bytes()
+        ));
+        assert!(!is_rate_limit_error(
+            body("insufficientPermissions").as_bytes()
+        ));
+        assert!(!is_rate_limit_error(b"Forbidden"));
+    }
+
+    #[test]
+    fn test_is_auth_error() {
+        let api_error = |status: reqwest::StatusCode| {
+            anyhow::Error::new(GmailApiError::new(status, String::new()))
+                .context("Error loading next email page")
+        };
+
+        assert!(is_auth_error(&api_error(reqwest::StatusCode::UNAUTHORIZED)));
+        assert!(!is_auth_error(&api_error(reqwest::StatusCode::FORBIDDEN)));
+        assert!(is_auth_error(&anyhow::Error::new(GmailAuthError(
+            "invalid_grant".to_string()
+        ))));
+        assert!(!is_auth_error(&anyhow!("Error getting access token")));
+    }
+
     #[test]
     fn test_build_label_update() {
         let user_labels = vec![Label {
@@ -705,6 +1195,8 @@ mod tests {
                     AssociatedEmailClientCategory::CategoryPromotions,
                 ),
             },
+            InboxSetting::default(),
+            false,
         ) {
             Ok((json_body, update)) => {
                 assert_eq!(
@@ -729,6 +1221,148 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_build_label_update_inbox_settings() {
+        let user_labels = vec![Label {
+            id: Some("Label_10".to_string()),
+            name: Some(cfg.label_name("ads")),
+            ..Label::default()
+        }];
+        let email_rule = super::EmailRule {
+            prompt_content: "Advertisment".to_string(),
+            mail_label: "ads".to_string(),
+            associated_email_client_category: None,
+        };
+
+        let (json_body, update) = super::build_label_update(
+            user_labels.clone(),
+            vec!["INBOX".to_string(), "UNREAD".to_string()],
+            email_rule.clone(),
+            InboxSetting {
+                skip_inbox: true,
+                mark_spam: false,
+            },
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            json_body,
+            serde_json::json!({
+                "addLabelIds": ["Label_10"],
+                "removeLabelIds": ["INBOX"]
+            })
+        );
+        assert_eq!(update.removed, Some(vec!["INBOX".to_string()]));
+
+        let (json_body, update) = super::build_label_update(
+            user_labels,
+            vec!["INBOX".to_string()],
+            email_rule,
+            InboxSetting {
+                skip_inbox: false,
+                mark_spam: true,
+            },
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            json_body,
+            serde_json::json!({
+                "addLabelIds": ["Label_10", "SPAM"],
+                "removeLabelIds": ["INBOX"]
+            })
+        );
+        assert_eq!(
+            update.added,
+            Some(vec!["ads".to_string(), "SPAM".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_build_label_update_important() {
+        let user_labels = vec![Label {
+            id: Some("Label_12".to_string()),
+            name: Some(cfg.label_name("security_alert")),
+            ..Label::default()
+        }];
+        let email_rule = super::EmailRule {
+            prompt_content: "Security alert".to_string(),
+            mail_label: "security_alert".to_string(),
+            associated_email_client_category: None,
+        };
+
+        // Important emails stay in the inbox even if the category would skip it
+        let (json_body, update) = super::build_label_update(
+            user_labels,
+            vec!["INBOX".to_string(), "STARRED".to_string()],
+            email_rule,
+            InboxSetting {
+                skip_inbox: true,
+                mark_spam: false,
+            },
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            json_body,
+            serde_json::json!({
+                "addLabelIds": ["Label_12", "IMPORTANT"],
+                "removeLabelIds": []
+            })
+        );
+        assert_eq!(
+            update.added,
+            Some(vec!["security_alert".to_string(), "IMPORTANT".to_string()])
+        );
+        assert_eq!(update.removed, None);
+    }
+
+    #[test]
+    fn test_build_label_move() {
+        let user_labels = vec![
+            Label {
+                id: Some("Label_10".to_string()),
+                name: Some(cfg.label_name("ads")),
+                ..Label::default()
+            },
+            Label {
+                id: Some("Label_11".to_string()),
+                name: Some(cfg.label_name("newsletters")),
+                ..Label::default()
+            },
+        ];
+        let (json_body, update) = super::build_label_move(
+            user_labels,
+            vec!["Label_10".to_string(), "INBOX".to_string()],
+            "ads",
+            super::EmailRule {
+                prompt_content: "Newsletter".to_string(),
+                mail_label: "newsletters".to_string(),
+                associated_email_client_category: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            json_body,
+            serde_json::json!({
+                "addLabelIds": ["Label_11"],
+                "removeLabelIds": ["Label_10"]
+            })
+        );
+        assert_eq!(update.added, Some(vec!["newsletters".to_string()]));
+        assert_eq!(update.removed, Some(vec!["ads".to_string()]));
+    }
+
+    #[test]
+    fn test_with_parents() {
+        assert_eq!(
+            super::with_parents("Finance/Bills/2024").collect::<Vec<_>>(),
+            vec!["Finance", "Finance/Bills", "Finance/Bills/2024"]
+        );
+        assert_eq!(super::with_parents("ads").collect::<Vec<_>>(), vec!["ads"]);
+    }
+
     #[test]
     fn test_sanitize_message() {
         use super::*;
@@ -776,7 +1410,9 @@ mod tests {
             "Also Consider -- Based on your resumeSenior Software Engineer-(PHP, TypeScript, Node, AWS) [[LINK]] Senior Software Engineer-CONTRACT-REMOTE [] REMOTE [] Charlotte, NC [] $70 - $90 1-Click Apply [[LINK]] [Chris Chomic] Chris ",
             "Also Consider -- Based on your resume Senior React Native Developer [[LINK]] [] REMOTE [] San Francisco, CA [] $160,000 - $180,000 1-Click Apply [[LINK]] [Joe Lynch] Joe ",
             "Also Consider -- Based on yourresume Senior Backend Engineer [[LINK]] Build out modern platforms supporting the short term rental SaaS space 100% Remote [] REMOTE [] Philadelphia, PA +1 [] $130,000 - $175,000 1-Click Apply [[LINK]] [Charles Simmons] Charles [LinkedIn logo] [[LINK]][Instagram logo] [[LINK]] Jobot.com [[LINK]] | Unsubscribe [[LINK]] Copyright Jobot, LLC, All rights reserved. 3101 West Pacific Coast Hwy,Newport Beach, CA 92663"
-        ).to_string())
+        ).to_string()),
+        headers: sanitized.headers.clone(),
+        list_id: sanitized.list_id.clone(),
         };
         assert_eq!(sanitized, test);
     }
@@ -798,7 +1434,7 @@ mod tests {
             .categories
             .iter()
             .map(|c| c.mail_label.as_str())
-            .chain(cfg.heuristics.iter().map(|c| c.mail_label.as_str()))
+            .chain(cfg.heuristic_labels())
             .chain(labels::UtilityLabels::iter().map(|l| l.as_str()))
             .map(format_filter)
             .chain(std::iter::once("label:inbox".to_string()))
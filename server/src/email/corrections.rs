@@ -0,0 +1,402 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Context;
+use chrono::Utc;
+use google_gmail1::api::History;
+use indexmap::IndexMap;
+use sea_orm::DatabaseConnection;
+
+use crate::{
+    db_core::prelude::*,
+    email::client::EmailClient,
+    model::{
+        email_correction::EmailCorrectionCtrl, labels::UtilityLabels,
+        mailbox_sync_state::MailboxSyncStateCtrl, processed_email::ProcessedEmailCtrl,
+    },
+    server_config::{cfg, DAILY_SUMMARY_CATEGORY},
+};
+
+const BODY_SNIPPET_LENGTH: usize = 500;
+
+/// Net Mailclerk label changes made to a single message, by category name
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LabelChange {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetectedCorrection {
+    pub email_id: String,
+    pub original_category: String,
+    pub corrected_category: Option<String>,
+    pub ai_answer: String,
+}
+
+/// Maps Mailclerk label ids to their category names, skipping labels that are not categories
+pub fn category_labels_by_id(labels: &[google_gmail1::api::Label]) -> HashMap<String, String> {
+    labels
+        .iter()
+        .filter_map(|l| {
+            let id = l.id.clone()?;
+            let name = cfg.mail_label_of(l.name.as_ref()?)?;
+            if name == UtilityLabels::Keep.as_str() || name == DAILY_SUMMARY_CATEGORY.mail_label {
+                return None;
+            }
+            Some((id, name.to_string()))
+        })
+        .collect()
+}
+
+/// Collapses history records into the net label changes for every message, in the order
+/// the messages were first seen
+pub fn collect_label_changes(
+    history: &[History],
+    category_labels: &HashMap<String, String>,
+) -> IndexMap<String, LabelChange> {
+    let mut changes = IndexMap::<String, LabelChange>::new();
+
+    for record in history {
+        let added =
+            record.labels_added.iter().flatten().filter_map(|a| {
+                Some((a.message.as_ref()?.id.clone()?, a.label_ids.clone()?, true))
+            });
+        let removed =
+            record.labels_removed.iter().flatten().filter_map(|r| {
+                Some((r.message.as_ref()?.id.clone()?, r.label_ids.clone()?, false))
+            });
+
+        for (message_id, label_ids, is_added) in added.chain(removed) {
+            for category in label_ids.iter().filter_map(|id| category_labels.get(id)) {
+                let change = changes.entry(message_id.clone()).or_default();
+                let (current, opposite) = if is_added {
+                    (&mut change.added, &mut change.removed)
+                } else {
+                    (&mut change.removed, &mut change.added)
+                };
+                // A later change cancels out an earlier opposite change
+                if let Some(pos) = opposite.iter().position(|c| c == category) {
+                    opposite.remove(pos);
+                } else if !current.contains(category) {
+                    current.push(category.clone());
+                }
+            }
+        }
+    }
+
+    changes.retain(|_, c| !c.added.is_empty() || !c.removed.is_empty());
+    changes
+}
+
+/// A correction is recorded when the user removes the category label we applied, or moves
+/// the email to another category
+pub fn detect_corrections(
+    changes: &IndexMap<String, LabelChange>,
+    processed_emails: &HashMap<String, processed_email::Model>,
+) -> Vec<DetectedCorrection> {
+    changes
+        .iter()
+        .filter_map(|(email_id, change)| {
+            let processed = processed_emails.get(email_id)?;
+            let applied = processed.labels_applied.clone().unwrap_or_default();
+            let original_category = applied
+                .iter()
+                .find(|l| **l == processed.category)
+                .cloned()
+                .unwrap_or_else(|| processed.category.clone());
+
+            let corrected_category = change
+                .added
+                .iter()
+                .find(|c| **c != original_category)
+                .cloned();
+
+            let was_removed = change.removed.contains(&original_category);
+            if !was_removed && corrected_category.is_none() {
+                return None;
+            }
+
+            Some(DetectedCorrection {
+                email_id: email_id.clone(),
+                original_category,
+                corrected_category,
+                ai_answer: processed.ai_answer.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Reads the user's Gmail history since the last sync and records any corrections they made
+/// to our labels. Returns the corrections recorded in this sync.
+pub async fn sync_user_corrections(
+    conn: &DatabaseConnection,
+    email_client: &EmailClient,
+    user_id: i32,
+) -> anyhow::Result<Vec<email_correction::Model>> {
+    let last_history_id = MailboxSyncStateCtrl::get_last_history_id(conn, user_id).await?;
+    let Some(start_history_id) = last_history_id else {
+        reset_history_cursor(conn, email_client, user_id).await?;
+        return Ok(vec![]);
+    };
+
+    let mut history = vec![];
+    let mut latest_history_id = start_history_id;
+    let mut next_page_token = None;
+    loop {
+        let resp = match email_client
+            .get_label_history(start_history_id, next_page_token.clone())
+            .await
+            .context("Error loading label history")?
+        {
+            Some(resp) => resp,
+            None => {
+                tracing::warn!(
+                    "History id {} expired for {}, restarting sync",
+                    start_history_id,
+                    email_client.email_address
+                );
+                reset_history_cursor(conn, email_client, user_id).await?;
+                return Ok(vec![]);
+            }
+        };
+
+        latest_history_id = latest_history_id.max(resp.history_id.unwrap_or_default());
+        history.extend(resp.history.unwrap_or_default());
+        next_page_token = resp.next_page_token;
+        if next_page_token.is_none() {
+            break;
+        }
+    }
+
+    let mut recorded = vec![];
+    if !history.is_empty() {
+        let labels = email_client.get_labels().await?;
+        let changes = collect_label_changes(&history, &category_labels_by_id(&labels));
+        let processed_emails = ProcessedEmailCtrl::get_by_ids(
+            conn,
+            user_id,
+            changes
+                .keys()
+                .cloned()
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect(),
+        )
+        .await?
+        .into_iter()
+        .map(|e| (e.id.clone(), e))
+        .collect::<HashMap<_, _>>();
+
+        for correction in detect_corrections(&changes, &processed_emails) {
+            match record_correction(conn, email_client, user_id, correction).await {
+                Ok(model) => recorded.push(model),
+                Err(e) => {
+                    tracing::error!(
+                        "Error recording correction for {}: {:?}",
+                        email_client.email_address,
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    MailboxSyncStateCtrl::set_last_history_id(conn, user_id, latest_history_id).await?;
+
+    Ok(recorded)
+}
+
+async fn reset_history_cursor(
+    conn: &DatabaseConnection,
+    email_client: &EmailClient,
+    user_id: i32,
+) -> anyhow::Result<()> {
+    let profile = email_client.get_profile().await?;
+    let history_id = profile
+        .history_id
+        .context("No history id in profile response")?;
+    MailboxSyncStateCtrl::set_last_history_id(conn, user_id, history_id).await?;
+
+    Ok(())
+}
+
+async fn record_correction(
+    conn: &DatabaseConnection,
+    email_client: &EmailClient,
+    user_id: i32,
+    correction: DetectedCorrection,
+) -> anyhow::Result<email_correction::Model> {
+    // The message is fetched so the correction can be reused as a labeled example later
+    let message = email_client
+        .get_parsed_message(&correction.email_id)
+        .await
+        .map_err(|e| {
+            tracing::warn!(
+                "Could not fetch corrected email {}: {:?}",
+                correction.email_id,
+                e
+            );
+        })
+        .ok();
+
+    let (from, subject, body_snippet) = message
+        .map(|m| {
+            (
+                m.from,
+                m.subject,
+                m.body
+                    .map(|b| b.chars().take(BODY_SNIPPET_LENGTH).collect::<String>()),
+            )
+        })
+        .unwrap_or_default();
+
+    let model = EmailCorrectionCtrl::upsert(
+        conn,
+        email_correction::ActiveModel {
+            id: ActiveValue::NotSet,
+            user_id: ActiveValue::Set(user_id),
+            email_id: ActiveValue::Set(correction.email_id),
+            original_category: ActiveValue::Set(correction.original_category),
+            corrected_category: ActiveValue::Set(correction.corrected_category),
+            ai_answer: ActiveValue::Set(correction.ai_answer),
+            from: ActiveValue::Set(from),
+            subject: ActiveValue::Set(subject),
+            body_snippet: ActiveValue::Set(body_snippet),
+            created_at: ActiveValue::NotSet,
+            updated_at: ActiveValue::Set(Utc::now().into()),
+        },
+    )
+    .await?;
+
+    Ok(model)
+}
+
+#[cfg(test)]
+mod tests {
+    use google_gmail1::api::{HistoryLabelAdded, HistoryLabelRemoved, Message};
+
+    use super::*;
+
+    fn message(id: &str) -> Option<Message> {
+        Some(Message {
+            id: Some(id.to_string()),
+            ..Message::default()
+        })
+    }
+
+    fn labels() -> HashMap<String, String> {
+        HashMap::from([
+            ("Label_1".to_string(), "ads".to_string()),
+            ("Label_2".to_string(), "receipts".to_string()),
+        ])
+    }
+
+    fn processed(id: &str, category: &str) -> (String, processed_email::Model) {
+        (
+            id.to_string(),
+            processed_email::Model {
+                id: id.to_string(),
+                user_id: 1,
+                processed_at: Utc::now().into(),
+                labels_applied: Some(vec![
+                    "CATEGORY_PROMOTIONS".to_string(),
+                    category.to_string(),
+                ]),
+                labels_removed: None,
+                ai_answer: "Advertisement".to_string(),
+                category: category.to_string(),
+                matched_rule: None,
+            },
+        )
+    }
+
+    #[test]
+    fn test_detects_moved_email() {
+        let history = vec![History {
+            labels_removed: Some(vec![HistoryLabelRemoved {
+                label_ids: Some(vec!["Label_1".to_string()]),
+                message: message("a1"),
+            }]),
+            labels_added: Some(vec![HistoryLabelAdded {
+                label_ids: Some(vec!["Label_2".to_string(), "STARRED".to_string()]),
+                message: message("a1"),
+            }]),
+            ..History::default()
+        }];
+
+        let changes = collect_label_changes(&history, &labels());
+        let corrections = detect_corrections(&changes, &HashMap::from([processed("a1", "ads")]));
+
+        assert_eq!(
+            corrections,
+            vec![DetectedCorrection {
+                email_id: "a1".to_string(),
+                original_category: "ads".to_string(),
+                corrected_category: Some("receipts".to_string()),
+                ai_answer: "Advertisement".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_detects_removed_label() {
+        let history = vec![History {
+            labels_removed: Some(vec![HistoryLabelRemoved {
+                label_ids: Some(vec!["Label_1".to_string()]),
+                message: message("a1"),
+            }]),
+            ..History::default()
+        }];
+
+        let changes = collect_label_changes(&history, &labels());
+        let corrections = detect_corrections(&changes, &HashMap::from([processed("a1", "ads")]));
+
+        assert_eq!(corrections.len(), 1);
+        assert_eq!(corrections[0].corrected_category, None);
+    }
+
+    #[test]
+    fn test_ignores_own_labeling_and_reverted_changes() {
+        let history = vec![
+            // Our own label being applied
+            History {
+                labels_added: Some(vec![HistoryLabelAdded {
+                    label_ids: Some(vec!["Label_1".to_string()]),
+                    message: message("a1"),
+                }]),
+                ..History::default()
+            },
+            // Removed and re-added by the user
+            History {
+                labels_removed: Some(vec![HistoryLabelRemoved {
+                    label_ids: Some(vec!["Label_2".to_string()]),
+                    message: message("a2"),
+                }]),
+                ..History::default()
+            },
+            History {
+                labels_added: Some(vec![HistoryLabelAdded {
+                    label_ids: Some(vec!["Label_2".to_string()]),
+                    message: message("a2"),
+                }]),
+                ..History::default()
+            },
+            // Not an email we processed
+            History {
+                labels_removed: Some(vec![HistoryLabelRemoved {
+                    label_ids: Some(vec!["Label_2".to_string()]),
+                    message: message("a3"),
+                }]),
+                ..History::default()
+            },
+        ];
+
+        let changes = collect_label_changes(&history, &labels());
+        let corrections = detect_corrections(
+            &changes,
+            &HashMap::from([processed("a1", "ads"), processed("a2", "receipts")]),
+        );
+
+        assert!(corrections.is_empty());
+    }
+}
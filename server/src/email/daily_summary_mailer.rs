@@ -13,12 +13,15 @@ use crate::{
     email::{client::EmailClient, email_template::DAILY_SUMMARY_EMAIL_TEMPLATE},
     error::AppResult,
     model::user::UserWithAccountAccess,
+    rate_limiters::RateLimiters,
+    server_config::DAILY_SUMMARY_CATEGORY,
     HttpClient,
 };
 
 pub struct DailySummaryMailer {
     conn: DatabaseConnection,
     http_client: HttpClient,
+    rate_limiters: RateLimiters,
     user: UserWithAccountAccess,
 }
 
@@ -26,11 +29,13 @@ impl DailySummaryMailer {
     pub async fn new(
         conn: DatabaseConnection,
         http_client: HttpClient,
+        rate_limiters: RateLimiters,
         user: UserWithAccountAccess,
     ) -> AppResult<Self> {
         Ok(Self {
             conn,
             http_client,
+            rate_limiters,
             user,
         })
     }
@@ -45,6 +50,7 @@ impl DailySummaryMailer {
         let email_client = EmailClient::new(
             self.http_client.clone(),
             self.conn.clone(),
+            self.rate_limiters.clone(),
             self.user.clone(),
         )
         .await?;
@@ -112,18 +118,14 @@ impl DailySummaryMailer {
     ) -> anyhow::Result<Vec<u8>> {
         let mut category_counts = HashMap::new();
         for email in processed_emails {
-            let labels = email.labels_applied.unwrap_or_default();
-
-            let label = labels
-                .iter()
-                .find(|label| label.contains("Mailclerk/"))
-                .cloned();
-
-            if let Some(label) = label {
-                let category = label.split(":").last().unwrap().to_string();
-                let count = category_counts.entry(capitalize(&category)).or_insert(0);
-                *count += 1;
+            // Nested categories are counted under their full path, e.g. Finance/Receipts
+            if email.category == DAILY_SUMMARY_CATEGORY.mail_label {
+                continue;
             }
+            let count = category_counts
+                .entry(capitalize(&email.category))
+                .or_insert(0);
+            *count += 1;
         }
         let category_counts = category_counts.into_iter().collect::<Vec<_>>();
         let categorys_str = category_counts
@@ -0,0 +1,193 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::prompt::priority_queue::{PromptPriorityQueue, PromptQueueEmailEntry};
+
+use super::active_email_processors::ActiveEmailProcessorMap;
+
+/// Lets email processing stop cleanly on shutdown. Workers stop taking emails from the queue
+/// once draining starts, and emails already in a pipeline get until the deadline to finish, so
+/// they are not left labeled in Gmail without being recorded.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessingDrain {
+    token: CancellationToken,
+    next_id: Arc<AtomicU64>,
+    in_flight: Arc<Mutex<HashMap<u64, PromptQueueEmailEntry>>>,
+    drained: Arc<AtomicUsize>,
+}
+
+/// Marks an email as in a pipeline until dropped
+pub struct InFlight {
+    drain: ProcessingDrain,
+    id: u64,
+    processed: bool,
+}
+
+impl InFlight {
+    /// The email went back to the queue unprocessed, so it doesn't count as drained
+    pub fn returned(mut self) {
+        self.processed = false;
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.drain.in_flight.lock().unwrap().remove(&self.id);
+        if self.processed && self.drain.is_draining() {
+            self.drain.drained.fetch_add(1, Relaxed);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DrainReport {
+    pub drained: usize,
+    /// Emails still in a pipeline at the deadline, handed back to the queue
+    pub abandoned: Vec<PromptQueueEmailEntry>,
+    pub processors_cancelled: usize,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for DrainReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "drained {} emails and abandoned {} in {:.1}s, cancelled {} processors",
+            self.drained,
+            self.abandoned.len(),
+            self.elapsed.as_secs_f64(),
+            self.processors_cancelled
+        )?;
+        for entry in &self.abandoned {
+            write!(
+                f,
+                "\n\tabandoned {} for {}",
+                entry.email_id, entry.user_email
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ProcessingDrain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Resolves once draining starts
+    pub async fn draining(&self) {
+        self.token.cancelled().await
+    }
+
+    pub fn track(&self, entry: &PromptQueueEmailEntry) -> InFlight {
+        let id = self.next_id.fetch_add(1, Relaxed);
+        self.in_flight.lock().unwrap().insert(id, entry.clone());
+
+        InFlight {
+            drain: self.clone(),
+            id,
+            processed: true,
+        }
+    }
+
+    pub fn num_in_flight(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+
+    /// Stops dequeuing, waits up to `deadline` for in-flight emails, then cancels every
+    /// processor. Emails that didn't finish go back to the queue for another attempt.
+    pub async fn drain(
+        &self,
+        deadline: Duration,
+        prompt_priority_queue: &PromptPriorityQueue,
+        email_processor_map: &ActiveEmailProcessorMap,
+    ) -> DrainReport {
+        let started_at = Instant::now();
+        self.token.cancel();
+        tracing::info!(
+            "Draining {} in-flight emails, waiting up to {}s",
+            self.num_in_flight(),
+            deadline.as_secs()
+        );
+
+        while self.num_in_flight() > 0 && started_at.elapsed() < deadline {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        let processors = email_processor_map.entries();
+        for (_, processor) in &processors {
+            processor.cancel();
+        }
+
+        let abandoned = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, entry)| entry)
+            .collect::<Vec<_>>();
+        for entry in &abandoned {
+            if let Err(e) = prompt_priority_queue.release(entry).await {
+                tracing::error!("Error releasing email {}: {:?}", entry.email_id, e);
+            }
+        }
+
+        DrainReport {
+            drained: self.drained.load(Relaxed),
+            abandoned,
+            processors_cancelled: processors.len(),
+            elapsed: started_at.elapsed(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prompt::priority_queue::Priority;
+
+    use super::*;
+
+    fn entry(id: i32) -> PromptQueueEmailEntry {
+        PromptQueueEmailEntry {
+            id,
+            user_email: "user@example.com".to_string(),
+            email_id: id as u128,
+            priority: Priority::High,
+            attempts: 1,
+        }
+    }
+
+    #[test]
+    fn test_in_flight_tracking() {
+        let drain = ProcessingDrain::new();
+        let first = drain.track(&entry(1));
+        let second = drain.track(&entry(2));
+        assert_eq!(drain.num_in_flight(), 2);
+
+        drop(first);
+        assert_eq!(drain.num_in_flight(), 1);
+        assert_eq!(drain.drained.load(Relaxed), 0);
+
+        drain.token.cancel();
+        assert!(drain.is_draining());
+        drain.track(&entry(3)).returned();
+        drop(second);
+        assert_eq!(drain.num_in_flight(), 0);
+        // Only emails finished after draining started count as drained
+        assert_eq!(drain.drained.load(Relaxed), 1);
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{db_core::prelude::*, server_config::ClassifierConfig};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabeledEmbedding {
+    pub email_id: String,
+    pub category: String,
+    pub vector: Vec<f32>,
+}
+
+impl LabeledEmbedding {
+    pub fn from_model(model: email_embedding::Model) -> Option<Self> {
+        Some(Self {
+            email_id: model.email_id,
+            category: model.category,
+            vector: model.embedding?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KnnDecision {
+    Agreed {
+        category: String,
+        agreement: f32,
+    },
+    /// Too few similar neighbours, or they disagree
+    Undecided,
+}
+
+/// In-memory index of a user's most recently labeled emails
+#[derive(Debug, Default)]
+pub struct KnnIndex {
+    entries: VecDeque<LabeledEmbedding>,
+    max_size: usize,
+}
+
+impl KnnIndex {
+    /// Entries are expected oldest first
+    pub fn new(entries: Vec<LabeledEmbedding>, max_size: usize) -> Self {
+        let mut index = Self {
+            entries: VecDeque::with_capacity(entries.len().min(max_size)),
+            max_size,
+        };
+        for entry in entries {
+            index.insert(entry);
+        }
+
+        index
+    }
+
+    pub fn insert(&mut self, entry: LabeledEmbedding) {
+        self.remove(&entry.email_id);
+        if self.max_size == 0 {
+            return;
+        }
+        while self.entries.len() >= self.max_size {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn remove(&mut self, email_id: &str) {
+        self.entries.retain(|e| e.email_id != email_id);
+    }
+
+    /// Applies a user's correction, dropping the email if they only removed our label
+    pub fn relabel(&mut self, email_id: &str, category: Option<&str>) {
+        match category {
+            Some(category) => self
+                .entries
+                .iter_mut()
+                .filter(|e| e.email_id == email_id)
+                .for_each(|e| e.category = category.to_string()),
+            None => self.remove(email_id),
+        }
+    }
+
+    pub fn classify(&self, vector: &[f32], config: &ClassifierConfig) -> KnnDecision {
+        let mut neighbours = self
+            .entries
+            .iter()
+            .map(|e| (cosine_similarity(vector, &e.vector), e))
+            .filter(|(similarity, _)| *similarity >= config.min_similarity)
+            .collect::<Vec<_>>();
+
+        neighbours.sort_by(|a, b| b.0.total_cmp(&a.0));
+        neighbours.truncate(config.k);
+
+        if neighbours.is_empty() || neighbours.len() < config.min_neighbours {
+            return KnnDecision::Undecided;
+        }
+
+        let mut votes = HashMap::<&str, f32>::new();
+        for (similarity, entry) in &neighbours {
+            *votes.entry(entry.category.as_str()).or_default() += similarity;
+        }
+
+        let total = votes.values().sum::<f32>();
+        let (category, weight) = votes
+            .into_iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .expect("neighbours is not empty");
+
+        let agreement = weight / total;
+        if agreement < config.min_agreement {
+            return KnnDecision::Undecided;
+        }
+
+        KnnDecision::Agreed {
+            category: category.to_string(),
+            agreement,
+        }
+    }
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+
+    let dot = a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+
+    dot / (norm_a * norm_b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(email_id: &str, category: &str, vector: Vec<f32>) -> LabeledEmbedding {
+        LabeledEmbedding {
+            email_id: email_id.to_string(),
+            category: category.to_string(),
+            vector,
+        }
+    }
+
+    fn config() -> ClassifierConfig {
+        ClassifierConfig {
+            k: 3,
+            min_neighbours: 2,
+            min_similarity: 0.5,
+            min_agreement: 0.8,
+            ..ClassifierConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_cosine_similarity() {
+        assert!((cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < 1e-6);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]), 0.0);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[1.0]), 0.0);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn test_classify_agreeing_neighbours() {
+        let index = KnnIndex::new(
+            vec![
+                entry("a", "seatgeek", vec![1.0, 0.1]),
+                entry("b", "seatgeek", vec![0.9, 0.2]),
+                entry("c", "receipts", vec![0.0, 1.0]),
+            ],
+            10,
+        );
+
+        match index.classify(&[1.0, 0.15], &config()) {
+            KnnDecision::Agreed {
+                category,
+                agreement,
+            } => {
+                assert_eq!(category, "seatgeek");
+                assert!((agreement - 1.0).abs() < 1e-6);
+            }
+            KnnDecision::Undecided => panic!("Expected neighbours to agree"),
+        }
+    }
+
+    #[test]
+    fn test_classify_falls_back_when_undecided() {
+        let index = KnnIndex::new(
+            vec![
+                entry("a", "seatgeek", vec![1.0, 0.9]),
+                entry("b", "receipts", vec![0.9, 1.0]),
+            ],
+            10,
+        );
+
+        // Neighbours disagree
+        assert_eq!(
+            index.classify(&[1.0, 1.0], &config()),
+            KnnDecision::Undecided
+        );
+        // Not enough similar neighbours
+        assert_eq!(
+            index.classify(&[1.0, -1.0], &config()),
+            KnnDecision::Undecided
+        );
+    }
+
+    #[test]
+    fn test_index_evicts_oldest_and_relabels() {
+        let mut index = KnnIndex::new(
+            vec![
+                entry("a", "ads", vec![1.0]),
+                entry("b", "ads", vec![1.0]),
+                entry("c", "ads", vec![1.0]),
+            ],
+            2,
+        );
+        assert_eq!(index.entries.len(), 2);
+
+        index.relabel("b", Some("receipts"));
+        index.relabel("c", None);
+        assert_eq!(index.entries.len(), 1);
+        assert_eq!(index.entries[0], entry("b", "receipts", vec![1.0]));
+    }
+}
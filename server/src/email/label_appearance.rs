@@ -0,0 +1,231 @@
+use entity::label_setting;
+use google_gmail1::api::{Label, LabelColor};
+use lib_email_clients::gmail::label_colors::{color_by_name, GmailLabelColorMap};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::model::labels::UtilityLabels;
+
+static COLOR_MAP: Lazy<GmailLabelColorMap> = Lazy::new(GmailLabelColorMap::new);
+
+/// Whether the label shows in Gmail's label list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LabelListVisibility {
+    #[serde(rename = "labelShow")]
+    Show,
+    #[serde(rename = "labelShowIfUnread")]
+    ShowIfUnread,
+    #[serde(rename = "labelHide")]
+    Hide,
+}
+
+impl LabelListVisibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LabelListVisibility::Show => "labelShow",
+            LabelListVisibility::ShowIfUnread => "labelShowIfUnread",
+            LabelListVisibility::Hide => "labelHide",
+        }
+    }
+
+    pub fn from_gmail(value: &str) -> Option<Self> {
+        match value {
+            "labelShow" => Some(LabelListVisibility::Show),
+            "labelShowIfUnread" => Some(LabelListVisibility::ShowIfUnread),
+            "labelHide" => Some(LabelListVisibility::Hide),
+            _ => None,
+        }
+    }
+}
+
+/// Whether the label shows next to messages in the message list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MessageListVisibility {
+    Show,
+    Hide,
+}
+
+impl MessageListVisibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageListVisibility::Show => "show",
+            MessageListVisibility::Hide => "hide",
+        }
+    }
+
+    pub fn from_gmail(value: &str) -> Option<Self> {
+        match value {
+            "show" => Some(MessageListVisibility::Show),
+            "hide" => Some(MessageListVisibility::Hide),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LabelAppearance {
+    pub color: LabelColor,
+    pub label_list_visibility: LabelListVisibility,
+    pub message_list_visibility: MessageListVisibility,
+}
+
+// `LabelColor` doesn't implement `PartialEq`
+impl PartialEq for LabelAppearance {
+    fn eq(&self, other: &Self) -> bool {
+        self.color.background_color == other.color.background_color
+            && self.color.text_color == other.color.text_color
+            && self.label_list_visibility == other.label_list_visibility
+            && self.message_list_visibility == other.message_list_visibility
+    }
+}
+
+impl LabelAppearance {
+    /// Appearance of a category label the user hasn't customised
+    pub fn default_for(mail_label: &str) -> Self {
+        let (label_list_visibility, message_list_visibility) =
+            if mail_label == UtilityLabels::Uncategorized.as_str() {
+                (LabelListVisibility::Hide, MessageListVisibility::Hide)
+            } else {
+                (
+                    LabelListVisibility::ShowIfUnread,
+                    MessageListVisibility::Show,
+                )
+            };
+
+        Self {
+            color: COLOR_MAP.get(mail_label),
+            label_list_visibility,
+            message_list_visibility,
+        }
+    }
+
+    /// Applies the user's settings on top of the default, unset or invalid fields keep the
+    /// default
+    pub fn resolve(mail_label: &str, setting: Option<&label_setting::Model>) -> Self {
+        let default = Self::default_for(mail_label);
+        let Some(setting) = setting else {
+            return default;
+        };
+
+        Self {
+            color: setting
+                .color
+                .as_deref()
+                .and_then(color_by_name)
+                .unwrap_or(default.color),
+            label_list_visibility: setting
+                .label_list_visibility
+                .as_deref()
+                .and_then(LabelListVisibility::from_gmail)
+                .unwrap_or(default.label_list_visibility),
+            message_list_visibility: setting
+                .message_list_visibility
+                .as_deref()
+                .and_then(MessageListVisibility::from_gmail)
+                .unwrap_or(default.message_list_visibility),
+        }
+    }
+
+    /// Builds a new label with this appearance
+    pub fn to_label(&self, name: String) -> Label {
+        Label {
+            id: None,
+            type_: Some("user".to_string()),
+            color: Some(self.color.clone()),
+            name: Some(name),
+            messages_total: None,
+            messages_unread: None,
+            threads_total: None,
+            threads_unread: None,
+            message_list_visibility: Some(self.message_list_visibility.as_str().to_string()),
+            label_list_visibility: Some(self.label_list_visibility.as_str().to_string()),
+        }
+    }
+
+    pub fn matches(&self, label: &Label) -> bool {
+        let color_matches = label.color.as_ref().is_some_and(|c| {
+            c.background_color == self.color.background_color
+                && c.text_color == self.color.text_color
+        });
+
+        color_matches
+            && label.label_list_visibility.as_deref() == Some(self.label_list_visibility.as_str())
+            && label.message_list_visibility.as_deref()
+                == Some(self.message_list_visibility.as_str())
+    }
+
+    /// The existing label with this appearance applied, keeping its id and name
+    pub fn apply_to(&self, label: &Label) -> Label {
+        Label {
+            id: label.id.clone(),
+            ..self.to_label(label.name.clone().unwrap_or_default())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setting(color: Option<&str>, label_list_visibility: Option<&str>) -> label_setting::Model {
+        label_setting::Model {
+            id: 1,
+            user_id: 1,
+            category: "ads".to_string(),
+            color: color.map(|c| c.to_string()),
+            label_list_visibility: label_list_visibility.map(|v| v.to_string()),
+            message_list_visibility: None,
+            created_at: chrono::Utc::now().into(),
+            updated_at: chrono::Utc::now().into(),
+        }
+    }
+
+    #[test]
+    fn test_uncategorized_is_hidden_by_default() {
+        let appearance = LabelAppearance::default_for(UtilityLabels::Uncategorized.as_str());
+        assert_eq!(appearance.label_list_visibility, LabelListVisibility::Hide);
+        assert_eq!(
+            appearance.message_list_visibility,
+            MessageListVisibility::Hide
+        );
+
+        let appearance = LabelAppearance::default_for("ads");
+        assert_eq!(
+            appearance.label_list_visibility,
+            LabelListVisibility::ShowIfUnread
+        );
+    }
+
+    #[test]
+    fn test_resolve_and_match() {
+        let appearance =
+            LabelAppearance::resolve("ads", Some(&setting(Some("black"), Some("labelShow"))));
+        assert_eq!(
+            appearance.color.background_color,
+            Some("#000000".to_string())
+        );
+        assert_eq!(appearance.label_list_visibility, LabelListVisibility::Show);
+        assert_eq!(
+            appearance.message_list_visibility,
+            MessageListVisibility::Show
+        );
+
+        // Invalid values fall back to the default
+        let fallback =
+            LabelAppearance::resolve("ads", Some(&setting(Some("not-a-color"), Some("x"))));
+        assert_eq!(fallback, LabelAppearance::default_for("ads"));
+
+        let existing = Label {
+            id: Some("Label_1".to_string()),
+            name: Some("Mailclerk/ads".to_string()),
+            ..LabelAppearance::default_for("ads").to_label("Mailclerk/ads".to_string())
+        };
+        assert!(!appearance.matches(&existing));
+
+        let updated = appearance.apply_to(&existing);
+        assert!(appearance.matches(&updated));
+        assert_eq!(updated.id, Some("Label_1".to_string()));
+        assert_eq!(updated.name, Some("Mailclerk/ads".to_string()));
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Context;
+use sea_orm::DatabaseConnection;
+
+use crate::{
+    email::client::{required_label_names, EmailClient, MAX_BATCH_MODIFY_IDS},
+    model::{
+        label_migration::LabelMigrationCtrl, labels::UtilityLabels,
+        processed_email::ProcessedEmailCtrl,
+    },
+    server_config::{cfg, DAILY_SUMMARY_CATEGORY},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedMigration {
+    pub from_label: String,
+    pub to_label: String,
+}
+
+/// Old label -> new label, from the categories' previous labels in the config
+pub fn config_renames() -> HashMap<String, String> {
+    cfg.categories
+        .iter()
+        .flat_map(|c| {
+            c.previous_mail_labels
+                .iter()
+                .map(|previous| (previous.clone(), c.mail_label.clone()))
+        })
+        .collect()
+}
+
+/// Picks the labels under the root label that are no longer needed and where their emails
+/// should go. Labels are given without the root prefix. Renamed labels move to their new name,
+/// everything else moves to uncategorized.
+pub fn plan_migrations(
+    existing_labels: &[String],
+    required_labels: &HashSet<String>,
+    renames: &HashMap<String, String>,
+    already_migrated: &HashSet<String>,
+) -> Vec<PlannedMigration> {
+    let uncategorized = UtilityLabels::Uncategorized.as_str();
+    let has_children = |label: &str| {
+        existing_labels.iter().any(|l| {
+            l.strip_prefix(label)
+                .is_some_and(|rest| rest.starts_with('/'))
+        })
+    };
+
+    let mut planned = existing_labels
+        .iter()
+        .filter(|label| {
+            !required_labels.contains(*label)
+                && !already_migrated.contains(*label)
+                && **label != DAILY_SUMMARY_CATEGORY.mail_label
+                // Users nest their own labels under ours, so nested labels are only moved when
+                // the config says they were renamed
+                && (!label.contains('/') || renames.contains_key(*label))
+                && !has_children(label)
+        })
+        .map(|label| {
+            let to_label = renames
+                .get(label)
+                .filter(|to| required_labels.contains(*to))
+                .map_or(uncategorized, |to| to.as_str());
+
+            PlannedMigration {
+                from_label: label.clone(),
+                to_label: to_label.to_string(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    planned.sort_by(|a, b| a.from_label.cmp(&b.from_label));
+    planned
+}
+
+/// Moves emails off obsolete labels under the root label and deletes them. Each label is migrated once
+/// per mailbox, so a label the user creates again afterwards is left alone.
+pub async fn migrate_obsolete_labels(
+    conn: &DatabaseConnection,
+    email_client: &EmailClient,
+    user_id: i32,
+    user_custom_labels: &[String],
+) -> anyhow::Result<Vec<PlannedMigration>> {
+    let label_ids = email_client
+        .get_labels()
+        .await?
+        .into_iter()
+        .filter_map(|l| {
+            let name = cfg.mail_label_of(&l.name?)?.to_string();
+            Some((name, l.id?))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let required_labels = required_label_names(user_custom_labels)
+        .into_iter()
+        .filter_map(|l| cfg.mail_label_of(&l).map(|l| l.to_string()))
+        .collect::<HashSet<_>>();
+    let already_migrated = LabelMigrationCtrl::migrated_labels(conn, user_id).await?;
+    let planned = plan_migrations(
+        &label_ids.keys().cloned().collect::<Vec<_>>(),
+        &required_labels,
+        &config_renames(),
+        &already_migrated,
+    );
+
+    for migration in &planned {
+        let from_id = &label_ids[&migration.from_label];
+        let to_id = label_ids
+            .get(&migration.to_label)
+            .with_context(|| format!("Label {} does not exist yet", migration.to_label))?;
+
+        // Categories are renamed first so the label moves are not seen as user corrections
+        ProcessedEmailCtrl::rename_category(
+            conn,
+            user_id,
+            &migration.from_label,
+            &migration.to_label,
+        )
+        .await?;
+
+        // Moved messages drop out of the listing, so the first page is listed again until
+        // it comes back empty. Paging would skip messages and the label would be deleted
+        // with them still on it.
+        let mut messages_moved = 0;
+        loop {
+            let message_ids = email_client
+                .list_message_ids_with_label(from_id)
+                .await?
+                .messages
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|m| m.id)
+                .collect::<Vec<_>>();
+            if message_ids.is_empty() {
+                break;
+            }
+
+            for chunk in message_ids.chunks(MAX_BATCH_MODIFY_IDS) {
+                email_client
+                    .batch_modify_labels(chunk.to_vec(), vec![to_id.clone()], vec![from_id.clone()])
+                    .await?;
+                messages_moved += chunk.len();
+            }
+        }
+
+        email_client.delete_label(from_id.clone()).await?;
+        LabelMigrationCtrl::record(
+            conn,
+            user_id,
+            &migration.from_label,
+            &migration.to_label,
+            messages_moved as i32,
+        )
+        .await?;
+
+        tracing::info!(
+            "Migrated {} emails from {} to {} for {}",
+            messages_moved,
+            migration.from_label,
+            migration.to_label,
+            email_client.email_address
+        );
+    }
+
+    Ok(planned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_plan_migrations() {
+        let existing = strings(&[
+            "ads",
+            "promos",
+            "old_custom",
+            "keep",
+            "uncategorized",
+            "daily_summary",
+            "old_custom/nested",
+            "travel",
+            "finance",
+            "finance/bills",
+            "finance/invoices",
+        ]);
+        let required = strings(&[
+            "ads",
+            "keep",
+            "uncategorized",
+            "travel",
+            "finance",
+            "finance/bills",
+        ])
+        .into_iter()
+        .collect::<HashSet<_>>();
+        let renames = HashMap::from([
+            ("promos".to_string(), "ads".to_string()),
+            // Renamed to a label that is not required anymore either
+            ("old_custom".to_string(), "gone".to_string()),
+            ("finance/invoices".to_string(), "finance/bills".to_string()),
+        ]);
+
+        let planned = plan_migrations(&existing, &required, &renames, &HashSet::new());
+        // old_custom still has a nested label the user made, so it stays
+        assert_eq!(
+            planned,
+            vec![
+                PlannedMigration {
+                    from_label: "finance/invoices".to_string(),
+                    to_label: "finance/bills".to_string(),
+                },
+                PlannedMigration {
+                    from_label: "promos".to_string(),
+                    to_label: "ads".to_string(),
+                },
+            ]
+        );
+
+        let existing = existing
+            .into_iter()
+            .filter(|l| l != "old_custom/nested")
+            .collect::<Vec<_>>();
+        let planned = plan_migrations(&existing, &required, &renames, &HashSet::new());
+        assert_eq!(
+            planned,
+            vec![
+                PlannedMigration {
+                    from_label: "finance/invoices".to_string(),
+                    to_label: "finance/bills".to_string(),
+                },
+                PlannedMigration {
+                    from_label: "old_custom".to_string(),
+                    to_label: UtilityLabels::Uncategorized.as_str().to_string(),
+                },
+                PlannedMigration {
+                    from_label: "promos".to_string(),
+                    to_label: "ads".to_string(),
+                },
+            ]
+        );
+
+        // Labels are only migrated once per mailbox
+        let migrated = HashSet::from([
+            "promos".to_string(),
+            "old_custom".to_string(),
+            "finance/invoices".to_string(),
+        ]);
+        assert!(plan_migrations(&existing, &required, &renames, &migrated).is_empty());
+    }
+}
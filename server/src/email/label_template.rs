@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+
+use minijinja::{Environment, UndefinedBehavior};
+use once_cell::sync::Lazy;
+
+use crate::{
+    email::parsed_message::{sender_domain, ParsedMessage},
+    model::labels::UtilityLabels,
+};
+
+/// Gmail rejects longer label names
+const MAX_LABEL_LENGTH: usize = 225;
+
+static TEMPLATE_ENV: Lazy<Environment<'static>> = Lazy::new(|| {
+    let mut env = Environment::new();
+    // A value the email doesn't have fails the render, so the email falls back to the parent
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env
+});
+
+/// Whether a mail label is evaluated per email, e.g. `Vendors/{{ sender_domain }}`
+pub fn is_template(mail_label: &str) -> bool {
+    mail_label.contains("{{") || mail_label.contains("{%")
+}
+
+/// The fixed part of a mail label. For templates this is the parent every rendered label is
+/// nested under, or uncategorized if the template has no fixed part, which `validate` rejects.
+/// Plain labels are returned as is.
+pub fn static_label(mail_label: &str) -> &str {
+    match mail_label.find('{') {
+        Some(i) if is_template(mail_label) => {
+            let parent = mail_label[..i].trim_end_matches('/').trim();
+            if parent.is_empty() {
+                UtilityLabels::Uncategorized.as_str()
+            } else {
+                parent
+            }
+        }
+        _ => mail_label,
+    }
+}
+
+/// Templates must have a fixed parent, which their rendered labels are counted and migrated
+/// under. A template without one would create a top level label per value.
+pub fn validate(mail_label: &str) -> Result<(), String> {
+    let has_parent = mail_label
+        .find('{')
+        .is_some_and(|i| !mail_label[..i].trim_end_matches('/').trim().is_empty());
+    if is_template(mail_label) && !has_parent {
+        return Err(format!(
+            "Templated label {mail_label} needs a fixed parent, e.g. Vendors/{{{{ sender_domain }}}}"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Whether `label` is the mail label itself, or was rendered from it when it is a template
+pub fn label_matches(mail_label: &str, label: &str) -> bool {
+    if !is_template(mail_label) {
+        return mail_label == label;
+    }
+
+    let parent = static_label(mail_label);
+    label == parent
+        || label
+            .strip_prefix(parent)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Values can't add nesting of their own
+fn clean_value(value: &str) -> Option<String> {
+    let value = value.replace('/', "-").trim().to_string();
+    (!value.is_empty()).then_some(value)
+}
+
+fn template_context(email_message: &ParsedMessage) -> BTreeMap<&'static str, String> {
+    let list_id = email_message.list_id.as_deref().map(|list_id| {
+        // `Name <id.example.com>` -> `id.example.com`
+        match (list_id.rfind('<'), list_id.rfind('>')) {
+            (Some(start), Some(end)) if start < end => list_id[start + 1..end].to_string(),
+            _ => list_id.to_string(),
+        }
+    });
+
+    [
+        (
+            "sender_domain",
+            email_message.from.as_deref().and_then(sender_domain),
+        ),
+        ("sender", email_message.from.clone()),
+        ("list_id", list_id),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| Some((key, clean_value(&value?)?)))
+    .collect()
+}
+
+/// Renders a templated mail label for an email. Returns `None` if the template uses a value
+/// the email doesn't have or renders to an invalid label. The length is checked by the caller,
+/// which knows the root label.
+pub fn render(template: &str, email_message: &ParsedMessage) -> Option<String> {
+    let rendered = match TEMPLATE_ENV.render_str(template, template_context(email_message)) {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            tracing::debug!("Could not render label template {}: {}", template, e);
+            return None;
+        }
+    };
+
+    let parts = rendered.split('/').map(|p| p.trim()).collect::<Vec<_>>();
+    if parts.iter().any(|p| p.is_empty()) {
+        return None;
+    }
+
+    Some(parts.join("/"))
+}
+
+/// Whether Gmail accepts a label name. This is the full name, including the root label.
+pub fn fits_label_name(label_name: &str) -> bool {
+    label_name.len() <= MAX_LABEL_LENGTH
+}
+
+/// The label an email gets from a mail label, without checking the label limit. Templates that
+/// can't be rendered give their parent.
+pub fn resolve(mail_label: &str, email_message: &ParsedMessage) -> String {
+    if !is_template(mail_label) {
+        return mail_label.to_string();
+    }
+
+    render(mail_label, email_message).unwrap_or_else(|| static_label(mail_label).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(from: &str, list_id: Option<&str>) -> ParsedMessage {
+        ParsedMessage {
+            from: Some(from.to_string()),
+            list_id: list_id.map(|l| l.to_string()),
+            ..ParsedMessage::default()
+        }
+    }
+
+    #[test]
+    fn test_static_label() {
+        assert!(is_template("Vendors/{{ sender_domain }}"));
+        assert!(!is_template("Finance/Receipts"));
+        assert_eq!(static_label("Vendors/{{ sender_domain }}"), "Vendors");
+        assert_eq!(static_label("Finance/Receipts"), "Finance/Receipts");
+        assert_eq!(
+            static_label("{{ sender_domain }}"),
+            UtilityLabels::Uncategorized.as_str()
+        );
+
+        assert!(label_matches(
+            "Vendors/{{ sender_domain }}",
+            "Vendors/shop.com"
+        ));
+        assert!(label_matches("Vendors/{{ sender_domain }}", "Vendors"));
+        assert!(!label_matches(
+            "Vendors/{{ sender_domain }}",
+            "VendorsX/shop.com"
+        ));
+        assert!(!label_matches("ads", "ads/shop.com"));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("Vendors/{{ sender_domain }}").is_ok());
+        assert!(validate("Finance/Receipts").is_ok());
+        assert!(validate("{{ sender_domain }}").is_err());
+        assert!(validate("/{{ sender_domain }}").is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let email = message("Shop <Orders@Shop.com>", Some("Deals <deals.shop.com>"));
+        assert_eq!(
+            render("Vendors/{{ sender_domain }}", &email),
+            Some("Vendors/shop.com".to_string())
+        );
+        assert_eq!(
+            render("Newsletters/{{ list_id }}", &email),
+            Some("Newsletters/deals.shop.com".to_string())
+        );
+
+        // Missing values fall back to the parent
+        let email = message("orders@shop.com", None);
+        assert_eq!(render("Newsletters/{{ list_id }}", &email), None);
+        assert_eq!(resolve("Newsletters/{{ list_id }}", &email), "Newsletters");
+        assert_eq!(resolve("receipts", &email), "receipts");
+
+        // Values can't create extra levels
+        let email = message("a/b <x@y.com>", Some("news/letter"));
+        assert_eq!(
+            render("Newsletters/{{ list_id }}", &email),
+            Some("Newsletters/news-letter".to_string())
+        );
+    }
+}
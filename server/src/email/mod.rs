@@ -1,8 +1,19 @@
 pub(crate) mod active_email_processors;
+pub mod classifier;
 pub(crate) mod client;
+pub(crate) mod corrections;
 pub(crate) mod daily_summary_mailer;
+pub(crate) mod drain;
 pub(crate) mod email_template;
-pub(crate) mod parsed_message;
+pub(crate) mod knn;
+pub(crate) mod label_appearance;
+pub(crate) mod label_migration;
+pub mod label_template;
+pub mod parsed_message;
 pub(crate) mod processor;
-pub(crate) mod rules;
+pub(crate) mod reclassification;
+pub(crate) mod rule_engine;
+pub(crate) mod rule_preview;
+pub mod rules;
+pub(crate) mod sender_memory;
 pub(crate) mod tasks;
@@ -3,7 +3,7 @@ use mail_parser::MessageParser;
 use once_cell::sync::Lazy;
 use regex::Regex;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct ParsedMessage {
     pub id: String,
     pub label_ids: Vec<String>,
@@ -13,6 +13,9 @@ pub struct ParsedMessage {
     pub from: Option<String>,
     pub subject: Option<String>,
     pub body: Option<String>,
+    /// Raw header values keyed by lowercase name, in the order they appear
+    pub headers: Vec<(String, String)>,
+    pub list_id: Option<String>,
 }
 
 impl ParsedMessage {
@@ -26,6 +29,11 @@ impl ParsedMessage {
             .as_ref()
             .map(|input| {
                 let msg = MessageParser::default().parse(input);
+                let headers = msg.as_ref().map(raw_headers).unwrap_or_default();
+                let list_id = headers
+                    .iter()
+                    .find(|(name, _)| name == "list-id")
+                    .map(|(_, value)| value.clone());
                 let StrippedMessage {
                     from,
                     subject,
@@ -41,6 +49,8 @@ impl ParsedMessage {
                     internal_date,
                     subject,
                     body,
+                    headers,
+                    list_id,
                 }
             })
             .context(format!(
@@ -50,6 +60,33 @@ impl ParsedMessage {
     }
 }
 
+/// Lowercased domain of a sender such as `Name <user@example.com>`
+pub fn sender_domain(from: &str) -> Option<String> {
+    let address = match (from.rfind('<'), from.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &from[start + 1..end],
+        _ => from,
+    };
+
+    address
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.trim().to_lowercase())
+}
+
+fn raw_headers(msg: &mail_parser::Message) -> Vec<(String, String)> {
+    msg.headers()
+        .iter()
+        .filter_map(|h| {
+            let value = msg.raw_message().get(h.offset_start..h.offset_end)?;
+            // Unfolds values that span several lines
+            let value = String::from_utf8_lossy(value)
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            Some((h.name().to_lowercase(), value))
+        })
+        .collect()
+}
+
 const RE_WHITESPACE_STR: &str = r"[\r\t\n]+";
 const RE_LONG_SPACE_STR: &str = r" {2,}";
 const RE_NON_ASCII_STR: &str = r"[^\x20-\x7E]";
@@ -121,6 +158,8 @@ mod tests {
 
         dbg!(&parsed);
 
+        assert!(parsed.headers.iter().any(|(name, _)| name == "subject"));
+
         let regexes = vec![
             RE_WHITESPACE_STR,
             RE_LONG_SPACE_STR,
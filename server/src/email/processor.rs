@@ -7,46 +7,58 @@ use std::{
 use anyhow::{anyhow, Context};
 use chrono::Utc;
 use derive_more::Display;
-use entity::{email_training, prelude::*, processed_email};
+use entity::{
+    email_embedding, email_training,
+    prelude::*,
+    processed_email,
+    sea_orm_active_enums::{BackfillStatus, ProcessorFailureReason},
+};
+use google_gmail1::api::Message;
 use indexmap::IndexSet;
 use num_traits::FromPrimitive;
-use sea_orm::{
-    entity::*, query::*, sea_query::OnConflict, ActiveValue, DatabaseConnection, EntityTrait,
-    FromQueryResult,
-};
+use sea_orm::{sea_query::OnConflict, ActiveValue, DatabaseConnection, EntityTrait};
 use std::sync::atomic::Ordering::Relaxed;
 use tokio::sync::watch;
 
 use crate::{
     email::{
-        client::{EmailClient, MessageListOptions},
+        classifier::{EmailClassifier, PromptReturnData},
+        client::{is_auth_error, EmailClient, GmailApiError, MessageListOptions},
+        corrections,
+        knn::LabeledEmbedding,
+        label_migration,
         parsed_message::ParsedMessage,
         rules::UserEmailRules,
     },
     error::{extract_database_error_code, AppError, AppResult, DatabaseErrorCode},
     model::{
-        labels::UtilityLabels, processed_email::ProcessedEmailCtrl, response::LabelUpdate,
-        user::UserWithAccountAccessAndUsage, user_token_usage::UserTokenUsageStatsCtrl,
+        dead_letter::DeadLetterCtrl,
+        email_embedding::EmailEmbeddingCtrl,
+        email_failure::{EmailFailureCtrl, FailureKind, FailureOutcome},
+        label_setting::LabelSettingCtrl,
+        mailbox_backfill::{backfill_cutoff, MailboxBackfillCtrl},
+        processed_email::ProcessedEmailCtrl,
+        processor_state::{ProcessorFailure, ProcessorStateCtrl},
+        response::LabelUpdate,
+        user::UserWithAccountAccessAndUsage,
+        user_token_usage::UserTokenUsageStatsCtrl,
     },
+    notify::notifier::{ImportantEmailNotification, SharedNotifier},
     prompt::{
-        mistral::{self, CategoryPromptResponse},
+        embeddings::EmailEmbedding,
         priority_queue::{Priority, PromptPriorityQueue},
+        shadow::ShadowAnswer,
     },
     rate_limiters::RateLimiters,
-    server_config::cfg,
-    HttpClient, ServerState,
+    server_config::{cfg, ClassifierMode, UNKNOWN_CATEGORY},
+    ServerState,
 };
 
-use super::rules::{EmailRule, HEURISTIC_EMAIL_RULES};
+use super::rules::EmailRule;
 
 lazy_static::lazy_static!(
     static ref DAILY_QUOTA: i64 = cfg.api.token_limits.daily_user_quota as i64;
     static ref LOW_PRIORITY_CUTOFF: i64 = *DAILY_QUOTA / 2;
-    static ref UNKNOWN_RULE: EmailRule = EmailRule {
-            prompt_content: "Unknown".to_string(),
-            mail_label: UtilityLabels::Uncategorized.as_str().to_string(),
-            associated_email_client_category: None,
-        };
 );
 
 #[derive(Clone)]
@@ -58,13 +70,14 @@ pub struct EmailProcessor {
     pub created_at: chrono::DateTime<Utc>,
     processed_email_count: Arc<AtomicI64>,
     failed_email_count: Arc<AtomicI64>,
+    dead_letter_count: Arc<AtomicI64>,
     email_client: Arc<EmailClient>,
     token_count: Arc<AtomicI64>,
-    http_client: HttpClient,
     conn: DatabaseConnection,
     rate_limiters: RateLimiters,
     priority_queue: PromptPriorityQueue,
-    user_email_rules: Arc<UserEmailRules>,
+    classifier: EmailClassifier,
+    notifier: SharedNotifier,
     interrupt_channel: (
         tokio::sync::watch::Sender<InterruptSignal>,
         tokio::sync::watch::Receiver<InterruptSignal>,
@@ -75,7 +88,7 @@ impl EmailProcessor {
     pub async fn new(
         server_state: ServerState,
         user: UserWithAccountAccessAndUsage,
-    ) -> AppResult<Self> {
+    ) -> Result<Self, ProcessorFailure> {
         let user_id = user.id;
         let user_account_access_id = user.user_account_access_id;
         let email_address = user.email.clone();
@@ -84,16 +97,30 @@ impl EmailProcessor {
         let http_client = server_state.http_client.clone();
         let rate_limiters = server_state.rate_limiters.clone();
         let priority_queue = server_state.priority_queue.clone();
+        let notifier = server_state.notifier.clone();
 
-        let email_client = EmailClient::new(http_client.clone(), conn.clone(), user)
-            .await
-            .map_err(|e| {
-                AppError::Internal(anyhow!(
+        let email_client = EmailClient::new(
+            http_client.clone(),
+            conn.clone(),
+            rate_limiters.clone(),
+            user,
+        )
+        .await
+        .map_err(|e| {
+            // Without a token the user has to reconnect their account
+            let reason = if is_auth_error(&e) {
+                ProcessorFailureReason::Auth
+            } else {
+                ProcessorFailureReason::Internal
+            };
+            ProcessorFailure::new(
+                reason,
+                format!(
                     "Could not create email client for: {}, error: {}",
-                    email_address,
-                    e.to_string()
-                ))
-            })?;
+                    email_address, e
+                ),
+            )
+        })?;
 
         tracing::info!("Email client created successfully for {}", email_address);
 
@@ -103,7 +130,23 @@ impl EmailProcessor {
         // -- DEBUG
 
         let user_email_rules = UserEmailRules::from_user(&conn, user_id).await?;
+        let classifier = EmailClassifier::load(
+            &conn,
+            http_client.clone(),
+            rate_limiters.clone(),
+            user_id,
+            user_email_rules,
+        )
+        .await?;
         let interrupt_channel = watch::channel(InterruptSignal::Run);
+        let dead_letter_count = DeadLetterCtrl::count_by_user(&conn, user_id).await?;
+        ProcessorStateCtrl::record_start(
+            &conn,
+            user_id,
+            &server_state.cluster.instance_id,
+            &ProcessorStatus::Queueing.to_string(),
+        )
+        .await?;
 
         let processor = EmailProcessor {
             user_id,
@@ -112,13 +155,14 @@ impl EmailProcessor {
             created_at: chrono::Utc::now(),
             processed_email_count: Arc::new(AtomicI64::new(0)),
             failed_email_count: Arc::new(AtomicI64::new(0)),
+            dead_letter_count: Arc::new(AtomicI64::new(dead_letter_count as i64)),
             email_client: Arc::new(email_client),
             token_count: Arc::new(AtomicI64::new(quota_used)),
-            http_client,
             conn,
             rate_limiters,
             priority_queue,
-            user_email_rules: Arc::new(user_email_rules),
+            classifier,
+            notifier,
             interrupt_channel,
         };
 
@@ -152,7 +196,8 @@ impl EmailProcessor {
                     self.email_address,
                     e
                 );
-                self.fail();
+                self.fail(ProcessorFailure::new(ProcessorFailureReason::Labels, &e))
+                    .await;
                 return Err(e.into());
             }
         };
@@ -167,6 +212,39 @@ impl EmailProcessor {
               }
             }
 
+            if let Err(e) = self.sync_corrections().await {
+                // Corrections are best effort and should not stop the processor
+                tracing::error!(
+                    "Error syncing corrections for {}: {:?}",
+                    self.email_address,
+                    e
+                );
+            }
+
+            if let Err(e) = self
+                .classifier
+                .refresh_deterministic_rules(&self.conn, self.user_id)
+                .await
+            {
+                tracing::error!(
+                    "Error refreshing deterministic rules for {}: {:?}",
+                    self.email_address,
+                    e
+                );
+            }
+
+            if let Err(e) = self
+                .classifier
+                .refresh_few_shot_pool(&self.conn, self.user_id)
+                .await
+            {
+                tracing::error!(
+                    "Error refreshing examples for {}: {:?}",
+                    self.email_address,
+                    e
+                );
+            }
+
             if self
                 .priority_queue
                 .num_high_priority_in_queue(&self.email_address)
@@ -174,17 +252,14 @@ impl EmailProcessor {
             {
                 match self.queue_recent_emails().await {
                     Ok(n) if n > 0 => {}
-                    // If there are no recent emails and sufficient quota remaining, queue older emails in low priority
+                    // If there are no recent emails and sufficient quota remaining, backfill older emails in low priority
                     Ok(_) if self.current_token_usage() < *LOW_PRIORITY_CUTOFF => {
-                        match self.queue_older_emails().await {
-                            Ok(_) => {}
-                            Err(e) => {
-                                tracing::error!(
-                                    "Error queuing older emails for {}: {:?}",
-                                    self.email_address,
-                                    e
-                                );
-                            }
+                        if let Err(e) = self.advance_backfill().await {
+                            tracing::error!(
+                                "Error backfilling older emails for {}: {:?}",
+                                self.email_address,
+                                e
+                            );
                         }
                     }
                     Err(e) => {
@@ -193,12 +268,29 @@ impl EmailProcessor {
                             self.email_address,
                             e
                         );
-                        self.fail();
+                        // The user may have revoked access while the processor was running
+                        let reason = match &e {
+                            AppError::Internal(e) if is_auth_error(e) => {
+                                ProcessorFailureReason::Auth
+                            }
+                            _ => ProcessorFailureReason::Provider,
+                        };
+                        self.fail(ProcessorFailure::new(reason, &e)).await;
                         return Err(e);
                     }
                     _ => {}
                 }
             }
+
+            // A label failure during the cycle has already been recorded
+            if !self.is_failed() {
+                self.record_status(true).await;
+            }
+        }
+
+        // Failures are recorded as they happen
+        if !self.is_failed() {
+            self.record_status(false).await;
         }
 
         tracing::info!(
@@ -210,26 +302,113 @@ impl EmailProcessor {
         Ok(())
     }
 
-    async fn fetch_email_ids(
+    async fn sync_corrections(&self) -> anyhow::Result<()> {
+        let new_corrections =
+            corrections::sync_user_corrections(&self.conn, &self.email_client, self.user_id)
+                .await?;
+
+        if new_corrections.is_empty() {
+            return Ok(());
+        }
+
+        tracing::info!(
+            "Recorded {} corrections for {}",
+            new_corrections.len(),
+            self.email_address
+        );
+
+        if cfg.classifier.mode == ClassifierMode::Knn {
+            for correction in &new_corrections {
+                let corrected = EmailEmbeddingCtrl::relabel(
+                    &self.conn,
+                    self.user_id,
+                    &correction.email_id,
+                    correction.corrected_category.as_deref(),
+                    true,
+                )
+                .await?;
+
+                // Emails labeled by kNN only join the index once the user has corrected them
+                if let Some(email_embedding::Model {
+                    email_id,
+                    category,
+                    embedding: Some(vector),
+                    ..
+                }) = corrected
+                {
+                    self.classifier.insert_embedding(LabeledEmbedding {
+                        email_id,
+                        category,
+                        vector,
+                    });
+                }
+            }
+        }
+
+        self.classifier.apply_corrections(&new_corrections);
+
+        Ok(())
+    }
+
+    async fn record_embedding(
         &self,
-        options: Option<FetchOptions>,
-    ) -> anyhow::Result<IndexSet<u128>> {
-        #[derive(FromQueryResult)]
-        struct ProcessedEmailId {
-            id: String,
+        email_id: &str,
+        category: &str,
+        embedding: EmailEmbedding,
+        confirmed: bool,
+    ) -> anyhow::Result<()> {
+        if category == UNKNOWN_CATEGORY.mail_label {
+            return Ok(());
+        }
+
+        EmailEmbeddingCtrl::upsert(
+            &self.conn,
+            self.user_id,
+            email_id.to_string(),
+            category.to_string(),
+            embedding.model,
+            embedding.vector.clone(),
+            confirmed,
+        )
+        .await?;
+
+        // A kNN answer added to the index would vote for itself on the next similar email
+        if !confirmed {
+            return Ok(());
         }
 
-        let already_processed_ids = processed_email::Entity::find()
-            .filter(processed_email::Column::UserId.eq(self.user_id))
-            .select_only()
-            .column(processed_email::Column::Id)
-            .into_model::<ProcessedEmailId>()
-            .all(&self.conn)
+        self.classifier.insert_embedding(LabeledEmbedding {
+            email_id: email_id.to_string(),
+            category: category.to_string(),
+            vector: embedding.vector,
+        });
+
+        Ok(())
+    }
+
+    /// The listed emails that weren't processed or skipped yet, in listing order
+    async fn filter_unprocessed(&self, messages: Vec<Message>) -> AppResult<Vec<u128>> {
+        let ids = messages
+            .into_iter()
+            .filter_map(|m| m.id)
+            .collect::<Vec<_>>();
+        let handled = ProcessedEmailCtrl::filter_handled(&self.conn, self.user_id, ids.clone())
             .await?
             .into_iter()
-            .map(|e| parse_id_to_int(e.id))
+            .map(parse_id_to_int)
             .collect::<HashSet<_>>();
 
+        Ok(ids
+            .into_iter()
+            .map(parse_id_to_int)
+            .filter(|id| !handled.contains(id))
+            .collect())
+    }
+
+    async fn fetch_email_ids(
+        &self,
+        options: Option<FetchOptions>,
+    ) -> anyhow::Result<IndexSet<u128>> {
         let mut message_ids_to_process = IndexSet::new();
         let load_page = |next_page_token: Option<String>| async {
             let resp = match self
@@ -255,15 +434,18 @@ impl EmailProcessor {
 
         // Collect at least 500 unprocessed emails or until there are no more emails
         let mut next_page_token = None;
-        while let Ok(resp) = load_page(next_page_token.clone()).await {
+        loop {
+            let resp = match load_page(next_page_token.clone()).await {
+                Ok(resp) => resp,
+                // A revoked token fails every request, so the processor has to stop
+                Err(e) if is_auth_error(&e) => return Err(e),
+                Err(_) => break,
+            };
             next_page_token = resp.next_page_token.clone();
 
-            for id in resp
-                .messages
-                .unwrap_or_default()
-                .into_iter()
-                .filter_map(|m| m.id.map(parse_id_to_int))
-                .filter(|id| !already_processed_ids.contains(id))
+            for id in self
+                .filter_unprocessed(resp.messages.unwrap_or_default())
+                .await?
             {
                 if message_ids_to_process.len() >= 500 {
                     break;
@@ -297,6 +479,7 @@ impl EmailProcessor {
             if self
                 .priority_queue
                 .push(self.email_address.clone(), *email_id, Priority::High)
+                .await?
             {
                 num_added += 1;
             }
@@ -305,104 +488,140 @@ impl EmailProcessor {
         Ok(num_added)
     }
 
-    async fn queue_older_emails(&self) -> AppResult<i32> {
-        let new_email_ids = self.fetch_email_ids(None).await?;
-
-        if new_email_ids.is_empty() {
-            return Ok(0);
+    /// Lists the next pages of the user's older emails and queues the ones not processed yet.
+    /// The cursor is saved after every page, so the backfill picks up where it left off after a
+    /// restart, and a paused backfill is left alone.
+    async fn advance_backfill(&self) -> AppResult<()> {
+        let backfill = match MailboxBackfillCtrl::get_by_user(&self.conn, self.user_id).await? {
+            Some(backfill) => backfill,
+            None => {
+                let not_before = backfill_cutoff();
+                tracing::info!(
+                    "Starting backfill for {} back to {}",
+                    self.email_address,
+                    not_before
+                );
+                MailboxBackfillCtrl::start(&self.conn, self.user_id, not_before).await?
+            }
+        };
+        if backfill.status != BackfillStatus::Running {
+            return Ok(());
         }
 
-        let mut num_added = 0;
-        for email_id in &new_email_ids {
+        let mut page_token = backfill.page_token.clone();
+        for _ in 0..cfg.backfill.pages_per_tick {
+            // Let the queued emails be processed before listing more
             if self
                 .priority_queue
-                .push(self.email_address.clone(), *email_id, Priority::Low)
+                .num_low_priority_in_queue(&self.email_address)
+                >= cfg.backfill.max_queued
             {
-                num_added += 1;
+                break;
             }
-        }
 
-        Ok(num_added)
-    }
-
-    pub fn reset_quota(&self) {
-        self.token_count.store(0, Relaxed);
-    }
-
-    async fn parse_and_prompt_email(
-        &self,
-        email_message: &ParsedMessage,
-    ) -> anyhow::Result<PromptReturnData> {
-        let CategoryPromptResponse {
-            category: ai_answer,
-            confidence,
-            token_usage,
-        } = mistral::send_category_prompt(
-            &self.http_client,
-            &self.rate_limiters,
-            email_message,
-            &self.user_email_rules,
-        )
-        .await
-        .map_err(|e| anyhow!("Error sending prompt: {e}"))?;
-
-        let mut selected_email_rule = self
-            .user_email_rules
-            .data()
-            .iter()
-            .find(|c| c.prompt_content == ai_answer)
-            .unwrap_or(&UNKNOWN_RULE);
-
-        if let Some(from) = email_message.from.as_ref() {
-            if selected_email_rule.prompt_content != "Terms of Service Update"
-                && selected_email_rule.prompt_content != "Verification Code"
-                && selected_email_rule.prompt_content != "Security Alert"
-                && cfg.heuristics.iter().any(|h| from.contains(&h.from))
+            let resp = match self
+                .email_client
+                .get_message_list(MessageListOptions {
+                    page_token: page_token.clone(),
+                    received_after: Some(backfill.not_before.to_utc()),
+                    ..Default::default()
+                })
+                .await
             {
-                selected_email_rule = HEURISTIC_EMAIL_RULES
-                    .iter()
-                    .find(|c| from.contains(&c.prompt_content))
-                    .unwrap();
+                Ok(resp) => resp,
+                // The page token expired, emails that were already queued are skipped
+                Err(e)
+                    if page_token.is_some()
+                        && e.downcast_ref::<GmailApiError>()
+                            .is_some_and(|e| e.status == reqwest::StatusCode::BAD_REQUEST) =>
+                {
+                    tracing::warn!(
+                        "Backfill cursor for {} is no longer valid, listing again: {:?}",
+                        self.email_address,
+                        e
+                    );
+                    MailboxBackfillCtrl::reset_cursor(&self.conn, &backfill).await?;
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            let messages = resp.messages.unwrap_or_default();
+            let num_scanned = messages.len() as i64;
+            let mut num_queued = 0;
+            for email_id in self.filter_unprocessed(messages).await? {
+                if self
+                    .priority_queue
+                    .push(self.email_address.clone(), email_id, Priority::Low)
+                    .await?
+                {
+                    num_queued += 1;
+                }
             }
-        }
 
-        let heuristics_used = selected_email_rule.prompt_content != ai_answer;
+            // Gmail estimates the total with the first page
+            let estimated_total = page_token
+                .is_none()
+                .then_some(resp.result_size_estimate)
+                .flatten()
+                .map(i64::from);
+            page_token = resp.next_page_token;
+            let recorded = MailboxBackfillCtrl::record_page(
+                &self.conn,
+                &backfill,
+                page_token.as_deref(),
+                num_scanned,
+                num_queued,
+                estimated_total,
+            )
+            .await?;
 
-        if confidence < cfg.model.email_confidence_threshold && !heuristics_used {
-            selected_email_rule = &UNKNOWN_RULE;
+            if !recorded {
+                tracing::info!("Backfill for {} was restarted", self.email_address);
+                break;
+            }
+            if page_token.is_none() {
+                tracing::info!("Backfill for {} completed", self.email_address);
+                break;
+            }
         }
 
-        Ok(PromptReturnData {
-            email_rule: selected_email_rule.clone(),
-            ai_answer,
-            ai_confidence: confidence,
-            heuristics_used,
-            token_usage,
-        })
+        Ok(())
+    }
+
+    pub fn reset_quota(&self) {
+        self.token_count.store(0, Relaxed);
     }
 
     async fn record_email_for_training(
         &self,
         email_message: &ParsedMessage,
-        parse_and_prompt_return: &PromptReturnData,
+        primary_answer: &TrainingAnswer,
+        shadow_answer: Option<ShadowAnswer>,
     ) -> anyhow::Result<()> {
-        let PromptReturnData {
-            ai_answer,
-            ai_confidence,
-            heuristics_used,
-            ..
-        } = parse_and_prompt_return;
+        // Outside training mode only the answers are kept, never the email content
+        let content = |field: &Option<String>| {
+            if cfg.settings.training_mode {
+                field.clone().unwrap_or_default()
+            } else {
+                String::new()
+            }
+        };
 
         let email_training = email_training::ActiveModel {
             id: ActiveValue::NotSet,
             user_email: ActiveValue::Set(self.email_address.clone()),
             email_id: ActiveValue::Set(email_message.id.clone()),
-            from: ActiveValue::Set(email_message.from.clone().unwrap_or_default()),
-            subject: ActiveValue::Set(email_message.subject.clone().unwrap_or_default()),
-            body: ActiveValue::Set(email_message.body.clone().unwrap_or_default()),
-            ai_answer: ActiveValue::Set(ai_answer.clone()),
-            confidence: ActiveValue::Set(*ai_confidence),
-            heuristics_used: ActiveValue::Set(*heuristics_used),
+            from: ActiveValue::Set(content(&email_message.from)),
+            subject: ActiveValue::Set(content(&email_message.subject)),
+            body: ActiveValue::Set(content(&email_message.body)),
+            ai_answer: ActiveValue::Set(primary_answer.ai_answer.clone()),
+            confidence: ActiveValue::Set(primary_answer.ai_confidence),
+            heuristics_used: ActiveValue::Set(primary_answer.heuristics_used),
+            model_id: ActiveValue::Set(Some(cfg.model.id.clone())),
+            shadow_model_id: ActiveValue::Set(shadow_answer.as_ref().map(|s| s.model_id.clone())),
+            shadow_answer: ActiveValue::Set(shadow_answer.as_ref().map(|s| s.answer.clone())),
+            shadow_confidence: ActiveValue::Set(shadow_answer.as_ref().map(|s| s.confidence)),
         };
 
         EmailTraining::insert(email_training)
@@ -412,6 +631,10 @@ impl EmailProcessor {
                         email_training::Column::AiAnswer,
                         email_training::Column::Confidence,
                         email_training::Column::HeuristicsUsed,
+                        email_training::Column::ModelId,
+                        email_training::Column::ShadowModelId,
+                        email_training::Column::ShadowAnswer,
+                        email_training::Column::ShadowConfidence,
                     ])
                     .to_owned(),
             )
@@ -422,6 +645,21 @@ impl EmailProcessor {
         Ok(())
     }
 
+    async fn log_training_result(
+        &self,
+        email_message: &ParsedMessage,
+        primary_answer: &TrainingAnswer,
+        shadow_answer: Option<ShadowAnswer>,
+    ) {
+        if let Err(e) = self
+            .record_email_for_training(email_message, primary_answer, shadow_answer)
+            .await
+        {
+            // This is a non-critical error, so we log it and continue
+            tracing::error!("Error recording email for training: {:?}", e);
+        }
+    }
+
     async fn categorize_email_in_client(
         &self,
         email_message: &ParsedMessage,
@@ -444,7 +682,8 @@ impl EmailProcessor {
                     Ok(_) => {}
                     Err(e) => {
                         tracing::error!("Could not fix labels for {}: {:?}", self.email_address, e);
-                        self.fail();
+                        self.fail(ProcessorFailure::new(ProcessorFailureReason::Labels, &e))
+                            .await;
                     }
                 }
                 // We allow email to be queued again later if labeling fails
@@ -470,6 +709,7 @@ impl EmailProcessor {
                 labels_removed: ActiveValue::Set(data.label_update.removed),
                 category: ActiveValue::Set(data.prompt_return_data.email_rule.mail_label.clone()),
                 ai_answer: ActiveValue::Set(data.prompt_return_data.ai_answer.clone()),
+                matched_rule: ActiveValue::Set(data.prompt_return_data.matched_rule.clone()),
                 processed_at: ActiveValue::NotSet,
             },
         )
@@ -500,24 +740,41 @@ impl EmailProcessor {
 
         self.rate_limiters.acquire_one().await;
 
-        let result = self.parse_and_prompt_email(&email_message).await?;
-        if cfg.settings.training_mode {
-            match self
-                .record_email_for_training(&email_message, &result)
-                .await
-            {
-                Ok(_) => {}
-                Err(e) => {
-                    // This is a non-critical error, so we log it and continue
-                    tracing::error!("Error recording email for training: {:?}", e);
-                }
-            };
-        };
+        let mut result = self.classifier.classify(&email_message).await?;
+        result.email_rule.mail_label = self
+            .email_client
+            .resolve_label(&result.email_rule.mail_label, &email_message)
+            .await?;
         let label_update = self
             .categorize_email_in_client(&email_message, result.email_rule.clone())
             .await?;
 
+        let primary_answer = TrainingAnswer {
+            ai_answer: result.ai_answer.clone(),
+            ai_confidence: result.ai_confidence,
+            heuristics_used: result.heuristics_used,
+        };
+        if result.llm_prompted {
+            // The shadow model is asked off the labeling path so it never delays the user
+            let processor = self.clone();
+            let email_message = email_message.clone();
+            tokio::spawn(async move {
+                let shadow_answer = processor.classifier.shadow_answer(&email_message).await;
+                if cfg.settings.training_mode || shadow_answer.is_some() {
+                    processor
+                        .log_training_result(&email_message, &primary_answer, shadow_answer)
+                        .await;
+                }
+            });
+        } else if cfg.settings.training_mode {
+            self.log_training_result(&email_message, &primary_answer, None)
+                .await;
+        }
+
         let token_usage = result.token_usage;
+        let embedding = result.embedding.take();
+        let knn_decided = result.knn_decided;
+        let category = result.email_rule.mail_label.clone();
 
         match self
             .record_processed_email(
@@ -530,9 +787,21 @@ impl EmailProcessor {
             .await
         {
             Ok(_) => {
+                if self.email_client.is_important(&category) {
+                    self.notify_important(&email_message, &category);
+                }
                 self.fetch_add_total_emails_processed(1);
                 self.fetch_add_token_count(token_usage);
                 self.add_tally_to_user_daily_quota(token_usage).await?;
+                if let Some(embedding) = embedding {
+                    if let Err(e) = self
+                        .record_embedding(&email_message.id, &category, embedding, !knn_decided)
+                        .await
+                    {
+                        // This is a non-critical error, so we log it and continue
+                        tracing::error!("Error recording email embedding: {:?}", e);
+                    }
+                }
                 Ok(())
             }
             Err(e) => {
@@ -542,6 +811,28 @@ impl EmailProcessor {
         }
     }
 
+    /// Sends the notification in the background, a failed notification doesn't fail the email
+    fn notify_important(&self, email_message: &ParsedMessage, category: &str) {
+        let notifier = self.notifier.clone();
+        let notification = ImportantEmailNotification {
+            user_email: self.email_address.clone(),
+            email_id: email_message.id.clone(),
+            category: category.to_string(),
+            from: email_message.from.clone(),
+            subject: email_message.subject.clone(),
+        };
+        tokio::spawn(async move {
+            if let Err(e) = notifier.notify(&notification).await {
+                tracing::error!(
+                    "Failed to send notification for important email {} of {}: {:?}",
+                    notification.email_id,
+                    notification.user_email,
+                    e
+                );
+            }
+        });
+    }
+
     pub async fn process_email(&self, id: u128, priority: Priority) {
         if self.is_cancelled() || self.is_quota_reached() || self.is_failed() {
             // Do not process email if processor is failed, cancelled or quota is reached
@@ -553,14 +844,52 @@ impl EmailProcessor {
             return;
         }
 
-        match self.run_processor_pipeline(id).await {
-            Ok(_) => {}
+        let email_id = parse_int_to_id(id);
+        let result = match self.run_processor_pipeline(id).await {
+            Ok(_) => EmailFailureCtrl::clear(&self.conn, self.user_id, &email_id).await,
             Err(e) => {
                 tracing::error!("Error processing email {}: {:?}", id, e);
+                self.record_failure(&email_id, &e).await
             }
+        };
+        if let Err(e) = result {
+            tracing::error!("Error updating failures for email {}: {:?}", email_id, e);
         }
     }
 
+    /// Failed emails are retried later instead of on the next fetch, and dead lettered once
+    /// they run out of attempts
+    async fn record_failure(&self, email_id: &str, error: &anyhow::Error) -> AppResult<()> {
+        let outcome = EmailFailureCtrl::record(
+            &self.conn,
+            self.user_id,
+            email_id,
+            &format!("{:?}", error),
+            FailureKind::of(error),
+        )
+        .await?;
+        match outcome {
+            FailureOutcome::RetryAt(at) => {
+                tracing::info!(
+                    "Retrying email {} for {} at {}",
+                    email_id,
+                    self.email_address,
+                    at
+                );
+            }
+            FailureOutcome::DeadLettered => {
+                tracing::warn!(
+                    "Dead lettered email {} for {}",
+                    email_id,
+                    self.email_address
+                );
+                self.dead_letter_count.fetch_add(1, Relaxed);
+            }
+        }
+
+        Ok(())
+    }
+
     async fn add_tally_to_user_daily_quota(&self, tokens: i64) -> anyhow::Result<()> {
         if tokens == 0 {
             return Ok(());
@@ -576,16 +905,74 @@ impl EmailProcessor {
         Ok(())
     }
 
+    pub fn email_rules(&self) -> &UserEmailRules {
+        self.classifier.user_email_rules()
+    }
+
     async fn configure_user_labels(&self) -> anyhow::Result<bool> {
-        let user_custom_labels = self.user_email_rules.get_custom_labels();
-        self.email_client
-            .configure_labels_if_needed(user_custom_labels)
-            .await
+        let user_custom_labels = self.classifier.user_email_rules().get_custom_labels();
+        let label_settings = LabelSettingCtrl::all_by_user(&self.conn, self.user_id).await?;
+        let configured = self
+            .email_client
+            .configure_labels_if_needed(user_custom_labels.clone(), &label_settings)
+            .await?;
+
+        // Replacement labels exist now, so emails can be moved off obsolete ones
+        if let Err(e) = label_migration::migrate_obsolete_labels(
+            &self.conn,
+            &self.email_client,
+            self.user_id,
+            &user_custom_labels,
+        )
+        .await
+        {
+            tracing::error!("Error migrating labels for {}: {:?}", self.email_address, e);
+        }
+
+        Ok(configured)
     }
 
-    fn fail(&self) {
+    /// Stops the processor and records why, so it is restarted after a backoff
+    async fn fail(&self, failure: ProcessorFailure) {
         let (tx, _) = &self.interrupt_channel;
         tx.send(InterruptSignal::Fail).unwrap();
+
+        match ProcessorStateCtrl::record_failure(&self.conn, self.user_id, &failure).await {
+            Ok(next_restart_at) => {
+                tracing::warn!(
+                    "Processor for {} failed ({:?}), restarting after {}",
+                    self.email_address,
+                    failure.reason,
+                    next_restart_at
+                );
+            }
+            Err(e) => {
+                tracing::error!(
+                    "Error recording processor failure for {}: {:?}",
+                    self.email_address,
+                    e
+                );
+            }
+        }
+    }
+
+    async fn record_status(&self, healthy: bool) {
+        if let Err(e) = ProcessorStateCtrl::record_status(
+            &self.conn,
+            self.user_id,
+            &self.status().to_string(),
+            self.total_emails_processed(),
+            self.total_emails_failed(),
+            healthy,
+        )
+        .await
+        {
+            tracing::error!(
+                "Error recording processor status for {}: {:?}",
+                self.email_address,
+                e
+            );
+        }
     }
 
     pub fn cancel(&self) {
@@ -634,6 +1021,10 @@ impl EmailProcessor {
         self.failed_email_count.fetch_add(count, Relaxed)
     }
 
+    pub fn total_emails_dead_lettered(&self) -> i64 {
+        self.dead_letter_count.load(Relaxed)
+    }
+
     pub fn is_cancelled(&self) -> bool {
         let (_, rx) = &self.interrupt_channel;
         matches!(*rx.borrow(), InterruptSignal::Cancel)
@@ -690,6 +1081,7 @@ impl EmailProcessor {
             status,
             emails_processed: self.total_emails_processed(),
             emails_failed: self.total_emails_failed(),
+            emails_dead_lettered: self.total_emails_dead_lettered(),
             emails_remaining: self.emails_remaining(),
             total_emails: num_processing_lp + num_processing_hp,
             hp_emails: num_processing_hp,
@@ -738,6 +1130,7 @@ pub struct EmailProcessorStatusUpdate {
     pub status: ProcessorStatus,
     pub emails_processed: i64,
     pub emails_failed: i64,
+    pub emails_dead_lettered: i64,
     pub emails_remaining: i64,
     pub total_emails: usize,
     pub hp_emails: usize,
@@ -746,17 +1139,16 @@ pub struct EmailProcessorStatusUpdate {
     pub quota_remaining: i64,
 }
 
-#[derive(Debug)]
-pub struct PromptReturnData {
-    pub email_rule: EmailRule,
-    pub ai_answer: String,
-    pub ai_confidence: f32,
-    pub heuristics_used: bool,
-    pub token_usage: i64,
-}
-
 #[derive(Debug)]
 pub struct EmailProcessingData {
     pub prompt_return_data: PromptReturnData,
     pub label_update: LabelUpdate,
 }
+
+/// The primary classifier's answer, recorded alongside a shadow answer or for training
+#[derive(Debug, Clone)]
+struct TrainingAnswer {
+    ai_answer: String,
+    ai_confidence: f32,
+    heuristics_used: bool,
+}
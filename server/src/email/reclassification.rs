@@ -0,0 +1,376 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Context;
+use entity::{processed_email, reclassification_job, sea_orm_active_enums::ReclassificationStatus};
+use sea_orm::DatabaseConnection;
+use tokio::task::JoinHandle;
+
+use crate::{
+    email::{classifier::EmailClassifier, client::EmailClient, rules::UserEmailRules},
+    model::{
+        email_embedding::EmailEmbeddingCtrl, label_setting::LabelSettingCtrl,
+        labels::UtilityLabels, processed_email::ProcessedEmailCtrl,
+        reclassification_job::ReclassificationJobCtrl, user::UserCtrl,
+        user_token_usage::UserTokenUsageStatsCtrl,
+    },
+    rate_limiters::RateLimiters,
+    server_config::{cfg, ClassifierMode},
+    ServerState,
+};
+
+/// Categories whose emails could be labeled differently under the new rules. Emails in a
+/// removed or reworded category need a new home, and a new category can only claim emails
+/// that previously fit nowhere.
+pub fn affected_categories(old: &UserEmailRules, new: &UserEmailRules) -> Vec<String> {
+    let old_rules = old
+        .data()
+        .iter()
+        .map(|r| (r.mail_label.as_str(), r.prompt_content.as_str()))
+        .collect::<HashMap<_, _>>();
+    let new_rules = new
+        .data()
+        .iter()
+        .map(|r| (r.mail_label.as_str(), r.prompt_content.as_str()))
+        .collect::<HashMap<_, _>>();
+
+    let mut affected = old_rules
+        .iter()
+        .filter(|(label, prompt)| new_rules.get(*label) != Some(*prompt))
+        .map(|(label, _)| label.to_string())
+        .collect::<Vec<_>>();
+
+    if new_rules.keys().any(|label| !old_rules.contains_key(label)) {
+        affected.push(UtilityLabels::Uncategorized.as_str().to_string());
+    }
+
+    affected.sort();
+    affected.dedup();
+    affected
+}
+
+/// Queues a reclassification job when a rule change affects the user's past emails
+pub async fn queue_if_affected(
+    conn: &DatabaseConnection,
+    user_id: i32,
+    old: &UserEmailRules,
+    new: &UserEmailRules,
+) -> anyhow::Result<Option<reclassification_job::Model>> {
+    if !cfg.reclassification.enabled {
+        return Ok(None);
+    }
+
+    let categories = affected_categories(old, new);
+    if categories.is_empty() {
+        return Ok(None);
+    }
+
+    let since = chrono::Utc::now() - chrono::Duration::days(cfg.reclassification.lookback_days);
+    let job = ReclassificationJobCtrl::create(conn, user_id, categories, since.into()).await?;
+
+    Ok(Some(job))
+}
+
+enum JobOutcome {
+    Completed,
+    Cancelled,
+    QuotaExceeded,
+    /// This instance stopped being the leader, the new leader picks the job up
+    LostLeadership,
+}
+
+/// Re-runs one processed email, returns whether its category changed
+async fn reclassify_email(
+    conn: &DatabaseConnection,
+    email_client: &EmailClient,
+    classifier: &EmailClassifier,
+    rate_limiters: &RateLimiters,
+    processed: processed_email::Model,
+) -> anyhow::Result<bool> {
+    let email_message = email_client.get_parsed_message(&processed.id).await?;
+
+    rate_limiters.acquire_one().await;
+    let mut result = classifier.classify(&email_message).await?;
+    if result.token_usage > 0 {
+        UserTokenUsageStatsCtrl::add_to_daily_quota(
+            conn,
+            &email_client.email_address,
+            result.token_usage,
+        )
+        .await?;
+    }
+
+    result.email_rule.mail_label = email_client
+        .resolve_label(&result.email_rule.mail_label, &email_message)
+        .await?;
+    let new_category = result.email_rule.mail_label.clone();
+    if new_category == processed.category {
+        return Ok(false);
+    }
+
+    // The database is updated before Gmail, otherwise the label move would be picked up as a
+    // user correction if the update failed
+    let updated = ProcessedEmailCtrl::replace(
+        conn,
+        processed_email::Model {
+            category: new_category.clone(),
+            ai_answer: result.ai_answer.clone(),
+            matched_rule: result.matched_rule.clone(),
+            ..processed.clone()
+        },
+    )
+    .await?;
+
+    let label_update = match email_client
+        .move_email_label(
+            processed.id.clone(),
+            email_message.label_ids.clone(),
+            &processed.category,
+            result.email_rule,
+        )
+        .await
+    {
+        Ok(label_update) => label_update,
+        Err(e) => {
+            ProcessedEmailCtrl::replace(conn, processed)
+                .await
+                .context("Could not revert processed email")?;
+            return Err(e);
+        }
+    };
+
+    ProcessedEmailCtrl::replace(
+        conn,
+        processed_email::Model {
+            labels_applied: label_update.added,
+            labels_removed: label_update.removed,
+            ..updated
+        },
+    )
+    .await?;
+
+    if cfg.classifier.mode == ClassifierMode::Knn {
+        EmailEmbeddingCtrl::relabel(
+            conn,
+            processed.user_id,
+            &processed.id,
+            Some(&new_category),
+            !result.knn_decided,
+        )
+        .await?;
+    }
+
+    Ok(true)
+}
+
+async fn run_job(
+    state: &ServerState,
+    mut job: reclassification_job::Model,
+) -> anyhow::Result<JobOutcome> {
+    let conn = &state.conn;
+    let categories = job.categories.clone().unwrap_or_default();
+    let user = UserCtrl::get_with_account_access_by_id(conn, job.user_id).await?;
+
+    if job.cursor.is_none() {
+        let total =
+            ProcessedEmailCtrl::count_in_categories(conn, job.user_id, &categories, job.since)
+                .await?;
+        ReclassificationJobCtrl::set_total(conn, job.id, total as i32).await?;
+    }
+
+    let email_client = EmailClient::new(
+        state.http_client.clone(),
+        conn.clone(),
+        state.rate_limiters.clone(),
+        user,
+    )
+    .await?;
+    let user_email_rules = UserEmailRules::from_user(conn, job.user_id).await?;
+    let label_settings = LabelSettingCtrl::all_by_user(conn, job.user_id).await?;
+    email_client
+        .configure_labels_if_needed(user_email_rules.get_custom_labels(), &label_settings)
+        .await?;
+    let classifier = EmailClassifier::load(
+        conn,
+        state.http_client.clone(),
+        state.rate_limiters.clone(),
+        job.user_id,
+        user_email_rules,
+    )
+    .await?;
+    let daily_quota = cfg.api.token_limits.daily_user_quota as i64;
+
+    loop {
+        let page = ProcessedEmailCtrl::get_page_in_categories(
+            conn,
+            job.user_id,
+            &categories,
+            job.since,
+            job.cursor.as_deref(),
+            cfg.reclassification.batch_size,
+        )
+        .await?;
+        if page.is_empty() {
+            return Ok(JobOutcome::Completed);
+        }
+
+        for processed in page {
+            if ReclassificationJobCtrl::status(conn, job.id).await?
+                == ReclassificationStatus::Cancelled
+            {
+                return Ok(JobOutcome::Cancelled);
+            }
+            if !state.cluster.leader.is_leader() {
+                return Ok(JobOutcome::LostLeadership);
+            }
+            let usage =
+                UserTokenUsageStatsCtrl::get_usage_today(conn, &email_client.email_address).await?;
+            if usage >= daily_quota {
+                return Ok(JobOutcome::QuotaExceeded);
+            }
+
+            let email_id = processed.id.clone();
+            match reclassify_email(
+                conn,
+                &email_client,
+                &classifier,
+                &state.rate_limiters,
+                processed,
+            )
+            .await
+            {
+                Ok(changed) => {
+                    job.changed += changed as i32;
+                }
+                Err(e) => {
+                    tracing::error!("Error reclassifying email {}: {:?}", email_id, e);
+                    job.failed += 1;
+                }
+            }
+            job.processed += 1;
+            job.cursor = Some(email_id);
+            ReclassificationJobCtrl::update_progress(conn, &job).await?;
+        }
+    }
+}
+
+/// Runs pending reclassification jobs one at a time on the leader. Jobs that run out of quota
+/// are put back and resume from their cursor once the user has quota again.
+pub fn run_reclassification_loop(state: ServerState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut was_leader = false;
+        let mut interval = tokio::time::interval(Duration::from_secs(30));
+        loop {
+            interval.tick().await;
+
+            // Only the leader runs jobs, otherwise every instance would requeue the jobs the
+            // others are running
+            if !state.cluster.leader.is_leader() {
+                was_leader = false;
+                continue;
+            }
+            if !was_leader {
+                // Jobs left running by the previous leader resume from their cursor
+                match ReclassificationJobCtrl::requeue_interrupted(&state.conn).await {
+                    Ok(0) => {}
+                    Ok(n) => tracing::info!("Resuming {} interrupted reclassification jobs", n),
+                    Err(e) => {
+                        tracing::error!("Error resuming reclassification jobs: {:?}", e);
+                        continue;
+                    }
+                }
+                was_leader = true;
+            }
+
+            let job = match ReclassificationJobCtrl::claim_next(&state.conn).await {
+                Ok(Some(job)) => job,
+                Ok(None) => continue,
+                Err(e) => {
+                    tracing::error!("Error claiming reclassification job: {:?}", e);
+                    continue;
+                }
+            };
+
+            let job_id = job.id;
+            tracing::info!("Running reclassification job {}", job_id);
+            let result = match run_job(&state, job).await {
+                Ok(JobOutcome::Completed) => ReclassificationJobCtrl::finish(
+                    &state.conn,
+                    job_id,
+                    ReclassificationStatus::Completed,
+                    None,
+                )
+                .await
+                .map(|_| ()),
+                Ok(JobOutcome::Cancelled) => {
+                    tracing::info!("Reclassification job {} was cancelled", job_id);
+                    Ok(())
+                }
+                Ok(JobOutcome::QuotaExceeded | JobOutcome::LostLeadership) => {
+                    ReclassificationJobCtrl::release(&state.conn, job_id).await
+                }
+                Err(e) => {
+                    tracing::error!("Reclassification job {} failed: {:?}", job_id, e);
+                    ReclassificationJobCtrl::finish(
+                        &state.conn,
+                        job_id,
+                        ReclassificationStatus::Failed,
+                        Some(e.to_string()),
+                    )
+                    .await
+                    .map(|_| ())
+                }
+            };
+
+            if let Err(e) = result {
+                tracing::error!("Error updating reclassification job {}: {:?}", job_id, e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::rules::EmailRule;
+
+    fn rules(rules: &[(&str, &str)]) -> UserEmailRules {
+        UserEmailRules::new(
+            rules
+                .iter()
+                .map(|(label, prompt)| EmailRule {
+                    prompt_content: prompt.to_string(),
+                    mail_label: label.to_string(),
+                    associated_email_client_category: None,
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_affected_categories() {
+        let old = rules(&[
+            ("ads", "Advertisement"),
+            ("receipts", "Order Receipt"),
+            ("travel", "Travel"),
+        ]);
+
+        // Unchanged rules affect nothing
+        assert!(affected_categories(&old, &old).is_empty());
+
+        // Reworded and removed categories are affected
+        let new = rules(&[("ads", "Marketing email"), ("receipts", "Order Receipt")]);
+        assert_eq!(affected_categories(&old, &new), vec!["ads", "travel"]);
+
+        // A new category can only take uncategorized emails
+        let new = rules(&[
+            ("ads", "Advertisement"),
+            ("receipts", "Order Receipt"),
+            ("travel", "Travel"),
+            ("newsletters", "Newsletter"),
+        ]);
+        assert_eq!(
+            affected_categories(&old, &new),
+            vec![UtilityLabels::Uncategorized.as_str()]
+        );
+    }
+}
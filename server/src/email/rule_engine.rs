@@ -0,0 +1,536 @@
+use anyhow::{anyhow, Context};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::{db_core::prelude::*, server_config::Heuristic};
+
+use super::parsed_message::{sender_domain, ParsedMessage};
+
+/// Categories the legacy sender heuristics never override
+const LEGACY_EXCEPT_CATEGORIES: [&str; 3] = [
+    "Terms of Service Update",
+    "Verification Code",
+    "Security Alert",
+];
+
+/// A condition on an email. Text is compared case insensitively, and regexes are case
+/// insensitive unless they set their own flags.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    /// Sender address contains the text
+    From(String),
+    /// Sender address is at the domain or one of its subdomains
+    SenderDomain(String),
+    /// Whole sender address matches a glob such as `*@github.com`, where `*` matches any text
+    /// and `?` any single character
+    SenderGlob(String),
+    /// Subject matches the regex
+    Subject(String),
+    /// Body matches the regex
+    Body(String),
+    /// List-Id header contains the text
+    ListId(String),
+    /// Any header with the name matches the regex
+    Header {
+        name: String,
+        regex: String,
+    },
+    /// Gmail put the email in the category, e.g. `promotions` or `CATEGORY_PROMOTIONS`
+    GmailCategory(String),
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+    Not(Box<Condition>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CategoryAction {
+    pub mail_label: String,
+    #[serde(default)]
+    pub gmail_category: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAction {
+    /// Replaces the model's answer with the category
+    Category(CategoryAction),
+    /// Applies the category without prompting the model
+    SkipLlm(CategoryAction),
+    /// Adds a hint to the prompt
+    Hint(String),
+}
+
+impl RuleAction {
+    pub fn mail_label(&self) -> Option<&str> {
+        match self {
+            RuleAction::Category(a) | RuleAction::SkipLlm(a) => Some(a.mail_label.as_str()),
+            RuleAction::Hint(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleDefinition {
+    pub name: String,
+    /// Higher priority rules are checked first
+    #[serde(default)]
+    pub priority: i32,
+    pub when: Condition,
+    /// The rule does not apply to emails that also match this condition
+    #[serde(default)]
+    pub unless: Option<Condition>,
+    /// The rule does not override the model when it answers one of these categories
+    #[serde(default)]
+    pub except_categories: Vec<String>,
+    pub action: RuleAction,
+}
+
+impl RuleDefinition {
+    /// Converts a `[[heuristics]]` entry, keeping the exceptions the processor used to hard-code
+    pub fn from_legacy(heuristic: &Heuristic) -> Self {
+        Self {
+            name: format!("heuristic:{}", heuristic.from),
+            priority: 0,
+            when: Condition::From(heuristic.from.clone()),
+            unless: None,
+            except_categories: LEGACY_EXCEPT_CATEGORIES
+                .iter()
+                .map(|c| String::from(*c))
+                .collect(),
+            action: RuleAction::Category(CategoryAction {
+                mail_label: heuristic.mail_label.clone(),
+                gmail_category: heuristic.gmail_categories.first().cloned(),
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Matcher {
+    From(String),
+    SenderDomain(String),
+    SenderGlob(Regex),
+    Subject(Regex),
+    Body(Regex),
+    ListId(String),
+    Header { name: String, regex: Regex },
+    GmailCategory(String),
+    All(Vec<Matcher>),
+    Any(Vec<Matcher>),
+    Not(Box<Matcher>),
+}
+
+fn compile_regex(pattern: &str) -> anyhow::Result<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .with_context(|| format!("Invalid regex: {pattern}"))
+}
+
+fn compile_glob(glob: &str) -> anyhow::Result<Regex> {
+    let pattern = glob
+        .chars()
+        .map(|c| match c {
+            '*' => ".*".to_string(),
+            '?' => ".".to_string(),
+            c => regex::escape(&c.to_string()),
+        })
+        .collect::<String>();
+
+    compile_regex(&format!("^{pattern}$"))
+}
+
+impl Matcher {
+    fn compile(condition: &Condition) -> anyhow::Result<Self> {
+        let matcher = match condition {
+            Condition::From(text) => Matcher::From(text.to_lowercase()),
+            Condition::SenderDomain(domain) => {
+                Matcher::SenderDomain(domain.trim_start_matches('@').to_lowercase())
+            }
+            Condition::SenderGlob(glob) => Matcher::SenderGlob(compile_glob(glob)?),
+            Condition::Subject(pattern) => Matcher::Subject(compile_regex(pattern)?),
+            Condition::Body(pattern) => Matcher::Body(compile_regex(pattern)?),
+            Condition::ListId(text) => Matcher::ListId(text.to_lowercase()),
+            Condition::Header { name, regex } => Matcher::Header {
+                name: name.to_lowercase(),
+                regex: compile_regex(regex)?,
+            },
+            Condition::GmailCategory(category) => {
+                let category = category.to_uppercase();
+                if category.starts_with("CATEGORY_") {
+                    Matcher::GmailCategory(category)
+                } else {
+                    Matcher::GmailCategory(format!("CATEGORY_{category}"))
+                }
+            }
+            Condition::All(conditions) | Condition::Any(conditions) if conditions.is_empty() => {
+                return Err(anyhow!("all and any need at least one condition"));
+            }
+            Condition::All(conditions) => Matcher::All(
+                conditions
+                    .iter()
+                    .map(Matcher::compile)
+                    .collect::<anyhow::Result<_>>()?,
+            ),
+            Condition::Any(conditions) => Matcher::Any(
+                conditions
+                    .iter()
+                    .map(Matcher::compile)
+                    .collect::<anyhow::Result<_>>()?,
+            ),
+            Condition::Not(condition) => Matcher::Not(Box::new(Matcher::compile(condition)?)),
+        };
+
+        Ok(matcher)
+    }
+
+    fn matches(&self, msg: &ParsedMessage) -> bool {
+        match self {
+            Matcher::From(text) => msg
+                .from
+                .as_ref()
+                .is_some_and(|f| f.to_lowercase().contains(text)),
+            Matcher::SenderDomain(domain) => msg
+                .from
+                .as_deref()
+                .and_then(sender_domain)
+                .is_some_and(|d| d == *domain || d.ends_with(&format!(".{domain}"))),
+            Matcher::SenderGlob(regex) => msg.from.as_ref().is_some_and(|f| regex.is_match(f)),
+            Matcher::Subject(regex) => msg.subject.as_ref().is_some_and(|s| regex.is_match(s)),
+            Matcher::Body(regex) => msg.body.as_ref().is_some_and(|b| regex.is_match(b)),
+            Matcher::ListId(text) => msg
+                .list_id
+                .as_ref()
+                .is_some_and(|l| l.to_lowercase().contains(text)),
+            Matcher::Header { name, regex } => msg
+                .headers
+                .iter()
+                .any(|(n, value)| n == name && regex.is_match(value)),
+            Matcher::GmailCategory(category) => msg.label_ids.iter().any(|l| l == category),
+            Matcher::All(matchers) => matchers.iter().all(|m| m.matches(msg)),
+            Matcher::Any(matchers) => matchers.iter().any(|m| m.matches(msg)),
+            Matcher::Not(matcher) => !matcher.matches(msg),
+        }
+    }
+}
+
+/// Category a rule assigns, with the Gmail category validated
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleCategory {
+    pub rule_name: String,
+    pub mail_label: String,
+    pub gmail_category: Option<AssociatedEmailClientCategory>,
+}
+
+#[derive(Debug)]
+enum CompiledAction {
+    Category {
+        category: RuleCategory,
+        skip_llm: bool,
+    },
+    Hint(String),
+}
+
+#[derive(Debug)]
+struct CompiledRule {
+    priority: i32,
+    when: Matcher,
+    unless: Option<Matcher>,
+    except_categories: Vec<String>,
+    action: CompiledAction,
+}
+
+impl CompiledRule {
+    fn compile(definition: &RuleDefinition) -> anyhow::Result<Self> {
+        let action = match &definition.action {
+            RuleAction::Category(a) | RuleAction::SkipLlm(a) => {
+                let skip_llm = matches!(definition.action, RuleAction::SkipLlm(_));
+                if skip_llm && !definition.except_categories.is_empty() {
+                    return Err(anyhow!(
+                        "except_categories cannot be used with skip_llm since the model is not asked"
+                    ));
+                }
+                let gmail_category = a
+                    .gmail_category
+                    .as_ref()
+                    .map(|c| {
+                        AssociatedEmailClientCategory::try_from_value(c)
+                            .map_err(|_| anyhow!("Invalid email client category: {c}"))
+                    })
+                    .transpose()?;
+
+                CompiledAction::Category {
+                    category: RuleCategory {
+                        rule_name: definition.name.clone(),
+                        mail_label: a.mail_label.clone(),
+                        gmail_category,
+                    },
+                    skip_llm,
+                }
+            }
+            RuleAction::Hint(hint) => CompiledAction::Hint(hint.clone()),
+        };
+
+        Ok(Self {
+            priority: definition.priority,
+            when: Matcher::compile(&definition.when)?,
+            unless: definition
+                .unless
+                .as_ref()
+                .map(Matcher::compile)
+                .transpose()?,
+            except_categories: definition.except_categories.clone(),
+            action,
+        })
+    }
+
+    fn matches(&self, msg: &ParsedMessage) -> bool {
+        self.when.matches(msg) && !self.unless.as_ref().is_some_and(|u| u.matches(msg))
+    }
+
+    fn category(&self) -> Option<(&RuleCategory, bool)> {
+        match &self.action {
+            CompiledAction::Category { category, skip_llm } => Some((category, *skip_llm)),
+            CompiledAction::Hint(_) => None,
+        }
+    }
+}
+
+/// Heuristic rules compiled once per user
+#[derive(Debug, Default)]
+pub struct RuleEngine {
+    rules: Vec<CompiledRule>,
+}
+
+impl RuleEngine {
+    /// Rules of equal priority keep the order they are given in
+    pub fn compile(definitions: &[RuleDefinition]) -> anyhow::Result<Self> {
+        let mut rules = definitions
+            .iter()
+            .map(|d| CompiledRule::compile(d).with_context(|| format!("Invalid rule: {}", d.name)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        rules.sort_by_key(|r| std::cmp::Reverse(r.priority));
+
+        Ok(Self { rules })
+    }
+
+    pub fn evaluate(&self, msg: &ParsedMessage) -> RuleMatches<'_> {
+        RuleMatches {
+            rules: self.rules.iter().filter(|r| r.matches(msg)).collect(),
+        }
+    }
+}
+
+/// Rules matching an email, highest priority first
+pub struct RuleMatches<'a> {
+    rules: Vec<&'a CompiledRule>,
+}
+
+impl<'a> RuleMatches<'a> {
+    /// Category to apply without prompting, if the highest priority category rule skips the model
+    pub fn skip_llm(&self) -> Option<&'a RuleCategory> {
+        self.rules
+            .iter()
+            .find_map(|r| r.category())
+            .and_then(|(category, skip_llm)| skip_llm.then_some(category))
+    }
+
+    pub fn hints(&self) -> Vec<String> {
+        self.rules
+            .iter()
+            .filter_map(|r| match &r.action {
+                CompiledAction::Hint(hint) => Some(hint.clone()),
+                CompiledAction::Category { .. } => None,
+            })
+            .collect()
+    }
+
+    /// Category replacing the model's answer, from the highest priority rule that does not
+    /// except the answer
+    pub fn override_for(&self, ai_answer: &str) -> Option<&'a RuleCategory> {
+        self.rules
+            .iter()
+            .filter(|r| !r.except_categories.iter().any(|c| c == ai_answer))
+            .find_map(|r| r.category())
+            .map(|(category, _)| category)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(from: &str, subject: &str) -> ParsedMessage {
+        ParsedMessage {
+            from: Some(from.to_string()),
+            subject: Some(subject.to_string()),
+            ..ParsedMessage::default()
+        }
+    }
+
+    fn category_rule(
+        name: &str,
+        priority: i32,
+        when: Condition,
+        mail_label: &str,
+    ) -> RuleDefinition {
+        RuleDefinition {
+            name: name.to_string(),
+            priority,
+            when,
+            unless: None,
+            except_categories: vec![],
+            action: RuleAction::Category(CategoryAction {
+                mail_label: mail_label.to_string(),
+                gmail_category: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_conditions() {
+        let msg = ParsedMessage {
+            label_ids: vec!["CATEGORY_PROMOTIONS".to_string()],
+            body: Some("Save 20% on your next order".to_string()),
+            headers: vec![("x-mailer".to_string(), "Mailchimp".to_string())],
+            list_id: Some("Deals <deals.shop.com>".to_string()),
+            ..message("Deals@News.Shop.com", "Weekly Deals")
+        };
+        let matches = |condition: Condition| Matcher::compile(&condition).unwrap().matches(&msg);
+
+        assert!(matches(Condition::From("deals@news".to_string())));
+        assert!(matches(Condition::SenderDomain("shop.com".to_string())));
+        assert!(!matches(Condition::SenderDomain("hop.com".to_string())));
+        assert!(matches(Condition::SenderGlob(
+            "*@news.shop.com".to_string()
+        )));
+        assert!(matches(Condition::SenderGlob(
+            "deals@????.shop.com".to_string()
+        )));
+        assert!(!matches(Condition::SenderGlob("*@shop.com".to_string())));
+        assert!(!matches(Condition::SenderGlob(
+            "deals@news.shop.co".to_string()
+        )));
+        assert!(matches(Condition::Subject("^weekly".to_string())));
+        assert!(!matches(Condition::Subject("(?-i)^weekly".to_string())));
+        assert!(matches(Condition::Body(r"\d+% on".to_string())));
+        assert!(matches(Condition::ListId("deals.shop.com".to_string())));
+        assert!(matches(Condition::Header {
+            name: "X-Mailer".to_string(),
+            regex: "mailchimp".to_string(),
+        }));
+        assert!(matches(Condition::GmailCategory("promotions".to_string())));
+        assert!(!matches(Condition::GmailCategory(
+            "CATEGORY_SOCIAL".to_string()
+        )));
+        assert!(matches(Condition::All(vec![
+            Condition::From("shop.com".to_string()),
+            Condition::Not(Box::new(Condition::Subject("receipt".to_string()))),
+        ])));
+        assert!(!matches(Condition::Any(vec![
+            Condition::From("bank.com".to_string()),
+            Condition::GmailCategory("social".to_string()),
+        ])));
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let invalid_regex = category_rule("bad", 0, Condition::Subject("(".to_string()), "ads");
+        assert!(RuleEngine::compile(&[invalid_regex]).is_err());
+
+        let empty_all = category_rule("empty", 0, Condition::All(vec![]), "ads");
+        assert!(RuleEngine::compile(&[empty_all]).is_err());
+
+        let mut excepted_skip = category_rule("skip", 0, Condition::From("a".to_string()), "ads");
+        excepted_skip.action = RuleAction::SkipLlm(CategoryAction {
+            mail_label: "ads".to_string(),
+            gmail_category: None,
+        });
+        excepted_skip.except_categories = vec!["Security Alert".to_string()];
+        assert!(RuleEngine::compile(&[excepted_skip]).is_err());
+    }
+
+    #[test]
+    fn test_priority_and_exceptions() {
+        let mut newsletter = category_rule(
+            "newsletter",
+            0,
+            Condition::SenderDomain("shop.com".to_string()),
+            "newsletter",
+        );
+        newsletter.except_categories = vec!["Order Receipt".to_string()];
+        newsletter.unless = Some(Condition::Subject("unsubscribed".to_string()));
+        let mut skip = category_rule(
+            "receipts",
+            10,
+            Condition::Subject("your receipt".to_string()),
+            "receipts",
+        );
+        skip.action = RuleAction::SkipLlm(CategoryAction {
+            mail_label: "receipts".to_string(),
+            gmail_category: Some("CATEGORY_UPDATES".to_string()),
+        });
+        let hint = RuleDefinition {
+            action: RuleAction::Hint("This sender mostly sends promotions".to_string()),
+            ..category_rule("hint", 5, Condition::From("shop.com".to_string()), "")
+        };
+        let engine = RuleEngine::compile(&[newsletter, hint, skip]).unwrap();
+
+        let receipt = engine.evaluate(&message("orders@shop.com", "Your receipt"));
+        let category = receipt.skip_llm().unwrap();
+        assert_eq!(category.mail_label, "receipts");
+        assert_eq!(
+            category.gmail_category,
+            Some(AssociatedEmailClientCategory::CategoryUpdates)
+        );
+
+        let deals = engine.evaluate(&message("deals@shop.com", "Weekly deals"));
+        assert_eq!(deals.skip_llm(), None);
+        assert_eq!(deals.hints(), vec!["This sender mostly sends promotions"]);
+        assert_eq!(
+            deals.override_for("Advertisement").unwrap().rule_name,
+            "newsletter"
+        );
+        assert_eq!(deals.override_for("Order Receipt"), None);
+
+        let unsubscribed = engine.evaluate(&message("deals@shop.com", "You unsubscribed"));
+        assert_eq!(unsubscribed.override_for("Advertisement"), None);
+    }
+
+    #[test]
+    fn test_legacy_heuristic() {
+        let rule = RuleDefinition::from_legacy(&Heuristic {
+            from: "linkedin.com".to_string(),
+            mail_label: "linkedin".to_string(),
+            gmail_categories: vec!["CATEGORY_SOCIAL".to_string()],
+        });
+        let engine = RuleEngine::compile(&[rule]).unwrap();
+        let matches = engine.evaluate(&message("jobs@linkedin.com", "New jobs"));
+
+        assert_eq!(matches.skip_llm(), None);
+        assert_eq!(
+            matches.override_for("Job Alert").unwrap().mail_label,
+            "linkedin"
+        );
+        assert_eq!(matches.override_for("Security Alert"), None);
+    }
+
+    #[test]
+    fn test_deserialize_definition() {
+        let json = serde_json::json!({
+            "name": "github",
+            "priority": 3,
+            "when": { "any": [
+                { "sender_domain": "github.com" },
+                { "header": { "name": "X-GitHub-Reason", "regex": "." } }
+            ] },
+            "action": { "skip_llm": { "mail_label": "notifications" } }
+        });
+        let rule = serde_json::from_value::<RuleDefinition>(json).unwrap();
+
+        assert_eq!(rule.priority, 3);
+        assert_eq!(rule.action.mail_label(), Some("notifications"));
+        assert!(rule.except_categories.is_empty());
+    }
+}
@@ -0,0 +1,410 @@
+use std::collections::{BTreeMap, HashMap};
+
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db_core::prelude::*,
+    email::{
+        classifier::EmailClassifier,
+        client::{EmailClient, MessageListOptions},
+        label_template,
+        rules::{DefaultRuleOverride, EmailRule, UserEmailRules},
+    },
+    error::{AppError, AppResult},
+    model::{
+        processed_email::ProcessedEmailCtrl, user::UserCtrl,
+        user_token_usage::UserTokenUsageStatsCtrl,
+    },
+    rate_limiters::RateLimiters,
+    server_config::cfg,
+    HttpClient,
+};
+
+const DEFAULT_PREVIEW_LIMIT: u32 = 20;
+const MAX_PREVIEW_LIMIT: u32 = 50;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProposedCustomRule {
+    pub prompt_content: String,
+    pub mail_label: String,
+    pub associated_email_client_category: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProposedDefaultOverride {
+    pub category: String,
+    #[serde(default)]
+    pub is_disabled: bool,
+    pub associated_email_client_category: Option<String>,
+}
+
+/// A rule set to try against the user's recent inbox. It replaces the user's saved custom
+/// rules and default overrides for the preview only.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuleDryRunInput {
+    #[serde(default)]
+    pub custom_rules: Vec<ProposedCustomRule>,
+    #[serde(default)]
+    pub default_rule_overrides: Vec<ProposedDefaultOverride>,
+    #[serde(default = "default_preview_limit")]
+    pub limit: u32,
+}
+
+fn default_preview_limit() -> u32 {
+    DEFAULT_PREVIEW_LIMIT
+}
+
+fn parse_email_client_category(
+    category: Option<&String>,
+) -> AppResult<Option<AssociatedEmailClientCategory>> {
+    category
+        .map(|c| {
+            AssociatedEmailClientCategory::try_from_value(c)
+                .map_err(|_| AppError::BadRequest(format!("Invalid email client category: {c}")))
+        })
+        .transpose()
+}
+
+impl RuleDryRunInput {
+    pub fn to_user_email_rules(&self) -> AppResult<UserEmailRules> {
+        if self.limit == 0 || self.limit > MAX_PREVIEW_LIMIT {
+            return Err(AppError::BadRequest(format!(
+                "Limit must be between 1 and {MAX_PREVIEW_LIMIT}"
+            )));
+        }
+
+        let custom_rules = self
+            .custom_rules
+            .iter()
+            .map(|rule| {
+                if rule.prompt_content.trim().is_empty() || rule.mail_label.trim().is_empty() {
+                    return Err(AppError::BadRequest(
+                        "Custom rules need a prompt and a label".to_string(),
+                    ));
+                }
+                if rule
+                    .mail_label
+                    .trim()
+                    .split('/')
+                    .any(|part| part.trim().is_empty())
+                {
+                    return Err(AppError::BadRequest(format!(
+                        "Invalid nested label: {}",
+                        rule.mail_label
+                    )));
+                }
+                label_template::validate(rule.mail_label.trim()).map_err(AppError::BadRequest)?;
+
+                Ok(EmailRule {
+                    prompt_content: rule.prompt_content.trim().to_string(),
+                    mail_label: rule.mail_label.trim().to_string(),
+                    associated_email_client_category: parse_email_client_category(
+                        rule.associated_email_client_category.as_ref(),
+                    )?,
+                })
+            })
+            .collect::<AppResult<Vec<_>>>()?;
+
+        let default_labels = UserEmailRules::default_labels();
+        let overrides = self
+            .default_rule_overrides
+            .iter()
+            .map(|ro| {
+                if !default_labels.contains(&ro.category) {
+                    return Err(AppError::BadRequest(format!(
+                        "Unknown default category: {}",
+                        ro.category
+                    )));
+                }
+
+                Ok(DefaultRuleOverride {
+                    category: ro.category.clone(),
+                    is_disabled: ro.is_disabled,
+                    associated_email_client_category: parse_email_client_category(
+                        ro.associated_email_client_category.as_ref(),
+                    )?,
+                })
+            })
+            .collect::<AppResult<Vec<_>>>()?;
+
+        Ok(UserEmailRules::from_proposal(overrides, custom_rules))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CurrentCategorySource {
+    /// The category the processor already applied
+    Processed,
+    /// The email hasn't been processed yet, so the saved rules were run for the preview
+    Classified,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EmailPreview {
+    pub email_id: String,
+    pub from: Option<String>,
+    pub subject: Option<String>,
+    pub current_category: String,
+    pub current_source: CurrentCategorySource,
+    pub proposed_category: String,
+    pub changed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RulePreview {
+    pub emails: Vec<EmailPreview>,
+    pub changed: usize,
+    /// Current category -> proposed category -> count, for changed emails only
+    pub transitions: BTreeMap<String, BTreeMap<String, usize>>,
+    pub tokens_used: i64,
+    pub preview_tokens_remaining: i64,
+    /// Set when the preview stopped early because the daily preview budget ran out
+    pub budget_exhausted: bool,
+    /// Emails that could not be fetched, so they are left out of the preview
+    pub skipped_emails: Vec<String>,
+}
+
+impl RulePreview {
+    pub fn from_emails(
+        emails: Vec<EmailPreview>,
+        tokens_used: i64,
+        preview_tokens_remaining: i64,
+        budget_exhausted: bool,
+        skipped_emails: Vec<String>,
+    ) -> Self {
+        let mut transitions = BTreeMap::<String, BTreeMap<String, usize>>::new();
+        for email in emails.iter().filter(|e| e.changed) {
+            *transitions
+                .entry(email.current_category.clone())
+                .or_default()
+                .entry(email.proposed_category.clone())
+                .or_default() += 1;
+        }
+
+        Self {
+            changed: emails.iter().filter(|e| e.changed).count(),
+            emails,
+            transitions,
+            tokens_used,
+            preview_tokens_remaining,
+            budget_exhausted,
+            skipped_emails,
+        }
+    }
+}
+
+/// Classifies the user's most recent inbox emails with both the saved and the proposed rules.
+/// Nothing is labeled in Gmail or written to processed_email, and tokens are charged to the
+/// preview budget rather than the daily quota.
+pub async fn preview_rules(
+    conn: &DatabaseConnection,
+    http_client: &HttpClient,
+    rate_limiters: &RateLimiters,
+    user_email: &str,
+    input: RuleDryRunInput,
+) -> AppResult<RulePreview> {
+    let proposed_rules = input.to_user_email_rules()?;
+    let user = UserCtrl::get_with_account_access_by_email(conn, user_email).await?;
+    let user_id = user.id;
+
+    let current_rules = UserEmailRules::from_user(conn, user_id).await?;
+    let (current_classifier, proposed_classifier) = tokio::try_join!(
+        EmailClassifier::load(
+            conn,
+            http_client.clone(),
+            rate_limiters.clone(),
+            user_id,
+            current_rules
+        ),
+        EmailClassifier::load(
+            conn,
+            http_client.clone(),
+            rate_limiters.clone(),
+            user_id,
+            proposed_rules
+        )
+    )?;
+
+    let email_client = EmailClient::new(
+        http_client.clone(),
+        conn.clone(),
+        rate_limiters.clone(),
+        user,
+    )
+    .await?;
+    let message_ids = email_client
+        .get_message_list(MessageListOptions {
+            label_filter: Some("label:inbox".to_string()),
+            max_results: Some(input.limit),
+            ..Default::default()
+        })
+        .await?
+        .messages
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|m| m.id)
+        .collect::<Vec<_>>();
+
+    let processed = ProcessedEmailCtrl::get_by_ids(conn, user_id, message_ids.clone())
+        .await?
+        .into_iter()
+        .map(|email| (email.id, email.category))
+        .collect::<HashMap<_, _>>();
+
+    let quota = cfg.api.token_limits.daily_preview_quota as i64;
+    let estimated_tokens = cfg.api.token_limits.estimated_token_usage_per_email as i64;
+    let mut preview_usage =
+        UserTokenUsageStatsCtrl::get_preview_usage_today(conn, user_email).await?;
+    let mut tokens_used = 0;
+    let mut budget_exhausted = false;
+    let mut emails = vec![];
+    let mut skipped_emails = vec![];
+
+    for id in message_ids {
+        if preview_usage + estimated_tokens > quota {
+            budget_exhausted = true;
+            break;
+        }
+
+        let email_message = match email_client.get_parsed_message(&id).await {
+            Ok(email_message) => email_message,
+            Err(e) => {
+                tracing::warn!("Skipping email {} in rule preview: {:?}", id, e);
+                skipped_emails.push(id);
+                continue;
+            }
+        };
+        let mut email_tokens = 0;
+
+        let (current_category, current_source) = match processed.get(&id) {
+            Some(category) => (category.clone(), CurrentCategorySource::Processed),
+            None => {
+                rate_limiters.acquire_one().await;
+                let current = match current_classifier.classify(&email_message).await {
+                    Ok(current) => current,
+                    Err(e) => {
+                        tracing::warn!("Skipping email {} in rule preview: {:?}", id, e);
+                        skipped_emails.push(id);
+                        continue;
+                    }
+                };
+                email_tokens += current.token_usage;
+                (
+                    label_template::resolve(&current.email_rule.mail_label, &email_message),
+                    CurrentCategorySource::Classified,
+                )
+            }
+        };
+
+        rate_limiters.acquire_one().await;
+        let proposed = proposed_classifier.classify(&email_message).await;
+        if let Ok(proposed) = &proposed {
+            email_tokens += proposed.token_usage;
+        }
+
+        // Tokens spent on the current category count even if the proposed one failed
+        preview_usage =
+            UserTokenUsageStatsCtrl::add_to_daily_preview_quota(conn, user_email, email_tokens)
+                .await?;
+        tokens_used += email_tokens;
+
+        let proposed = match proposed {
+            Ok(proposed) => proposed,
+            Err(e) => {
+                tracing::warn!("Skipping email {} in rule preview: {:?}", id, e);
+                skipped_emails.push(id);
+                continue;
+            }
+        };
+
+        // Templated labels are rendered but not created, so the label limit is not checked
+        let proposed_category =
+            label_template::resolve(&proposed.email_rule.mail_label, &email_message);
+        emails.push(EmailPreview {
+            changed: current_category != proposed_category,
+            email_id: id,
+            from: email_message.from,
+            subject: email_message.subject,
+            current_category,
+            current_source,
+            proposed_category,
+        });
+    }
+
+    Ok(RulePreview::from_emails(
+        emails,
+        tokens_used,
+        (quota - preview_usage).max(0),
+        budget_exhausted,
+        skipped_emails,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email(id: &str, current: &str, proposed: &str) -> EmailPreview {
+        EmailPreview {
+            email_id: id.to_string(),
+            from: None,
+            subject: None,
+            current_category: current.to_string(),
+            current_source: CurrentCategorySource::Processed,
+            proposed_category: proposed.to_string(),
+            changed: current != proposed,
+        }
+    }
+
+    #[test]
+    fn test_preview_transitions() {
+        let preview = RulePreview::from_emails(
+            vec![
+                email("1", "ads", "newsletters"),
+                email("2", "ads", "newsletters"),
+                email("3", "ads", "ads"),
+                email("4", "receipts", "orders"),
+            ],
+            120,
+            880,
+            false,
+            vec!["5".to_string()],
+        );
+
+        assert_eq!(preview.changed, 3);
+        assert_eq!(preview.transitions["ads"]["newsletters"], 2);
+        assert_eq!(preview.transitions["receipts"]["orders"], 1);
+        assert!(!preview.transitions["ads"].contains_key("ads"));
+        assert_eq!(preview.emails.len(), 4);
+        assert_eq!(preview.skipped_emails, vec!["5".to_string()]);
+    }
+
+    #[test]
+    fn test_preview_limit_validation() {
+        let input = RuleDryRunInput {
+            custom_rules: vec![],
+            default_rule_overrides: vec![],
+            limit: MAX_PREVIEW_LIMIT + 1,
+        };
+        assert!(matches!(
+            input.to_user_email_rules(),
+            Err(AppError::BadRequest(_))
+        ));
+
+        let input = RuleDryRunInput {
+            custom_rules: vec![ProposedCustomRule {
+                prompt_content: " ".to_string(),
+                mail_label: "label".to_string(),
+                associated_email_client_category: None,
+            }],
+            default_rule_overrides: vec![],
+            limit: 10,
+        };
+        assert!(matches!(
+            input.to_user_email_rules(),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 
 use crate::db_core::prelude::*;
+use crate::email::label_template;
 use anyhow::Context;
 use futures::join;
 use lazy_static::lazy_static;
@@ -22,20 +23,6 @@ lazy_static! {
             })
             .collect()
     };
-    pub static ref HEURISTIC_EMAIL_RULES: Vec<EmailRule> = {
-        use crate::server_config::cfg;
-        cfg.heuristics
-            .iter()
-            .map(|h| EmailRule {
-                prompt_content: h.from.clone(),
-                mail_label: h.mail_label.clone(),
-                associated_email_client_category: h.gmail_categories.first().map(|s| {
-                    AssociatedEmailClientCategory::try_from_value(s)
-                        .unwrap_or_else(|_| panic!("Invalid email client category: {s}"))
-                }),
-            })
-            .collect()
-    };
 }
 
 #[derive(Debug, Clone)]
@@ -45,6 +32,38 @@ pub struct EmailRule {
     pub associated_email_client_category: Option<AssociatedEmailClientCategory>,
 }
 
+impl EmailRule {
+    /// The category as shown to the model. Nested categories are prefixed with their parent
+    /// path, e.g. `Finance / Receipt` for `Finance/Receipts`, so similar categories under
+    /// different parents stay distinct.
+    pub fn prompt_category(&self) -> String {
+        match self.mail_label.rsplit_once('/') {
+            Some((parent, _)) => {
+                format!("{} / {}", parent.replace('/', " / "), self.prompt_content)
+            }
+            None => self.prompt_content.clone(),
+        }
+    }
+}
+
+/// A user's change to one of the default categories
+#[derive(Debug, Clone)]
+pub struct DefaultRuleOverride {
+    pub category: String,
+    pub is_disabled: bool,
+    pub associated_email_client_category: Option<AssociatedEmailClientCategory>,
+}
+
+impl From<default_email_rule_override::Model> for DefaultRuleOverride {
+    fn from(model: default_email_rule_override::Model) -> Self {
+        Self {
+            category: model.category,
+            is_disabled: model.is_disabled,
+            associated_email_client_category: model.associated_email_client_category,
+        }
+    }
+}
+
 pub struct UserEmailRules {
     data: Vec<EmailRule>,
 }
@@ -77,15 +96,56 @@ impl UserEmailRules {
         let default_rule_overrides =
             default_rule_overrides.context("Failed to fetch default overrides")?;
         let custom_email_rules = custom_email_rules.context("Failed to fetch custom rules")?;
-        let data =
-            Self::build_category_rules(default_rule_overrides.clone(), custom_email_rules.clone());
+        let data = Self::build_category_rules(
+            default_rule_overrides
+                .into_iter()
+                .map(DefaultRuleOverride::from)
+                .collect(),
+            custom_email_rules
+                .into_iter()
+                // Rules are saved by the frontend, a template without a parent would create a
+                // top level label per value
+                .filter(|rule| match label_template::validate(&rule.category) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        tracing::warn!("Skipping custom rule {}: {}", rule.id, e);
+                        false
+                    }
+                })
+                .map(|rule| EmailRule {
+                    prompt_content: rule.prompt_content,
+                    mail_label: rule.category,
+                    associated_email_client_category: rule.associated_email_client_category,
+                })
+                .collect(),
+        );
 
         Ok(Self::new(data))
     }
 
+    /// Builds rules from a proposed set of overrides and custom rules, as if they replaced
+    /// the user's saved ones
+    pub fn from_proposal(
+        default_rule_overrides: Vec<DefaultRuleOverride>,
+        custom_rules: Vec<EmailRule>,
+    ) -> Self {
+        Self::new(Self::build_category_rules(
+            default_rule_overrides,
+            custom_rules,
+        ))
+    }
+
+    /// Labels of the built in categories, which overrides refer to
+    pub fn default_labels() -> Vec<String> {
+        DEFAULT_EMAIL_RULES
+            .iter()
+            .map(|r| r.mail_label.clone())
+            .collect()
+    }
+
     fn build_category_rules(
-        default_rule_overrides: Vec<default_email_rule_override::Model>,
-        custom_rules: Vec<custom_email_rule::Model>,
+        default_rule_overrides: Vec<DefaultRuleOverride>,
+        custom_rules: Vec<EmailRule>,
     ) -> Vec<EmailRule> {
         let mut default_rules = DEFAULT_EMAIL_RULES
             .iter()
@@ -102,13 +162,8 @@ impl UserEmailRules {
             });
         }
 
-        let custom_rules = custom_rules.into_iter().map(|rule| EmailRule {
-            prompt_content: rule.prompt_content,
-            mail_label: rule.category,
-            associated_email_client_category: rule.associated_email_client_category,
-        });
-
         custom_rules
+            .into_iter()
             .chain(default_rules.values().cloned())
             .collect()
     }
@@ -122,6 +177,63 @@ impl UserEmailRules {
     }
 
     pub fn get_prompt_categories(&self) -> Vec<String> {
-        self.data.iter().map(|r| r.prompt_content.clone()).collect()
+        self.data.iter().map(|r| r.prompt_category()).collect()
+    }
+
+    /// The rule the model's answer picks. Answers are matched by their full prompted path, a
+    /// bare category is only accepted when a single rule has it.
+    pub fn rule_for_answer(&self, ai_answer: &str) -> Option<&EmailRule> {
+        if let Some(rule) = self.data.iter().find(|r| r.prompt_category() == ai_answer) {
+            return Some(rule);
+        }
+
+        let mut matches = self.data.iter().filter(|r| r.prompt_content == ai_answer);
+        match (matches.next(), matches.next()) {
+            (Some(rule), None) => Some(rule),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_category() {
+        let rule = EmailRule {
+            prompt_content: "Receipt".to_string(),
+            mail_label: "Finance/Purchases/Receipts".to_string(),
+            associated_email_client_category: None,
+        };
+        assert_eq!(rule.prompt_category(), "Finance / Purchases / Receipt");
+
+        let rule = EmailRule {
+            mail_label: "receipts".to_string(),
+            ..rule
+        };
+        assert_eq!(rule.prompt_category(), "Receipt");
+    }
+
+    #[test]
+    fn test_rule_for_answer() {
+        let rule = |prompt_content: &str, mail_label: &str| EmailRule {
+            prompt_content: prompt_content.to_string(),
+            mail_label: mail_label.to_string(),
+            associated_email_client_category: None,
+        };
+        let rules = UserEmailRules::new(vec![
+            rule("Receipt", "Finance/Receipts"),
+            rule("Receipt", "Travel/Receipts"),
+            rule("Invoice", "Finance/Invoices"),
+        ]);
+
+        let label = |answer: &str| rules.rule_for_answer(answer).map(|r| r.mail_label.as_str());
+        assert_eq!(label("Travel / Receipt"), Some("Travel/Receipts"));
+        assert_eq!(label("Finance / Receipt"), Some("Finance/Receipts"));
+        assert_eq!(label("Invoice"), Some("Finance/Invoices"));
+        // Ambiguous without the parent path
+        assert_eq!(label("Receipt"), None);
+        assert_eq!(label("Travel"), None);
     }
 }
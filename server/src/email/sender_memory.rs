@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+use entity::email_correction;
+
+/// Number of consistent corrections needed before a sender's emails skip the prompt
+const MIN_CONSISTENT_CORRECTIONS: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SenderEntry {
+    category: String,
+    count: usize,
+}
+
+/// Remembers which category a user keeps moving a sender's emails to
+#[derive(Debug, Default)]
+pub struct SenderMemory {
+    by_sender: HashMap<String, SenderEntry>,
+}
+
+impl SenderMemory {
+    /// Corrections are expected in the order they were made
+    pub fn from_corrections(corrections: &[email_correction::Model]) -> Self {
+        let mut memory = Self::default();
+        for correction in corrections {
+            if let (Some(from), Some(category)) = (&correction.from, &correction.corrected_category)
+            {
+                memory.record(from, category);
+            }
+        }
+
+        memory
+    }
+
+    pub fn record(&mut self, sender: &str, category: &str) {
+        let sender = sender.to_lowercase();
+        match self.by_sender.get_mut(&sender) {
+            Some(entry) if entry.category == category => entry.count += 1,
+            // The most recent correction wins when a user changes their mind
+            _ => {
+                self.by_sender.insert(
+                    sender,
+                    SenderEntry {
+                        category: category.to_string(),
+                        count: 1,
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn lookup(&self, sender: &str) -> Option<&str> {
+        self.by_sender
+            .get(&sender.to_lowercase())
+            .filter(|e| e.count >= MIN_CONSISTENT_CORRECTIONS)
+            .map(|e| e.category.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sender_memory_requires_consistent_corrections() {
+        let mut memory = SenderMemory::default();
+        memory.record("deals@shop.com", "ads");
+        assert_eq!(memory.lookup("deals@shop.com"), None);
+
+        memory.record("Deals@Shop.com", "ads");
+        assert_eq!(memory.lookup("deals@shop.com"), Some("ads"));
+
+        memory.record("deals@shop.com", "receipts");
+        assert_eq!(memory.lookup("deals@shop.com"), None);
+    }
+}
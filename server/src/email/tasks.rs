@@ -10,9 +10,12 @@ use sea_orm::DatabaseConnection;
 use tokio::task::JoinHandle;
 use tokio::time::interval;
 
+use crate::cluster::ClusterMembership;
 use crate::model::auto_cleanup_setting::AutoCleanupSettingCtrl;
 use crate::model::daily_email_summary::DailyEmailSentStatus;
+use crate::model::labels::UtilityLabels;
 use crate::model::processed_email::ProcessedEmailCtrl;
+use crate::model::processor_state::ProcessorStateCtrl;
 use crate::model::user::UserCtrl;
 use crate::prompt::priority_queue::PromptPriorityQueue;
 use crate::rate_limiters::RateLimiters;
@@ -21,17 +24,32 @@ use crate::HttpClient;
 use crate::{error::AppResult, ServerState};
 
 use super::active_email_processors::ActiveEmailProcessorMap;
-use super::client::{EmailClient, MessageListOptions};
+use super::client::{format_filter, EmailClient, MessageListOptions};
 use super::daily_summary_mailer::DailySummaryMailer;
+use super::drain::ProcessingDrain;
 
 pub async fn add_users_to_processing(
     state: ServerState,
     email_processor_map: ActiveEmailProcessorMap,
 ) -> AppResult<()> {
     let conn = &state.conn;
-    let user_accounts = UserCtrl::all_with_available_quota(conn).await?;
+    let backing_off = ProcessorStateCtrl::backing_off_user_ids(conn).await?;
+    if !backing_off.is_empty() {
+        tracing::info!(
+            "Waiting to restart failed processors for {} users",
+            backing_off.len()
+        );
+    }
+    // Other instances process the users they own
+    let user_accounts = UserCtrl::all_with_available_quota(conn)
+        .await?
+        .into_iter()
+        .filter(|user| state.cluster.owns(&user.email) && !backing_off.contains(&user.id))
+        .collect::<Vec<_>>();
     tracing::info!("Adding {} users to processing", user_accounts.len());
 
+    release_unowned_processors(&state.cluster, &email_processor_map);
+
     for user in user_accounts {
         match email_processor_map.insert_processor(user).await {
             Ok(_) => {}
@@ -44,6 +62,94 @@ pub async fn add_users_to_processing(
     Ok(())
 }
 
+/// Stops processors for users that moved to another instance
+fn release_unowned_processors(
+    cluster: &ClusterMembership,
+    email_processor_map: &ActiveEmailProcessorMap,
+) {
+    let unowned = email_processor_map
+        .entries()
+        .into_iter()
+        .map(|(email, _)| email)
+        .filter(|email| !cluster.owns(email))
+        .collect::<HashSet<_>>();
+    if unowned.is_empty() {
+        return;
+    }
+
+    tracing::info!("Handing {} users over to other instances", unowned.len());
+    for email in &unowned {
+        email_processor_map.cancel_processor(email);
+    }
+    email_processor_map.cleanup_processors(unowned);
+}
+
+/// Keeps this instance registered and moves users between instances as they join or leave
+pub fn run_cluster_heartbeat_loop(
+    state: ServerState,
+    email_processor_map: ActiveEmailProcessorMap,
+) -> JoinHandle<()> {
+    let mut interval = interval(Duration::from_secs(cfg.cluster.heartbeat_secs));
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+            match state.cluster.heartbeat(&state.conn).await {
+                Ok(true) => {
+                    release_unowned_processors(&state.cluster, &email_processor_map);
+                    if let Err(e) =
+                        add_users_to_processing(state.clone(), email_processor_map.clone()).await
+                    {
+                        tracing::error!("Error rebalancing users: {:?}", e);
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    tracing::error!("Error sending cluster heartbeat: {:?}", e);
+                }
+            }
+        }
+    })
+}
+
+/// Keeps electing a leader for the singleton jobs, and logs when leadership moves
+pub fn run_leader_election_loop(state: ServerState) -> JoinHandle<()> {
+    let mut interval = interval(Duration::from_secs(cfg.cluster.leader_renew_secs));
+    tokio::spawn(async move {
+        let leader = &state.cluster.leader;
+        let mut was_leader = false;
+        let mut last_leader = None;
+        loop {
+            interval.tick().await;
+            let is_leader = match leader.renew(&state.conn).await {
+                Ok(is_leader) => is_leader,
+                Err(e) => {
+                    tracing::error!("Error renewing leadership: {:?}", e);
+                    leader.is_leader()
+                }
+            };
+            if is_leader != was_leader {
+                if is_leader {
+                    tracing::info!("{} is now the leader", state.cluster.instance_id);
+                } else {
+                    tracing::warn!("{} is no longer the leader", state.cluster.instance_id);
+                }
+                was_leader = is_leader;
+            }
+
+            match leader.current_leader(&state.conn).await {
+                Ok(current) => {
+                    let current = current.map(|(instance_id, _)| instance_id);
+                    if current != last_leader {
+                        tracing::info!("Leader is {}", current.as_deref().unwrap_or("nobody"));
+                        last_leader = current;
+                    }
+                }
+                Err(e) => tracing::error!("Error loading the leader: {:?}", e),
+            }
+        }
+    })
+}
+
 pub async fn sweep_for_cancelled_subscriptions(
     state: &ServerState,
     email_processor_map: ActiveEmailProcessorMap,
@@ -72,10 +178,15 @@ pub async fn send_user_daily_email_summary(
         ));
     }
 
-    DailySummaryMailer::new(state.conn.clone(), state.http_client.clone(), user)
-        .await?
-        .send()
-        .await;
+    DailySummaryMailer::new(
+        state.conn.clone(),
+        state.http_client.clone(),
+        state.rate_limiters.clone(),
+        user,
+    )
+    .await?
+    .send()
+    .await;
 
     Ok(DailyEmailSentStatus::Sent)
 }
@@ -83,27 +194,83 @@ pub async fn send_user_daily_email_summary(
 async fn email_processing_task(
     prompt_priority_queue: PromptPriorityQueue,
     email_processor_map: ActiveEmailProcessorMap,
+    drain: ProcessingDrain,
 ) {
-    loop {
-        let entry = prompt_priority_queue.pop();
+    // Emails already taken are finished, only taking new ones stops
+    while !drain.is_draining() {
+        let entry = match prompt_priority_queue.pop().await {
+            Ok(entry) => entry,
+            Err(e) => {
+                tracing::error!("Error popping from prompt queue: {:?}", e);
+                None
+            }
+        };
         if let Some(entry) = entry {
+            let in_flight = drain.track(&entry);
+            if drain.is_draining() {
+                // Taken just as draining started, too late to process it here
+                if let Err(e) = prompt_priority_queue.release(&entry).await {
+                    tracing::error!("Error releasing email {}: {:?}", entry.email_id, e);
+                }
+                in_flight.returned();
+                break;
+            }
             let email = &entry.user_email;
             let email_id = entry.email_id;
-            if let Some(processor) = email_processor_map.get(email) {
-                processor.process_email(email_id, entry.priority).await;
-                prompt_priority_queue.remove_from_processing(email_id);
+            let result = if let Some(processor) = email_processor_map.get(email) {
+                // The lease is extended while the email is processed, so a slow prompt is
+                // not handed to another worker
+                let lease_interval = Duration::from_secs(
+                    (cfg.prompt_queue.visibility_timeout_secs / 3).max(1) as u64,
+                );
+                let process = processor.process_email(email_id, entry.priority);
+                tokio::pin!(process);
+                loop {
+                    tokio::select! {
+                        _ = &mut process => break,
+                        _ = tokio::time::sleep(lease_interval) => {
+                            if let Err(e) = prompt_priority_queue.extend_lease(&entry).await {
+                                tracing::error!("Error extending lease for {}: {:?}", email_id, e);
+                            }
+                        }
+                    }
+                }
+                prompt_priority_queue.remove_from_processing(&entry).await
+            } else {
+                // The user's processor may be on another server or not created yet
+                prompt_priority_queue.release(&entry).await
+            };
+            if let Err(e) = result {
+                tracing::error!("Error updating prompt queue for {}: {:?}", email_id, e);
             }
         } else {
-            tokio::time::sleep(Duration::from_millis(500)).await;
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_millis(500)) => {}
+                _ = drain.draining() => {}
+            }
         }
     }
 }
 
+/// Keeps the prompt queue's counts in line with the database, which other servers also write to
+pub fn run_prompt_queue_refresh_loop(prompt_priority_queue: PromptPriorityQueue) -> JoinHandle<()> {
+    let mut interval = interval(Duration::from_secs(cfg.prompt_queue.count_refresh_secs));
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+            if let Err(e) = prompt_priority_queue.refresh_counts().await {
+                tracing::error!("Error refreshing prompt queue counts: {:?}", e);
+            }
+        }
+    })
+}
+
 /// This function pulls emails from the prompt priority queue and sends them to the
 /// appropriate email processor for processing.
 pub fn run_email_processing_loop(
     prompt_priority_queue: PromptPriorityQueue,
     email_processor_map: ActiveEmailProcessorMap,
+    drain: ProcessingDrain,
 ) -> JoinHandle<()> {
     let max_threads = cfg.api.prompt_limits.rate_limit_per_sec * 2 - 1;
     tracing::info!(
@@ -117,11 +284,19 @@ pub fn run_email_processing_loop(
         join_set.spawn(email_processing_task(
             prompt_priority_queue.clone(),
             email_processor_map.clone(),
+            drain.clone(),
         ));
     }
 
     tokio::task::spawn(async move {
         loop {
+            if drain.is_draining() {
+                // Workers finish their emails and exit on their own
+                while join_set.join_next().await.is_some() {}
+                tracing::info!("Email processing threads stopped");
+                return;
+            }
+
             if join_set.len() != max_threads {
                 tracing::error!(
                     "Email processing thread count mismatch: expected {}, got {}",
@@ -137,6 +312,7 @@ pub fn run_email_processing_loop(
                     join_set.spawn(email_processing_task(
                         prompt_priority_queue.clone(),
                         email_processor_map.clone(),
+                        drain.clone(),
                     ));
                 }
             }
@@ -194,10 +370,11 @@ pub async fn cleanup_email(
 async fn get_email_client(
     http_client: HttpClient,
     conn: DatabaseConnection,
+    rate_limiters: RateLimiters,
     user_id: i32,
 ) -> anyhow::Result<EmailClient> {
     let user = UserCtrl::get_with_account_access_by_id(&conn, user_id).await?;
-    let client = EmailClient::new(http_client, conn, user).await?;
+    let client = EmailClient::new(http_client, conn, rate_limiters, user).await?;
     Ok(client)
 }
 
@@ -212,7 +389,7 @@ async fn get_all_message_ids_with_keep_label(
                 label_filter: Some(
                     [
                         "label:inbox".to_string(),
-                        "label:Mailclerk/keep".to_string(),
+                        format_filter(UtilityLabels::Keep.as_str()),
                     ]
                     .join(" AND "),
                 ),
@@ -250,6 +427,7 @@ async fn get_all_message_ids_with_keep_label(
 pub async fn run_auto_email_cleanup(
     http_client: HttpClient,
     conn: DatabaseConnection,
+    rate_limiters: RateLimiters,
 ) -> anyhow::Result<()> {
     let active_user_cleanup_settings =
         AutoCleanupSettingCtrl::all_active_user_cleanup_settings(&conn)
@@ -265,7 +443,13 @@ pub async fn run_auto_email_cleanup(
     );
 
     for (user_id, settings) in user_to_cleanup_settings {
-        let email_client = match get_email_client(http_client.clone(), conn.clone(), user_id).await
+        let email_client = match get_email_client(
+            http_client.clone(),
+            conn.clone(),
+            rate_limiters.clone(),
+            user_id,
+        )
+        .await
         {
             Ok(client) => client,
             Err(err) => {
@@ -282,6 +466,16 @@ pub async fn run_auto_email_cleanup(
         let keep_ids = Arc::new(get_all_message_ids_with_keep_label(email_client.clone()).await?);
 
         for setting in settings {
+            // Important emails are never cleaned up, whatever the user's settings say
+            if email_client.is_important(&setting.category) {
+                tracing::info!(
+                    "Skipping cleanup of important category {} for user {}",
+                    setting.category,
+                    email_client.email_address
+                );
+                continue;
+            }
+
             if let Ok(emails_to_cleanup) =
                 ProcessedEmailCtrl::get_users_processed_emails_for_cleanup(&conn, &setting).await
             {
@@ -373,7 +567,7 @@ pub fn watch(
             let emails_per_second = diff as f64 / now.elapsed().as_secs_f64();
             now = std::time::Instant::now();
             last_recorded = email_processor_map.total_emails_processed();
-            let limiter_status = rate_limiters.get_status();
+            let limiter_status = rate_limiters.get_status().await;
             let in_processing = prompt_priority_queue.num_in_processing();
             if let Some(update) = email_processor_map.get_current_state() {
                 tracing::info!(
@@ -396,6 +590,9 @@ mod tests {
     #[tokio::test]
     async fn test_auto_cleanup() {
         let (conn, http_client) = setup().await;
-        run_auto_email_cleanup(http_client, conn).await.unwrap();
+        let rate_limiters = RateLimiters::from_config(conn.clone());
+        run_auto_email_cleanup(http_client, conn, rate_limiters)
+            .await
+            .unwrap();
     }
 }
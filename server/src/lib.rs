@@ -0,0 +1,394 @@
+#![allow(dead_code)]
+#[macro_use]
+mod macros;
+
+mod auth;
+mod cluster;
+mod cron_time_utils;
+mod db_core;
+pub mod email;
+mod error;
+mod model;
+mod notify;
+mod prompt;
+pub mod rate_limiters;
+mod request_tracing;
+mod routes;
+pub mod server_config;
+mod testing;
+
+use std::{
+    env,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{atomic::AtomicU64, Arc},
+    time::Duration,
+};
+
+use auth::session_store::AuthSessionStore;
+use axum::{extract::FromRef, Router};
+use cluster::ClusterMembership;
+use db_core::prelude::*;
+use email::{active_email_processors::ActiveEmailProcessorMap, drain::ProcessingDrain};
+use futures::future::join_all;
+use notify::notifier::SharedNotifier;
+use prompt::priority_queue::PromptPriorityQueue;
+use rate_limiters::RateLimiters;
+use reqwest::Certificate;
+use routes::AppRouter;
+use sea_orm::{ConnectOptions, Database, DatabaseConnection};
+use server_config::get_cert;
+use tokio::{signal, task::JoinHandle};
+use tokio_cron_scheduler::{Job, JobScheduler};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+pub type TokenCounter = Arc<AtomicU64>;
+pub type HttpClient = reqwest::Client;
+pub type PubsubClient = Arc<google_cloud_pubsub::client::Client>;
+
+#[derive(Clone, FromRef)]
+pub struct ServerState {
+    http_client: HttpClient,
+    conn: DatabaseConnection,
+    rate_limiters: RateLimiters,
+    session_store: AuthSessionStore,
+    pub priority_queue: PromptPriorityQueue,
+    notifier: SharedNotifier,
+    cluster: ClusterMembership,
+}
+
+/// Starts the API server and the background jobs, returns once the server shuts down
+pub async fn run() -> anyhow::Result<()> {
+    env::set_var("RUST_LOG", "info");
+    dotenvy::dotenv().ok();
+    let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set in .env file");
+    let mut db_options = ConnectOptions::new(db_url);
+    db_options.sqlx_logging(false);
+
+    let conn = Database::connect(db_options)
+        .await
+        .expect("Database connection failed");
+
+    let cert = get_cert();
+    let http_client = reqwest::ClientBuilder::new()
+        .use_rustls_tls()
+        .add_root_certificate(Certificate::from_pem(&cert)?)
+        .build()?;
+    let session_store = AuthSessionStore::new();
+
+    let state = ServerState {
+        notifier: notify::notifier::from_config(http_client.clone()),
+        priority_queue: PromptPriorityQueue::new(conn.clone()),
+        rate_limiters: RateLimiters::from_config(conn.clone()),
+        http_client,
+        conn,
+        session_store,
+        cluster: ClusterMembership::new(),
+    };
+
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::from_env("RUST_LOG"))
+        .with(tracing_subscriber::fmt::Layer::default().with_ansi(false))
+        .init();
+
+    let router = AppRouter::create(state.clone());
+    let email_processing_map = ActiveEmailProcessorMap::new(state.clone());
+    let processing_drain = ProcessingDrain::new();
+    let queue_refresh_handle =
+        email::tasks::run_prompt_queue_refresh_loop(state.priority_queue.clone());
+    let processing_watch_handle = email::tasks::watch(
+        state.priority_queue.clone(),
+        email_processing_map.clone(),
+        state.rate_limiters.clone(),
+    );
+
+    let mut scheduler = JobScheduler::new()
+        .await
+        .expect("Failed to create scheduler");
+
+    {
+        let state_clone = state.clone();
+        let map = email_processing_map.clone();
+        scheduler
+            .add(Job::new_one_shot_async(
+                Duration::from_secs(1),
+                move |uuid, l| {
+                    create_processors_for_users(uuid, l, state_clone.clone(), map.clone())
+                },
+            )?)
+            .await?;
+
+        let queue = state.priority_queue.clone();
+        let map = email_processing_map.clone();
+        let drain = processing_drain.clone();
+        scheduler
+            .add(Job::new_one_shot(
+                Duration::from_secs(2),
+                move |_uuid, _l| {
+                    email::tasks::run_email_processing_loop(
+                        queue.clone(),
+                        map.clone(),
+                        drain.clone(),
+                    );
+                },
+            )?)
+            .await?;
+
+        if server_config::cfg.reclassification.enabled {
+            let state_clone = state.clone();
+            scheduler
+                .add(Job::new_one_shot(
+                    Duration::from_secs(3),
+                    move |_uuid, _l| {
+                        email::reclassification::run_reclassification_loop(state_clone.clone());
+                    },
+                )?)
+                .await?;
+        }
+
+        let queue = state.priority_queue.clone();
+        let map = email_processing_map.clone();
+        scheduler
+            .add(Job::new_one_shot(
+                chrono::Duration::minutes(30).to_std().unwrap(),
+                move |_uuid, _l| {
+                    email::tasks::run_processor_cleanup_loop(queue.clone(), map.clone());
+                },
+            )?)
+            .await?;
+
+        let state_clone = state.clone();
+        let map = email_processing_map.clone();
+        // Start of every minute, create processors for the active users this instance owns.
+        // Runs on every instance, users are sharded between them
+        scheduler
+            .add(Job::new_async("0 * * * * *", move |uuid, l| {
+                create_processors_for_users(uuid, l, state_clone.clone(), map.clone())
+            })?)
+            .await?;
+
+        let http_client = state.http_client.clone();
+        let conn = state.conn.clone();
+        let rate_limiters = state.rate_limiters.clone();
+        let cluster = state.cluster.clone();
+        // Start of every hour, run auto email cleanup on the leader
+        scheduler
+            .add(Job::new_async("0 0 * * * *", move |uuid, mut l| {
+                let http_client = http_client.clone();
+                let conn = conn.clone();
+                let rate_limiters = rate_limiters.clone();
+                let cluster = cluster.clone();
+                Box::pin(async move {
+                    if !cluster.leader.is_leader() {
+                        tracing::info!(
+                            "Skipping auto cleanup job {}, {} is not the leader",
+                            uuid,
+                            cluster.instance_id
+                        );
+                        return;
+                    }
+                    tracing::info!("Running auto cleanup job {}", uuid);
+                    match email::tasks::run_auto_email_cleanup(http_client, conn, rate_limiters)
+                        .await
+                    {
+                        Ok(_) => {
+                            tracing::info!("Auto cleanup job {} succeeded", uuid);
+                        }
+                        Err(e) => {
+                            tracing::error!("Failed to run auto cleanup: {:?}", e);
+                        }
+                    }
+
+                    let next_tick = l.next_tick_for_job(uuid).await;
+                    if let Ok(Some(ts)) = next_tick {
+                        tracing::info!("Next time for auto cleanup job is {:?}", ts)
+                    }
+                })
+            })?)
+            .await?;
+
+        // Cleanup session storage, sessions are kept in memory so every instance cleans its own
+        let state_clone = state.clone();
+        scheduler
+            .add(Job::new_repeated(
+                Duration::from_secs(3 * 60),
+                move |_uuid, _lock| {
+                    state_clone.session_store.clean_store();
+                },
+            )?)
+            .await?;
+    }
+
+    scheduler.shutdown_on_ctrl_c();
+
+    scheduler.set_shutdown_handler(Box::new(move || {
+        Box::pin(async move {
+            tracing::info!("Shutting down scheduler");
+        })
+    }));
+
+    if env::var("SERVER_ONLY").map_or(false, |v| v == "true") {
+        tracing::info!("-------- RUNNING SERVER ONLY --------");
+        // Handle Ctrl+C
+        join_all([run_server(
+            router,
+            scheduler,
+            state,
+            email_processing_map,
+            processing_drain,
+        )])
+        .await;
+        return Ok(());
+    }
+
+    // Join the cluster before creating processors, so this instance knows which users it owns
+    if let Err(e) = state.cluster.heartbeat(&state.conn).await {
+        tracing::error!("Failed to register server instance: {:?}", e);
+    }
+    let cluster_heartbeat_handle =
+        email::tasks::run_cluster_heartbeat_loop(state.clone(), email_processing_map.clone());
+    let leader_election_handle = email::tasks::run_leader_election_loop(state.clone());
+
+    match scheduler.start().await {
+        Ok(_) => {
+            tracing::info!("Scheduler started");
+        }
+        Err(e) => {
+            tracing::error!("Failed to start scheduler: {:?}", e);
+        }
+    }
+
+    for join in join_all(vec![
+        run_server(
+            router,
+            scheduler,
+            state,
+            email_processing_map,
+            processing_drain,
+        ),
+        // inbox_subscription_handle,
+        processing_watch_handle,
+        queue_refresh_handle,
+        cluster_heartbeat_handle,
+        leader_election_handle,
+    ])
+    .await
+    {
+        join.unwrap();
+    }
+
+    Ok(())
+}
+
+async fn shutdown_signal(
+    mut scheduler: JobScheduler,
+    state: ServerState,
+    email_processing_map: ActiveEmailProcessorMap,
+    processing_drain: ProcessingDrain,
+) {
+    if env::var("NO_SHUTDOWN").unwrap_or("false".to_string()) == "true" {
+        return;
+    }
+
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    scheduler.shutdown().await.unwrap();
+
+    // Let emails already in a pipeline finish, so none are left labeled but unrecorded
+    let report = processing_drain
+        .drain(
+            Duration::from_secs(server_config::cfg.shutdown.drain_timeout_secs),
+            &state.priority_queue,
+            &email_processing_map,
+        )
+        .await;
+    if report.abandoned.is_empty() {
+        tracing::info!("Shutdown {}", report);
+    } else {
+        tracing::warn!("Shutdown {}", report);
+    }
+
+    // Hand this instance's users over to the others right away
+    if let Err(e) = state.cluster.leave(&state.conn).await {
+        tracing::error!("Failed to leave the cluster: {:?}", e);
+    }
+    println!("Cleanups done, shutting down");
+    std::process::exit(0);
+}
+
+fn run_server(
+    router: Router,
+    scheduler: JobScheduler,
+    state: ServerState,
+    email_processing_map: ActiveEmailProcessorMap,
+    processing_drain: ProcessingDrain,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        // Start the server
+        let port = env::var("PORT").unwrap_or("5006".to_string());
+        tracing::info!("Mailclerk server running on http://0.0.0.0:{}", port);
+        // check config
+        println!("{}", *server_config::cfg);
+
+        // run it with hyper
+        let addr = SocketAddr::from(([0, 0, 0, 0], port.parse::<u16>().unwrap()));
+        tracing::debug!("listening on {addr}");
+        let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        axum::serve(listener, router)
+            .with_graceful_shutdown(shutdown_signal(
+                scheduler,
+                state,
+                email_processing_map,
+                processing_drain,
+            ))
+            .await
+            .unwrap();
+    })
+}
+
+fn create_processors_for_users(
+    uuid: Uuid,
+    mut l: JobScheduler,
+    state: ServerState,
+    map: ActiveEmailProcessorMap,
+) -> Pin<Box<dyn Future<Output = ()> + Send + 'static>> {
+    let state = state.clone();
+    let map = map.clone();
+    tracing::info!("Job: {}\n Creating processors for active users...", uuid);
+    Box::pin(async move {
+        match email::tasks::add_users_to_processing(state, map.clone()).await {
+            Ok(_) => {
+                tracing::info!("Processor Creation Job {} succeeded", uuid);
+            }
+            Err(e) => {
+                tracing::error!("Job failed: {:?}", e);
+            }
+        }
+
+        let next_tick = l.next_tick_for_job(uuid).await;
+        if let Ok(Some(ts)) = next_tick {
+            tracing::info!("Next time for processor creation job is {:?}", ts)
+        }
+    })
+}
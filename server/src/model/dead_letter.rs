@@ -0,0 +1,78 @@
+use sea_orm::DatabaseConnection;
+
+use crate::{
+    db_core::prelude::*,
+    error::{AppError, AppResult},
+};
+
+pub struct DeadLetterCtrl;
+
+impl DeadLetterCtrl {
+    /// Newest first, for every user if `user_id` is not given
+    pub async fn all(
+        conn: &DatabaseConnection,
+        user_id: Option<i32>,
+    ) -> AppResult<Vec<dead_letter::Model>> {
+        let mut query = DeadLetter::find();
+        if let Some(user_id) = user_id {
+            query = query.filter(dead_letter::Column::UserId.eq(user_id));
+        }
+
+        let dead_letters = query
+            .order_by(dead_letter::Column::Id, Order::Desc)
+            .all(conn)
+            .await?;
+
+        Ok(dead_letters)
+    }
+
+    pub async fn count_by_user(conn: &DatabaseConnection, user_id: i32) -> AppResult<u64> {
+        let count = DeadLetter::find()
+            .filter(dead_letter::Column::UserId.eq(user_id))
+            .count(conn)
+            .await?;
+
+        Ok(count)
+    }
+
+    pub async fn insert(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        email_id: &str,
+        attempts: i32,
+        last_error: &str,
+    ) -> AppResult<()> {
+        DeadLetter::insert(dead_letter::ActiveModel {
+            id: ActiveValue::NotSet,
+            user_id: ActiveValue::Set(user_id),
+            email_id: ActiveValue::Set(email_id.to_string()),
+            attempts: ActiveValue::Set(attempts),
+            last_error: ActiveValue::Set(last_error.to_string()),
+            created_at: ActiveValue::NotSet,
+        })
+        .on_conflict(
+            OnConflict::columns([dead_letter::Column::UserId, dead_letter::Column::EmailId])
+                .update_columns([
+                    dead_letter::Column::Attempts,
+                    dead_letter::Column::LastError,
+                ])
+                .to_owned(),
+        )
+        .exec_without_returning(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Removes the dead letter so the email can be queued again
+    pub async fn take(conn: &DatabaseConnection, id: i32) -> AppResult<dead_letter::Model> {
+        let dead_letter = DeadLetter::find_by_id(id)
+            .one(conn)
+            .await?
+            .ok_or(AppError::NotFound("Dead letter not found".to_string()))?;
+
+        DeadLetter::delete_by_id(id).exec(conn).await?;
+
+        Ok(dead_letter)
+    }
+}
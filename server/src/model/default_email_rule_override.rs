@@ -1,7 +1,30 @@
+use std::collections::HashSet;
+
 use chrono::Utc;
 use sea_orm::DatabaseConnection;
 
-use crate::{db_core::prelude::*, error::AppResult};
+use crate::{db_core::prelude::*, email::label_template, error::AppResult, server_config::cfg};
+
+/// Mail labels of the categories a user wants to be alerted about
+#[derive(Debug, Clone, Default)]
+pub struct ImportantCategories(HashSet<String>);
+
+impl ImportantCategories {
+    pub fn new(mail_labels: HashSet<String>) -> Self {
+        Self(mail_labels)
+    }
+
+    /// Whether the label is an important category, or was rendered from a templated one
+    pub fn contains(&self, label: &str) -> bool {
+        self.0
+            .iter()
+            .any(|mail_label| label_template::label_matches(mail_label, label))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.0.iter()
+    }
+}
 
 pub struct DefaultEmailRuleOverrideCtrl;
 
@@ -36,4 +59,93 @@ impl DefaultEmailRuleOverrideCtrl {
 
         Ok(latest)
     }
+
+    /// Categories marked important in the config
+    pub fn default_important() -> HashSet<String> {
+        cfg.categories
+            .iter()
+            .filter(|c| c.important == Some(true))
+            .map(|c| c.mail_label.clone())
+            .collect()
+    }
+
+    /// The config's important categories with the user's overrides applied
+    pub async fn important_categories(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<ImportantCategories> {
+        let overrides = DefaultEmailRuleOverride::find()
+            .filter(default_email_rule_override::Column::UserId.eq(user_id))
+            .filter(default_email_rule_override::Column::IsImportant.is_not_null())
+            .all(conn)
+            .await?;
+
+        let mut important = Self::default_important();
+        for ro in overrides {
+            match ro.is_important {
+                Some(true) if !ro.is_disabled => {
+                    important.insert(ro.category);
+                }
+                _ => {
+                    important.remove(&ro.category);
+                }
+            }
+        }
+
+        Ok(ImportantCategories::new(important))
+    }
+
+    /// Sets whether a default category is important for the user, `None` goes back to the
+    /// config's default
+    pub async fn set_important(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        category: &str,
+        is_important: Option<bool>,
+    ) -> AppResult<default_email_rule_override::Model> {
+        let model = DefaultEmailRuleOverride::insert(default_email_rule_override::ActiveModel {
+            id: ActiveValue::NotSet,
+            user_id: ActiveValue::Set(user_id),
+            category: ActiveValue::Set(category.to_string()),
+            is_disabled: ActiveValue::NotSet,
+            created_at: ActiveValue::NotSet,
+            updated_at: ActiveValue::Set(Utc::now().into()),
+            associated_email_client_category: ActiveValue::NotSet,
+            is_important: ActiveValue::Set(is_important),
+        })
+        .on_conflict(
+            OnConflict::columns([
+                default_email_rule_override::Column::Category,
+                default_email_rule_override::Column::UserId,
+            ])
+            .update_columns([
+                default_email_rule_override::Column::IsImportant,
+                default_email_rule_override::Column::UpdatedAt,
+            ])
+            .to_owned(),
+        )
+        .exec_with_returning(conn)
+        .await?;
+
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_templated_important_categories() {
+        let important = ImportantCategories::new(HashSet::from([
+            "security/{{ sender_domain }}".to_string(),
+            "verification_code".to_string(),
+        ]));
+
+        assert!(important.contains("verification_code"));
+        assert!(important.contains("security/bank.com"));
+        assert!(important.contains("security"));
+        assert!(!important.contains("verification_code/nested"));
+        assert!(!important.contains("ads"));
+    }
 }
@@ -0,0 +1,162 @@
+use chrono::Utc;
+use sea_orm::DatabaseConnection;
+use serde::Deserialize;
+
+use crate::{
+    db_core::prelude::*,
+    email::rule_engine::{self, CategoryAction, RuleAction, RuleDefinition},
+    error::{AppError, AppResult},
+};
+
+/// Deterministic rule contents, as sent by the API
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeterministicRuleInput {
+    pub name: String,
+    #[serde(default)]
+    pub priority: i32,
+    pub condition: rule_engine::Condition,
+    pub mail_label: String,
+    #[serde(default)]
+    pub is_disabled: bool,
+}
+
+impl DeterministicRuleInput {
+    /// Deterministic rules always apply their label without prompting
+    pub fn to_definition(&self) -> RuleDefinition {
+        RuleDefinition {
+            name: self.name.clone(),
+            priority: self.priority,
+            when: self.condition.clone(),
+            unless: None,
+            except_categories: vec![],
+            action: RuleAction::SkipLlm(CategoryAction {
+                mail_label: self.mail_label.clone(),
+                gmail_category: None,
+            }),
+        }
+    }
+}
+
+pub struct DeterministicEmailRuleCtrl;
+
+impl DeterministicEmailRuleCtrl {
+    pub async fn all_by_user(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<Vec<deterministic_email_rule::Model>> {
+        let rules = DeterministicEmailRule::find()
+            .filter(deterministic_email_rule::Column::UserId.eq(user_id))
+            .order_by(deterministic_email_rule::Column::Priority, Order::Desc)
+            .order_by(deterministic_email_rule::Column::Id, Order::Asc)
+            .all(conn)
+            .await?;
+
+        Ok(rules)
+    }
+
+    /// Returns the user's enabled rules, skipping any whose stored condition no longer parses
+    pub async fn enabled_definitions(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<Vec<RuleDefinition>> {
+        let rules = Self::all_by_user(conn, user_id).await?;
+
+        Ok(rules
+            .into_iter()
+            .filter(|rule| !rule.is_disabled)
+            .filter_map(|rule| match Self::to_input(&rule) {
+                Ok(input) => Some(input.to_definition()),
+                Err(e) => {
+                    tracing::warn!("Skipping deterministic rule {}: {:?}", rule.id, e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    pub fn to_input(
+        rule: &deterministic_email_rule::Model,
+    ) -> Result<DeterministicRuleInput, serde_json::Error> {
+        Ok(DeterministicRuleInput {
+            name: rule.name.clone(),
+            priority: rule.priority,
+            condition: serde_json::from_value(rule.condition.clone())?,
+            mail_label: rule.mail_label.clone(),
+            is_disabled: rule.is_disabled,
+        })
+    }
+
+    pub async fn create(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        input: DeterministicRuleInput,
+    ) -> AppResult<deterministic_email_rule::Model> {
+        let rule = DeterministicEmailRule::insert(deterministic_email_rule::ActiveModel {
+            id: ActiveValue::NotSet,
+            user_id: ActiveValue::Set(user_id),
+            name: ActiveValue::Set(input.name),
+            priority: ActiveValue::Set(input.priority),
+            condition: ActiveValue::Set(condition_json(&input.condition)?),
+            mail_label: ActiveValue::Set(input.mail_label),
+            is_disabled: ActiveValue::Set(input.is_disabled),
+            created_at: ActiveValue::NotSet,
+            updated_at: ActiveValue::NotSet,
+        })
+        .exec_with_returning(conn)
+        .await?;
+
+        Ok(rule)
+    }
+
+    pub async fn update(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        id: i32,
+        input: DeterministicRuleInput,
+    ) -> AppResult<deterministic_email_rule::Model> {
+        let existing = Self::get_by_id(conn, user_id, id).await?;
+
+        let mut active_model: deterministic_email_rule::ActiveModel = existing.into();
+        active_model.name = ActiveValue::Set(input.name);
+        active_model.priority = ActiveValue::Set(input.priority);
+        active_model.condition = ActiveValue::Set(condition_json(&input.condition)?);
+        active_model.mail_label = ActiveValue::Set(input.mail_label);
+        active_model.is_disabled = ActiveValue::Set(input.is_disabled);
+        active_model.updated_at = ActiveValue::Set(Utc::now().into());
+
+        let rule = active_model.update(conn).await?;
+
+        Ok(rule)
+    }
+
+    pub async fn delete(conn: &DatabaseConnection, user_id: i32, id: i32) -> AppResult<()> {
+        let result = DeterministicEmailRule::delete_many()
+            .filter(deterministic_email_rule::Column::UserId.eq(user_id))
+            .filter(deterministic_email_rule::Column::Id.eq(id))
+            .exec(conn)
+            .await?;
+
+        if result.rows_affected == 0 {
+            return Err(AppError::NotFound("Rule not found".to_string()));
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_by_id(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        id: i32,
+    ) -> AppResult<deterministic_email_rule::Model> {
+        DeterministicEmailRule::find()
+            .filter(deterministic_email_rule::Column::UserId.eq(user_id))
+            .filter(deterministic_email_rule::Column::Id.eq(id))
+            .one(conn)
+            .await?
+            .ok_or(AppError::NotFound("Rule not found".to_string()))
+    }
+}
+
+fn condition_json(condition: &rule_engine::Condition) -> AppResult<Json> {
+    serde_json::to_value(condition).map_err(|e| AppError::Internal(e.into()))
+}
@@ -0,0 +1,157 @@
+use sea_orm::{DatabaseConnection, DbBackend};
+use serde::Serialize;
+
+use crate::{db_core::prelude::*, error::AppResult};
+
+pub struct EmailCorrectionCtrl;
+
+impl EmailCorrectionCtrl {
+    pub async fn all_by_user(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<Vec<email_correction::Model>> {
+        let corrections = EmailCorrection::find()
+            .filter(email_correction::Column::UserId.eq(user_id))
+            .order_by(email_correction::Column::UpdatedAt, Order::Asc)
+            .all(conn)
+            .await?;
+
+        Ok(corrections)
+    }
+
+    /// Inserts a correction, replacing the corrected category if the user has
+    /// already corrected the same email before
+    pub async fn upsert(
+        conn: &DatabaseConnection,
+        active_model: email_correction::ActiveModel,
+    ) -> AppResult<email_correction::Model> {
+        let correction = EmailCorrection::insert(active_model)
+            .on_conflict(
+                OnConflict::columns([
+                    email_correction::Column::UserId,
+                    email_correction::Column::EmailId,
+                ])
+                .update_columns([
+                    email_correction::Column::CorrectedCategory,
+                    email_correction::Column::UpdatedAt,
+                ])
+                .to_owned(),
+            )
+            .exec_with_returning(conn)
+            .await?;
+
+        Ok(correction)
+    }
+
+    pub async fn accuracy_stats(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<UserAccuracyStats> {
+        let categories = CategoryAccuracy::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"
+                SELECT
+                    pe.category,
+                    COUNT(pe.id) AS processed,
+                    COUNT(ec.id) AS corrected
+                FROM processed_email AS pe
+                LEFT JOIN email_correction AS ec
+                    ON ec.user_id = pe.user_id AND ec.email_id = pe.id
+                WHERE pe.user_id = $1
+                GROUP BY pe.category
+                ORDER BY processed DESC
+            "#,
+            [user_id.into()],
+        ))
+        .all(conn)
+        .await?;
+
+        Ok(UserAccuracyStats::from_categories(categories))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, FromQueryResult)]
+pub struct CategoryAccuracy {
+    pub category: String,
+    pub processed: i64,
+    pub corrected: i64,
+}
+
+impl CategoryAccuracy {
+    pub fn accuracy(&self) -> f64 {
+        if self.processed == 0 {
+            return 1.0;
+        }
+        1.0 - (self.corrected as f64 / self.processed as f64)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UserAccuracyStats {
+    pub processed: i64,
+    pub corrected: i64,
+    pub accuracy: f64,
+    pub categories: Vec<CategoryAccuracyStats>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CategoryAccuracyStats {
+    pub category: String,
+    pub processed: i64,
+    pub corrected: i64,
+    pub accuracy: f64,
+}
+
+impl UserAccuracyStats {
+    fn from_categories(categories: Vec<CategoryAccuracy>) -> Self {
+        let processed = categories.iter().map(|c| c.processed).sum::<i64>();
+        let corrected = categories.iter().map(|c| c.corrected).sum::<i64>();
+        let accuracy = if processed == 0 {
+            1.0
+        } else {
+            1.0 - (corrected as f64 / processed as f64)
+        };
+
+        Self {
+            processed,
+            corrected,
+            accuracy,
+            categories: categories
+                .into_iter()
+                .map(|c| CategoryAccuracyStats {
+                    accuracy: c.accuracy(),
+                    category: c.category,
+                    processed: c.processed,
+                    corrected: c.corrected,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accuracy_stats_from_categories() {
+        let stats = UserAccuracyStats::from_categories(vec![
+            CategoryAccuracy {
+                category: "ads".to_string(),
+                processed: 8,
+                corrected: 2,
+            },
+            CategoryAccuracy {
+                category: "receipts".to_string(),
+                processed: 2,
+                corrected: 0,
+            },
+        ]);
+
+        assert_eq!(stats.processed, 10);
+        assert_eq!(stats.corrected, 2);
+        assert!((stats.accuracy - 0.8).abs() < f64::EPSILON);
+        assert!((stats.categories[0].accuracy - 0.75).abs() < f64::EPSILON);
+        assert!((stats.categories[1].accuracy - 1.0).abs() < f64::EPSILON);
+    }
+}
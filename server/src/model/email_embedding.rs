@@ -0,0 +1,109 @@
+use chrono::Utc;
+use sea_orm::DatabaseConnection;
+
+use crate::{db_core::prelude::*, error::AppResult};
+
+pub struct EmailEmbeddingCtrl;
+
+impl EmailEmbeddingCtrl {
+    /// Returns the user's most recent confirmed embeddings, oldest first
+    pub async fn recent_by_user(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        limit: u64,
+    ) -> AppResult<Vec<email_embedding::Model>> {
+        let mut embeddings = EmailEmbedding::find()
+            .filter(email_embedding::Column::UserId.eq(user_id))
+            .filter(email_embedding::Column::Embedding.is_not_null())
+            .filter(email_embedding::Column::Confirmed.eq(true))
+            .order_by(email_embedding::Column::CreatedAt, Order::Desc)
+            .limit(limit)
+            .all(conn)
+            .await?;
+
+        embeddings.reverse();
+
+        Ok(embeddings)
+    }
+
+    /// Stores an email's embedding. Unconfirmed embeddings are kept out of the index until
+    /// the user corrects their label.
+    pub async fn upsert(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        email_id: String,
+        category: String,
+        model: String,
+        vector: Vec<f32>,
+        confirmed: bool,
+    ) -> AppResult<()> {
+        EmailEmbedding::insert(email_embedding::ActiveModel {
+            id: ActiveValue::NotSet,
+            user_id: ActiveValue::Set(user_id),
+            email_id: ActiveValue::Set(email_id),
+            category: ActiveValue::Set(category),
+            model: ActiveValue::Set(model),
+            embedding: ActiveValue::Set(Some(vector)),
+            confirmed: ActiveValue::Set(confirmed),
+            created_at: ActiveValue::NotSet,
+            updated_at: ActiveValue::Set(Utc::now().into()),
+        })
+        .on_conflict(
+            OnConflict::columns([
+                email_embedding::Column::UserId,
+                email_embedding::Column::EmailId,
+            ])
+            .update_columns([
+                email_embedding::Column::Category,
+                email_embedding::Column::Model,
+                email_embedding::Column::Embedding,
+                email_embedding::Column::Confirmed,
+                email_embedding::Column::UpdatedAt,
+            ])
+            .to_owned(),
+        )
+        .exec(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Moves a stored embedding to a new label, returning the updated row. Emails whose label
+    /// was only removed no longer have a known category, so they are dropped.
+    pub async fn relabel(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        email_id: &str,
+        category: Option<&str>,
+        confirmed: bool,
+    ) -> AppResult<Option<email_embedding::Model>> {
+        let filter = Condition::all()
+            .add(email_embedding::Column::UserId.eq(user_id))
+            .add(email_embedding::Column::EmailId.eq(email_id));
+
+        match category {
+            Some(category) => {
+                let updated = EmailEmbedding::update_many()
+                    .col_expr(email_embedding::Column::Category, Expr::value(category))
+                    .col_expr(email_embedding::Column::Confirmed, Expr::value(confirmed))
+                    .col_expr(
+                        email_embedding::Column::UpdatedAt,
+                        Expr::current_timestamp().into(),
+                    )
+                    .filter(filter)
+                    .exec_with_returning(conn)
+                    .await?;
+
+                Ok(updated.into_iter().next())
+            }
+            None => {
+                EmailEmbedding::delete_many()
+                    .filter(filter)
+                    .exec(conn)
+                    .await?;
+
+                Ok(None)
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use chrono::Utc;
+use sea_orm::{DatabaseConnection, DbBackend};
+use serde::Deserialize;
+
+use crate::{
+    db_core::prelude::*,
+    error::{AppError, AppResult},
+};
+
+/// Pinned example contents, as sent by the API
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmailExampleInput {
+    pub category: String,
+    pub from: Option<String>,
+    pub subject: Option<String>,
+    pub body_snippet: Option<String>,
+}
+
+/// Changes whenever one of a user's examples is created, updated or deleted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromQueryResult)]
+pub struct EmailExampleWatermark {
+    pub count: i64,
+    pub last_updated: Option<DateTimeWithTimeZone>,
+}
+
+pub struct EmailExampleCtrl;
+
+impl EmailExampleCtrl {
+    pub async fn all_by_user(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<Vec<email_example::Model>> {
+        let examples = EmailExample::find()
+            .filter(email_example::Column::UserId.eq(user_id))
+            .order_by(email_example::Column::Id, Order::Asc)
+            .all(conn)
+            .await?;
+
+        Ok(examples)
+    }
+
+    pub async fn watermark(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<EmailExampleWatermark> {
+        let watermark = EmailExampleWatermark::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"
+                SELECT COUNT(*) AS count, MAX(updated_at) AS last_updated
+                FROM email_example
+                WHERE user_id = $1
+            "#,
+            [user_id.into()],
+        ))
+        .one(conn)
+        .await?
+        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("Missing email example watermark")))?;
+
+        Ok(watermark)
+    }
+
+    pub async fn create(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        input: EmailExampleInput,
+    ) -> AppResult<email_example::Model> {
+        let example = EmailExample::insert(email_example::ActiveModel {
+            id: ActiveValue::NotSet,
+            user_id: ActiveValue::Set(user_id),
+            category: ActiveValue::Set(input.category),
+            from: ActiveValue::Set(input.from),
+            subject: ActiveValue::Set(input.subject),
+            body_snippet: ActiveValue::Set(input.body_snippet),
+            created_at: ActiveValue::NotSet,
+            updated_at: ActiveValue::NotSet,
+        })
+        .exec_with_returning(conn)
+        .await?;
+
+        Ok(example)
+    }
+
+    pub async fn update(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        id: i32,
+        input: EmailExampleInput,
+    ) -> AppResult<email_example::Model> {
+        let existing = Self::get_by_id(conn, user_id, id).await?;
+
+        let mut active_model: email_example::ActiveModel = existing.into();
+        active_model.category = ActiveValue::Set(input.category);
+        active_model.from = ActiveValue::Set(input.from);
+        active_model.subject = ActiveValue::Set(input.subject);
+        active_model.body_snippet = ActiveValue::Set(input.body_snippet);
+        active_model.updated_at = ActiveValue::Set(Utc::now().into());
+
+        let example = active_model.update(conn).await?;
+
+        Ok(example)
+    }
+
+    pub async fn delete(conn: &DatabaseConnection, user_id: i32, id: i32) -> AppResult<()> {
+        let result = EmailExample::delete_many()
+            .filter(email_example::Column::UserId.eq(user_id))
+            .filter(email_example::Column::Id.eq(id))
+            .exec(conn)
+            .await?;
+
+        if result.rows_affected == 0 {
+            return Err(AppError::NotFound("Example not found".to_string()));
+        }
+
+        Ok(())
+    }
+
+    async fn get_by_id(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        id: i32,
+    ) -> AppResult<email_example::Model> {
+        EmailExample::find()
+            .filter(email_example::Column::UserId.eq(user_id))
+            .filter(email_example::Column::Id.eq(id))
+            .one(conn)
+            .await?
+            .ok_or(AppError::NotFound("Example not found".to_string()))
+    }
+}
@@ -0,0 +1,261 @@
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::DatabaseConnection;
+
+use crate::{
+    db_core::prelude::*,
+    email::client::GmailApiError,
+    error::{AppError, AppResult},
+    model::dead_letter::DeadLetterCtrl,
+    server_config::{cfg, EmailRetryConfig},
+};
+
+/// Errors are stored for inspection, long chains are cut off
+const MAX_ERROR_LENGTH: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureOutcome {
+    RetryAt(DateTime<Utc>),
+    DeadLettered,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// Outages, rate limits and timeouts, the email is retried without using up an attempt
+    Transient,
+    /// Failures that will happen again, e.g. an email that can't be parsed or labeled
+    Permanent,
+}
+
+impl FailureKind {
+    fn of_status(status: reqwest::StatusCode) -> Self {
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            || status == reqwest::StatusCode::REQUEST_TIMEOUT
+            || status.is_server_error()
+        {
+            FailureKind::Transient
+        } else {
+            FailureKind::Permanent
+        }
+    }
+
+    fn of_app_error(error: &AppError) -> Option<Self> {
+        match error {
+            AppError::Internal(e) => Self::find(e),
+            AppError::DbError(_) | AppError::RequestTimeout | AppError::TooManyRequests => {
+                Some(FailureKind::Transient)
+            }
+            _ => None,
+        }
+    }
+
+    fn find(error: &anyhow::Error) -> Option<Self> {
+        error.chain().find_map(|cause| {
+            if let Some(e) = cause.downcast_ref::<GmailApiError>() {
+                if e.rate_limited {
+                    return Some(FailureKind::Transient);
+                }
+                return Some(Self::of_status(e.status));
+            }
+            if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+                return match e.status() {
+                    Some(status) => Some(Self::of_status(status)),
+                    None if e.is_timeout() || e.is_connect() || e.is_request() => {
+                        Some(FailureKind::Transient)
+                    }
+                    None => None,
+                };
+            }
+            if cause.downcast_ref::<DbErr>().is_some() {
+                return Some(FailureKind::Transient);
+            }
+            cause
+                .downcast_ref::<AppError>()
+                .and_then(Self::of_app_error)
+        })
+    }
+
+    /// Errors that aren't recognised as transient count as permanent, so an email that
+    /// keeps failing is dead lettered eventually
+    pub fn of(error: &anyhow::Error) -> Self {
+        Self::find(error).unwrap_or(FailureKind::Permanent)
+    }
+}
+
+/// `base_secs` doubled for every attempt after the first, up to `max_secs`
+pub fn exponential_delay(attempts: i32, base_secs: i64, max_secs: i64) -> Duration {
+    let doublings = attempts.saturating_sub(1).clamp(0, 30) as u32;
+    let delay_secs = base_secs
+        .saturating_mul(2_i64.saturating_pow(doublings))
+        .min(max_secs);
+
+    Duration::seconds(delay_secs)
+}
+
+/// How long to wait before retrying an email that failed `attempts` times
+pub fn retry_delay(attempts: i32, retry_cfg: &EmailRetryConfig) -> Duration {
+    exponential_delay(
+        attempts,
+        retry_cfg.base_delay_secs,
+        retry_cfg.max_delay_secs,
+    )
+}
+
+pub(crate) fn truncate_error(error: &str) -> String {
+    match error.char_indices().nth(MAX_ERROR_LENGTH) {
+        Some((i, _)) => error[..i].to_string(),
+        None => error.to_string(),
+    }
+}
+
+pub struct EmailFailureCtrl;
+
+impl EmailFailureCtrl {
+    /// Records a failed attempt. The email is retried after an exponential delay, and dead
+    /// lettered once it runs out of attempts. Transient failures don't use up an attempt.
+    pub async fn record(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        email_id: &str,
+        error: &str,
+        kind: FailureKind,
+    ) -> AppResult<FailureOutcome> {
+        let error = truncate_error(error);
+        let previous_attempts = EmailFailure::find()
+            .filter(email_failure::Column::UserId.eq(user_id))
+            .filter(email_failure::Column::EmailId.eq(email_id))
+            .one(conn)
+            .await?
+            .map_or(0, |f| f.attempts);
+        let attempts = match kind {
+            FailureKind::Transient => previous_attempts,
+            FailureKind::Permanent => previous_attempts + 1,
+        };
+
+        if attempts >= cfg.email_retries.max_attempts {
+            DeadLetterCtrl::insert(conn, user_id, email_id, attempts, &error).await?;
+            Self::clear(conn, user_id, email_id).await?;
+            return Ok(FailureOutcome::DeadLettered);
+        }
+
+        let now = Utc::now();
+        let next_retry_at = now + retry_delay(attempts.max(1), &cfg.email_retries);
+        EmailFailure::insert(email_failure::ActiveModel {
+            id: ActiveValue::NotSet,
+            user_id: ActiveValue::Set(user_id),
+            email_id: ActiveValue::Set(email_id.to_string()),
+            attempts: ActiveValue::Set(attempts),
+            last_error: ActiveValue::Set(error),
+            next_retry_at: ActiveValue::Set(next_retry_at.into()),
+            created_at: ActiveValue::NotSet,
+            updated_at: ActiveValue::Set(now.into()),
+        })
+        .on_conflict(
+            OnConflict::columns([
+                email_failure::Column::UserId,
+                email_failure::Column::EmailId,
+            ])
+            .update_columns([
+                email_failure::Column::Attempts,
+                email_failure::Column::LastError,
+                email_failure::Column::NextRetryAt,
+                email_failure::Column::UpdatedAt,
+            ])
+            .to_owned(),
+        )
+        .exec_without_returning(conn)
+        .await?;
+
+        Ok(FailureOutcome::RetryAt(next_retry_at))
+    }
+
+    pub async fn clear(conn: &DatabaseConnection, user_id: i32, email_id: &str) -> AppResult<()> {
+        EmailFailure::delete_many()
+            .filter(email_failure::Column::UserId.eq(user_id))
+            .filter(email_failure::Column::EmailId.eq(email_id))
+            .exec(conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        let retry_cfg = EmailRetryConfig {
+            max_attempts: 5,
+            base_delay_secs: 60,
+            max_delay_secs: 600,
+        };
+
+        assert_eq!(retry_delay(1, &retry_cfg), Duration::seconds(60));
+        assert_eq!(retry_delay(2, &retry_cfg), Duration::seconds(120));
+        assert_eq!(retry_delay(4, &retry_cfg), Duration::seconds(480));
+        assert_eq!(retry_delay(5, &retry_cfg), Duration::seconds(600));
+        assert_eq!(retry_delay(1000, &retry_cfg), Duration::seconds(600));
+    }
+
+    #[test]
+    fn test_failure_kind() {
+        let gmail_error = |status: reqwest::StatusCode| {
+            anyhow::Error::new(GmailApiError::new(status, String::new()))
+        };
+
+        assert_eq!(
+            FailureKind::of(&gmail_error(reqwest::StatusCode::TOO_MANY_REQUESTS)),
+            FailureKind::Transient
+        );
+        assert_eq!(
+            FailureKind::of(&gmail_error(reqwest::StatusCode::SERVICE_UNAVAILABLE)),
+            FailureKind::Transient
+        );
+        assert_eq!(
+            FailureKind::of(
+                &gmail_error(reqwest::StatusCode::BAD_REQUEST).context("Error labeling email")
+            ),
+            FailureKind::Permanent
+        );
+        assert_eq!(
+            FailureKind::of(&anyhow::Error::new(AppError::DbError(DbErr::Conn(
+                RuntimeErr::Internal("connection refused".to_string())
+            )))),
+            FailureKind::Transient
+        );
+        assert_eq!(
+            FailureKind::of(&anyhow::anyhow!("Could not parse email")),
+            FailureKind::Permanent
+        );
+    }
+
+    #[test]
+    fn test_failure_kind_rate_limited_403() {
+        let rate_limited = GmailApiError::new(
+            reqwest::StatusCode::FORBIDDEN,
+            r#"{"error":{"code":403,"errors":[{"reason":"userRateLimitExceeded"}]}}"#.to_string(),
+        );
+        assert!(rate_limited.rate_limited);
+        assert_eq!(
+            FailureKind::of(&anyhow::Error::new(rate_limited).context("Error labeling email")),
+            FailureKind::Transient
+        );
+
+        let forbidden = GmailApiError::new(
+            reqwest::StatusCode::FORBIDDEN,
+            r#"{"error":{"code":403,"errors":[{"reason":"insufficientPermissions"}]}}"#.to_string(),
+        );
+        assert_eq!(
+            FailureKind::of(&anyhow::Error::new(forbidden)),
+            FailureKind::Permanent
+        );
+    }
+
+    #[test]
+    fn test_truncate_error() {
+        let error = "é".repeat(MAX_ERROR_LENGTH + 10);
+        assert_eq!(truncate_error(&error).chars().count(), MAX_ERROR_LENGTH);
+        assert_eq!(truncate_error("short"), "short");
+    }
+}
@@ -0,0 +1,93 @@
+use sea_orm::{DatabaseConnection, DbBackend};
+use serde::Serialize;
+
+use crate::{db_core::prelude::*, error::AppResult};
+
+pub struct EmailTrainingCtrl;
+
+impl EmailTrainingCtrl {
+    /// How often the shadow model agreed with the primary model, grouped by the primary
+    /// answer and by user
+    pub async fn shadow_agreement(
+        conn: &DatabaseConnection,
+        shadow_model_id: Option<String>,
+    ) -> AppResult<ShadowAgreementReport> {
+        let query = |group_column: &str| {
+            Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                format!(
+                    r#"
+                    SELECT
+                        {group_column} AS key,
+                        COUNT(*) AS total,
+                        COUNT(*) FILTER (WHERE shadow_answer = ai_answer) AS agreed,
+                        AVG(confidence)::float8 AS primary_confidence,
+                        AVG(shadow_confidence)::float8 AS shadow_confidence
+                    FROM email_training
+                    WHERE shadow_answer IS NOT NULL
+                        AND ($1::varchar IS NULL OR shadow_model_id = $1)
+                    GROUP BY {group_column}
+                    ORDER BY total DESC
+                    "#
+                ),
+                [shadow_model_id.clone().into()],
+            )
+        };
+
+        let by_category = ShadowAgreementRow::find_by_statement(query("ai_answer"))
+            .all(conn)
+            .await?;
+        let by_user = ShadowAgreementRow::find_by_statement(query("user_email"))
+            .all(conn)
+            .await?;
+
+        Ok(ShadowAgreementReport {
+            shadow_model_id,
+            by_category: by_category.into_iter().map(Into::into).collect(),
+            by_user: by_user.into_iter().map(Into::into).collect(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, FromQueryResult)]
+struct ShadowAgreementRow {
+    key: String,
+    total: i64,
+    agreed: i64,
+    primary_confidence: Option<f64>,
+    shadow_confidence: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShadowAgreement {
+    pub key: String,
+    pub total: i64,
+    pub agreed: i64,
+    pub agreement: f64,
+    pub primary_confidence: Option<f64>,
+    pub shadow_confidence: Option<f64>,
+}
+
+impl From<ShadowAgreementRow> for ShadowAgreement {
+    fn from(row: ShadowAgreementRow) -> Self {
+        Self {
+            agreement: if row.total == 0 {
+                0.0
+            } else {
+                row.agreed as f64 / row.total as f64
+            },
+            key: row.key,
+            total: row.total,
+            agreed: row.agreed,
+            primary_confidence: row.primary_confidence,
+            shadow_confidence: row.shadow_confidence,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShadowAgreementReport {
+    pub shadow_model_id: Option<String>,
+    pub by_category: Vec<ShadowAgreement>,
+    pub by_user: Vec<ShadowAgreement>,
+}
@@ -0,0 +1,61 @@
+use sea_orm::{Condition, DatabaseConnection};
+use serde::Deserialize;
+
+use crate::{
+    db_core::prelude::*,
+    email::rule_engine::{self, RuleAction, RuleDefinition},
+    error::AppResult,
+};
+
+/// Rule contents stored in the `definition` column
+#[derive(Debug, Deserialize)]
+struct StoredDefinition {
+    when: rule_engine::Condition,
+    #[serde(default)]
+    unless: Option<rule_engine::Condition>,
+    #[serde(default)]
+    except_categories: Vec<String>,
+    action: RuleAction,
+}
+
+pub struct HeuristicRuleCtrl;
+
+impl HeuristicRuleCtrl {
+    /// Returns the enabled global rules and the user's own rules. Rules with an invalid
+    /// definition are skipped so one bad row does not stop every processor.
+    pub async fn definitions_for_user(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<Vec<RuleDefinition>> {
+        let rules = HeuristicRule::find()
+            .filter(
+                Condition::any()
+                    .add(heuristic_rule::Column::UserId.is_null())
+                    .add(heuristic_rule::Column::UserId.eq(user_id)),
+            )
+            .filter(heuristic_rule::Column::IsDisabled.eq(false))
+            .order_by(heuristic_rule::Column::Id, Order::Asc)
+            .all(conn)
+            .await?;
+
+        Ok(rules
+            .into_iter()
+            .filter_map(
+                |rule| match serde_json::from_value::<StoredDefinition>(rule.definition) {
+                    Ok(definition) => Some(RuleDefinition {
+                        name: rule.name,
+                        priority: rule.priority,
+                        when: definition.when,
+                        unless: definition.unless,
+                        except_categories: definition.except_categories,
+                        action: definition.action,
+                    }),
+                    Err(e) => {
+                        tracing::warn!("Skipping heuristic rule {}: {:?}", rule.id, e);
+                        None
+                    }
+                },
+            )
+            .collect())
+    }
+}
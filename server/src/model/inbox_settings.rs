@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db_core::prelude::*,
+    email::{client::with_parents, label_template},
+    error::{AppError, AppResult},
+    server_config::cfg,
+};
+
+/// What happens to an email in the inbox once it is labeled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct InboxSetting {
+    pub skip_inbox: bool,
+    pub mark_spam: bool,
+}
+
+impl From<&inbox_settings::Model> for InboxSetting {
+    fn from(model: &inbox_settings::Model) -> Self {
+        Self {
+            skip_inbox: model.skip_inbox,
+            mark_spam: model.mark_spam,
+        }
+    }
+}
+
+/// Inbox settings keyed by mail label
+#[derive(Debug, Clone, Default)]
+pub struct CategoryInboxSettings(HashMap<String, InboxSetting>);
+
+impl CategoryInboxSettings {
+    pub fn new(settings: HashMap<String, InboxSetting>) -> Self {
+        Self(settings)
+    }
+
+    /// The setting of the label, or of the closest parent with one for nested and templated
+    /// labels
+    pub fn get(&self, mail_label: &str) -> InboxSetting {
+        with_parents(mail_label)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .find_map(|label| self.0.get(label))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &InboxSetting)> {
+        self.0.iter()
+    }
+}
+
+pub struct UserInboxSettingsCtrl;
+
+impl UserInboxSettingsCtrl {
+    /// Defaults from the config for the categories that set any
+    pub fn default() -> HashMap<String, InboxSetting> {
+        cfg.categories
+            .iter()
+            .filter(|c| c.skip_inbox || c.mark_spam)
+            .map(|c| {
+                (
+                    label_template::static_label(&c.mail_label).to_string(),
+                    InboxSetting {
+                        skip_inbox: c.skip_inbox,
+                        mark_spam: c.mark_spam,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    pub async fn all_by_user(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<Vec<inbox_settings::Model>> {
+        let settings = InboxSettings::find()
+            .filter(inbox_settings::Column::UserId.eq(user_id))
+            .order_by(inbox_settings::Column::Category, Order::Asc)
+            .all(conn)
+            .await?;
+
+        Ok(settings)
+    }
+
+    /// The user's settings keyed by mail label
+    pub async fn get(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<HashMap<String, InboxSetting>> {
+        let settings = Self::all_by_user(conn, user_id).await?;
+
+        Ok(settings
+            .iter()
+            .map(|s| (s.category.clone(), InboxSetting::from(s)))
+            .collect())
+    }
+
+    /// The defaults with the user's settings applied on top
+    pub async fn for_user(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<CategoryInboxSettings> {
+        let mut settings = Self::default();
+        settings.extend(Self::get(conn, user_id).await?);
+
+        Ok(CategoryInboxSettings::new(settings))
+    }
+
+    pub async fn upsert(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        category: &str,
+        setting: InboxSetting,
+    ) -> AppResult<inbox_settings::Model> {
+        let model = InboxSettings::insert(inbox_settings::ActiveModel {
+            id: ActiveValue::NotSet,
+            user_id: ActiveValue::Set(user_id),
+            category: ActiveValue::Set(category.to_string()),
+            skip_inbox: ActiveValue::Set(setting.skip_inbox),
+            mark_spam: ActiveValue::Set(setting.mark_spam),
+            created_at: ActiveValue::NotSet,
+            updated_at: ActiveValue::Set(Utc::now().into()),
+        })
+        .on_conflict(
+            OnConflict::columns([
+                inbox_settings::Column::Category,
+                inbox_settings::Column::UserId,
+            ])
+            .update_columns([
+                inbox_settings::Column::SkipInbox,
+                inbox_settings::Column::MarkSpam,
+                inbox_settings::Column::UpdatedAt,
+            ])
+            .to_owned(),
+        )
+        .exec_with_returning(conn)
+        .await?;
+
+        Ok(model)
+    }
+
+    pub async fn delete(conn: &DatabaseConnection, user_id: i32, category: &str) -> AppResult<()> {
+        let result = InboxSettings::delete_many()
+            .filter(inbox_settings::Column::UserId.eq(user_id))
+            .filter(inbox_settings::Column::Category.eq(category))
+            .exec(conn)
+            .await?;
+
+        if result.rows_affected == 0 {
+            return Err(AppError::NotFound("Inbox setting not found".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_labels_use_closest_setting() {
+        let skip = InboxSetting {
+            skip_inbox: true,
+            mark_spam: false,
+        };
+        let spam = InboxSetting {
+            skip_inbox: false,
+            mark_spam: true,
+        };
+        let settings = CategoryInboxSettings::new(HashMap::from([
+            ("Vendors".to_string(), skip),
+            ("Vendors/spammy.com".to_string(), spam),
+        ]));
+
+        assert_eq!(settings.get("Vendors"), skip);
+        assert_eq!(settings.get("Vendors/shop.com"), skip);
+        assert_eq!(settings.get("Vendors/spammy.com"), spam);
+        assert_eq!(settings.get("ads"), InboxSetting::default());
+    }
+}
@@ -0,0 +1,53 @@
+use std::collections::HashSet;
+
+use sea_orm::DatabaseConnection;
+
+use crate::{db_core::prelude::*, error::AppResult};
+
+pub struct LabelMigrationCtrl;
+
+impl LabelMigrationCtrl {
+    pub async fn all_by_user(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<Vec<label_migration::Model>> {
+        let migrations = LabelMigration::find()
+            .filter(label_migration::Column::UserId.eq(user_id))
+            .order_by(label_migration::Column::Id, Order::Asc)
+            .all(conn)
+            .await?;
+
+        Ok(migrations)
+    }
+
+    /// Labels that were already migrated away from in this mailbox
+    pub async fn migrated_labels(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<HashSet<String>> {
+        let migrations = Self::all_by_user(conn, user_id).await?;
+
+        Ok(migrations.into_iter().map(|m| m.from_label).collect())
+    }
+
+    pub async fn record(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        from_label: &str,
+        to_label: &str,
+        messages_moved: i32,
+    ) -> AppResult<label_migration::Model> {
+        let migration = LabelMigration::insert(label_migration::ActiveModel {
+            id: ActiveValue::NotSet,
+            user_id: ActiveValue::Set(user_id),
+            from_label: ActiveValue::Set(from_label.to_string()),
+            to_label: ActiveValue::Set(to_label.to_string()),
+            messages_moved: ActiveValue::Set(messages_moved),
+            created_at: ActiveValue::NotSet,
+        })
+        .exec_with_returning(conn)
+        .await?;
+
+        Ok(migration)
+    }
+}
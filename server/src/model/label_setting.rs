@@ -0,0 +1,77 @@
+use chrono::Utc;
+use sea_orm::DatabaseConnection;
+
+use crate::{
+    db_core::prelude::*,
+    error::{AppError, AppResult},
+};
+
+pub struct LabelSettingCtrl;
+
+impl LabelSettingCtrl {
+    pub async fn all_by_user(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<Vec<label_setting::Model>> {
+        let settings = LabelSetting::find()
+            .filter(label_setting::Column::UserId.eq(user_id))
+            .order_by(label_setting::Column::Category, Order::Asc)
+            .all(conn)
+            .await?;
+
+        Ok(settings)
+    }
+
+    /// Creates or replaces the user's setting for a category. Fields left as `None` keep the
+    /// default appearance.
+    pub async fn upsert(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        category: &str,
+        color: Option<String>,
+        label_list_visibility: Option<String>,
+        message_list_visibility: Option<String>,
+    ) -> AppResult<label_setting::Model> {
+        let setting = LabelSetting::insert(label_setting::ActiveModel {
+            id: ActiveValue::NotSet,
+            user_id: ActiveValue::Set(user_id),
+            category: ActiveValue::Set(category.to_string()),
+            color: ActiveValue::Set(color),
+            label_list_visibility: ActiveValue::Set(label_list_visibility),
+            message_list_visibility: ActiveValue::Set(message_list_visibility),
+            created_at: ActiveValue::NotSet,
+            updated_at: ActiveValue::Set(Utc::now().into()),
+        })
+        .on_conflict(
+            OnConflict::columns([
+                label_setting::Column::UserId,
+                label_setting::Column::Category,
+            ])
+            .update_columns([
+                label_setting::Column::Color,
+                label_setting::Column::LabelListVisibility,
+                label_setting::Column::MessageListVisibility,
+                label_setting::Column::UpdatedAt,
+            ])
+            .to_owned(),
+        )
+        .exec_with_returning(conn)
+        .await?;
+
+        Ok(setting)
+    }
+
+    pub async fn delete(conn: &DatabaseConnection, user_id: i32, category: &str) -> AppResult<()> {
+        let result = LabelSetting::delete_many()
+            .filter(label_setting::Column::UserId.eq(user_id))
+            .filter(label_setting::Column::Category.eq(category))
+            .exec(conn)
+            .await?;
+
+        if result.rows_affected == 0 {
+            return Err(AppError::NotFound("Label setting not found".to_string()));
+        }
+
+        Ok(())
+    }
+}
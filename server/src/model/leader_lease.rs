@@ -0,0 +1,67 @@
+use chrono::{DateTime, Utc};
+use sea_orm::{DatabaseConnection, DbBackend};
+
+use crate::{db_core::prelude::*, error::AppResult};
+
+pub struct LeaderLeaseCtrl;
+
+impl LeaderLeaseCtrl {
+    /// Takes the lease, or extends it if the instance already holds it. Returns false while
+    /// another instance's lease has not expired.
+    pub async fn renew(
+        conn: &DatabaseConnection,
+        name: &str,
+        instance_id: &str,
+        expires_at: DateTime<Utc>,
+    ) -> AppResult<bool> {
+        let raw_sql = r#"
+            INSERT INTO leader_lease (name, instance_id, expires_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (name) DO UPDATE SET
+                instance_id = EXCLUDED.instance_id,
+                acquired_at = CASE
+                    WHEN leader_lease.instance_id = EXCLUDED.instance_id
+                    THEN leader_lease.acquired_at
+                    ELSE NOW()
+                END,
+                renewed_at = NOW(),
+                expires_at = EXCLUDED.expires_at
+            WHERE leader_lease.instance_id = EXCLUDED.instance_id
+                OR leader_lease.expires_at < NOW()
+        "#;
+
+        let result = conn
+            .execute(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                raw_sql,
+                [name.into(), instance_id.into(), expires_at.into()],
+            ))
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get(
+        conn: &DatabaseConnection,
+        name: &str,
+    ) -> AppResult<Option<leader_lease::Model>> {
+        let lease = LeaderLease::find_by_id(name).one(conn).await?;
+
+        Ok(lease)
+    }
+
+    /// Ends the lease early so another instance can take over without waiting for it to expire
+    pub async fn release(
+        conn: &DatabaseConnection,
+        name: &str,
+        instance_id: &str,
+    ) -> AppResult<()> {
+        LeaderLease::delete_many()
+            .filter(leader_lease::Column::Name.eq(name))
+            .filter(leader_lease::Column::InstanceId.eq(instance_id))
+            .exec(conn)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::DatabaseConnection;
+
+use crate::{db_core::prelude::*, error::AppResult, server_config::cfg};
+
+/// Where a backfill stands. `done` counts queued emails that left the queue, processed or
+/// dead lettered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackfillProgress {
+    pub scanned: i64,
+    pub queued: i64,
+    pub done: i64,
+    pub pending: i64,
+    pub estimated_total: Option<i64>,
+    pub eta: Option<Duration>,
+}
+
+impl BackfillProgress {
+    /// `pending` is how many of the user's older emails are still queued
+    pub fn new(backfill: &mailbox_backfill::Model, pending: i64, now: DateTime<Utc>) -> Self {
+        let done = (backfill.queued - pending).max(0);
+        let left_to_list = match backfill.status {
+            BackfillStatus::Completed => 0,
+            _ => backfill
+                .estimated_total
+                .map_or(0, |total| (total - backfill.scanned).max(0)),
+        };
+        // Only the share of listed emails that weren't processed yet gets queued
+        let left_to_queue = if backfill.scanned > 0 {
+            left_to_list * backfill.queued / backfill.scanned
+        } else {
+            left_to_list
+        };
+        let remaining = pending + left_to_queue;
+        let elapsed = (backfill.completed_at.map_or(now, |at| at.to_utc())
+            - backfill.started_at.to_utc())
+        .num_seconds();
+
+        let eta = match backfill.status {
+            _ if remaining == 0 => Some(Duration::zero()),
+            BackfillStatus::Paused => None,
+            _ if done == 0 || elapsed <= 0 => None,
+            _ => Some(Duration::seconds(remaining * elapsed / done)),
+        };
+
+        Self {
+            scanned: backfill.scanned,
+            queued: backfill.queued,
+            done,
+            pending,
+            estimated_total: backfill.estimated_total,
+            eta,
+        }
+    }
+}
+
+/// Oldest emails a new backfill goes back to
+pub fn backfill_cutoff() -> DateTime<Utc> {
+    Utc::now() - Duration::days(cfg.settings.email_max_age_days)
+}
+
+pub struct MailboxBackfillCtrl;
+
+impl MailboxBackfillCtrl {
+    pub async fn get_by_user(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<Option<mailbox_backfill::Model>> {
+        let backfill = MailboxBackfill::find_by_id(user_id).one(conn).await?;
+
+        Ok(backfill)
+    }
+
+    /// Starts listing the mailbox from the newest email, replacing any earlier backfill
+    pub async fn start(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        not_before: DateTime<Utc>,
+    ) -> AppResult<mailbox_backfill::Model> {
+        let now = Utc::now();
+        let backfill = MailboxBackfill::insert(mailbox_backfill::ActiveModel {
+            user_id: ActiveValue::Set(user_id),
+            status: ActiveValue::Set(BackfillStatus::Running),
+            page_token: ActiveValue::Set(None),
+            not_before: ActiveValue::Set(not_before.into()),
+            estimated_total: ActiveValue::Set(None),
+            scanned: ActiveValue::Set(0),
+            queued: ActiveValue::Set(0),
+            started_at: ActiveValue::Set(now.into()),
+            completed_at: ActiveValue::Set(None),
+            updated_at: ActiveValue::Set(now.into()),
+        })
+        .on_conflict(
+            OnConflict::column(mailbox_backfill::Column::UserId)
+                .update_columns([
+                    mailbox_backfill::Column::Status,
+                    mailbox_backfill::Column::PageToken,
+                    mailbox_backfill::Column::NotBefore,
+                    mailbox_backfill::Column::EstimatedTotal,
+                    mailbox_backfill::Column::Scanned,
+                    mailbox_backfill::Column::Queued,
+                    mailbox_backfill::Column::StartedAt,
+                    mailbox_backfill::Column::CompletedAt,
+                    mailbox_backfill::Column::UpdatedAt,
+                ])
+                .to_owned(),
+        )
+        .exec_with_returning(conn)
+        .await?;
+
+        Ok(backfill)
+    }
+
+    /// Moves the backfill from `from` to `to`, returns false if it wasn't in `from`
+    pub async fn set_status(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        from: BackfillStatus,
+        to: BackfillStatus,
+    ) -> AppResult<bool> {
+        let result = MailboxBackfill::update_many()
+            .col_expr(mailbox_backfill::Column::Status, Expr::value(to))
+            .col_expr(mailbox_backfill::Column::UpdatedAt, Expr::value(Utc::now()))
+            .filter(mailbox_backfill::Column::UserId.eq(user_id))
+            .filter(mailbox_backfill::Column::Status.eq(from))
+            .exec(conn)
+            .await?;
+
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Saves the cursor after a listed page, completing the backfill after the last page.
+    /// Returns false if the backfill was restarted in the meantime.
+    pub async fn record_page(
+        conn: &DatabaseConnection,
+        backfill: &mailbox_backfill::Model,
+        next_page_token: Option<&str>,
+        scanned: i64,
+        queued: i64,
+        estimated_total: Option<i64>,
+    ) -> AppResult<bool> {
+        let now = Utc::now();
+        let mut update = MailboxBackfill::update_many()
+            .col_expr(
+                mailbox_backfill::Column::PageToken,
+                Expr::value(next_page_token.map(str::to_string)),
+            )
+            .col_expr(
+                mailbox_backfill::Column::Scanned,
+                Expr::col(mailbox_backfill::Column::Scanned).add(scanned),
+            )
+            .col_expr(
+                mailbox_backfill::Column::Queued,
+                Expr::col(mailbox_backfill::Column::Queued).add(queued),
+            )
+            .col_expr(mailbox_backfill::Column::UpdatedAt, Expr::value(now));
+        if let Some(estimated_total) = estimated_total {
+            update = update.col_expr(
+                mailbox_backfill::Column::EstimatedTotal,
+                Expr::value(estimated_total),
+            );
+        }
+        if next_page_token.is_none() {
+            update = update
+                .col_expr(
+                    mailbox_backfill::Column::Status,
+                    Expr::value(BackfillStatus::Completed),
+                )
+                .col_expr(mailbox_backfill::Column::CompletedAt, Expr::value(now));
+        }
+
+        let result = update
+            .filter(mailbox_backfill::Column::UserId.eq(backfill.user_id))
+            .filter(mailbox_backfill::Column::StartedAt.eq(backfill.started_at))
+            .exec(conn)
+            .await?;
+
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Lists the mailbox again from the newest email, keeping the counts. Emails that were
+    /// already queued are skipped.
+    pub async fn reset_cursor(
+        conn: &DatabaseConnection,
+        backfill: &mailbox_backfill::Model,
+    ) -> AppResult<()> {
+        MailboxBackfill::update_many()
+            .col_expr(
+                mailbox_backfill::Column::PageToken,
+                Expr::value(Option::<String>::None),
+            )
+            .col_expr(mailbox_backfill::Column::UpdatedAt, Expr::value(Utc::now()))
+            .filter(mailbox_backfill::Column::UserId.eq(backfill.user_id))
+            .filter(mailbox_backfill::Column::StartedAt.eq(backfill.started_at))
+            .exec(conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backfill(status: BackfillStatus, scanned: i64, queued: i64) -> mailbox_backfill::Model {
+        let started_at = Utc::now() - Duration::hours(1);
+        mailbox_backfill::Model {
+            user_id: 1,
+            status,
+            page_token: None,
+            not_before: (started_at - Duration::days(365)).into(),
+            estimated_total: Some(10_000),
+            scanned,
+            queued,
+            started_at: started_at.into(),
+            completed_at: None,
+            updated_at: started_at.into(),
+        }
+    }
+
+    #[test]
+    fn test_backfill_progress() {
+        let now = Utc::now();
+
+        // Half listed, half of the listed emails needed processing and half of those are done
+        let progress =
+            BackfillProgress::new(&backfill(BackfillStatus::Running, 5000, 2500), 1250, now);
+        assert_eq!(progress.done, 1250);
+        // 1250 still queued and 2500 more to queue, at 1250 an hour
+        let eta = progress.eta.unwrap();
+        assert!(
+            (eta - Duration::hours(3)).num_seconds().abs() <= 5,
+            "{:?}",
+            eta
+        );
+
+        let progress =
+            BackfillProgress::new(&backfill(BackfillStatus::Paused, 5000, 2500), 1250, now);
+        assert_eq!(progress.eta, None);
+
+        let progress =
+            BackfillProgress::new(&backfill(BackfillStatus::Running, 500, 500), 500, now);
+        assert_eq!(progress.eta, None);
+
+        let progress =
+            BackfillProgress::new(&backfill(BackfillStatus::Completed, 9000, 4000), 0, now);
+        assert_eq!(progress.done, 4000);
+        assert_eq!(progress.eta, Some(Duration::zero()));
+    }
+}
@@ -0,0 +1,46 @@
+use chrono::Utc;
+use sea_orm::DatabaseConnection;
+
+use crate::{db_core::prelude::*, error::AppResult};
+
+pub struct MailboxSyncStateCtrl;
+
+impl MailboxSyncStateCtrl {
+    pub async fn get_last_history_id(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<Option<u64>> {
+        let state = MailboxSyncState::find()
+            .filter(mailbox_sync_state::Column::UserId.eq(user_id))
+            .one(conn)
+            .await?;
+
+        Ok(state.map(|s| s.last_history_id as u64))
+    }
+
+    pub async fn set_last_history_id(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        history_id: u64,
+    ) -> AppResult<()> {
+        MailboxSyncState::insert(mailbox_sync_state::ActiveModel {
+            id: ActiveValue::NotSet,
+            user_id: ActiveValue::Set(user_id),
+            last_history_id: ActiveValue::Set(history_id as i64),
+            created_at: ActiveValue::NotSet,
+            updated_at: ActiveValue::Set(Utc::now().into()),
+        })
+        .on_conflict(
+            OnConflict::column(mailbox_sync_state::Column::UserId)
+                .update_columns([
+                    mailbox_sync_state::Column::LastHistoryId,
+                    mailbox_sync_state::Column::UpdatedAt,
+                ])
+                .to_owned(),
+        )
+        .exec(conn)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -1,9 +1,27 @@
 pub mod auto_cleanup_setting;
 pub mod custom_email_rule;
 pub mod daily_email_summary;
+pub mod dead_letter;
 pub mod default_email_rule_override;
+pub mod deterministic_email_rule;
+pub mod email_correction;
+pub mod email_embedding;
+pub mod email_example;
+pub mod email_failure;
+pub mod email_training;
+pub mod heuristic_rule;
+pub mod inbox_settings;
+pub mod label_migration;
+pub mod label_setting;
 pub mod labels;
+pub mod leader_lease;
+pub mod mailbox_backfill;
+pub mod mailbox_sync_state;
 pub mod processed_email;
+pub mod processor_state;
+pub mod prompt_queue;
+pub mod reclassification_job;
 pub mod response;
+pub mod server_instance;
 pub mod user;
 pub mod user_token_usage;
@@ -1,4 +1,7 @@
+use std::collections::HashSet;
+
 use chrono::{Duration, Utc};
+use sea_orm::DbBackend;
 
 use crate::{db_core::prelude::*, error::AppResult};
 
@@ -17,6 +20,20 @@ impl ProcessedEmailCtrl {
         Ok(processed_emails)
     }
 
+    pub async fn get_by_ids(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        ids: Vec<String>,
+    ) -> AppResult<Vec<processed_email::Model>> {
+        let processed_emails = ProcessedEmail::find()
+            .filter(processed_email::Column::UserId.eq(user_id))
+            .filter(processed_email::Column::Id.is_in(ids))
+            .all(conn)
+            .await?;
+
+        Ok(processed_emails)
+    }
+
     pub async fn get_users_processed_emails_for_cleanup(
         conn: &DatabaseConnection,
         cleanup_setting: &auto_cleanup_setting::Model,
@@ -38,12 +55,120 @@ impl ProcessedEmailCtrl {
         Ok(processed_emails)
     }
 
+    /// Emails in any of the categories processed since `since`, in id order after `after_id`
+    pub async fn get_page_in_categories(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        categories: &[String],
+        since: DateTimeWithTimeZone,
+        after_id: Option<&str>,
+        limit: u64,
+    ) -> AppResult<Vec<processed_email::Model>> {
+        let mut query = ProcessedEmail::find()
+            .filter(processed_email::Column::UserId.eq(user_id))
+            .filter(processed_email::Column::Category.is_in(categories.iter().cloned()))
+            .filter(processed_email::Column::ProcessedAt.gte(since));
+        if let Some(after_id) = after_id {
+            query = query.filter(processed_email::Column::Id.gt(after_id));
+        }
+
+        let processed_emails = query
+            .order_by(processed_email::Column::Id, Order::Asc)
+            .limit(limit)
+            .all(conn)
+            .await?;
+
+        Ok(processed_emails)
+    }
+
+    pub async fn count_in_categories(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        categories: &[String],
+        since: DateTimeWithTimeZone,
+    ) -> AppResult<u64> {
+        let count = ProcessedEmail::find()
+            .filter(processed_email::Column::UserId.eq(user_id))
+            .filter(processed_email::Column::Category.is_in(categories.iter().cloned()))
+            .filter(processed_email::Column::ProcessedAt.gte(since))
+            .count(conn)
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Moves the user's emails from one category to another, returns the number moved
+    pub async fn rename_category(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        from: &str,
+        to: &str,
+    ) -> AppResult<u64> {
+        let result = ProcessedEmail::update_many()
+            .filter(processed_email::Column::UserId.eq(user_id))
+            .filter(processed_email::Column::Category.eq(from))
+            .col_expr(processed_email::Column::Category, Expr::value(to))
+            .exec(conn)
+            .await?;
+
+        Ok(result.rows_affected)
+    }
+
+    /// Overwrites every column of an existing processed email
+    pub async fn replace(
+        conn: &DatabaseConnection,
+        model: processed_email::Model,
+    ) -> AppResult<processed_email::Model> {
+        let active_model = processed_email::ActiveModel::from(model).reset_all();
+        let updated = active_model.update(conn).await?;
+
+        Ok(updated)
+    }
+
     pub async fn insert(
         conn: &DatabaseConnection,
         active_model: processed_email::ActiveModel,
     ) -> Result<InsertResult<processed_email::ActiveModel>, DbErr> {
         ProcessedEmail::insert(active_model).exec(conn).await
     }
+
+    /// The ids that shouldn't be queued: processed, dead lettered or waiting for a retry. Only
+    /// the given ids are looked up, so the cost doesn't grow with the user's history.
+    pub async fn filter_handled(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        ids: Vec<String>,
+    ) -> AppResult<HashSet<String>> {
+        if ids.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let raw_sql = r#"
+            SELECT id FROM processed_email
+            WHERE user_id = $1 AND id = ANY($2)
+            UNION
+            SELECT email_id AS id FROM email_failure
+            WHERE user_id = $1 AND email_id = ANY($2) AND next_retry_at > NOW()
+            UNION
+            SELECT email_id AS id FROM dead_letter
+            WHERE user_id = $1 AND email_id = ANY($2)
+        "#;
+
+        #[derive(FromQueryResult)]
+        struct HandledId {
+            id: String,
+        }
+
+        let handled = HandledId::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            raw_sql,
+            [user_id.into(), ids.into()],
+        ))
+        .all(conn)
+        .await?;
+
+        Ok(handled.into_iter().map(|row| row.id).collect())
+    }
 }
 
 #[derive(Debug, Clone, FromQueryResult)]
@@ -52,3 +177,93 @@ pub struct ProcessedEmailIdCategoryAndTimestamp {
     pub category: String,
     pub processed_at: chrono::DateTime<Utc>,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::db_core::test::setup_conn;
+
+    use super::*;
+
+    const BENCH_EMAIL: &str = "dedupe-bench@example.com";
+    const PAGE_SIZE: usize = 500;
+    const RUNS: u32 = 20;
+
+    fn bench_id(n: usize) -> String {
+        format!("bench-{}", n)
+    }
+
+    async fn seed_history(conn: &DatabaseConnection, user_id: i32, from: usize, to: usize) {
+        for chunk in (from..to).collect::<Vec<_>>().chunks(1000) {
+            ProcessedEmail::insert_many(chunk.iter().map(|n| processed_email::ActiveModel {
+                id: ActiveValue::Set(bench_id(*n)),
+                user_id: ActiveValue::Set(user_id),
+                processed_at: ActiveValue::NotSet,
+                labels_applied: ActiveValue::NotSet,
+                labels_removed: ActiveValue::NotSet,
+                ai_answer: ActiveValue::Set("bench".to_string()),
+                category: ActiveValue::Set("bench".to_string()),
+                matched_rule: ActiveValue::Set(None),
+            }))
+            .exec(conn)
+            .await
+            .unwrap();
+        }
+    }
+
+    /// Times deduplicating one listed page as the user's history grows. Needs a database, run
+    /// with `cargo test bench_filter_handled -- --ignored --nocapture`.
+    #[tokio::test]
+    #[ignore]
+    async fn bench_filter_handled() {
+        let conn = setup_conn().await;
+        User::delete_many()
+            .filter(user::Column::Email.eq(BENCH_EMAIL))
+            .exec(&conn)
+            .await
+            .unwrap();
+        let user = User::insert(user::ActiveModel {
+            email: ActiveValue::Set(BENCH_EMAIL.to_string()),
+            ..Default::default()
+        })
+        .exec_with_returning(&conn)
+        .await
+        .unwrap();
+
+        let mut per_poll = vec![];
+        let mut history = 0;
+        for size in [1_000, 10_000, 100_000] {
+            seed_history(&conn, user.id, history, size).await;
+            history = size;
+
+            // Half of the page was processed in an earlier poll
+            let page = (history - PAGE_SIZE / 2..history + PAGE_SIZE / 2)
+                .map(bench_id)
+                .collect::<Vec<_>>();
+            ProcessedEmailCtrl::filter_handled(&conn, user.id, page.clone())
+                .await
+                .unwrap();
+
+            let started_at = Instant::now();
+            for _ in 0..RUNS {
+                let handled = ProcessedEmailCtrl::filter_handled(&conn, user.id, page.clone())
+                    .await
+                    .unwrap();
+                assert_eq!(handled.len(), PAGE_SIZE / 2);
+            }
+            let elapsed = started_at.elapsed() / RUNS;
+            println!("{:>7} processed emails: {:?} per poll", history, elapsed);
+            per_poll.push(elapsed);
+        }
+
+        User::delete_by_id(user.id).exec(&conn).await.unwrap();
+
+        // A hundred times the history costs about the same per poll
+        assert!(
+            per_poll[2] < per_poll[0] * 3 + std::time::Duration::from_millis(5),
+            "{:?}",
+            per_poll
+        );
+    }
+}
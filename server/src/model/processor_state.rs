@@ -0,0 +1,221 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::{DatabaseConnection, DbBackend};
+
+use crate::{
+    db_core::prelude::*,
+    error::{AppError, AppResult},
+    model::email_failure::{exponential_delay, truncate_error},
+    server_config::{cfg, ProcessorRestartConfig},
+};
+
+/// Why a processor stopped, kept so support can see why a user's mail isn't being sorted
+#[derive(Debug)]
+pub struct ProcessorFailure {
+    pub reason: ProcessorFailureReason,
+    pub message: String,
+}
+
+impl ProcessorFailure {
+    pub fn new(reason: ProcessorFailureReason, error: impl std::fmt::Debug) -> Self {
+        Self {
+            reason,
+            message: format!("{:?}", error),
+        }
+    }
+}
+
+impl From<AppError> for ProcessorFailure {
+    fn from(error: AppError) -> Self {
+        Self::new(ProcessorFailureReason::Internal, error)
+    }
+}
+
+impl From<anyhow::Error> for ProcessorFailure {
+    fn from(error: anyhow::Error) -> Self {
+        Self::new(ProcessorFailureReason::Internal, error)
+    }
+}
+
+/// How long to wait before restarting a processor that failed `failures` times in a row
+pub fn restart_delay(failures: i32, restart_cfg: &ProcessorRestartConfig) -> Duration {
+    exponential_delay(
+        failures,
+        restart_cfg.base_delay_secs,
+        restart_cfg.max_delay_secs,
+    )
+}
+
+pub struct ProcessorStateCtrl;
+
+impl ProcessorStateCtrl {
+    pub async fn get_by_user(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<Option<processor_state::Model>> {
+        let state = ProcessorState::find_by_id(user_id).one(conn).await?;
+
+        Ok(state)
+    }
+
+    /// Users whose processor should not be restarted yet. An auth failure stops counting once
+    /// the user has reconnected their account.
+    pub async fn backing_off_user_ids(conn: &DatabaseConnection) -> AppResult<HashSet<i32>> {
+        let raw_sql = r#"
+            SELECT
+                processor_state.user_id
+            FROM
+                processor_state
+                JOIN "user" ON "user".id = processor_state.user_id
+                LEFT JOIN user_account_access ON user_account_access.user_email = "user".email
+            WHERE
+                processor_state.next_restart_at > NOW()
+                AND NOT (
+                    processor_state.failure_reason = 'AUTH'
+                    AND user_account_access.updated_at > processor_state.last_failed_at
+                )
+        "#;
+
+        #[derive(FromQueryResult)]
+        struct BackingOff {
+            user_id: i32,
+        }
+
+        let rows = BackingOff::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            raw_sql,
+            [],
+        ))
+        .all(conn)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| row.user_id).collect())
+    }
+
+    pub async fn record_start(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        instance_id: &str,
+        status: &str,
+    ) -> AppResult<()> {
+        let now = Utc::now();
+        ProcessorState::insert(processor_state::ActiveModel {
+            user_id: ActiveValue::Set(user_id),
+            status: ActiveValue::Set(status.to_string()),
+            instance_id: ActiveValue::Set(Some(instance_id.to_string())),
+            emails_processed: ActiveValue::Set(0),
+            emails_failed: ActiveValue::Set(0),
+            started_at: ActiveValue::Set(Some(now.into())),
+            updated_at: ActiveValue::Set(now.into()),
+            ..Default::default()
+        })
+        .on_conflict(
+            OnConflict::column(processor_state::Column::UserId)
+                .update_columns([
+                    processor_state::Column::Status,
+                    processor_state::Column::InstanceId,
+                    processor_state::Column::EmailsProcessed,
+                    processor_state::Column::EmailsFailed,
+                    processor_state::Column::StartedAt,
+                    processor_state::Column::UpdatedAt,
+                ])
+                .to_owned(),
+        )
+        .exec_without_returning(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Saves the running processor's status. A processor that got through a cycle is healthy,
+    /// so its failures stop counting towards the restart delay.
+    pub async fn record_status(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        status: &str,
+        emails_processed: i64,
+        emails_failed: i64,
+        healthy: bool,
+    ) -> AppResult<()> {
+        let mut update = processor_state::ActiveModel {
+            user_id: ActiveValue::Unchanged(user_id),
+            status: ActiveValue::Set(status.to_string()),
+            emails_processed: ActiveValue::Set(emails_processed),
+            emails_failed: ActiveValue::Set(emails_failed),
+            updated_at: ActiveValue::Set(Utc::now().into()),
+            ..Default::default()
+        };
+        if healthy {
+            update.consecutive_failures = ActiveValue::Set(0);
+            update.next_restart_at = ActiveValue::Set(None);
+        }
+        ProcessorState::update_many()
+            .set(update)
+            .filter(processor_state::Column::UserId.eq(user_id))
+            .exec(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Counts a failure and returns when the processor may be restarted
+    pub async fn record_failure(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        failure: &ProcessorFailure,
+    ) -> AppResult<DateTime<Utc>> {
+        let failures = Self::get_by_user(conn, user_id)
+            .await?
+            .map_or(0, |state| state.consecutive_failures)
+            + 1;
+        let now = Utc::now();
+        let next_restart_at = now + restart_delay(failures, &cfg.processor_restarts);
+
+        ProcessorState::insert(processor_state::ActiveModel {
+            user_id: ActiveValue::Set(user_id),
+            status: ActiveValue::Set("Failed".to_string()),
+            consecutive_failures: ActiveValue::Set(failures),
+            failure_reason: ActiveValue::Set(Some(failure.reason)),
+            failure_message: ActiveValue::Set(Some(truncate_error(&failure.message))),
+            last_failed_at: ActiveValue::Set(Some(now.into())),
+            next_restart_at: ActiveValue::Set(Some(next_restart_at.into())),
+            updated_at: ActiveValue::Set(now.into()),
+            ..Default::default()
+        })
+        .on_conflict(
+            OnConflict::column(processor_state::Column::UserId)
+                .update_columns([
+                    processor_state::Column::Status,
+                    processor_state::Column::ConsecutiveFailures,
+                    processor_state::Column::FailureReason,
+                    processor_state::Column::FailureMessage,
+                    processor_state::Column::LastFailedAt,
+                    processor_state::Column::NextRestartAt,
+                    processor_state::Column::UpdatedAt,
+                ])
+                .to_owned(),
+        )
+        .exec_without_returning(conn)
+        .await?;
+
+        Ok(next_restart_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restart_delay() {
+        let restart_cfg = ProcessorRestartConfig {
+            base_delay_secs: 60,
+            max_delay_secs: 3600,
+        };
+
+        assert_eq!(restart_delay(1, &restart_cfg), Duration::seconds(60));
+        assert_eq!(restart_delay(3, &restart_cfg), Duration::seconds(240));
+        assert_eq!(restart_delay(7, &restart_cfg), Duration::seconds(3600));
+    }
+}
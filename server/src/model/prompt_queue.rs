@@ -0,0 +1,290 @@
+use chrono::{Duration, Utc};
+use sea_orm::{DatabaseConnection, DbBackend};
+
+use crate::{db_core::prelude::*, error::AppResult};
+
+#[derive(Debug, Clone, FromQueryResult)]
+pub struct QueuedCount {
+    pub user_email: String,
+    pub priority: PromptPriority,
+    pub plan: Option<String>,
+    pub count: i64,
+}
+
+pub struct PromptQueueCtrl;
+
+impl PromptQueueCtrl {
+    /// Returns false if the email is already queued or being processed
+    pub async fn push(
+        conn: &DatabaseConnection,
+        user_email: &str,
+        email_id: &str,
+        priority: PromptPriority,
+    ) -> AppResult<bool> {
+        let inserted = PromptQueueEntry::insert(prompt_queue_entry::ActiveModel {
+            id: ActiveValue::NotSet,
+            user_email: ActiveValue::Set(user_email.to_string()),
+            email_id: ActiveValue::Set(email_id.to_string()),
+            priority: ActiveValue::Set(priority),
+            attempts: ActiveValue::NotSet,
+            visible_at: ActiveValue::NotSet,
+            created_at: ActiveValue::NotSet,
+        })
+        .on_conflict(
+            OnConflict::columns([
+                prompt_queue_entry::Column::EmailId,
+                prompt_queue_entry::Column::UserEmail,
+            ])
+            .do_nothing()
+            .to_owned(),
+        )
+        .exec_without_returning(conn)
+        .await?;
+
+        Ok(inserted > 0)
+    }
+
+    /// Claims the next visible entry, of `user_email` if given. High priority entries and low
+    /// priority ones older than `aging_secs` go first. The entry is hidden from other workers
+    /// until the visibility timeout passes, so it is handed out again if the claimer never
+    /// finishes it.
+    pub async fn claim_next(
+        conn: &DatabaseConnection,
+        user_email: Option<&str>,
+        visibility_timeout_secs: i64,
+        max_attempts: i32,
+        aging_secs: i64,
+    ) -> AppResult<Option<prompt_queue_entry::Model>> {
+        let raw_sql = r#"
+            UPDATE
+                prompt_queue_entry
+            SET
+                attempts = attempts + 1,
+                visible_at = NOW() + make_interval(secs => $1)
+            WHERE
+                id = (
+                    SELECT
+                        id
+                    FROM
+                        prompt_queue_entry
+                    WHERE
+                        visible_at <= NOW()
+                        AND attempts < $2
+                        AND ($4::VARCHAR IS NULL OR user_email = $4)
+                    ORDER BY
+                        (priority = 'HIGH' OR created_at <= NOW() - make_interval(secs => $3)) DESC,
+                        id
+                    LIMIT 1
+                    FOR UPDATE SKIP LOCKED
+                )
+            RETURNING
+                *
+        "#;
+
+        let entry = PromptQueueEntry::find()
+            .from_raw_sql(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                raw_sql,
+                [
+                    (visibility_timeout_secs as f64).into(),
+                    max_attempts.into(),
+                    (aging_secs as f64).into(),
+                    user_email.map(|e| e.to_string()).into(),
+                ],
+            ))
+            .one(conn)
+            .await?;
+
+        Ok(entry)
+    }
+
+    /// Puts a claimed entry back without counting the attempt
+    pub async fn release(conn: &DatabaseConnection, id: i32, delay: Duration) -> AppResult<()> {
+        PromptQueueEntry::update_many()
+            .filter(prompt_queue_entry::Column::Id.eq(id))
+            .col_expr(
+                prompt_queue_entry::Column::Attempts,
+                Expr::col(prompt_queue_entry::Column::Attempts).sub(1),
+            )
+            .col_expr(
+                prompt_queue_entry::Column::VisibleAt,
+                Expr::value(DateTimeWithTimeZone::from(Utc::now() + delay)),
+            )
+            .exec(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Keeps a claimed entry hidden while it is still being processed
+    pub async fn extend(
+        conn: &DatabaseConnection,
+        id: i32,
+        visibility_timeout_secs: i64,
+    ) -> AppResult<()> {
+        PromptQueueEntry::update_many()
+            .filter(prompt_queue_entry::Column::Id.eq(id))
+            .col_expr(
+                prompt_queue_entry::Column::VisibleAt,
+                Expr::value(DateTimeWithTimeZone::from(
+                    Utc::now() + Duration::seconds(visibility_timeout_secs),
+                )),
+            )
+            .exec(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(
+        conn: &DatabaseConnection,
+        user_email: &str,
+        email_id: &str,
+    ) -> AppResult<()> {
+        PromptQueueEntry::delete_many()
+            .filter(prompt_queue_entry::Column::UserEmail.eq(user_email))
+            .filter(prompt_queue_entry::Column::EmailId.eq(email_id))
+            .exec(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Drops entries that were claimed too many times without finishing
+    pub async fn delete_exhausted(conn: &DatabaseConnection, max_attempts: i32) -> AppResult<u64> {
+        let result = PromptQueueEntry::delete_many()
+            .filter(prompt_queue_entry::Column::Attempts.gte(max_attempts))
+            .filter(prompt_queue_entry::Column::VisibleAt.lte(Utc::now()))
+            .exec(conn)
+            .await?;
+
+        Ok(result.rows_affected)
+    }
+
+    /// Entries waiting to be claimed, by user and priority. Low priority entries older than
+    /// `aging_secs` are counted as high priority.
+    pub async fn queued_counts(
+        conn: &DatabaseConnection,
+        max_attempts: i32,
+        aging_secs: i64,
+    ) -> AppResult<Vec<QueuedCount>> {
+        let raw_sql = r#"
+            SELECT
+                q.user_email,
+                CAST(
+                    CASE
+                        WHEN q.priority = 'HIGH' OR q.created_at <= NOW() - make_interval(secs => $2)
+                        THEN 'HIGH'
+                        ELSE 'LOW'
+                    END AS prompt_priority
+                ) AS priority,
+                u.plan,
+                COUNT(*) AS count
+            FROM
+                prompt_queue_entry q
+            LEFT JOIN
+                "user" u ON u.email = q.user_email
+            WHERE
+                q.visible_at <= NOW()
+                AND q.attempts < $1
+            GROUP BY
+                1, 2, 3
+        "#;
+
+        let counts = QueuedCount::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            raw_sql,
+            [max_attempts.into(), (aging_secs as f64).into()],
+        ))
+        .all(conn)
+        .await?;
+
+        Ok(counts)
+    }
+
+    /// The user's entries of `priority`, queued or being processed
+    pub async fn count_by_user(
+        conn: &DatabaseConnection,
+        user_email: &str,
+        priority: PromptPriority,
+    ) -> AppResult<u64> {
+        let count = PromptQueueEntry::find()
+            .filter(prompt_queue_entry::Column::UserEmail.eq(user_email))
+            .filter(prompt_queue_entry::Column::Priority.eq(priority))
+            .count(conn)
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Entries queued or being processed
+    pub async fn count_all(conn: &DatabaseConnection) -> AppResult<u64> {
+        let count = PromptQueueEntry::find().count(conn).await?;
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db_core::test::setup_conn;
+
+    use super::*;
+
+    const USER_EMAIL: &str = "prompt-queue-test@example.com";
+    const OTHER_USER_EMAIL: &str = "prompt-queue-other@example.com";
+
+    /// Needs a database, run with `cargo test test_delete_and_extend -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn test_delete_and_extend() {
+        let conn = setup_conn().await;
+        PromptQueueEntry::delete_many()
+            .filter(prompt_queue_entry::Column::UserEmail.is_in([USER_EMAIL, OTHER_USER_EMAIL]))
+            .exec(&conn)
+            .await
+            .unwrap();
+
+        // Gmail ids are only unique per mailbox
+        for user_email in [USER_EMAIL, OTHER_USER_EMAIL] {
+            assert!(
+                PromptQueueCtrl::push(&conn, user_email, "shared-id", PromptPriority::High)
+                    .await
+                    .unwrap()
+            );
+        }
+
+        let entry = PromptQueueCtrl::claim_next(&conn, Some(USER_EMAIL), 1, 5, 60)
+            .await
+            .unwrap()
+            .unwrap();
+        PromptQueueCtrl::extend(&conn, entry.id, 300).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+        assert!(
+            PromptQueueCtrl::claim_next(&conn, Some(USER_EMAIL), 1, 5, 60)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        PromptQueueCtrl::delete(&conn, USER_EMAIL, "shared-id")
+            .await
+            .unwrap();
+        assert_eq!(
+            PromptQueueCtrl::count_by_user(&conn, USER_EMAIL, PromptPriority::High)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            PromptQueueCtrl::count_by_user(&conn, OTHER_USER_EMAIL, PromptPriority::High)
+                .await
+                .unwrap(),
+            1
+        );
+
+        PromptQueueCtrl::delete(&conn, OTHER_USER_EMAIL, "shared-id")
+            .await
+            .unwrap();
+    }
+}
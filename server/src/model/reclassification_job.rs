@@ -0,0 +1,237 @@
+use chrono::Utc;
+use sea_orm::DatabaseConnection;
+
+use crate::{
+    db_core::prelude::*,
+    error::{AppError, AppResult},
+};
+
+const ACTIVE_STATUSES: [ReclassificationStatus; 2] = [
+    ReclassificationStatus::Pending,
+    ReclassificationStatus::Running,
+];
+
+pub struct ReclassificationJobCtrl;
+
+impl ReclassificationJobCtrl {
+    pub async fn all_by_user(
+        conn: &DatabaseConnection,
+        user_id: i32,
+    ) -> AppResult<Vec<reclassification_job::Model>> {
+        let jobs = ReclassificationJob::find()
+            .filter(reclassification_job::Column::UserId.eq(user_id))
+            .order_by(reclassification_job::Column::Id, Order::Desc)
+            .all(conn)
+            .await?;
+
+        Ok(jobs)
+    }
+
+    pub async fn get_by_id(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        id: i32,
+    ) -> AppResult<reclassification_job::Model> {
+        ReclassificationJob::find()
+            .filter(reclassification_job::Column::UserId.eq(user_id))
+            .filter(reclassification_job::Column::Id.eq(id))
+            .one(conn)
+            .await?
+            .ok_or(AppError::NotFound(
+                "Reclassification job not found".to_string(),
+            ))
+    }
+
+    pub async fn status(conn: &DatabaseConnection, id: i32) -> AppResult<ReclassificationStatus> {
+        let job =
+            ReclassificationJob::find_by_id(id)
+                .one(conn)
+                .await?
+                .ok_or(AppError::NotFound(
+                    "Reclassification job not found".to_string(),
+                ))?;
+
+        Ok(job.status)
+    }
+
+    /// Queues a job for the categories. An unfinished job for the same user is replaced, and
+    /// its categories are carried over since those emails were never re-run.
+    pub async fn create(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        categories: Vec<String>,
+        since: DateTimeWithTimeZone,
+    ) -> AppResult<reclassification_job::Model> {
+        let active_jobs = ReclassificationJob::find()
+            .filter(reclassification_job::Column::UserId.eq(user_id))
+            .filter(reclassification_job::Column::Status.is_in(ACTIVE_STATUSES))
+            .all(conn)
+            .await?;
+
+        let mut categories = categories;
+        for job in &active_jobs {
+            categories.extend(job.categories.clone().unwrap_or_default());
+            Self::finish(conn, job.id, ReclassificationStatus::Cancelled, None).await?;
+        }
+        categories.sort();
+        categories.dedup();
+
+        let job = ReclassificationJob::insert(reclassification_job::ActiveModel {
+            id: ActiveValue::NotSet,
+            user_id: ActiveValue::Set(user_id),
+            status: ActiveValue::Set(ReclassificationStatus::Pending),
+            categories: ActiveValue::Set(Some(categories)),
+            since: ActiveValue::Set(since),
+            cursor: ActiveValue::NotSet,
+            total: ActiveValue::NotSet,
+            processed: ActiveValue::NotSet,
+            changed: ActiveValue::NotSet,
+            failed: ActiveValue::NotSet,
+            error: ActiveValue::NotSet,
+            created_at: ActiveValue::NotSet,
+            updated_at: ActiveValue::NotSet,
+            finished_at: ActiveValue::NotSet,
+        })
+        .exec_with_returning(conn)
+        .await?;
+
+        Ok(job)
+    }
+
+    /// Marks the oldest pending job as running and returns it. Returns `None` if another
+    /// runner claimed it first.
+    pub async fn claim_next(
+        conn: &DatabaseConnection,
+    ) -> AppResult<Option<reclassification_job::Model>> {
+        let Some(job) = ReclassificationJob::find()
+            .filter(reclassification_job::Column::Status.eq(ReclassificationStatus::Pending))
+            .order_by(reclassification_job::Column::Id, Order::Asc)
+            .one(conn)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let result = ReclassificationJob::update_many()
+            .filter(reclassification_job::Column::Id.eq(job.id))
+            .filter(reclassification_job::Column::Status.eq(ReclassificationStatus::Pending))
+            .set(reclassification_job::ActiveModel {
+                status: ActiveValue::Set(ReclassificationStatus::Running),
+                updated_at: ActiveValue::Set(Utc::now().into()),
+                ..Default::default()
+            })
+            .exec(conn)
+            .await?;
+
+        if result.rows_affected == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(reclassification_job::Model {
+            status: ReclassificationStatus::Running,
+            ..job
+        }))
+    }
+
+    /// Jobs left running by a previous leader resume from their cursor
+    pub async fn requeue_interrupted(conn: &DatabaseConnection) -> AppResult<u64> {
+        let result = ReclassificationJob::update_many()
+            .filter(reclassification_job::Column::Status.eq(ReclassificationStatus::Running))
+            .set(reclassification_job::ActiveModel {
+                status: ActiveValue::Set(ReclassificationStatus::Pending),
+                ..Default::default()
+            })
+            .exec(conn)
+            .await?;
+
+        Ok(result.rows_affected)
+    }
+
+    /// Puts a running job back so it can be claimed again later
+    pub async fn release(conn: &DatabaseConnection, id: i32) -> AppResult<()> {
+        ReclassificationJob::update_many()
+            .filter(reclassification_job::Column::Id.eq(id))
+            .filter(reclassification_job::Column::Status.eq(ReclassificationStatus::Running))
+            .set(reclassification_job::ActiveModel {
+                status: ActiveValue::Set(ReclassificationStatus::Pending),
+                updated_at: ActiveValue::Set(Utc::now().into()),
+                ..Default::default()
+            })
+            .exec(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_total(conn: &DatabaseConnection, id: i32, total: i32) -> AppResult<()> {
+        ReclassificationJob::update_many()
+            .filter(reclassification_job::Column::Id.eq(id))
+            .set(reclassification_job::ActiveModel {
+                total: ActiveValue::Set(total),
+                ..Default::default()
+            })
+            .exec(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn update_progress(
+        conn: &DatabaseConnection,
+        job: &reclassification_job::Model,
+    ) -> AppResult<()> {
+        ReclassificationJob::update_many()
+            .filter(reclassification_job::Column::Id.eq(job.id))
+            .set(reclassification_job::ActiveModel {
+                cursor: ActiveValue::Set(job.cursor.clone()),
+                processed: ActiveValue::Set(job.processed),
+                changed: ActiveValue::Set(job.changed),
+                failed: ActiveValue::Set(job.failed),
+                updated_at: ActiveValue::Set(Utc::now().into()),
+                ..Default::default()
+            })
+            .exec(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Only unfinished jobs are updated, so a cancelled job is never marked completed
+    pub async fn finish(
+        conn: &DatabaseConnection,
+        id: i32,
+        status: ReclassificationStatus,
+        error: Option<String>,
+    ) -> AppResult<bool> {
+        let now = Utc::now();
+        let result = ReclassificationJob::update_many()
+            .filter(reclassification_job::Column::Id.eq(id))
+            .filter(reclassification_job::Column::Status.is_in(ACTIVE_STATUSES))
+            .set(reclassification_job::ActiveModel {
+                status: ActiveValue::Set(status),
+                error: ActiveValue::Set(error),
+                updated_at: ActiveValue::Set(now.into()),
+                finished_at: ActiveValue::Set(Some(now.into())),
+                ..Default::default()
+            })
+            .exec(conn)
+            .await?;
+
+        Ok(result.rows_affected > 0)
+    }
+
+    pub async fn cancel(
+        conn: &DatabaseConnection,
+        user_id: i32,
+        id: i32,
+    ) -> AppResult<reclassification_job::Model> {
+        let job = Self::get_by_id(conn, user_id, id).await?;
+        if !Self::finish(conn, job.id, ReclassificationStatus::Cancelled, None).await? {
+            return Err(AppError::BadRequest(
+                "Reclassification job has already finished".to_string(),
+            ));
+        }
+
+        Self::get_by_id(conn, user_id, id).await
+    }
+}
@@ -0,0 +1,57 @@
+use chrono::{Duration, Utc};
+use sea_orm::DatabaseConnection;
+
+use crate::{db_core::prelude::*, error::AppResult};
+
+pub struct ServerInstanceCtrl;
+
+impl ServerInstanceCtrl {
+    /// Registers the instance, or marks it alive if it already is
+    pub async fn heartbeat(conn: &DatabaseConnection, id: &str, hostname: &str) -> AppResult<()> {
+        ServerInstance::insert(server_instance::ActiveModel {
+            id: ActiveValue::Set(id.to_string()),
+            hostname: ActiveValue::Set(hostname.to_string()),
+            started_at: ActiveValue::NotSet,
+            last_heartbeat_at: ActiveValue::Set(Utc::now().into()),
+        })
+        .on_conflict(
+            OnConflict::column(server_instance::Column::Id)
+                .update_column(server_instance::Column::LastHeartbeatAt)
+                .to_owned(),
+        )
+        .exec_without_returning(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Instances that sent a heartbeat within `ttl`
+    pub async fn live(
+        conn: &DatabaseConnection,
+        ttl: Duration,
+    ) -> AppResult<Vec<server_instance::Model>> {
+        let instances = ServerInstance::find()
+            .filter(server_instance::Column::LastHeartbeatAt.gt(Utc::now() - ttl))
+            .order_by(server_instance::Column::Id, Order::Asc)
+            .all(conn)
+            .await?;
+
+        Ok(instances)
+    }
+
+    pub async fn remove(conn: &DatabaseConnection, id: &str) -> AppResult<()> {
+        ServerInstance::delete_by_id(id).exec(conn).await?;
+
+        Ok(())
+    }
+
+    /// Forgets instances that stopped without removing themselves
+    pub async fn delete_stale(conn: &DatabaseConnection, older_than: Duration) -> AppResult<u64> {
+        let result = ServerInstance::delete_many()
+            .filter(server_instance::Column::LastHeartbeatAt.lt(Utc::now() - older_than))
+            .exec(conn)
+            .await?;
+
+        Ok(result.rows_affected)
+    }
+}
@@ -24,6 +24,16 @@ impl UserCtrl {
         Ok(user)
     }
 
+    pub async fn get_by_id(conn: &DatabaseConnection, id: i32) -> AppResult<user::Model> {
+        let user = User::find_by_id(id)
+            .one(conn)
+            .await
+            .context("Error fetching user by id")?
+            .ok_or(AppError::NotFound("User not found".to_string()))?;
+
+        Ok(user)
+    }
+
     pub async fn get_with_account_access_by_id(
         conn: &DatabaseConnection,
         user_id: i32,
@@ -130,7 +140,7 @@ impl UserCtrl {
                 uaa.refresh_token,
                 uaa.expires_at,
                 COALESCE("user_token_usage_stat".tokens_consumed, 0) AS tokens_consumed,
-                GREATEST(latest_email_rule_override.updated_at, latest_custom_email_rule.updated_at) AS last_rule_update_time
+                GREATEST(latest_email_rule_override.updated_at, latest_custom_email_rule.updated_at, latest_label_setting.updated_at, latest_inbox_setting.updated_at) AS last_rule_update_time
             FROM
                 "user" AS u
             JOIN
@@ -169,6 +179,26 @@ impl UserCtrl {
                         ) AS subquery
                     WHERE row_num = 1
                 ) AS latest_custom_email_rule ON u.id = latest_custom_email_rule.user_id
+            LEFT JOIN
+                (
+                    SELECT
+                        user_id,
+                        MAX(updated_at) AS updated_at
+                    FROM
+                        label_setting
+                    GROUP BY
+                        user_id
+                ) AS latest_label_setting ON u.id = latest_label_setting.user_id
+            LEFT JOIN
+                (
+                    SELECT
+                        user_id,
+                        MAX(updated_at) AS updated_at
+                    FROM
+                        inbox_settings
+                    GROUP BY
+                        user_id
+                ) AS latest_inbox_setting ON u.id = latest_inbox_setting.user_id
             WHERE
                 u.subscription_status = (CAST('ACTIVE' AS subscription_status))
                 AND ("user_token_usage_stat".tokens_consumed < $2 OR "user_token_usage_stat".tokens_consumed IS NULL)
@@ -91,6 +91,7 @@ impl UserTokenUsageStatsCtrl {
                 id: ActiveValue::NotSet,
                 user_email: ActiveValue::Set(user_email.to_string()),
                 tokens_consumed: ActiveValue::Set(tokens),
+                preview_tokens_consumed: ActiveValue::NotSet,
                 date: ActiveValue::NotSet,
                 month: ActiveValue::NotSet,
                 year: ActiveValue::NotSet,
@@ -111,4 +112,52 @@ impl UserTokenUsageStatsCtrl {
 
         Ok(inserted.tokens_consumed)
     }
+
+    pub async fn get_preview_usage_today(
+        conn: &DatabaseConnection,
+        user_email: &str,
+    ) -> AppResult<i64> {
+        let usage = UserTokenUsageStat::find()
+            .filter(user_token_usage_stat::Column::UserEmail.eq(user_email))
+            .filter(user_token_usage_stat::Column::Date.eq(chrono::Utc::now().date_naive()))
+            .one(conn)
+            .await?
+            .map(|usage| usage.preview_tokens_consumed)
+            .unwrap_or(0);
+
+        Ok(usage)
+    }
+
+    /// Rule previews are charged separately so they never eat into the daily processing quota
+    pub async fn add_to_daily_preview_quota(
+        conn: &DatabaseConnection,
+        user_email: &str,
+        tokens: i64,
+    ) -> AppResult<i64> {
+        #[derive(Debug, FromQueryResult)]
+        struct PreviewUsage {
+            preview_tokens_consumed: i64,
+        }
+
+        let result = PreviewUsage::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"
+                INSERT INTO user_token_usage_stat (user_email, preview_tokens_consumed)
+                VALUES ($1, $2)
+                ON CONFLICT (date, user_email) DO UPDATE
+                SET preview_tokens_consumed =
+                        user_token_usage_stat.preview_tokens_consumed + EXCLUDED.preview_tokens_consumed,
+                    updated_at = CURRENT_TIMESTAMP
+                RETURNING preview_tokens_consumed
+            "#,
+            [user_email.into(), tokens.into()],
+        ))
+        .one(conn)
+        .await?
+        .ok_or(AppError::NotFound(
+            "Could not find updated token usage record".to_string(),
+        ))?;
+
+        Ok(result.preview_tokens_consumed)
+    }
 }
@@ -1,3 +1,5 @@
+pub mod notifier;
+
 pub async fn notify_invalid_token(user_email: &str) {
     // TODO: Implement user notify when credentials need updating
     unimplemented!("notify_invalid_token not implemented")
@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use futures::future::BoxFuture;
+use serde::Serialize;
+
+use crate::{server_config::cfg, HttpClient};
+
+/// Sent as soon as an email in an important category is labeled
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportantEmailNotification {
+    pub user_email: String,
+    pub email_id: String,
+    pub category: String,
+    pub from: Option<String>,
+    pub subject: Option<String>,
+}
+
+pub trait Notifier: Send + Sync {
+    fn notify<'a>(
+        &'a self,
+        notification: &'a ImportantEmailNotification,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+pub type SharedNotifier = Arc<dyn Notifier>;
+
+/// Posts to the configured webhook, or only logs if there is none
+pub fn from_config(http_client: HttpClient) -> SharedNotifier {
+    match &cfg.notifications.webhook_url {
+        Some(url) => Arc::new(WebhookNotifier {
+            http_client,
+            url: url.clone(),
+        }),
+        None => Arc::new(LogNotifier),
+    }
+}
+
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn notify<'a>(
+        &'a self,
+        notification: &'a ImportantEmailNotification,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            tracing::info!(
+                "Important email {} for {} in {}",
+                notification.email_id,
+                notification.user_email,
+                notification.category
+            );
+            Ok(())
+        })
+    }
+}
+
+/// Posts the notification as JSON
+pub struct WebhookNotifier {
+    http_client: HttpClient,
+    url: String,
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify<'a>(
+        &'a self,
+        notification: &'a ImportantEmailNotification,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            self.http_client
+                .post(&self.url)
+                .json(notification)
+                .send()
+                .await?
+                .error_for_status()
+                .context("Notification webhook returned an error")?;
+            Ok(())
+        })
+    }
+}
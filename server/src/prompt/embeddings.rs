@@ -0,0 +1,150 @@
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    cluster::hash_ring::stable_hash,
+    email::parsed_message::ParsedMessage,
+    server_config::{cfg, EmbeddingsConfig, EmbeddingsProvider},
+    HttpClient,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailEmbedding {
+    pub model: String,
+    pub vector: Vec<f32>,
+    pub token_usage: i64,
+}
+
+/// The text that gets embedded for an email, truncated to keep requests small
+pub fn embedding_input(email_message: &ParsedMessage, max_chars: usize) -> String {
+    let from = email_message.from.as_deref().unwrap_or_default();
+    let subject = email_message.subject.as_deref().unwrap_or_default();
+    let body = email_message.body.as_deref().unwrap_or_default();
+
+    format!("From: {from}\nSubject: {subject}\n{body}")
+        .chars()
+        .take(max_chars)
+        .collect()
+}
+
+pub async fn embed_email(
+    http_client: &HttpClient,
+    email_message: &ParsedMessage,
+) -> anyhow::Result<EmailEmbedding> {
+    let config = &cfg.embeddings;
+    let input = embedding_input(email_message, config.max_input_chars);
+
+    match config.provider {
+        EmbeddingsProvider::Mock => Ok(EmailEmbedding {
+            model: format!("mock-{}", config.dimensions),
+            vector: mock_embedding(&input, config.dimensions),
+            token_usage: 0,
+        }),
+        EmbeddingsProvider::Http => request_embedding(http_client, config, &input).await,
+    }
+}
+
+async fn request_embedding(
+    http_client: &HttpClient,
+    config: &EmbeddingsConfig,
+    input: &str,
+) -> anyhow::Result<EmailEmbedding> {
+    let resp = http_client
+        .post(&config.endpoint)
+        .bearer_auth(&cfg.api.key)
+        .json(&json!({
+            "model": &config.model,
+            "input": [input],
+        }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<EmbeddingApiResponse>()
+        .await
+        .context("Could not parse embeddings response")?;
+
+    let vector = resp
+        .data
+        .into_iter()
+        .next()
+        .map(|d| d.embedding)
+        .ok_or(anyhow!("No embedding in response"))?;
+
+    Ok(EmailEmbedding {
+        model: config.model.clone(),
+        vector: normalize(vector),
+        token_usage: resp.usage.map_or(0, |u| u.total_tokens),
+    })
+}
+
+/// Hashed bag of words, so similar emails land close together without calling a model
+pub fn mock_embedding(input: &str, dimensions: usize) -> Vec<f32> {
+    let mut vector = vec![0.0; dimensions.max(1)];
+    for word in input
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+    {
+        // Stored vectors must stay comparable across releases, so the hash has to be stable
+        let idx = (stable_hash(&word.to_lowercase()) % vector.len() as u64) as usize;
+        vector[idx] += 1.0;
+    }
+
+    normalize(vector)
+}
+
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+
+    vector
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EmbeddingUsage {
+    total_tokens: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EmbeddingApiResponse {
+    data: Vec<EmbeddingData>,
+    usage: Option<EmbeddingUsage>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::knn::cosine_similarity;
+
+    #[test]
+    fn test_mock_embedding_similarity() {
+        let a = mock_embedding("Your SeatGeek tickets for Saturday", 256);
+        let b = mock_embedding("Your SeatGeek tickets for Sunday", 256);
+        let c = mock_embedding("Invoice 1234 from Acme billing", 256);
+
+        assert_eq!(a.len(), 256);
+        assert!((a.iter().map(|v| v * v).sum::<f32>() - 1.0).abs() < 1e-5);
+        assert!(cosine_similarity(&a, &b) > cosine_similarity(&a, &c));
+    }
+
+    #[test]
+    fn test_embedding_input_is_truncated() {
+        let message = ParsedMessage {
+            from: Some("events@seatgeek.com".to_string()),
+            subject: Some("Upcoming events".to_string()),
+            body: Some("a".repeat(100)),
+            ..ParsedMessage::default()
+        };
+
+        let input = embedding_input(&message, 60);
+        assert_eq!(input.chars().count(), 60);
+        assert!(input.starts_with("From: events@seatgeek.com\nSubject: Upcoming events\n"));
+    }
+}
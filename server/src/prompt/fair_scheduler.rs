@@ -0,0 +1,146 @@
+use std::collections::{HashMap, VecDeque};
+
+/// Deficit round robin across users, so a user with a large backlog can't starve the others.
+/// Each turn a user gets as many emails as their weight before the next user is served.
+#[derive(Debug, Default)]
+pub struct DeficitRoundRobin {
+    ring: VecDeque<String>,
+    deficits: HashMap<String, u32>,
+}
+
+impl DeficitRoundRobin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Picks the user to serve next from the users with emails waiting. Users join the end of
+    /// the round when they first have a backlog and leave it once they have none.
+    pub fn next(
+        &mut self,
+        backlog: &HashMap<String, usize>,
+        weight: impl Fn(&str) -> u32,
+    ) -> Option<String> {
+        let mut joining = backlog
+            .iter()
+            .filter(|(user, n)| **n > 0 && !self.deficits.contains_key(*user))
+            .map(|(user, _)| user.clone())
+            .collect::<Vec<_>>();
+        // Sorted so the order of a round doesn't depend on the map's order
+        joining.sort();
+        for user in joining {
+            self.deficits.insert(user.clone(), 0);
+            self.ring.push_back(user);
+        }
+
+        while let Some(user) = self.ring.front().cloned() {
+            if backlog.get(&user).copied().unwrap_or(0) == 0 {
+                self.ring.pop_front();
+                self.deficits.remove(&user);
+                continue;
+            }
+
+            let deficit = self.deficits.entry(user.clone()).or_default();
+            if *deficit == 0 {
+                *deficit = weight(&user).max(1);
+            }
+            *deficit -= 1;
+            if *deficit == 0 {
+                self.ring.rotate_left(1);
+            }
+
+            return Some(user);
+        }
+
+        None
+    }
+
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ring.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serves one email per tick, returns how many ticks each user's emails waited
+    fn simulate(
+        arrivals: &[(usize, &str, usize)],
+        weights: &HashMap<&str, u32>,
+        ticks: usize,
+    ) -> HashMap<String, Vec<usize>> {
+        let mut scheduler = DeficitRoundRobin::new();
+        let mut queues = HashMap::<String, VecDeque<usize>>::new();
+        let mut waits = HashMap::<String, Vec<usize>>::new();
+
+        for tick in 0..ticks {
+            for (at, user, n) in arrivals {
+                if *at == tick {
+                    queues
+                        .entry(user.to_string())
+                        .or_default()
+                        .extend(std::iter::repeat_n(tick, *n));
+                }
+            }
+
+            let backlog = queues
+                .iter()
+                .map(|(user, queue)| (user.clone(), queue.len()))
+                .collect::<HashMap<_, _>>();
+            let weight = |user: &str| weights.get(user).copied().unwrap_or(1);
+            if let Some(user) = scheduler.next(&backlog, weight) {
+                let arrived = queues.get_mut(&user).unwrap().pop_front().unwrap();
+                waits.entry(user).or_default().push(tick - arrived);
+            }
+        }
+
+        waits
+    }
+
+    #[test]
+    fn test_small_users_have_bounded_latency() {
+        let arrivals = [
+            (0, "big", 500),
+            (0, "small_a", 5),
+            (0, "small_b", 5),
+            (150, "small_a", 5),
+            (300, "small_c", 3),
+        ];
+        let waits = simulate(&arrivals, &HashMap::new(), 600);
+
+        // With four users in the round, a small user's email never waits for more than four
+        // of everyone else's per email ahead of it
+        for user in ["small_a", "small_b", "small_c"] {
+            let max_wait = waits[user].iter().max().copied().unwrap();
+            assert!(max_wait <= 4 * 5, "{} waited {} ticks", user, max_wait);
+        }
+        assert_eq!(waits["small_a"].len(), 10);
+        assert_eq!(waits["big"].len(), 500);
+    }
+
+    #[test]
+    fn test_weights() {
+        let arrivals = [(0, "pro", 100), (0, "standard", 100)];
+        let weights = HashMap::from([("pro", 3)]);
+        let waits = simulate(&arrivals, &weights, 80);
+
+        assert_eq!(waits["pro"].len(), 60);
+        assert_eq!(waits["standard"].len(), 20);
+    }
+
+    #[test]
+    fn test_users_leave_the_round() {
+        let mut scheduler = DeficitRoundRobin::new();
+        let backlog = HashMap::from([("a".to_string(), 1), ("b".to_string(), 1)]);
+        assert_eq!(scheduler.next(&backlog, |_| 1), Some("a".to_string()));
+        assert_eq!(scheduler.next(&backlog, |_| 1), Some("b".to_string()));
+
+        let backlog = HashMap::from([("a".to_string(), 0), ("b".to_string(), 0)]);
+        assert_eq!(scheduler.next(&backlog, |_| 1), None);
+        assert!(scheduler.is_empty());
+    }
+}
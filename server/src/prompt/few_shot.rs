@@ -0,0 +1,350 @@
+use std::collections::HashSet;
+
+use crate::{
+    db_core::prelude::*,
+    email::{
+        parsed_message::{sender_domain, ParsedMessage},
+        rules::UserEmailRules,
+    },
+    server_config::FewShotConfig,
+};
+
+/// A labeled email a user has categorized, either by correcting us or by pinning it
+#[derive(Debug, Clone, PartialEq)]
+pub struct FewShotExample {
+    pub category: String,
+    pub from: Option<String>,
+    pub subject: Option<String>,
+    pub body_snippet: Option<String>,
+}
+
+impl FewShotExample {
+    fn tokens(&self) -> HashSet<String> {
+        message_tokens(self.from.as_deref(), self.subject.as_deref())
+    }
+}
+
+impl From<&email_example::Model> for FewShotExample {
+    fn from(example: &email_example::Model) -> Self {
+        Self {
+            category: example.category.clone(),
+            from: example.from.clone(),
+            subject: example.subject.clone(),
+            body_snippet: example.body_snippet.clone(),
+        }
+    }
+}
+
+/// An example ready to be placed in the prompt, with its category as prompt content
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptExample {
+    pub category: String,
+    pub from: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl PromptExample {
+    pub fn render(&self) -> String {
+        format!(
+            "<example><from>{}</from><subject>{}</subject><body>{}</body><category>{}</category></example>",
+            self.from, self.subject, self.body, self.category
+        )
+    }
+
+    fn estimated_tokens(&self) -> usize {
+        // Roughly four characters per token
+        self.render().chars().count().div_ceil(4)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FewShotPool {
+    pinned: Vec<FewShotExample>,
+    /// Keyed by email id, since a later correction of the same email replaces the earlier one
+    corrected: Vec<(String, FewShotExample)>,
+}
+
+impl FewShotPool {
+    pub fn new(examples: Vec<FewShotExample>) -> Self {
+        Self {
+            pinned: examples,
+            corrected: vec![],
+        }
+    }
+
+    /// Builds the pool from pinned examples and corrections that moved an email to another
+    /// category. Corrections that only removed our label say nothing about the right category.
+    pub fn from_models(
+        pinned: &[email_example::Model],
+        corrections: &[email_correction::Model],
+    ) -> Self {
+        let mut pool = Self::default();
+        pool.replace_pinned(pinned);
+        pool.apply_corrections(corrections);
+        pool
+    }
+
+    pub fn replace_pinned(&mut self, pinned: &[email_example::Model]) {
+        self.pinned = pinned.iter().map(FewShotExample::from).collect();
+    }
+
+    pub fn apply_corrections(&mut self, corrections: &[email_correction::Model]) {
+        for correction in corrections {
+            self.corrected
+                .retain(|(email_id, _)| *email_id != correction.email_id);
+
+            if let Some(category) = &correction.corrected_category {
+                self.corrected.push((
+                    correction.email_id.clone(),
+                    FewShotExample {
+                        category: category.clone(),
+                        from: correction.from.clone(),
+                        subject: correction.subject.clone(),
+                        body_snippet: correction.body_snippet.clone(),
+                    },
+                ));
+            }
+        }
+    }
+
+    fn examples(&self) -> impl Iterator<Item = &FewShotExample> {
+        self.pinned
+            .iter()
+            .chain(self.corrected.iter().map(|(_, example)| example))
+    }
+
+    /// Picks the examples most similar to the message that fit within the token budget.
+    /// Examples for categories the user no longer has are skipped.
+    pub fn select(
+        &self,
+        email_message: &ParsedMessage,
+        email_rules: &UserEmailRules,
+        config: &FewShotConfig,
+    ) -> Vec<PromptExample> {
+        if !config.enabled || (self.pinned.is_empty() && self.corrected.is_empty()) {
+            return vec![];
+        }
+
+        let message_tokens = message_tokens(
+            email_message.from.as_deref(),
+            email_message.subject.as_deref(),
+        );
+
+        let mut scored = self
+            .examples()
+            .filter_map(|example| {
+                let rule = email_rules
+                    .data()
+                    .iter()
+                    .find(|r| r.mail_label == example.category)?;
+                let score = jaccard(&message_tokens, &example.tokens());
+                (score >= config.min_similarity).then_some((score, rule, example))
+            })
+            .collect::<Vec<_>>();
+
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut selected = vec![];
+        let mut tokens_used = 0;
+        for (_, rule, example) in scored {
+            if selected.len() >= config.max_examples {
+                break;
+            }
+
+            let prompt_example = PromptExample {
+                category: rule.prompt_category(),
+                from: example.from.clone().unwrap_or_default(),
+                subject: example.subject.clone().unwrap_or_default(),
+                body: example
+                    .body_snippet
+                    .as_deref()
+                    .unwrap_or_default()
+                    .chars()
+                    .take(config.body_snippet_chars)
+                    .collect(),
+            };
+
+            let cost = prompt_example.estimated_tokens();
+            if tokens_used + cost > config.token_budget {
+                continue;
+            }
+
+            tokens_used += cost;
+            selected.push(prompt_example);
+        }
+
+        selected
+    }
+}
+
+fn message_tokens(from: Option<&str>, subject: Option<&str>) -> HashSet<String> {
+    let mut tokens = subject
+        .unwrap_or_default()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| t.chars().count() >= 3)
+        .map(|t| t.to_lowercase())
+        .collect::<HashSet<_>>();
+
+    if let Some(domain) = from.and_then(sender_domain) {
+        tokens.insert(format!("@{domain}"));
+    }
+
+    tokens
+}
+
+fn jaccard(a: &HashSet<String>, b: &HashSet<String>) -> f32 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+
+    a.intersection(b).count() as f32 / union as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::rules::EmailRule;
+
+    fn example(category: &str, from: &str, subject: &str) -> FewShotExample {
+        FewShotExample {
+            category: category.to_string(),
+            from: Some(from.to_string()),
+            subject: Some(subject.to_string()),
+            body_snippet: Some("Some body".to_string()),
+        }
+    }
+
+    fn message(from: &str, subject: &str) -> ParsedMessage {
+        ParsedMessage {
+            from: Some(from.to_string()),
+            subject: Some(subject.to_string()),
+            ..ParsedMessage::default()
+        }
+    }
+
+    fn rules() -> UserEmailRules {
+        UserEmailRules::new(vec![
+            EmailRule {
+                prompt_content: "Seat Geek Upcoming Events".to_string(),
+                mail_label: "seatgeek".to_string(),
+                associated_email_client_category: None,
+            },
+            EmailRule {
+                prompt_content: "Receipts".to_string(),
+                mail_label: "receipts".to_string(),
+                associated_email_client_category: None,
+            },
+        ])
+    }
+
+    fn config() -> FewShotConfig {
+        FewShotConfig {
+            enabled: true,
+            max_examples: 2,
+            token_budget: 400,
+            min_similarity: 0.1,
+            body_snippet_chars: 200,
+        }
+    }
+
+    fn correction(email_id: &str, corrected_category: Option<&str>) -> email_correction::Model {
+        email_correction::Model {
+            id: 0,
+            user_id: 1,
+            email_id: email_id.to_string(),
+            original_category: "receipts".to_string(),
+            corrected_category: corrected_category.map(str::to_string),
+            ai_answer: "Receipts".to_string(),
+            from: Some("events@seatgeek.com".to_string()),
+            subject: Some("Upcoming events near you".to_string()),
+            body_snippet: None,
+            created_at: chrono::Utc::now().into(),
+            updated_at: chrono::Utc::now().into(),
+        }
+    }
+
+    #[test]
+    fn test_apply_corrections_replaces_earlier_correction() {
+        let mut pool = FewShotPool::default();
+        let upcoming_events = message("events@seatgeek.com", "Upcoming events");
+
+        pool.apply_corrections(&[correction("1", Some("seatgeek"))]);
+        pool.apply_corrections(&[correction("1", Some("seatgeek"))]);
+        assert_eq!(pool.select(&upcoming_events, &rules(), &config()).len(), 1);
+
+        pool.apply_corrections(&[correction("1", None)]);
+        assert!(pool
+            .select(&upcoming_events, &rules(), &config())
+            .is_empty());
+    }
+
+    #[test]
+    fn test_sender_domain() {
+        assert_eq!(
+            sender_domain("SeatGeek <events@SeatGeek.com>"),
+            Some("seatgeek.com".to_string())
+        );
+        assert_eq!(
+            sender_domain("orders@shop.com"),
+            Some("shop.com".to_string())
+        );
+        assert_eq!(sender_domain("Unknown sender"), None);
+    }
+
+    #[test]
+    fn test_select_prefers_similar_examples() {
+        let pool = FewShotPool::new(vec![
+            example("receipts", "orders@shop.com", "Your order receipt"),
+            example(
+                "seatgeek",
+                "events@seatgeek.com",
+                "Upcoming events near you",
+            ),
+            example(
+                "removed",
+                "events@seatgeek.com",
+                "Upcoming events this week",
+            ),
+        ]);
+
+        let selected = pool.select(
+            &message(
+                "SeatGeek <events@seatgeek.com>",
+                "Upcoming events this weekend",
+            ),
+            &rules(),
+            &config(),
+        );
+
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].category, "Seat Geek Upcoming Events");
+        assert_eq!(selected[0].subject, "Upcoming events near you");
+    }
+
+    #[test]
+    fn test_select_respects_token_budget() {
+        let pool = FewShotPool::new(vec![
+            example(
+                "seatgeek",
+                "events@seatgeek.com",
+                "Upcoming events near you",
+            ),
+            example("seatgeek", "events@seatgeek.com", "Upcoming events tonight"),
+        ]);
+
+        let one_example_budget = FewShotConfig {
+            token_budget: 60,
+            ..config()
+        };
+
+        let selected = pool.select(
+            &message("events@seatgeek.com", "Upcoming events"),
+            &rules(),
+            &one_example_budget,
+        );
+
+        assert_eq!(selected.len(), 1);
+    }
+}
@@ -9,22 +9,121 @@ use serde_json::json;
 
 use crate::email::parsed_message::ParsedMessage;
 use crate::email::rules::UserEmailRules;
+use crate::prompt::few_shot::PromptExample;
 use crate::rate_limiters;
 use crate::HttpClient;
 use crate::{
     error::{AppError, AppResult},
-    server_config::cfg,
+    server_config::{cfg, ModelConfig, PromptProvider, PromptVersion},
 };
 
 const AI_ENDPOINT: &str = "https://api.mistral.ai/v1/chat/completions";
 
-fn system_prompt(prompt_categories: Vec<String>) -> String {
+fn system_prompt(version: PromptVersion, prompt_categories: Vec<String>) -> String {
+    let categories = prompt_categories.join(", ");
+    match version {
+        PromptVersion::V1 => formatdoc! {r#"
+            You are a helpful assistant that can categorize emails such as the categories inside the square brackets below.
+            [{categories}]
+            You should try to choose a single category from the above, along with its confidence score.
+            You will only respond with a JSON object with the keys category and confidence. Do not provide explanations or multiple categories."#},
+        PromptVersion::V2 => formatdoc! {r#"
+            You are a helpful assistant that sorts emails into exactly one of the categories inside the square brackets below.
+            [{categories}]
+            Choose the single best category. Set confidence between 0 and 1 to how likely the category is correct, using values below 0.5 when the email fits none of the categories well.
+            You will only respond with a JSON object with the keys category and confidence. Do not provide explanations or multiple categories."#},
+    }
+}
+
+fn system_prompt_with_examples(
+    version: PromptVersion,
+    prompt_categories: Vec<String>,
+    examples: &[PromptExample],
+) -> String {
+    let prompt = system_prompt(version, prompt_categories);
+    if examples.is_empty() {
+        return prompt;
+    }
+
     formatdoc! {r#"
-        You are a helpful assistant that can categorize emails such as the categories inside the square brackets below.
-        [{categories}]
-        You should try to choose a single category from the above, along with its confidence score.
-        You will only respond with a JSON object with the keys category and confidence. Do not provide explanations or multiple categories."#, 
-    categories = prompt_categories.join(", ")}
+        {prompt}
+        Here are some emails this user has already categorized, use them as a guide:
+        {examples}"#,
+    examples = examples.iter().map(|e| e.render()).collect::<Vec<_>>().join("\n")}
+}
+
+/// Appends hints from heuristic rules after the email content
+fn with_hints(email_content_str: String, hints: &[String]) -> String {
+    if hints.is_empty() {
+        return email_content_str;
+    }
+
+    format!(
+        "{email_content_str}\nHints about this email:\n{}",
+        hints
+            .iter()
+            .map(|h| format!("- {h}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    )
+}
+
+fn user_prompt(email_message: &ParsedMessage, hints: &[String]) -> String {
+    let subject = email_message.subject.as_ref().map_or("", |s| s.as_str());
+    let body = email_message.body.as_ref().map_or("", |s| s.as_str());
+    let email_content_str = format!("<subject>{}</subject>\n<body>{}</body>", subject, body);
+    let email_content_str = with_hints(email_content_str, hints);
+
+    format!("r#
+                  Categorize the following email based on the email subject between the <subject> tags and the email body between the <body> tags.
+                  {}
+                 #", email_content_str)
+}
+
+fn words(s: &str) -> Vec<String> {
+    s.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.len() >= 3)
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+/// Answers with the category sharing the most words with the email. Tokens are estimated at
+/// four characters each, like a real provider would bill the prompt.
+fn mock_category_prompt(
+    email_message: &ParsedMessage,
+    email_rules: &UserEmailRules,
+    system_prompt: &str,
+    user_prompt: &str,
+) -> CategoryPromptResponse {
+    let email_words = words(&format!(
+        "{} {} {}",
+        email_message.from.as_deref().unwrap_or_default(),
+        email_message.subject.as_deref().unwrap_or_default(),
+        email_message.body.as_deref().unwrap_or_default()
+    ));
+    let best = email_rules
+        .get_prompt_categories()
+        .into_iter()
+        .map(|category| {
+            let category_words = words(&category);
+            let hits = category_words
+                .iter()
+                .filter(|w| email_words.contains(w))
+                .count();
+            (hits as f32 / category_words.len().max(1) as f32, category)
+        })
+        .filter(|(score, _)| *score > 0.0)
+        .max_by(|a, b| a.0.total_cmp(&b.0));
+
+    let (category, confidence) = best.map_or(("Unknown".to_string(), 0.0), |(score, category)| {
+        (category, score)
+    });
+
+    CategoryPromptResponse {
+        category,
+        confidence,
+        token_usage: (system_prompt.len() + user_prompt.len()).div_ceil(4) as i64,
+    }
 }
 
 pub async fn send_category_prompt(
@@ -32,29 +131,40 @@ pub async fn send_category_prompt(
     rate_limiters: &rate_limiters::RateLimiters,
     email_message: &ParsedMessage,
     email_rules: &UserEmailRules,
+    examples: &[PromptExample],
+    hints: &[String],
+    model: &ModelConfig,
 ) -> AppResult<CategoryPromptResponse> {
-    let subject = email_message.subject.as_ref().map_or("", |s| s.as_str());
-    let body = email_message.body.as_ref().map_or("", |s| s.as_str());
-    let email_content_str = format!("<subject>{}</subject>\n<body>{}</body>", subject, body);
+    let system_prompt = system_prompt_with_examples(
+        model.prompt_version,
+        email_rules.get_prompt_categories(),
+        examples,
+    );
+    let user_prompt = user_prompt(email_message, hints);
+    if model.provider == PromptProvider::Mock {
+        return Ok(mock_category_prompt(
+            email_message,
+            email_rules,
+            &system_prompt,
+            &user_prompt,
+        ));
+    }
 
     let resp = http_client
         .post(AI_ENDPOINT)
         .bearer_auth(&cfg.api.key)
         .json(&json!(
           {
-            "model": &cfg.model.id,
-            "temperature": cfg.model.temperature,
+            "model": &model.id,
+            "temperature": model.temperature,
             "messages": [
               {
                 "role": "system",
-                "content": system_prompt(email_rules.get_prompt_categories())
+                "content": system_prompt
               },
               {
                 "role": "user",
-                "content": format!("r#
-                  Categorize the following email based on the email subject between the <subject> tags and the email body between the <body> tags.
-                  {}
-                 #", email_content_str)
+                "content": user_prompt
               }
             ],
             "response_format": { "type": "json_object" }
@@ -236,7 +346,73 @@ mod tests {
         "You will only respond with a JSON object with the keys category and confidence. Do not provide explanations or multiple categories.",
         );
 
-        assert_eq!(system_prompt(prompt_categories), expected);
+        assert_eq!(
+            system_prompt(PromptVersion::V1, prompt_categories),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_system_prompt_with_examples() {
+        let prompt_categories = vec!["category1".to_string(), "category2".to_string()];
+        assert_eq!(
+            system_prompt_with_examples(PromptVersion::V1, prompt_categories.clone(), &[]),
+            system_prompt(PromptVersion::V1, prompt_categories.clone())
+        );
+
+        let examples = vec![PromptExample {
+            category: "category2".to_string(),
+            from: "events@seatgeek.com".to_string(),
+            subject: "Upcoming events".to_string(),
+            body: "Tickets on sale".to_string(),
+        }];
+        let prompt = system_prompt_with_examples(PromptVersion::V1, prompt_categories, &examples);
+
+        assert!(prompt.ends_with(concat!(
+            "Here are some emails this user has already categorized, use them as a guide:\n",
+            "<example><from>events@seatgeek.com</from><subject>Upcoming events</subject>",
+            "<body>Tickets on sale</body><category>category2</category></example>"
+        )));
+    }
+
+    #[test]
+    fn test_with_hints() {
+        let content = "<subject>Hi</subject>".to_string();
+        assert_eq!(with_hints(content.clone(), &[]), content);
+        assert_eq!(
+            with_hints(content, &["Usually a receipt".to_string()]),
+            "<subject>Hi</subject>\nHints about this email:\n- Usually a receipt"
+        );
+    }
+
+    #[test]
+    fn test_mock_category_prompt() {
+        let email_rules = UserEmailRules::new(vec![
+            EmailRule {
+                prompt_content: "Order Receipt".to_string(),
+                mail_label: "receipts".to_string(),
+                associated_email_client_category: None,
+            },
+            EmailRule {
+                prompt_content: "Newsletter".to_string(),
+                mail_label: "newsletters".to_string(),
+                associated_email_client_category: None,
+            },
+        ]);
+        let msg = ParsedMessage {
+            from: Some("orders@shop.com".to_string()),
+            subject: Some("Your order receipt".to_string()),
+            ..Default::default()
+        };
+
+        let resp = mock_category_prompt(&msg, &email_rules, "system", &user_prompt(&msg, &[]));
+        assert_eq!(resp.category, "Order Receipt");
+        assert_eq!(resp.confidence, 1.0);
+        assert!(resp.token_usage > 0);
+
+        let resp = mock_category_prompt(&ParsedMessage::default(), &email_rules, "", "");
+        assert_eq!(resp.category, "Unknown");
+        assert_eq!(resp.confidence, 0.0);
     }
 
     #[tokio::test]
@@ -259,9 +435,17 @@ mod tests {
             associated_email_client_category: None,
         }]);
 
-        let resp = send_category_prompt(&http_client, &rate_limiters, &msg, &email_rules)
-            .await
-            .unwrap();
+        let resp = send_category_prompt(
+            &http_client,
+            &rate_limiters,
+            &msg,
+            &email_rules,
+            &[],
+            &[],
+            &cfg.model,
+        )
+        .await
+        .unwrap();
 
         assert_eq!(resp.category, test_content);
     }
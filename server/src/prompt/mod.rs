@@ -1,4 +1,8 @@
 pub(crate) mod converse;
+pub(crate) mod embeddings;
+pub(crate) mod fair_scheduler;
+pub(crate) mod few_shot;
 pub(crate) mod groq;
 pub(crate) mod mistral;
 pub(crate) mod priority_queue;
+pub(crate) mod shadow;
@@ -1,143 +1,318 @@
 use std::{
-    cmp::Ordering,
-    collections::{BinaryHeap, HashMap, HashSet},
-    sync::{Arc, Mutex, RwLock},
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
 };
 
+use chrono::{Duration, Utc};
+use entity::{prompt_queue_entry, sea_orm_active_enums::PromptPriority};
+use sea_orm::DatabaseConnection;
+
+use crate::{
+    email::processor::{parse_id_to_int, parse_int_to_id},
+    error::AppResult,
+    model::prompt_queue::PromptQueueCtrl,
+    server_config::cfg,
+};
+
+use super::fair_scheduler::DeficitRoundRobin;
+
+/// How long an entry for a user without a processor on this server waits before it is handed
+/// out again
+const RELEASE_DELAY_SECS: i64 = 5;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Priority {
     High,
     Low,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl From<Priority> for PromptPriority {
+    fn from(priority: Priority) -> Self {
+        match priority {
+            Priority::High => PromptPriority::High,
+            Priority::Low => PromptPriority::Low,
+        }
+    }
+}
+
+impl From<PromptPriority> for Priority {
+    fn from(priority: PromptPriority) -> Self {
+        match priority {
+            PromptPriority::High => Priority::High,
+            PromptPriority::Low => Priority::Low,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PromptQueueEmailEntry {
+    pub id: i32,
     pub user_email: String,
     pub email_id: u128,
     pub priority: Priority,
+    pub attempts: i32,
 }
 
-impl Ord for PromptQueueEmailEntry {
-    fn cmp(&self, other: &Self) -> Ordering {
-        use Priority::*;
-        match (self, other) {
-            (Self { priority: High, .. }, Self { priority: Low, .. }) => Ordering::Greater,
-            (Self { priority: Low, .. }, Self { priority: High, .. }) => Ordering::Less,
-            _ => Ordering::Equal,
+impl From<prompt_queue_entry::Model> for PromptQueueEmailEntry {
+    fn from(model: prompt_queue_entry::Model) -> Self {
+        Self {
+            id: model.id,
+            user_email: model.user_email,
+            email_id: parse_id_to_int(model.email_id),
+            priority: model.priority.into(),
+            attempts: model.attempts,
         }
     }
 }
 
-impl PartialOrd for PromptQueueEmailEntry {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
-}
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct QueueCount {
     pub high_priority: usize,
     pub low_priority: usize,
 }
 
+impl QueueCount {
+    fn add(&mut self, priority: Priority, n: usize) {
+        match priority {
+            Priority::High => self.high_priority += n,
+            Priority::Low => self.low_priority += n,
+        }
+    }
+
+    fn sub(&mut self, priority: Priority) {
+        let count = match priority {
+            Priority::High => &mut self.high_priority,
+            Priority::Low => &mut self.low_priority,
+        };
+        *count = count.checked_sub(1).unwrap_or_else(|| {
+            tracing::error!("Detected negative {:?} priority count", priority);
+            0
+        });
+    }
+}
+
+/// Queue of emails waiting to be prompted, stored in Postgres so it survives restarts and can
+/// be shared by several servers. The counts are cached, they are updated on every push and pop
+/// and replaced with the database's counts by `refresh_counts`.
+///
+/// Users take turns within each priority, weighted by their plan, so one large backlog can't
+/// hold up everyone else's emails.
 #[derive(Debug, Clone)]
 pub struct PromptPriorityQueue {
-    queue: Arc<Mutex<BinaryHeap<PromptQueueEmailEntry>>>,
+    conn: DatabaseConnection,
     num_in_queue_by_email_address: Arc<RwLock<HashMap<String, QueueCount>>>,
-    in_processing_set: Arc<Mutex<HashSet<u128>>>,
+    num_in_processing: Arc<AtomicUsize>,
+    weight_by_email_address: Arc<RwLock<HashMap<String, u32>>>,
+    high_priority_scheduler: Arc<Mutex<DeficitRoundRobin>>,
+    low_priority_scheduler: Arc<Mutex<DeficitRoundRobin>>,
 }
 
 impl PromptPriorityQueue {
-    pub fn new() -> Self {
+    pub fn new(conn: DatabaseConnection) -> Self {
         Self {
-            queue: Arc::new(Mutex::new(BinaryHeap::new())),
+            conn,
             num_in_queue_by_email_address: Arc::new(RwLock::new(HashMap::new())),
-            in_processing_set: Arc::new(Mutex::new(HashSet::new())),
+            num_in_processing: Arc::new(AtomicUsize::new(0)),
+            weight_by_email_address: Arc::new(RwLock::new(HashMap::new())),
+            high_priority_scheduler: Arc::new(Mutex::new(DeficitRoundRobin::new())),
+            low_priority_scheduler: Arc::new(Mutex::new(DeficitRoundRobin::new())),
         }
     }
 
-    pub fn push(&self, user_email: String, email_id: u128, priority: Priority) -> bool {
-        let mut queue = self.queue.lock().unwrap();
-        let mut num_in_queue_by_email_address = self.num_in_queue_by_email_address.write().unwrap();
-        let mut in_processing_set = self.in_processing_set.lock().unwrap();
+    /// The user whose turn it is, from the cached counts
+    fn next_user(&self) -> Option<String> {
+        let counts = self.num_in_queue_by_email_address.read().unwrap();
+        let weights = self.weight_by_email_address.read().unwrap();
+        let weight = |user: &str| weights.get(user).copied().unwrap_or(1);
+        let backlog = |priority: Priority| {
+            counts
+                .iter()
+                .map(|(user, count)| {
+                    let n = match priority {
+                        Priority::High => count.high_priority,
+                        Priority::Low => count.low_priority,
+                    };
+                    (user.clone(), n)
+                })
+                .collect::<HashMap<_, _>>()
+        };
 
-        // Check if the email is already in processing
-        // so emails are not processed multiple times
-        if !in_processing_set.insert(email_id) {
-            return false;
-        }
+        self.high_priority_scheduler
+            .lock()
+            .unwrap()
+            .next(&backlog(Priority::High), weight)
+            .or_else(|| {
+                self.low_priority_scheduler
+                    .lock()
+                    .unwrap()
+                    .next(&backlog(Priority::Low), weight)
+            })
+    }
 
-        queue.push(PromptQueueEmailEntry {
-            user_email: user_email.clone(),
-            email_id,
-            priority,
-        });
+    async fn claim(
+        &self,
+        user_email: Option<&str>,
+    ) -> AppResult<Option<prompt_queue_entry::Model>> {
+        PromptQueueCtrl::claim_next(
+            &self.conn,
+            user_email,
+            cfg.prompt_queue.visibility_timeout_secs,
+            cfg.prompt_queue.max_attempts,
+            cfg.prompt_queue.low_priority_aging_secs,
+        )
+        .await
+    }
 
-        num_in_queue_by_email_address
-            .entry(user_email)
-            .and_modify(|e| {
-                if priority == Priority::High {
-                    e.high_priority += 1;
-                } else {
-                    e.low_priority += 1;
-                }
-            })
-            .or_insert(if priority == Priority::High {
-                QueueCount {
-                    high_priority: 1,
-                    low_priority: 0,
-                }
-            } else {
-                QueueCount {
-                    high_priority: 0,
-                    low_priority: 1,
-                }
-            });
+    /// Returns false if the email is already queued or being processed, so emails are not
+    /// processed multiple times
+    pub async fn push(
+        &self,
+        user_email: String,
+        email_id: u128,
+        priority: Priority,
+    ) -> AppResult<bool> {
+        let inserted = PromptQueueCtrl::push(
+            &self.conn,
+            &user_email,
+            &parse_int_to_id(email_id),
+            priority.into(),
+        )
+        .await?;
 
-        true
+        if inserted {
+            self.num_in_queue_by_email_address
+                .write()
+                .unwrap()
+                .entry(user_email)
+                .or_default()
+                .add(priority, 1);
+            self.num_in_processing.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(inserted)
     }
 
-    pub fn pop(&self) -> Option<PromptQueueEmailEntry> {
-        let mut queue = self.queue.lock().unwrap();
-        let next = queue.pop();
-        if let Some(entry) = next {
+    pub async fn pop(&self) -> AppResult<Option<PromptQueueEmailEntry>> {
+        let mut next = None;
+        if let Some(user_email) = self.next_user() {
+            next = self.claim(Some(&user_email)).await?;
+        }
+        // The cached counts may be behind, e.g. when another server took the user's emails
+        if next.is_none() {
+            next = self.claim(None).await?;
+        }
+
+        let Some(model) = next else {
+            return Ok(None);
+        };
+        // Aged low priority emails are counted as high priority
+        let aged = model.created_at.to_utc()
+            <= Utc::now() - Duration::seconds(cfg.prompt_queue.low_priority_aging_secs);
+        let entry = PromptQueueEmailEntry::from(model);
+        let counted_as = if aged { Priority::High } else { entry.priority };
+
+        {
             let mut num_in_queue_by_email_address =
                 self.num_in_queue_by_email_address.write().unwrap();
 
             if let Some(count) = num_in_queue_by_email_address.get_mut(&entry.user_email) {
-                match entry.priority {
-                    Priority::High => {
-                        count.high_priority =
-                            count.high_priority.checked_sub(1).unwrap_or_else(|| {
-                                tracing::error!("Detected negative high priority count");
-                                0
-                            });
-                    }
-                    Priority::Low => {
-                        count.low_priority =
-                            count.low_priority.checked_sub(1).unwrap_or_else(|| {
-                                tracing::error!("Detected negative low priority count");
-                                0
-                            });
-                    }
-                }
+                count.sub(counted_as);
                 // Cleanup entry when counts are 0
                 if count.high_priority == 0 && count.low_priority == 0 {
                     num_in_queue_by_email_address.remove(&entry.user_email);
                 }
             }
-
-            Some(entry)
-        } else {
-            next
         }
+
+        Ok(Some(entry))
+    }
+
+    /// Hands a popped entry back, e.g. when its user has no processor on this server
+    pub async fn release(&self, entry: &PromptQueueEmailEntry) -> AppResult<()> {
+        PromptQueueCtrl::release(&self.conn, entry.id, Duration::seconds(RELEASE_DELAY_SECS))
+            .await?;
+
+        self.num_in_queue_by_email_address
+            .write()
+            .unwrap()
+            .entry(entry.user_email.clone())
+            .or_default()
+            .add(entry.priority, 1);
+
+        Ok(())
+    }
+
+    /// Keeps a popped entry from being handed out again while it is still being processed
+    pub async fn extend_lease(&self, entry: &PromptQueueEmailEntry) -> AppResult<()> {
+        PromptQueueCtrl::extend(
+            &self.conn,
+            entry.id,
+            cfg.prompt_queue.visibility_timeout_secs,
+        )
+        .await
     }
 
     // When a processor finishes processing an email, it should call this method
-    // to remove the email from the in_processing set
-    pub fn remove_from_processing(&self, email_id: u128) {
-        let mut in_processing_set = self.in_processing_set.lock().unwrap();
-        in_processing_set.remove(&email_id);
+    // to remove the email from the queue
+    pub async fn remove_from_processing(&self, entry: &PromptQueueEmailEntry) -> AppResult<()> {
+        PromptQueueCtrl::delete(
+            &self.conn,
+            &entry.user_email,
+            &parse_int_to_id(entry.email_id),
+        )
+        .await?;
+        // Saturating, the count may have been refreshed since the email was pushed
+        let _ = self
+            .num_in_processing
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                Some(n.saturating_sub(1))
+            });
+
+        Ok(())
+    }
+
+    /// Replaces the cached counts with the database's, which include emails queued by other
+    /// servers. Entries that ran out of attempts are dropped first.
+    pub async fn refresh_counts(&self) -> AppResult<()> {
+        let max_attempts = cfg.prompt_queue.max_attempts;
+        let dropped = PromptQueueCtrl::delete_exhausted(&self.conn, max_attempts).await?;
+        if dropped > 0 {
+            tracing::warn!(
+                "Dropped {} queued emails after {} attempts",
+                dropped,
+                max_attempts
+            );
+        }
+
+        let mut counts = HashMap::<String, QueueCount>::new();
+        let mut weights = HashMap::new();
+        let rows = PromptQueueCtrl::queued_counts(
+            &self.conn,
+            max_attempts,
+            cfg.prompt_queue.low_priority_aging_secs,
+        )
+        .await?;
+        for row in rows {
+            weights.insert(
+                row.user_email.clone(),
+                cfg.prompt_queue.weight_for_plan(row.plan.as_deref()),
+            );
+            counts
+                .entry(row.user_email)
+                .or_default()
+                .add(row.priority.into(), row.count as usize);
+        }
+        let total = PromptQueueCtrl::count_all(&self.conn).await?;
+
+        *self.num_in_queue_by_email_address.write().unwrap() = counts;
+        *self.weight_by_email_address.write().unwrap() = weights;
+        self.num_in_processing
+            .store(total as usize, Ordering::Relaxed);
+
+        Ok(())
     }
 
     pub fn num_in_queue(&self, email_address: &str) -> usize {
@@ -186,6 +361,25 @@ impl PromptPriorityQueue {
     }
 
     pub fn num_in_processing(&self) -> usize {
-        self.in_processing_set.lock().unwrap().len()
+        self.num_in_processing.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_count() {
+        let mut count = QueueCount::default();
+        count.add(Priority::High, 2);
+        count.add(Priority::Low, 1);
+        count.sub(Priority::High);
+        count.sub(Priority::Low);
+        // Counts never go negative
+        count.sub(Priority::Low);
+
+        assert_eq!(count.high_priority, 1);
+        assert_eq!(count.low_priority, 0);
     }
 }
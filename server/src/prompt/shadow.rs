@@ -0,0 +1,42 @@
+use crate::cluster::hash_ring::stable_hash;
+
+/// The candidate model's answer for an email, recorded next to the primary answer
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShadowAnswer {
+    pub model_id: String,
+    pub answer: String,
+    pub confidence: f32,
+}
+
+/// Picks emails for shadow prompting by their id, so retries of the same email are
+/// sampled the same way on every instance and release
+pub fn in_sample(email_id: &str, percentage: f64) -> bool {
+    if percentage <= 0.0 {
+        return false;
+    }
+    if percentage >= 100.0 {
+        return true;
+    }
+
+    let bucket = stable_hash(email_id) % 10_000;
+
+    (bucket as f64) < percentage * 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_sample() {
+        let ids = (0..10_000).map(|i| format!("{i:x}")).collect::<Vec<_>>();
+
+        assert!(ids.iter().all(|id| !in_sample(id, 0.0)));
+        assert!(ids.iter().all(|id| in_sample(id, 100.0)));
+
+        let sampled = ids.iter().filter(|id| in_sample(id, 10.0)).count();
+        assert!((800..1200).contains(&sampled), "sampled {sampled}");
+
+        assert_eq!(in_sample("abc", 25.0), in_sample("abc", 25.0));
+    }
+}
@@ -1,63 +1,250 @@
+mod postgres;
+pub mod store;
+
+use std::collections::HashMap;
 use std::sync::atomic::Ordering::Relaxed;
-use std::sync::{atomic::AtomicBool, Arc};
+use std::sync::{atomic::AtomicBool, Arc, Mutex};
 use tokio::time::Duration;
 
-use leaky_bucket::RateLimiter;
+use chrono::Utc;
+use lib_email_clients::gmail::api_quota::GMAIL_QUOTA_PER_SECOND;
+use sea_orm::DatabaseConnection;
+
+use crate::server_config::{cfg, LimiterBackend, RateLimitConfig};
+
+use postgres::PostgresLimiterStore;
+use store::{Acquire, BucketSpec, LimiterStore, MemoryLimiterStore};
+
+const LLM_KEY: &str = "llm";
+const GMAIL_PROJECT_KEY: &str = "gmail";
+
+fn gmail_user_key(user_email: &str) -> String {
+    format!("gmail:{}", user_email)
+}
+
+/// Token buckets in a store shared by the server instances. While the store can't be reached,
+/// each instance limits itself with buckets in memory instead.
+#[derive(Debug, Clone)]
+struct SharedLimiter {
+    store: Arc<dyn LimiterStore>,
+    fallback: Arc<MemoryLimiterStore>,
+    degraded: Arc<AtomicBool>,
+    /// Tokens taken from the store ahead of time, by key
+    reserves: Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<f64>>>>>,
+}
+
+impl SharedLimiter {
+    fn new(store: Arc<dyn LimiterStore>) -> Self {
+        Self {
+            store,
+            fallback: Arc::new(MemoryLimiterStore::new()),
+            degraded: Arc::new(AtomicBool::new(false)),
+            reserves: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn reserve(&self, key: &str) -> Arc<tokio::sync::Mutex<f64>> {
+        self.reserves
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone()
+    }
+
+    async fn try_acquire(&self, key: &str, spec: BucketSpec, cost: f64) -> Acquire {
+        match self.store.acquire(key, spec, cost).await {
+            Ok(result) => {
+                if self.degraded.swap(false, Relaxed) {
+                    tracing::info!("Shared rate limiter is back");
+                }
+                result
+            }
+            Err(e) => {
+                if !self.degraded.swap(true, Relaxed) {
+                    tracing::warn!(
+                        "Shared rate limiter unavailable, limiting in memory: {:?}",
+                        e
+                    );
+                }
+                // The memory store never fails
+                self.fallback
+                    .acquire(key, spec, cost)
+                    .await
+                    .unwrap_or(Acquire::Granted)
+            }
+        }
+    }
+
+    async fn acquire(&self, key: &str, spec: BucketSpec, cost: f64) {
+        while let Acquire::Wait(wait) = self.try_acquire(key, spec, cost).await {
+            // Other instances take from the same bucket, so the wait is only an estimate
+            tokio::time::sleep(wait.max(Duration::from_millis(10))).await;
+        }
+    }
+
+    /// Takes `block` tokens from the store whenever the local reserve runs out, and pays from
+    /// the reserve until then. Only one task per key waits on the store, the others wait for it.
+    async fn acquire_in_blocks(&self, key: &str, spec: BucketSpec, cost: f64, block: f64) {
+        let reserve = self.reserve(key);
+        let mut reserve = reserve.lock().await;
+        let cost = spec.clamp_cost(cost);
+        if *reserve < cost {
+            let block = spec.clamp_cost(block.max(cost - *reserve));
+            self.acquire(key, spec, block).await;
+            *reserve += block;
+        }
+        *reserve -= cost;
+    }
 
-use crate::server_config::cfg;
+    fn back_off(&self, key: String, duration: chrono::Duration) {
+        let until = Utc::now() + duration;
+        let limiter = self.clone();
+        tokio::spawn(async move {
+            // Tokens taken before the backoff are not spent
+            *limiter.reserve(&key).lock().await = 0.0;
+            let _ = limiter.fallback.back_off(&key, until).await;
+            if let Err(e) = limiter.store.back_off(&key, until).await {
+                tracing::error!("Error sharing backoff for {}: {:?}", key, e);
+            }
+        });
+    }
+}
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct RateLimiters {
-    prompt: Arc<RateLimiter>,
-    backoff: Arc<AtomicBool>,
-    backoff_duration: Duration,
+    limiter: SharedLimiter,
+    prompt: BucketSpec,
+    gmail_project: BucketSpec,
+    gmail_user: BucketSpec,
+    gmail_project_block: f64,
+    backoff_duration: chrono::Duration,
 }
 
 impl RateLimiters {
-    pub fn new(prompt_limit_per_sec: usize, interval_ms: usize, refill: usize) -> Self {
-        let prompt = RateLimiter::builder()
-            .initial(prompt_limit_per_sec)
-            .interval(Duration::from_millis(interval_ms as u64))
-            .max(prompt_limit_per_sec)
-            .refill(refill)
-            .build();
-
+    fn with_store(
+        store: Arc<dyn LimiterStore>,
+        rate_limits: &RateLimitConfig,
+        prompt_limit_per_sec: usize,
+        interval_ms: usize,
+        refill: usize,
+    ) -> Self {
         Self {
-            prompt: Arc::new(prompt),
-            backoff: Arc::new(AtomicBool::new(false)),
-            backoff_duration: Duration::from_secs(60),
+            limiter: SharedLimiter::new(store),
+            prompt: BucketSpec {
+                capacity: prompt_limit_per_sec as f64,
+                refill_per_sec: refill as f64 * 1000.0 / interval_ms.max(1) as f64,
+            },
+            gmail_project: BucketSpec {
+                capacity: rate_limits.gmail_project_units_per_sec as f64,
+                refill_per_sec: rate_limits.gmail_project_units_per_sec as f64,
+            },
+            gmail_user: BucketSpec {
+                capacity: GMAIL_QUOTA_PER_SECOND as f64,
+                refill_per_sec: GMAIL_QUOTA_PER_SECOND as f64,
+            },
+            gmail_project_block: rate_limits.gmail_project_block_units as f64,
+            backoff_duration: chrono::Duration::seconds(rate_limits.backoff_secs),
         }
     }
 
-    pub fn from_env() -> Self {
-        let prompt_limit_per_sec = cfg.api.prompt_limits.rate_limit_per_sec;
-        let interval_ms = cfg.api.prompt_limits.refill_interval_ms;
-        let refill = cfg.api.prompt_limits.refill_amount;
-        Self::new(prompt_limit_per_sec, interval_ms, refill)
+    /// Limits kept in this process only, with the default Gmail quotas. Doesn't read the
+    /// server config, so tools can use it.
+    pub fn new(prompt_limit_per_sec: usize, interval_ms: usize, refill: usize) -> Self {
+        Self::with_store(
+            Arc::new(MemoryLimiterStore::new()),
+            &RateLimitConfig::default(),
+            prompt_limit_per_sec,
+            interval_ms,
+            refill,
+        )
+    }
+
+    pub fn from_config(conn: DatabaseConnection) -> Self {
+        let store: Arc<dyn LimiterStore> = match cfg.rate_limits.backend {
+            LimiterBackend::Postgres => Arc::new(PostgresLimiterStore::new(conn)),
+            LimiterBackend::Memory => Arc::new(MemoryLimiterStore::new()),
+        };
+        let prompt_limits = &cfg.api.prompt_limits;
+        Self::with_store(
+            store,
+            &cfg.rate_limits,
+            prompt_limits.rate_limit_per_sec,
+            prompt_limits.refill_interval_ms,
+            prompt_limits.refill_amount,
+        )
     }
 
+    /// Waits for a prompt to the LLM
     pub async fn acquire_one(&self) {
-        if self.backoff.load(Relaxed) {
-            tokio::time::sleep(self.backoff_duration).await;
-        }
-        self.prompt.acquire_one().await;
+        self.limiter.acquire(LLM_KEY, self.prompt, 1.0).await;
     }
 
+    /// Waits for `units` of Gmail quota, from both the user's and the project's quota. The
+    /// project's quota is shared by every user, so it is taken in blocks.
+    pub async fn acquire_gmail(&self, user_email: &str, units: usize) {
+        let units = units as f64;
+        self.limiter
+            .acquire(&gmail_user_key(user_email), self.gmail_user, units)
+            .await;
+        self.limiter
+            .acquire_in_blocks(
+                GMAIL_PROJECT_KEY,
+                self.gmail_project,
+                units,
+                self.gmail_project_block,
+            )
+            .await;
+    }
+
+    /// Pauses prompts on every instance
     pub fn trigger_backoff(&self) {
         tracing::info!("Triggering backoff...");
-        self.backoff
-            .store(true, std::sync::atomic::Ordering::Relaxed);
-        let self_ = self.clone();
-        tokio::spawn(async move {
-            tokio::time::sleep(Duration::from_secs(60)).await;
-            tracing::info!("Backoff expired");
-            self_
-                .backoff
-                .store(false, std::sync::atomic::Ordering::Relaxed);
-        });
+        self.limiter
+            .back_off(LLM_KEY.to_string(), self.backoff_duration);
+    }
+
+    /// Pauses Gmail requests for the user on every instance
+    pub fn trigger_gmail_backoff(&self, user_email: &str) {
+        tracing::info!("Triggering Gmail backoff for {}...", user_email);
+        self.limiter
+            .back_off(gmail_user_key(user_email), self.backoff_duration);
     }
 
-    pub fn get_status(&self) -> String {
-        format!("{}/{}", self.prompt.balance(), self.prompt.max(),)
+    pub async fn get_status(&self) -> String {
+        let balance = self
+            .limiter
+            .store
+            .balance(LLM_KEY, self.prompt)
+            .await
+            .unwrap_or_default();
+        format!("{:.0}/{}", balance, self.prompt.capacity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: BucketSpec = BucketSpec {
+        capacity: 100.0,
+        refill_per_sec: 0.0,
+    };
+
+    #[tokio::test]
+    async fn test_acquire_in_blocks() {
+        let limiter = SharedLimiter::new(Arc::new(MemoryLimiterStore::new()));
+
+        limiter.acquire_in_blocks("gmail", SPEC, 5.0, 20.0).await;
+        assert_eq!(limiter.store.balance("gmail", SPEC).await.unwrap(), 80.0);
+
+        // Paid from the reserve until it runs out
+        for _ in 0..3 {
+            limiter.acquire_in_blocks("gmail", SPEC, 5.0, 20.0).await;
+        }
+        assert_eq!(limiter.store.balance("gmail", SPEC).await.unwrap(), 80.0);
+
+        limiter.acquire_in_blocks("gmail", SPEC, 5.0, 20.0).await;
+        assert_eq!(limiter.store.balance("gmail", SPEC).await.unwrap(), 60.0);
     }
 }
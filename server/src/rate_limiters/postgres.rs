@@ -0,0 +1,146 @@
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use sea_orm::{DatabaseConnection, DbBackend};
+
+use crate::{db_core::prelude::*, error::AppResult};
+
+use super::store::{Acquire, BucketSpec, LimiterStore};
+
+#[derive(Debug, FromQueryResult)]
+struct BucketBalance {
+    tokens: f64,
+    backoff_until: Option<DateTimeWithTimeZone>,
+}
+
+/// Buckets in the `rate_limit_bucket` table, shared by every server instance. Each bucket is
+/// refilled from its last update time, so nothing needs to top them up in the background.
+#[derive(Debug, Clone)]
+pub struct PostgresLimiterStore {
+    conn: DatabaseConnection,
+}
+
+impl PostgresLimiterStore {
+    pub fn new(conn: DatabaseConnection) -> Self {
+        Self { conn }
+    }
+
+    async fn try_take(&self, key: &str, spec: BucketSpec, cost: f64) -> AppResult<bool> {
+        let raw_sql = r#"
+            INSERT INTO rate_limit_bucket AS bucket (key, tokens, updated_at)
+            VALUES ($1, $2 - $4, NOW())
+            ON CONFLICT (key) DO UPDATE SET
+                tokens = LEAST(
+                    $2,
+                    bucket.tokens
+                        + EXTRACT(EPOCH FROM NOW() - bucket.updated_at)::DOUBLE PRECISION * $3
+                ) - $4,
+                updated_at = NOW()
+            WHERE
+                LEAST(
+                    $2,
+                    bucket.tokens
+                        + EXTRACT(EPOCH FROM NOW() - bucket.updated_at)::DOUBLE PRECISION * $3
+                ) >= $4
+                AND (bucket.backoff_until IS NULL OR bucket.backoff_until <= NOW())
+        "#;
+
+        let result = self
+            .conn
+            .execute(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                raw_sql,
+                [
+                    key.into(),
+                    spec.capacity.into(),
+                    spec.refill_per_sec.into(),
+                    cost.into(),
+                ],
+            ))
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn current(&self, key: &str, spec: BucketSpec) -> AppResult<Option<BucketBalance>> {
+        let raw_sql = r#"
+            SELECT
+                LEAST(
+                    $2,
+                    tokens + EXTRACT(EPOCH FROM NOW() - updated_at)::DOUBLE PRECISION * $3
+                ) AS tokens,
+                backoff_until
+            FROM
+                rate_limit_bucket
+            WHERE
+                key = $1
+        "#;
+
+        let balance = BucketBalance::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            raw_sql,
+            [key.into(), spec.capacity.into(), spec.refill_per_sec.into()],
+        ))
+        .one(&self.conn)
+        .await?;
+
+        Ok(balance)
+    }
+}
+
+impl LimiterStore for PostgresLimiterStore {
+    fn acquire<'a>(
+        &'a self,
+        key: &'a str,
+        spec: BucketSpec,
+        cost: f64,
+    ) -> BoxFuture<'a, AppResult<Acquire>> {
+        Box::pin(async move {
+            let cost = spec.clamp_cost(cost);
+            if self.try_take(key, spec, cost).await? {
+                return Ok(Acquire::Granted);
+            }
+
+            let Some(balance) = self.current(key, spec).await? else {
+                // Removed in the meantime, it will be recreated full
+                return Ok(Acquire::Wait(Default::default()));
+            };
+            let backoff = balance
+                .backoff_until
+                .and_then(|until| (until.to_utc() - Utc::now()).to_std().ok());
+
+            Ok(Acquire::Wait(
+                backoff.unwrap_or_else(|| spec.wait_for(balance.tokens, cost)),
+            ))
+        })
+    }
+
+    fn back_off<'a>(&'a self, key: &'a str, until: DateTime<Utc>) -> BoxFuture<'a, AppResult<()>> {
+        Box::pin(async move {
+            let raw_sql = r#"
+                INSERT INTO rate_limit_bucket AS bucket (key, tokens, backoff_until)
+                VALUES ($1, 0, $2)
+                ON CONFLICT (key) DO UPDATE SET
+                    backoff_until = GREATEST(bucket.backoff_until, EXCLUDED.backoff_until)
+            "#;
+
+            self.conn
+                .execute(Statement::from_sql_and_values(
+                    DbBackend::Postgres,
+                    raw_sql,
+                    [key.into(), until.into()],
+                ))
+                .await?;
+
+            Ok(())
+        })
+    }
+
+    fn balance<'a>(&'a self, key: &'a str, spec: BucketSpec) -> BoxFuture<'a, AppResult<f64>> {
+        Box::pin(async move {
+            Ok(self
+                .current(key, spec)
+                .await?
+                .map_or(spec.capacity, |balance| balance.tokens))
+        })
+    }
+}
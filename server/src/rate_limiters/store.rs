@@ -0,0 +1,189 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+
+use crate::error::AppResult;
+
+/// Size and refill rate of a token bucket
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketSpec {
+    pub capacity: f64,
+    pub refill_per_sec: f64,
+}
+
+impl BucketSpec {
+    /// Tokens in a bucket that had `tokens` `elapsed_secs` ago
+    pub fn refilled(&self, tokens: f64, elapsed_secs: f64) -> f64 {
+        (tokens + elapsed_secs.max(0.0) * self.refill_per_sec).min(self.capacity)
+    }
+
+    /// How long until a bucket with `tokens` can pay `cost`
+    pub fn wait_for(&self, tokens: f64, cost: f64) -> Duration {
+        if tokens >= cost || self.refill_per_sec <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((cost - tokens) / self.refill_per_sec)
+    }
+
+    /// A cost above the capacity could never be paid, it takes the whole bucket instead
+    pub fn clamp_cost(&self, cost: f64) -> f64 {
+        cost.min(self.capacity)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Acquire {
+    Granted,
+    Wait(Duration),
+}
+
+/// Where the token buckets live. Buckets are created full the first time they are used.
+pub trait LimiterStore: Send + Sync + Debug {
+    fn acquire<'a>(
+        &'a self,
+        key: &'a str,
+        spec: BucketSpec,
+        cost: f64,
+    ) -> BoxFuture<'a, AppResult<Acquire>>;
+
+    /// Holds the bucket until `until`, e.g. when the provider asks us to slow down
+    fn back_off<'a>(&'a self, key: &'a str, until: DateTime<Utc>) -> BoxFuture<'a, AppResult<()>>;
+
+    fn balance<'a>(&'a self, key: &'a str, spec: BucketSpec) -> BoxFuture<'a, AppResult<f64>>;
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    updated_at: Instant,
+    backoff_until: Option<Instant>,
+}
+
+/// Buckets in this process only
+#[derive(Debug, Default)]
+pub struct MemoryLimiterStore {
+    buckets: Mutex<HashMap<String, BucketState>>,
+}
+
+impl MemoryLimiterStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn acquire_now(&self, key: &str, spec: BucketSpec, cost: f64) -> Acquire {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(key.to_string()).or_insert(BucketState {
+            tokens: spec.capacity,
+            updated_at: now,
+            backoff_until: None,
+        });
+
+        if let Some(until) = bucket.backoff_until {
+            if until > now {
+                return Acquire::Wait(until - now);
+            }
+            bucket.backoff_until = None;
+        }
+
+        bucket.tokens = spec.refilled(bucket.tokens, (now - bucket.updated_at).as_secs_f64());
+        bucket.updated_at = now;
+
+        let cost = spec.clamp_cost(cost);
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            Acquire::Granted
+        } else {
+            Acquire::Wait(spec.wait_for(bucket.tokens, cost))
+        }
+    }
+}
+
+impl LimiterStore for MemoryLimiterStore {
+    fn acquire<'a>(
+        &'a self,
+        key: &'a str,
+        spec: BucketSpec,
+        cost: f64,
+    ) -> BoxFuture<'a, AppResult<Acquire>> {
+        let result = self.acquire_now(key, spec, cost);
+        Box::pin(async move { Ok(result) })
+    }
+
+    fn back_off<'a>(&'a self, key: &'a str, until: DateTime<Utc>) -> BoxFuture<'a, AppResult<()>> {
+        let until = Instant::now() + (until - Utc::now()).to_std().unwrap_or_default();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(key.to_string()).or_insert(BucketState {
+            tokens: 0.0,
+            updated_at: Instant::now(),
+            backoff_until: None,
+        });
+        bucket.backoff_until = bucket.backoff_until.max(Some(until));
+
+        Box::pin(async { Ok(()) })
+    }
+
+    fn balance<'a>(&'a self, key: &'a str, spec: BucketSpec) -> BoxFuture<'a, AppResult<f64>> {
+        let balance = self
+            .buckets
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|bucket| spec.refilled(bucket.tokens, bucket.updated_at.elapsed().as_secs_f64()))
+            .unwrap_or(spec.capacity);
+
+        Box::pin(async move { Ok(balance) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: BucketSpec = BucketSpec {
+        capacity: 10.0,
+        refill_per_sec: 5.0,
+    };
+
+    #[test]
+    fn test_bucket_spec() {
+        assert_eq!(SPEC.refilled(2.0, 1.0), 7.0);
+        assert_eq!(SPEC.refilled(8.0, 1.0), 10.0);
+        assert_eq!(SPEC.wait_for(2.0, 4.5), Duration::from_millis(500));
+        assert_eq!(SPEC.wait_for(5.0, 4.0), Duration::ZERO);
+        assert_eq!(SPEC.clamp_cost(25.0), 10.0);
+    }
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        let store = MemoryLimiterStore::new();
+        assert_eq!(
+            store.acquire("a", SPEC, 6.0).await.unwrap(),
+            Acquire::Granted
+        );
+        assert!(matches!(
+            store.acquire("a", SPEC, 6.0).await.unwrap(),
+            Acquire::Wait(_)
+        ));
+        // Buckets are independent
+        assert_eq!(
+            store.acquire("b", SPEC, 6.0).await.unwrap(),
+            Acquire::Granted
+        );
+
+        store
+            .back_off("b", Utc::now() + chrono::Duration::seconds(60))
+            .await
+            .unwrap();
+        match store.acquire("b", SPEC, 1.0).await.unwrap() {
+            Acquire::Wait(wait) => assert!(wait > Duration::from_secs(50)),
+            Acquire::Granted => panic!("Backed off bucket granted tokens"),
+        }
+    }
+}
@@ -15,6 +15,7 @@ use crate::{
         response::{CheckAccountConnectionResponse, GmailAccountConnectionStatus, GoogleTokenInfo},
         user::{AccountAccess, UserCtrl},
     },
+    rate_limiters::RateLimiters,
     server_config::cfg,
     HttpClient,
 };
@@ -102,6 +103,7 @@ pub struct AccountConnectionQuery {
 pub async fn check_account_connection(
     State(http_client): State<HttpClient>,
     State(conn): State<DatabaseConnection>,
+    State(rate_limiters): State<RateLimiters>,
     Query(query): Query<AccountConnectionQuery>,
 ) -> AppJsonResult<CheckAccountConnectionResponse> {
     let user_access = match UserCtrl::get_with_account_access_by_email(&conn, &query.email).await {
@@ -117,8 +119,13 @@ pub async fn check_account_connection(
         }
     };
 
-    let email_client =
-        EmailClient::new(http_client.clone(), conn.clone(), user_access.clone()).await?;
+    let email_client = EmailClient::new(
+        http_client.clone(),
+        conn.clone(),
+        rate_limiters,
+        user_access.clone(),
+    )
+    .await?;
 
     let mut failed_checks = vec![];
 
@@ -191,9 +198,11 @@ mod tests {
             email: EMAIL.to_string(),
         };
 
+        let rate_limiters = RateLimiters::from_config(conn.clone());
         let check = check_account_connection(
             State(http_client.clone()),
             State(conn.clone()),
+            State(rate_limiters),
             Query(query),
         )
         .await
@@ -212,9 +221,11 @@ mod tests {
             email: EMAIL.to_string(),
         };
 
+        let rate_limiters = RateLimiters::from_config(conn.clone());
         let check = check_account_connection(
             State(http_client.clone()),
             State(conn.clone()),
+            State(rate_limiters),
             Query(query),
         )
         .await
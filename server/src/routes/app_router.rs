@@ -1,10 +1,18 @@
-use axum::{http::StatusCode, response::IntoResponse, routing::get, Router};
+use axum::{
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, post, put},
+    Router,
+};
 use tower_cookies::CookieManagerLayer;
 use tower_http::cors::CorsLayer;
 
 use crate::{request_tracing, ServerState};
 
-use super::{account_connection, auth};
+use super::{
+    account_connection, auth, backfill, cluster, dead_letters, examples, important_categories,
+    inbox_settings, label_settings, processor_state, reclassification, rules, stats,
+};
 
 pub struct AppRouter;
 
@@ -28,6 +36,92 @@ impl AppRouter {
                 "/refresh_user_token/:user_email",
                 get(auth::handler_refresh_user_token),
             )
+            .route(
+                "/accuracy_stats/:user_email",
+                get(stats::handler_accuracy_stats),
+            )
+            .route("/shadow_agreement", get(stats::handler_shadow_agreement))
+            .route(
+                "/examples/:user_email",
+                get(examples::handler_list_examples).post(examples::handler_create_example),
+            )
+            .route(
+                "/examples/:user_email/:id",
+                put(examples::handler_update_example).delete(examples::handler_delete_example),
+            )
+            .route(
+                "/rules/:user_email",
+                get(rules::handler_list_rules).post(rules::handler_create_rule),
+            )
+            .route(
+                "/rules/:user_email/:id",
+                put(rules::handler_update_rule).delete(rules::handler_delete_rule),
+            )
+            .route(
+                "/rules/dry_run/:user_email",
+                post(rules::handler_rule_dry_run),
+            )
+            .route(
+                "/reclassification/:user_email",
+                get(reclassification::handler_list_reclassifications)
+                    .post(reclassification::handler_create_reclassification),
+            )
+            .route(
+                "/reclassification/:user_email/:id/cancel",
+                post(reclassification::handler_cancel_reclassification),
+            )
+            .route(
+                "/label_settings/:user_email",
+                get(label_settings::handler_list_label_settings),
+            )
+            .route(
+                "/label_settings/:user_email/:category",
+                put(label_settings::handler_upsert_label_setting)
+                    .delete(label_settings::handler_delete_label_setting),
+            )
+            .route(
+                "/inbox_settings/:user_email",
+                get(inbox_settings::handler_list_inbox_settings),
+            )
+            .route(
+                "/inbox_settings/:user_email/:category",
+                put(inbox_settings::handler_upsert_inbox_setting)
+                    .delete(inbox_settings::handler_delete_inbox_setting),
+            )
+            .route(
+                "/important_categories/:user_email",
+                get(important_categories::handler_list_important_categories),
+            )
+            .route(
+                "/important_categories/:user_email/:category",
+                put(important_categories::handler_set_category_importance),
+            )
+            .route(
+                "/dead_letters",
+                get(dead_letters::handler_list_dead_letters),
+            )
+            .route(
+                "/dead_letters/:id/requeue",
+                post(dead_letters::handler_requeue_dead_letter),
+            )
+            .route("/cluster/status", get(cluster::handler_cluster_status))
+            .route(
+                "/processor_state/:user_email",
+                get(processor_state::handler_processor_state),
+            )
+            .route("/backfill/:user_email", get(backfill::handler_get_backfill))
+            .route(
+                "/backfill/:user_email/start",
+                post(backfill::handler_start_backfill),
+            )
+            .route(
+                "/backfill/:user_email/pause",
+                post(backfill::handler_pause_backfill),
+            )
+            .route(
+                "/backfill/:user_email/resume",
+                post(backfill::handler_resume_backfill),
+            )
             .layer(request_tracing::trace_with_request_id_layer())
             .layer(CorsLayer::permissive())
             .layer(CookieManagerLayer::new())
@@ -129,8 +129,11 @@ pub async fn handler_auth_gmail_callback(
         }
     }
 
-    let email_client =
-        EmailClient::from_access_code(state.http_client.clone(), resp.access_token.clone());
+    let email_client = EmailClient::from_access_code(
+        state.http_client.clone(),
+        state.rate_limiters.clone(),
+        resp.access_token.clone(),
+    );
     let profile = email_client
         .get_profile()
         .await
@@ -158,6 +161,7 @@ pub async fn handler_auth_gmail_callback(
         created_at: ActiveValue::NotSet,
         updated_at: ActiveValue::NotSet,
         subscription_status: ActiveValue::NotSet,
+        plan: ActiveValue::NotSet,
         last_payment_attempt_at: ActiveValue::NotSet,
         last_successful_payment_at: ActiveValue::NotSet,
     })
@@ -0,0 +1,114 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chrono::Utc;
+use sea_orm::DatabaseConnection;
+use serde_json::{json, Value};
+
+use crate::{
+    db_core::prelude::*,
+    error::{AppError, AppJsonResult, AppResult},
+    model::{
+        mailbox_backfill::{backfill_cutoff, BackfillProgress, MailboxBackfillCtrl},
+        prompt_queue::PromptQueueCtrl,
+        user::UserCtrl,
+    },
+};
+
+pub async fn handler_get_backfill(
+    State(conn): State<DatabaseConnection>,
+    Path(user_email): Path<String>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    let backfill = MailboxBackfillCtrl::get_by_user(&conn, user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No backfill for {}", user_email)))?;
+
+    Ok(Json(backfill_json(&conn, &user_email, backfill).await?))
+}
+
+/// Lists the whole mailbox again, back to the configured max email age
+pub async fn handler_start_backfill(
+    State(conn): State<DatabaseConnection>,
+    Path(user_email): Path<String>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    let backfill = MailboxBackfillCtrl::start(&conn, user.id, backfill_cutoff()).await?;
+
+    Ok(Json(backfill_json(&conn, &user_email, backfill).await?))
+}
+
+/// Stops listing older emails, the ones already queued are still processed
+pub async fn handler_pause_backfill(
+    State(conn): State<DatabaseConnection>,
+    Path(user_email): Path<String>,
+) -> AppJsonResult<Value> {
+    set_status(
+        &conn,
+        &user_email,
+        BackfillStatus::Running,
+        BackfillStatus::Paused,
+    )
+    .await
+    .map(Json)
+}
+
+pub async fn handler_resume_backfill(
+    State(conn): State<DatabaseConnection>,
+    Path(user_email): Path<String>,
+) -> AppJsonResult<Value> {
+    set_status(
+        &conn,
+        &user_email,
+        BackfillStatus::Paused,
+        BackfillStatus::Running,
+    )
+    .await
+    .map(Json)
+}
+
+async fn set_status(
+    conn: &DatabaseConnection,
+    user_email: &str,
+    from: BackfillStatus,
+    to: BackfillStatus,
+) -> AppResult<Value> {
+    let user = UserCtrl::get_by_email(conn, user_email).await?;
+    if !MailboxBackfillCtrl::set_status(conn, user.id, from, to).await? {
+        return Err(AppError::Conflict(format!(
+            "Backfill for {} is not {:?}",
+            user_email, from
+        )));
+    }
+    let backfill = MailboxBackfillCtrl::get_by_user(conn, user.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No backfill for {}", user_email)))?;
+
+    backfill_json(conn, user_email, backfill).await
+}
+
+async fn backfill_json(
+    conn: &DatabaseConnection,
+    user_email: &str,
+    backfill: mailbox_backfill::Model,
+) -> AppResult<Value> {
+    // Only the backfill queues older emails in low priority
+    let pending = PromptQueueCtrl::count_by_user(conn, user_email, PromptPriority::Low).await?;
+    let progress = BackfillProgress::new(&backfill, pending as i64, Utc::now());
+
+    Ok(json!({
+        "user_email": user_email,
+        "status": format!("{:?}", backfill.status),
+        "not_before": backfill.not_before,
+        "scanned": progress.scanned,
+        "queued": progress.queued,
+        "done": progress.done,
+        "pending": progress.pending,
+        "estimated_total": progress.estimated_total,
+        "eta_secs": progress.eta.map(|eta| eta.num_seconds()),
+        "started_at": backfill.started_at,
+        "completed_at": backfill.completed_at,
+        "updated_at": backfill.updated_at,
+    }))
+}
@@ -0,0 +1,36 @@
+use axum::{extract::State, Json};
+use chrono::Duration;
+use sea_orm::DatabaseConnection;
+use serde_json::{json, Value};
+
+use crate::{
+    cluster::ClusterMembership, error::AppJsonResult, model::server_instance::ServerInstanceCtrl,
+    server_config::cfg,
+};
+
+pub async fn handler_cluster_status(
+    State(conn): State<DatabaseConnection>,
+    State(cluster): State<ClusterMembership>,
+) -> AppJsonResult<Value> {
+    let leader = cluster.leader.current_leader(&conn).await?;
+    let instances =
+        ServerInstanceCtrl::live(&conn, Duration::seconds(cfg.cluster.instance_ttl_secs)).await?;
+
+    Ok(Json(json!({
+        "instance_id": cluster.instance_id,
+        "is_leader": cluster.leader.is_leader(),
+        "leader": leader.map(|(instance_id, expires_at)| json!({
+            "instance_id": instance_id,
+            "lease_expires_at": expires_at,
+        })),
+        "instances": instances
+            .into_iter()
+            .map(|instance| json!({
+                "id": instance.id,
+                "hostname": instance.hostname,
+                "started_at": instance.started_at,
+                "last_heartbeat_at": instance.last_heartbeat_at,
+            }))
+            .collect::<Vec<_>>(),
+    })))
+}
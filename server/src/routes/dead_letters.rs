@@ -0,0 +1,69 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use sea_orm::DatabaseConnection;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    db_core::prelude::*,
+    email::processor::parse_id_to_int,
+    error::AppJsonResult,
+    model::{dead_letter::DeadLetterCtrl, user::UserCtrl},
+    prompt::priority_queue::{Priority, PromptPriorityQueue},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct DeadLetterQuery {
+    pub user_email: Option<String>,
+}
+
+pub async fn handler_list_dead_letters(
+    State(conn): State<DatabaseConnection>,
+    Query(query): Query<DeadLetterQuery>,
+) -> AppJsonResult<Value> {
+    let user_id = match &query.user_email {
+        Some(user_email) => Some(UserCtrl::get_by_email(&conn, user_email).await?.id),
+        None => None,
+    };
+    let dead_letters = DeadLetterCtrl::all(&conn, user_id).await?;
+
+    Ok(Json(json!(dead_letters
+        .into_iter()
+        .map(dead_letter_json)
+        .collect::<Vec<_>>())))
+}
+
+/// Gives the email another full set of attempts, starting now
+pub async fn handler_requeue_dead_letter(
+    State(conn): State<DatabaseConnection>,
+    State(priority_queue): State<PromptPriorityQueue>,
+    Path(id): Path<i32>,
+) -> AppJsonResult<Value> {
+    let dead_letter = DeadLetterCtrl::take(&conn, id).await?;
+    let user = UserCtrl::get_by_id(&conn, dead_letter.user_id).await?;
+    let queued = priority_queue
+        .push(
+            user.email,
+            parse_id_to_int(dead_letter.email_id.clone()),
+            Priority::High,
+        )
+        .await?;
+
+    Ok(Json(json!({
+        "dead_letter": dead_letter_json(dead_letter),
+        "queued": queued,
+    })))
+}
+
+fn dead_letter_json(dead_letter: dead_letter::Model) -> Value {
+    json!({
+        "id": dead_letter.id,
+        "user_id": dead_letter.user_id,
+        "email_id": dead_letter.email_id,
+        "attempts": dead_letter.attempts,
+        "last_error": dead_letter.last_error,
+        "created_at": dead_letter.created_at,
+    })
+}
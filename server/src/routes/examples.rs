@@ -0,0 +1,104 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use sea_orm::DatabaseConnection;
+use serde_json::{json, Value};
+
+use crate::{
+    db_core::prelude::*,
+    email::rules::UserEmailRules,
+    error::{AppError, AppJsonResult, AppResult},
+    model::{
+        email_example::{EmailExampleCtrl, EmailExampleInput},
+        user::UserCtrl,
+    },
+};
+
+async fn validate_example(
+    conn: &DatabaseConnection,
+    user_id: i32,
+    input: &EmailExampleInput,
+) -> AppResult<()> {
+    let has_content = [&input.from, &input.subject, &input.body_snippet]
+        .iter()
+        .any(|field| field.as_ref().is_some_and(|f| !f.trim().is_empty()));
+    if !has_content {
+        return Err(AppError::BadRequest(
+            "Example needs a sender, subject or body".to_string(),
+        ));
+    }
+
+    let email_rules = UserEmailRules::from_user(conn, user_id).await?;
+    if !email_rules
+        .data()
+        .iter()
+        .any(|r| r.mail_label == input.category)
+    {
+        return Err(AppError::BadRequest(format!(
+            "Unknown category: {}",
+            input.category
+        )));
+    }
+
+    Ok(())
+}
+
+pub async fn handler_list_examples(
+    State(conn): State<DatabaseConnection>,
+    Path(user_email): Path<String>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    let examples = EmailExampleCtrl::all_by_user(&conn, user.id).await?;
+
+    Ok(Json(json!(examples
+        .into_iter()
+        .map(example_json)
+        .collect::<Vec<_>>())))
+}
+
+pub async fn handler_create_example(
+    State(conn): State<DatabaseConnection>,
+    Path(user_email): Path<String>,
+    Json(input): Json<EmailExampleInput>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    validate_example(&conn, user.id, &input).await?;
+    let example = EmailExampleCtrl::create(&conn, user.id, input).await?;
+
+    Ok(Json(example_json(example)))
+}
+
+pub async fn handler_update_example(
+    State(conn): State<DatabaseConnection>,
+    Path((user_email, id)): Path<(String, i32)>,
+    Json(input): Json<EmailExampleInput>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    validate_example(&conn, user.id, &input).await?;
+    let example = EmailExampleCtrl::update(&conn, user.id, id, input).await?;
+
+    Ok(Json(example_json(example)))
+}
+
+pub async fn handler_delete_example(
+    State(conn): State<DatabaseConnection>,
+    Path((user_email, id)): Path<(String, i32)>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    EmailExampleCtrl::delete(&conn, user.id, id).await?;
+
+    Ok(Json(json!({ "deleted": id })))
+}
+
+fn example_json(example: email_example::Model) -> Value {
+    json!({
+        "id": example.id,
+        "category": example.category,
+        "from": example.from,
+        "subject": example.subject,
+        "body_snippet": example.body_snippet,
+        "created_at": example.created_at,
+        "updated_at": example.updated_at,
+    })
+}
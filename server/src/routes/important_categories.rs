@@ -0,0 +1,58 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use sea_orm::DatabaseConnection;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    email::rules::UserEmailRules,
+    error::{AppError, AppJsonResult},
+    model::{default_email_rule_override::DefaultEmailRuleOverrideCtrl, user::UserCtrl},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ImportanceInput {
+    /// `null` goes back to the category's default
+    pub is_important: Option<bool>,
+}
+
+pub async fn handler_list_important_categories(
+    State(conn): State<DatabaseConnection>,
+    Path(user_email): Path<String>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    let important = DefaultEmailRuleOverrideCtrl::important_categories(&conn, user.id).await?;
+
+    let mut categories = important.iter().cloned().collect::<Vec<_>>();
+    categories.sort();
+
+    Ok(Json(json!(categories)))
+}
+
+/// Overrides whether one of the default categories is important for the user
+pub async fn handler_set_category_importance(
+    State(conn): State<DatabaseConnection>,
+    Path((user_email, category)): Path<(String, String)>,
+    Json(input): Json<ImportanceInput>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    if !UserEmailRules::default_labels().contains(&category) {
+        return Err(AppError::BadRequest(format!(
+            "Unknown default category: {}",
+            category
+        )));
+    }
+
+    let default_important = DefaultEmailRuleOverrideCtrl::default_important().contains(&category);
+    let ro =
+        DefaultEmailRuleOverrideCtrl::set_important(&conn, user.id, &category, input.is_important)
+            .await?;
+
+    Ok(Json(json!({
+        "category": ro.category,
+        "is_important": ro.is_important.unwrap_or(default_important) && !ro.is_disabled,
+        "is_default": ro.is_important.is_none(),
+    })))
+}
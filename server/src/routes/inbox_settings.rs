@@ -0,0 +1,76 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use sea_orm::DatabaseConnection;
+use serde_json::{json, Value};
+
+use crate::{
+    error::{AppError, AppJsonResult},
+    model::{
+        inbox_settings::{InboxSetting, UserInboxSettingsCtrl},
+        user::UserCtrl,
+    },
+};
+
+use super::label_settings::validate_category;
+
+/// Settings for every category that has any, with the user's settings applied over the
+/// defaults
+pub async fn handler_list_inbox_settings(
+    State(conn): State<DatabaseConnection>,
+    Path(user_email): Path<String>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    let user_settings = UserInboxSettingsCtrl::get(&conn, user.id).await?;
+    let settings = UserInboxSettingsCtrl::for_user(&conn, user.id).await?;
+
+    let mut settings = settings
+        .iter()
+        .map(|(category, setting)| {
+            json!({
+                "category": category,
+                "skip_inbox": setting.skip_inbox,
+                "mark_spam": setting.mark_spam,
+                "is_default": !user_settings.contains_key(category),
+            })
+        })
+        .collect::<Vec<_>>();
+    settings.sort_by(|a, b| a["category"].as_str().cmp(&b["category"].as_str()));
+
+    Ok(Json(json!(settings)))
+}
+
+pub async fn handler_upsert_inbox_setting(
+    State(conn): State<DatabaseConnection>,
+    Path((user_email, category)): Path<(String, String)>,
+    Json(input): Json<InboxSetting>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    validate_category(&conn, user.id, &category).await?;
+    if input.skip_inbox && input.mark_spam {
+        return Err(AppError::BadRequest(
+            "Emails marked as spam already leave the inbox".to_string(),
+        ));
+    }
+
+    let setting = UserInboxSettingsCtrl::upsert(&conn, user.id, &category, input).await?;
+
+    Ok(Json(json!({
+        "category": setting.category,
+        "skip_inbox": setting.skip_inbox,
+        "mark_spam": setting.mark_spam,
+        "is_default": false,
+    })))
+}
+
+/// Removes the user's setting so the category goes back to its default
+pub async fn handler_delete_inbox_setting(
+    State(conn): State<DatabaseConnection>,
+    Path((user_email, category)): Path<(String, String)>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    UserInboxSettingsCtrl::delete(&conn, user.id, &category).await?;
+
+    Ok(Json(json!({ "deleted": category })))
+}
@@ -0,0 +1,119 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use lib_email_clients::gmail::label_colors::color_by_name;
+use sea_orm::DatabaseConnection;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    db_core::prelude::*,
+    email::{
+        client::required_label_names,
+        label_appearance::{LabelAppearance, LabelListVisibility, MessageListVisibility},
+        rules::UserEmailRules,
+    },
+    error::{AppError, AppJsonResult, AppResult},
+    model::{label_setting::LabelSettingCtrl, user::UserCtrl},
+    server_config::cfg,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct LabelSettingInput {
+    /// One of the Gmail label colour names, e.g. `blue-600`
+    pub color: Option<String>,
+    pub label_list_visibility: Option<LabelListVisibility>,
+    pub message_list_visibility: Option<MessageListVisibility>,
+}
+
+/// Checks the category is one of the labels created for the user
+pub(super) async fn validate_category(
+    conn: &DatabaseConnection,
+    user_id: i32,
+    category: &str,
+) -> AppResult<()> {
+    let email_rules = UserEmailRules::from_user(conn, user_id).await?;
+    let category_exists = required_label_names(&email_rules.get_custom_labels())
+        .iter()
+        .any(|l| cfg.mail_label_of(l) == Some(category));
+    if !category_exists {
+        return Err(AppError::BadRequest(format!(
+            "Unknown category: {category}"
+        )));
+    }
+
+    Ok(())
+}
+
+pub async fn handler_list_label_settings(
+    State(conn): State<DatabaseConnection>,
+    Path(user_email): Path<String>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    let settings = LabelSettingCtrl::all_by_user(&conn, user.id).await?;
+
+    Ok(Json(json!(settings
+        .into_iter()
+        .map(setting_json)
+        .collect::<Vec<_>>())))
+}
+
+pub async fn handler_upsert_label_setting(
+    State(conn): State<DatabaseConnection>,
+    Path((user_email, category)): Path<(String, String)>,
+    Json(input): Json<LabelSettingInput>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    validate_category(&conn, user.id, &category).await?;
+    if let Some(color) = &input.color {
+        if color_by_name(color).is_none() {
+            return Err(AppError::BadRequest(format!(
+                "Unknown label color: {color}"
+            )));
+        }
+    }
+
+    let setting = LabelSettingCtrl::upsert(
+        &conn,
+        user.id,
+        &category,
+        input.color,
+        input.label_list_visibility.map(|v| v.as_str().to_string()),
+        input
+            .message_list_visibility
+            .map(|v| v.as_str().to_string()),
+    )
+    .await?;
+
+    Ok(Json(setting_json(setting)))
+}
+
+/// Removes the setting. The label keeps its current appearance in Gmail.
+pub async fn handler_delete_label_setting(
+    State(conn): State<DatabaseConnection>,
+    Path((user_email, category)): Path<(String, String)>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    LabelSettingCtrl::delete(&conn, user.id, &category).await?;
+
+    Ok(Json(json!({ "deleted": category })))
+}
+
+fn setting_json(setting: label_setting::Model) -> Value {
+    let appearance = LabelAppearance::resolve(&setting.category, Some(&setting));
+
+    json!({
+        "category": setting.category,
+        "color": setting.color,
+        "label_list_visibility": setting.label_list_visibility,
+        "message_list_visibility": setting.message_list_visibility,
+        "applied": {
+            "background_color": appearance.color.background_color,
+            "text_color": appearance.color.text_color,
+            "label_list_visibility": appearance.label_list_visibility,
+            "message_list_visibility": appearance.message_list_visibility,
+        },
+        "updated_at": setting.updated_at,
+    })
+}
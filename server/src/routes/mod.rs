@@ -1,4 +1,15 @@
 pub mod account_connection;
 mod app_router;
 pub mod auth;
+pub mod backfill;
+pub mod cluster;
+pub mod dead_letters;
+pub mod examples;
+pub mod important_categories;
+pub mod inbox_settings;
+pub mod label_settings;
+pub mod processor_state;
+pub mod reclassification;
+pub mod rules;
+pub mod stats;
 pub use app_router::*;
@@ -0,0 +1,36 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use sea_orm::DatabaseConnection;
+use serde_json::{json, Value};
+
+use crate::{
+    error::AppJsonResult,
+    model::{processor_state::ProcessorStateCtrl, user::UserCtrl},
+};
+
+pub async fn handler_processor_state(
+    State(conn): State<DatabaseConnection>,
+    Path(user_email): Path<String>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    let Some(state) = ProcessorStateCtrl::get_by_user(&conn, user.id).await? else {
+        return Ok(Json(json!(null)));
+    };
+
+    Ok(Json(json!({
+        "user_email": user_email,
+        "status": state.status,
+        "instance_id": state.instance_id,
+        "emails_processed": state.emails_processed,
+        "emails_failed": state.emails_failed,
+        "consecutive_failures": state.consecutive_failures,
+        "failure_reason": state.failure_reason.map(|reason| format!("{:?}", reason)),
+        "failure_message": state.failure_message,
+        "last_failed_at": state.last_failed_at,
+        "next_restart_at": state.next_restart_at,
+        "started_at": state.started_at,
+        "updated_at": state.updated_at,
+    })))
+}
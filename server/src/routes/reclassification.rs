@@ -0,0 +1,95 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use sea_orm::DatabaseConnection;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    db_core::prelude::*,
+    email::rules::UserEmailRules,
+    error::{AppError, AppJsonResult},
+    model::{reclassification_job::ReclassificationJobCtrl, user::UserCtrl},
+    server_config::cfg,
+};
+
+const MAX_LOOKBACK_DAYS: i64 = 90;
+
+#[derive(Debug, Deserialize)]
+pub struct ReclassificationInput {
+    /// Defaults to all of the user's categories
+    pub categories: Option<Vec<String>>,
+    pub lookback_days: Option<i64>,
+}
+
+pub async fn handler_list_reclassifications(
+    State(conn): State<DatabaseConnection>,
+    Path(user_email): Path<String>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    let jobs = ReclassificationJobCtrl::all_by_user(&conn, user.id).await?;
+
+    Ok(Json(json!(jobs
+        .into_iter()
+        .map(job_json)
+        .collect::<Vec<_>>())))
+}
+
+pub async fn handler_create_reclassification(
+    State(conn): State<DatabaseConnection>,
+    Path(user_email): Path<String>,
+    Json(input): Json<ReclassificationInput>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+
+    let lookback_days = input
+        .lookback_days
+        .unwrap_or(cfg.reclassification.lookback_days);
+    if !(1..=MAX_LOOKBACK_DAYS).contains(&lookback_days) {
+        return Err(AppError::BadRequest(format!(
+            "Lookback must be between 1 and {MAX_LOOKBACK_DAYS} days"
+        )));
+    }
+
+    let email_rules = UserEmailRules::from_user(&conn, user.id).await?;
+    let categories = match input.categories {
+        Some(categories) => categories,
+        None => email_rules.get_custom_labels(),
+    };
+    if categories.is_empty() {
+        return Err(AppError::BadRequest("No categories given".to_string()));
+    }
+
+    let since = chrono::Utc::now() - chrono::Duration::days(lookback_days);
+    let job = ReclassificationJobCtrl::create(&conn, user.id, categories, since.into()).await?;
+
+    Ok(Json(job_json(job)))
+}
+
+pub async fn handler_cancel_reclassification(
+    State(conn): State<DatabaseConnection>,
+    Path((user_email, id)): Path<(String, i32)>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    let job = ReclassificationJobCtrl::cancel(&conn, user.id, id).await?;
+
+    Ok(Json(job_json(job)))
+}
+
+fn job_json(job: reclassification_job::Model) -> Value {
+    json!({
+        "id": job.id,
+        "status": format!("{:?}", job.status),
+        "categories": job.categories.unwrap_or_default(),
+        "since": job.since,
+        "total": job.total,
+        "processed": job.processed,
+        "changed": job.changed,
+        "failed": job.failed,
+        "error": job.error,
+        "created_at": job.created_at,
+        "updated_at": job.updated_at,
+        "finished_at": job.finished_at,
+    })
+}
@@ -0,0 +1,152 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use sea_orm::DatabaseConnection;
+use serde_json::{json, Value};
+
+use crate::{
+    db_core::prelude::*,
+    email::{
+        rule_engine::RuleEngine,
+        rule_preview::{self, RuleDryRunInput},
+        rules::UserEmailRules,
+    },
+    error::{AppError, AppJsonResult, AppResult},
+    model::{
+        deterministic_email_rule::{DeterministicEmailRuleCtrl, DeterministicRuleInput},
+        user::UserCtrl,
+    },
+    rate_limiters::RateLimiters,
+    server_config::cfg,
+    HttpClient,
+};
+
+const MAX_RULES_PER_USER: usize = 100;
+const MAX_RULE_NAME_LENGTH: usize = 100;
+
+/// Checks the rule compiles, targets a label the processor creates and has a unique name
+async fn validate_rule(
+    conn: &DatabaseConnection,
+    user_id: i32,
+    rule_id: Option<i32>,
+    input: &DeterministicRuleInput,
+) -> AppResult<()> {
+    let name = input.name.trim();
+    if name.is_empty() || name.len() > MAX_RULE_NAME_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "Rule name must be between 1 and {MAX_RULE_NAME_LENGTH} characters"
+        )));
+    }
+
+    RuleEngine::compile(&[input.to_definition()])
+        .map_err(|e| AppError::BadRequest(format!("{e:#}")))?;
+
+    let email_rules = UserEmailRules::from_user(conn, user_id).await?;
+    let label_exists = email_rules
+        .data()
+        .iter()
+        .map(|r| r.mail_label.as_str())
+        .chain(cfg.heuristic_labels())
+        .any(|l| l == input.mail_label);
+    if !label_exists {
+        return Err(AppError::BadRequest(format!(
+            "Unknown label: {}",
+            input.mail_label
+        )));
+    }
+
+    let existing = DeterministicEmailRuleCtrl::all_by_user(conn, user_id).await?;
+    if existing
+        .iter()
+        .any(|r| r.name == name && Some(r.id) != rule_id)
+    {
+        return Err(AppError::Conflict(format!(
+            "A rule named {name} already exists"
+        )));
+    }
+    if rule_id.is_none() && existing.len() >= MAX_RULES_PER_USER {
+        return Err(AppError::BadRequest(format!(
+            "Users can have at most {MAX_RULES_PER_USER} rules"
+        )));
+    }
+
+    Ok(())
+}
+
+pub async fn handler_list_rules(
+    State(conn): State<DatabaseConnection>,
+    Path(user_email): Path<String>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    let rules = DeterministicEmailRuleCtrl::all_by_user(&conn, user.id).await?;
+
+    Ok(Json(json!(rules
+        .into_iter()
+        .map(rule_json)
+        .collect::<Vec<_>>())))
+}
+
+pub async fn handler_create_rule(
+    State(conn): State<DatabaseConnection>,
+    Path(user_email): Path<String>,
+    Json(mut input): Json<DeterministicRuleInput>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    input.name = input.name.trim().to_string();
+    validate_rule(&conn, user.id, None, &input).await?;
+    let rule = DeterministicEmailRuleCtrl::create(&conn, user.id, input).await?;
+
+    Ok(Json(rule_json(rule)))
+}
+
+pub async fn handler_update_rule(
+    State(conn): State<DatabaseConnection>,
+    Path((user_email, id)): Path<(String, i32)>,
+    Json(mut input): Json<DeterministicRuleInput>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    input.name = input.name.trim().to_string();
+    validate_rule(&conn, user.id, Some(id), &input).await?;
+    let rule = DeterministicEmailRuleCtrl::update(&conn, user.id, id, input).await?;
+
+    Ok(Json(rule_json(rule)))
+}
+
+pub async fn handler_delete_rule(
+    State(conn): State<DatabaseConnection>,
+    Path((user_email, id)): Path<(String, i32)>,
+) -> AppJsonResult<Value> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    DeterministicEmailRuleCtrl::delete(&conn, user.id, id).await?;
+
+    Ok(Json(json!({ "deleted": id })))
+}
+
+/// Shows how a proposed rule set would categorize the user's recent inbox without applying it
+pub async fn handler_rule_dry_run(
+    State(http_client): State<HttpClient>,
+    State(conn): State<DatabaseConnection>,
+    State(rate_limiters): State<RateLimiters>,
+    Path(user_email): Path<String>,
+    Json(input): Json<RuleDryRunInput>,
+) -> AppJsonResult<Value> {
+    let preview =
+        rule_preview::preview_rules(&conn, &http_client, &rate_limiters, &user_email, input)
+            .await?;
+
+    Ok(Json(json!(preview)))
+}
+
+fn rule_json(rule: deterministic_email_rule::Model) -> Value {
+    json!({
+        "id": rule.id,
+        "name": rule.name,
+        "priority": rule.priority,
+        "condition": rule.condition,
+        "mail_label": rule.mail_label,
+        "is_disabled": rule.is_disabled,
+        "created_at": rule.created_at,
+        "updated_at": rule.updated_at,
+    })
+}
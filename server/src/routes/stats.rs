@@ -0,0 +1,39 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use sea_orm::DatabaseConnection;
+use serde::Deserialize;
+
+use crate::{
+    error::AppJsonResult,
+    model::{
+        email_correction::{EmailCorrectionCtrl, UserAccuracyStats},
+        email_training::{EmailTrainingCtrl, ShadowAgreementReport},
+        user::UserCtrl,
+    },
+};
+
+pub async fn handler_accuracy_stats(
+    State(conn): State<DatabaseConnection>,
+    Path(user_email): Path<String>,
+) -> AppJsonResult<UserAccuracyStats> {
+    let user = UserCtrl::get_by_email(&conn, &user_email).await?;
+    let stats = EmailCorrectionCtrl::accuracy_stats(&conn, user.id).await?;
+
+    Ok(Json(stats))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ShadowAgreementQuery {
+    pub model_id: Option<String>,
+}
+
+pub async fn handler_shadow_agreement(
+    State(conn): State<DatabaseConnection>,
+    Query(query): Query<ShadowAgreementQuery>,
+) -> AppJsonResult<ShadowAgreementReport> {
+    let report = EmailTrainingCtrl::shadow_agreement(&conn, query.model_id).await?;
+
+    Ok(Json(report))
+}
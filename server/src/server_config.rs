@@ -1,9 +1,11 @@
 use config::{Config, ConfigError};
 use lazy_static::lazy_static;
 use serde::Deserialize;
-use std::{env, fs::File, io::Read, path::Path, result::Result};
+use std::{collections::HashMap, env, fs::File, io::Read, path::Path, result::Result};
 use url::Url;
 
+use crate::email::{label_template, rule_engine::RuleDefinition};
+
 #[derive(Debug, Deserialize)]
 pub struct GmailConfig {
     pub client_id: String,
@@ -32,6 +34,14 @@ pub struct Category {
     pub mail_label: String,
     pub gmail_categories: Vec<String>,
     pub important: Option<bool>,
+    /// Labels this category used to have, emails under them are moved to `mail_label`
+    #[serde(default)]
+    pub previous_mail_labels: Vec<String>,
+    /// Default inbox settings, users can override them per category
+    #[serde(default)]
+    pub skip_inbox: bool,
+    #[serde(default)]
+    pub mark_spam: bool,
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
@@ -41,11 +51,49 @@ pub struct Heuristic {
     pub gmail_categories: Vec<String>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptProvider {
+    #[default]
+    Mistral,
+    /// Answers from word overlap with the category names, for development and tests
+    Mock,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptVersion {
+    #[default]
+    V1,
+    /// Asks for a calibrated confidence instead of leaving it to the model
+    V2,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct ModelConfig {
     pub id: String,
     pub temperature: f64,
     pub email_confidence_threshold: f32,
+    #[serde(default)]
+    pub provider: PromptProvider,
+    #[serde(default)]
+    pub prompt_version: PromptVersion,
+}
+
+/// Runs a candidate model next to the primary one on a share of emails, without
+/// affecting labels or quota
+#[derive(Debug, Clone, Deserialize, Default)]
+#[serde(default)]
+pub struct ShadowConfig {
+    /// Share of emails sent to the candidate, from 0 to 100
+    pub percentage: f64,
+    pub model: Option<ModelConfig>,
+}
+
+impl ShadowConfig {
+    pub fn candidate(&self) -> Option<&ModelConfig> {
+        self.model.as_ref().filter(|_| self.percentage > 0.0)
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -62,12 +110,320 @@ pub struct TokenLimits {
     pub refill_amount: usize,
     pub estimated_token_usage_per_email: usize,
     pub daily_user_quota: usize,
+    #[serde(default = "default_daily_preview_quota")]
+    pub daily_preview_quota: usize,
+}
+
+fn default_daily_preview_quota() -> usize {
+    20_000
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Settings {
     pub training_mode: bool,
     pub email_max_age_days: i64,
+    /// Gmail label every category label is nested under
+    #[serde(default = "default_label_root")]
+    pub label_root: String,
+}
+
+fn default_label_root() -> String {
+    "Mailclerk".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FewShotConfig {
+    pub enabled: bool,
+    pub max_examples: usize,
+    pub token_budget: usize,
+    pub min_similarity: f32,
+    pub body_snippet_chars: usize,
+}
+
+impl Default for FewShotConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_examples: 3,
+            token_budget: 300,
+            min_similarity: 0.15,
+            body_snippet_chars: 200,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ClassifierMode {
+    #[default]
+    Llm,
+    Knn,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ClassifierConfig {
+    pub mode: ClassifierMode,
+    /// Number of neighbours that vote on a category
+    pub k: usize,
+    /// Fewer similar neighbours than this falls back to the LLM
+    pub min_neighbours: usize,
+    pub min_similarity: f32,
+    /// Share of the weighted vote the winning category needs
+    pub min_agreement: f32,
+    /// Most recent labeled emails kept in memory per user
+    pub max_index_size: usize,
+}
+
+impl Default for ClassifierConfig {
+    fn default() -> Self {
+        Self {
+            mode: ClassifierMode::Llm,
+            k: 7,
+            min_neighbours: 3,
+            min_similarity: 0.75,
+            min_agreement: 0.8,
+            max_index_size: 2000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EmbeddingsProvider {
+    /// Hashed bag of words computed locally, for development and tests
+    #[default]
+    Mock,
+    /// Any endpoint compatible with the Mistral embeddings API
+    Http,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EmbeddingsConfig {
+    pub provider: EmbeddingsProvider,
+    pub endpoint: String,
+    pub model: String,
+    /// Only used by the mock provider
+    pub dimensions: usize,
+    pub max_input_chars: usize,
+}
+
+impl Default for EmbeddingsConfig {
+    fn default() -> Self {
+        Self {
+            provider: EmbeddingsProvider::Mock,
+            endpoint: "https://api.mistral.ai/v1/embeddings".to_string(),
+            model: "mistral-embed".to_string(),
+            dimensions: 256,
+            max_input_chars: 2000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReclassificationConfig {
+    pub enabled: bool,
+    /// Only emails processed this recently are reclassified when rules change
+    pub lookback_days: i64,
+    pub batch_size: u64,
+}
+
+impl Default for ReclassificationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            lookback_days: 30,
+            batch_size: 25,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LabelTemplateConfig {
+    /// Labels a templated category may create per user, Gmail allows 10,000 in total
+    pub max_labels_per_template: usize,
+}
+
+impl Default for LabelTemplateConfig {
+    fn default() -> Self {
+        Self {
+            max_labels_per_template: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PromptQueueConfig {
+    /// A claimed email that isn't finished in this time is handed out again
+    pub visibility_timeout_secs: i64,
+    /// Emails are dropped from the queue after this many claims
+    pub max_attempts: i32,
+    pub count_refresh_secs: u64,
+    /// Low priority emails waiting this long are scheduled as high priority
+    pub low_priority_aging_secs: i64,
+    /// Emails a user may take per round of the fair scheduler, by plan. Unlisted plans get 1.
+    pub plan_weights: HashMap<String, u32>,
+}
+
+impl PromptQueueConfig {
+    pub fn weight_for_plan(&self, plan: Option<&str>) -> u32 {
+        plan.and_then(|plan| self.plan_weights.get(plan))
+            .copied()
+            .unwrap_or(1)
+            .max(1)
+    }
+}
+
+impl Default for PromptQueueConfig {
+    fn default() -> Self {
+        Self {
+            visibility_timeout_secs: 300,
+            max_attempts: 5,
+            count_refresh_secs: 5,
+            low_priority_aging_secs: 30 * 60,
+            plan_weights: HashMap::from([("standard".to_string(), 1)]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EmailRetryConfig {
+    /// Failed attempts before an email is dead lettered
+    pub max_attempts: i32,
+    /// Delay after the first failure, doubled after every further one
+    pub base_delay_secs: i64,
+    pub max_delay_secs: i64,
+}
+
+impl Default for EmailRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay_secs: 5 * 60,
+            max_delay_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ProcessorRestartConfig {
+    pub base_delay_secs: i64,
+    pub max_delay_secs: i64,
+}
+
+impl Default for ProcessorRestartConfig {
+    fn default() -> Self {
+        Self {
+            base_delay_secs: 60,
+            max_delay_secs: 6 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BackfillConfig {
+    /// Gmail pages of older emails listed per processor tick
+    pub pages_per_tick: usize,
+    /// Listing waits while the user has this many older emails queued
+    pub max_queued: usize,
+}
+
+impl Default for BackfillConfig {
+    fn default() -> Self {
+        Self {
+            pages_per_tick: 4,
+            max_queued: 2000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// How long emails already being processed get to finish on shutdown
+    pub drain_timeout_secs: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            drain_timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ClusterConfig {
+    pub heartbeat_secs: u64,
+    /// Instances without a heartbeat for this long no longer own any users
+    pub instance_ttl_secs: i64,
+    /// Points per instance on the hash ring, more spread users more evenly
+    pub virtual_nodes: usize,
+    /// How long a leader stays leader without renewing, scheduled jobs pause for up to this
+    /// long when it goes away
+    pub leader_lease_secs: i64,
+    pub leader_renew_secs: u64,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_secs: 10,
+            instance_ttl_secs: 30,
+            virtual_nodes: 100,
+            leader_lease_secs: 30,
+            leader_renew_secs: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LimiterBackend {
+    /// Shared by every server instance
+    #[default]
+    Postgres,
+    Memory,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub backend: LimiterBackend,
+    /// Gmail quota units per second for the whole project, across all users
+    pub gmail_project_units_per_sec: usize,
+    /// Project quota units each instance takes from the shared bucket at once, so the
+    /// project's row isn't written on every Gmail call
+    pub gmail_project_block_units: usize,
+    /// How long to pause after the provider asks us to slow down
+    pub backoff_secs: i64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            backend: LimiterBackend::default(),
+            gmail_project_units_per_sec: 20_000,
+            gmail_project_block_units: 500,
+            backoff_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NotificationConfig {
+    /// Important emails are posted here as JSON, they are only logged if unset
+    pub webhook_url: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -82,8 +438,39 @@ struct ConfigFile {
     settings: Settings,
     api: ApiConfig,
     categories: Vec<Category>,
+    #[serde(default)]
     heuristics: Vec<Heuristic>,
+    #[serde(default)]
+    heuristic_rules: Vec<RuleDefinition>,
     model: ModelConfig,
+    #[serde(default)]
+    few_shot: FewShotConfig,
+    #[serde(default)]
+    classifier: ClassifierConfig,
+    #[serde(default)]
+    embeddings: EmbeddingsConfig,
+    #[serde(default)]
+    shadow: ShadowConfig,
+    #[serde(default)]
+    reclassification: ReclassificationConfig,
+    #[serde(default)]
+    label_templates: LabelTemplateConfig,
+    #[serde(default)]
+    notifications: NotificationConfig,
+    #[serde(default)]
+    prompt_queue: PromptQueueConfig,
+    #[serde(default)]
+    email_retries: EmailRetryConfig,
+    #[serde(default)]
+    cluster: ClusterConfig,
+    #[serde(default)]
+    rate_limits: RateLimitConfig,
+    #[serde(default)]
+    shutdown: ShutdownConfig,
+    #[serde(default)]
+    processor_restarts: ProcessorRestartConfig,
+    #[serde(default)]
+    backfill: BackfillConfig,
 }
 
 #[derive(Debug)]
@@ -92,11 +479,50 @@ pub struct ServerConfig {
     pub api: ApiConfig,
     pub categories: Vec<Category>,
     pub heuristics: Vec<Heuristic>,
+    pub heuristic_rules: Vec<RuleDefinition>,
     pub gmail_config: GmailConfig,
     pub model: ModelConfig,
+    pub few_shot: FewShotConfig,
+    pub classifier: ClassifierConfig,
+    pub embeddings: EmbeddingsConfig,
+    pub shadow: ShadowConfig,
+    pub reclassification: ReclassificationConfig,
+    pub label_templates: LabelTemplateConfig,
+    pub notifications: NotificationConfig,
+    pub prompt_queue: PromptQueueConfig,
+    pub email_retries: EmailRetryConfig,
+    pub cluster: ClusterConfig,
+    pub rate_limits: RateLimitConfig,
+    pub shutdown: ShutdownConfig,
+    pub processor_restarts: ProcessorRestartConfig,
+    pub backfill: BackfillConfig,
     pub frontend_url: Url,
 }
 
+impl ServerConfig {
+    /// Gmail label name for a category's mail label, e.g. `Mailclerk/Finance/Receipts`
+    pub fn label_name(&self, mail_label: &str) -> String {
+        format!("{}/{}", self.settings.label_root, mail_label)
+    }
+
+    /// The mail label of a Gmail label under the root label
+    pub fn mail_label_of<'a>(&self, label_name: &'a str) -> Option<&'a str> {
+        label_name
+            .strip_prefix(self.settings.label_root.as_str())?
+            .strip_prefix('/')
+            .filter(|l| !l.is_empty())
+    }
+
+    /// Labels applied by heuristics rather than by the model
+    pub fn heuristic_labels(&self) -> impl Iterator<Item = &str> {
+        self.heuristics.iter().map(|h| h.mail_label.as_str()).chain(
+            self.heuristic_rules
+                .iter()
+                .filter_map(|r| r.action.mail_label()),
+        )
+    }
+}
+
 impl std::fmt::Display for ServerConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -111,6 +537,7 @@ impl std::fmt::Display for ServerConfig {
                 self.heuristics
                 .iter()
                 .map(|c| format!("{} -> {}", c.from, c.mail_label))
+                .chain(self.heuristic_rules.iter().map(|r| format!("{} -> {:?}", r.name, r.action)))
                 .collect::<Vec<_>>().join("\n"),
             self.gmail_config,
             self.model,
@@ -167,31 +594,80 @@ lazy_static! {
             categories,
             model,
             heuristics,
+            heuristic_rules,
+            few_shot,
+            classifier,
+            embeddings,
+            shadow,
+            reclassification,
+            label_templates,
+            notifications,
+            prompt_queue,
+            email_retries,
+            cluster,
+            rate_limits,
+            shutdown,
+            processor_restarts,
+            backfill,
         } = cfg_file;
 
         let frontend_url = Url::parse(&env::var("FRONTEND_URL").expect("FRONTEND_URL is required"))
             .expect("FRONTEND_URL is invalid");
 
-        ServerConfig {
+        let config = ServerConfig {
             settings,
             api,
             categories,
             heuristics,
+            heuristic_rules,
             gmail_config,
             model,
+            few_shot,
+            classifier,
+            embeddings,
+            shadow,
+            reclassification,
+            label_templates,
+            notifications,
+            prompt_queue,
+            email_retries,
+            cluster,
+            rate_limits,
+            shutdown,
+            processor_restarts,
+            backfill,
             frontend_url,
+        };
+
+        let mail_labels = config
+            .categories
+            .iter()
+            .map(|c| c.mail_label.as_str())
+            .chain(config.heuristic_labels());
+        for mail_label in mail_labels {
+            if let Err(e) = label_template::validate(mail_label) {
+                panic!("config.toml is invalid: {e}");
+            }
         }
+
+        config
     };
     pub static ref UNKNOWN_CATEGORY: Category = Category {
         content: "Unknown".to_string(),
         mail_label: "uncategorized".to_string(),
         gmail_categories: vec![],
         important: None,
+        previous_mail_labels: vec![],
+        skip_inbox: false,
+        mark_spam: false,
     };
     pub static ref DAILY_SUMMARY_CATEGORY: Category = Category {
         content: "".to_string(),
         mail_label: "daily_summary".to_string(),
         gmail_categories: vec![],
         important: None,
+        previous_mail_labels: vec![],
+        skip_inbox: false,
+        mark_spam: false,
     };
 }
@@ -3,7 +3,8 @@ use sea_orm::{ConnectOptions, Database, DatabaseConnection};
 use std::{env, path::PathBuf};
 
 use crate::{
-    email::client::EmailClient, model::user::UserCtrl, server_config::get_cert, HttpClient,
+    email::client::EmailClient, model::user::UserCtrl, rate_limiters::RateLimiters,
+    server_config::get_cert, HttpClient,
 };
 
 pub async fn setup() -> (DatabaseConnection, HttpClient) {
@@ -36,5 +37,8 @@ pub async fn setup_email_client(user_email: &str) -> EmailClient {
     let user = UserCtrl::get_with_account_access_by_email(&conn, user_email)
         .await
         .unwrap();
-    EmailClient::new(http_client, conn, user).await.unwrap()
+    let rate_limiters = RateLimiters::from_config(conn.clone());
+    EmailClient::new(http_client, conn, rate_limiters, user)
+        .await
+        .unwrap()
 }
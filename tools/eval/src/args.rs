@@ -0,0 +1,193 @@
+use std::{env, path::PathBuf};
+
+use anyhow::{anyhow, bail, Context};
+use server::server_config::{ModelConfig, PromptProvider, PromptVersion};
+
+pub const USAGE: &str = "\
+Usage: eval [options]
+
+Options:
+  --dataset <db|file.jsonl>     Corrected emails to replay (default: db)
+  --limit <n>                   Maximum number of emails to evaluate
+  --provider <mistral|mock>     Where category prompts are sent (default: mistral)
+  --prompt-version <v1|v2>      System prompt to evaluate (default: v1)
+  --model <id>                  Overrides the model id from config.toml
+  --temperature <f64>           Overrides the temperature from config.toml
+  --threshold <f32>             Overrides email_confidence_threshold from config.toml
+  --config <file>               Server config.toml for JSONL datasets (default: $APP_DIR/config.toml)
+  --json <file>                 Writes the report as JSON
+  --markdown <file>             Writes the report as Markdown
+";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DatasetSource {
+    Database,
+    Jsonl(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalArgs {
+    pub dataset: DatasetSource,
+    pub limit: Option<u64>,
+    pub provider: PromptProvider,
+    pub prompt_version: PromptVersion,
+    pub model: Option<String>,
+    pub temperature: Option<f64>,
+    pub threshold: Option<f32>,
+    pub config: PathBuf,
+    pub json: Option<PathBuf>,
+    pub markdown: Option<PathBuf>,
+}
+
+impl EvalArgs {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::parse(env::args().skip(1))
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let default_config = env::var("APP_DIR")
+            .map(|dir| PathBuf::from(dir).join("config.toml"))
+            .unwrap_or_else(|_| PathBuf::from("config/config.toml"));
+
+        let mut parsed = Self {
+            dataset: DatasetSource::Database,
+            limit: None,
+            provider: PromptProvider::Mistral,
+            prompt_version: PromptVersion::V1,
+            model: None,
+            temperature: None,
+            threshold: None,
+            config: default_config,
+            json: None,
+            markdown: None,
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                bail!(USAGE);
+            }
+
+            let value = args
+                .next()
+                .ok_or(anyhow!("Missing value for {arg}\n\n{USAGE}"))?;
+            match arg.as_str() {
+                "--dataset" => {
+                    parsed.dataset = match value.as_str() {
+                        "db" => DatasetSource::Database,
+                        path => DatasetSource::Jsonl(PathBuf::from(path)),
+                    }
+                }
+                "--limit" => parsed.limit = Some(value.parse().context("Invalid --limit")?),
+                "--provider" => {
+                    parsed.provider = match value.as_str() {
+                        "mistral" => PromptProvider::Mistral,
+                        "mock" => PromptProvider::Mock,
+                        other => bail!("Unknown provider: {other}"),
+                    }
+                }
+                "--prompt-version" => {
+                    parsed.prompt_version = match value.as_str() {
+                        "v1" => PromptVersion::V1,
+                        "v2" => PromptVersion::V2,
+                        other => bail!("Unknown prompt version: {other}"),
+                    }
+                }
+                "--model" => parsed.model = Some(value),
+                "--temperature" => {
+                    parsed.temperature = Some(value.parse().context("Invalid --temperature")?)
+                }
+                "--threshold" => {
+                    parsed.threshold = Some(value.parse().context("Invalid --threshold")?)
+                }
+                "--config" => parsed.config = PathBuf::from(value),
+                "--json" => parsed.json = Some(PathBuf::from(value)),
+                "--markdown" => parsed.markdown = Some(PathBuf::from(value)),
+                other => bail!("Unknown option: {other}\n\n{USAGE}"),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// The model to replay with, from the flags over the configured model
+    pub fn model_config(&self, configured: Option<&ModelConfig>) -> anyhow::Result<ModelConfig> {
+        let id = match (&self.model, configured, self.provider) {
+            (Some(id), _, _) => id.clone(),
+            (None, Some(model), _) => model.id.clone(),
+            (None, None, PromptProvider::Mock) => "mock".to_string(),
+            (None, None, PromptProvider::Mistral) => {
+                bail!("--model or model.id in config.toml is required")
+            }
+        };
+
+        Ok(ModelConfig {
+            id,
+            temperature: self
+                .temperature
+                .or(configured.map(|m| m.temperature))
+                .unwrap_or_default(),
+            email_confidence_threshold: self
+                .threshold
+                .or(configured.map(|m| m.email_confidence_threshold))
+                .unwrap_or_default(),
+            provider: self.provider,
+            prompt_version: self.prompt_version,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let parsed = EvalArgs::parse(args(
+            "--dataset data.jsonl --provider mock --prompt-version v2 --threshold 0.5",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            parsed.dataset,
+            DatasetSource::Jsonl(PathBuf::from("data.jsonl"))
+        );
+        assert_eq!(parsed.provider, PromptProvider::Mock);
+        assert_eq!(parsed.prompt_version, PromptVersion::V2);
+        assert_eq!(parsed.threshold, Some(0.5));
+    }
+
+    #[test]
+    fn test_parse_args_errors() {
+        assert!(EvalArgs::parse(args("--provider openai")).is_err());
+        assert!(EvalArgs::parse(args("--prompt-version v9")).is_err());
+        assert!(EvalArgs::parse(args("--limit")).is_err());
+        assert!(EvalArgs::parse(args("--unknown 1")).is_err());
+    }
+
+    #[test]
+    fn test_model_config() {
+        let configured = ModelConfig {
+            id: "mistral-small".to_string(),
+            temperature: 0.2,
+            email_confidence_threshold: 0.7,
+            provider: PromptProvider::Mistral,
+            prompt_version: PromptVersion::V1,
+        };
+        let parsed = EvalArgs::parse(args("--prompt-version v2 --threshold 0.5")).unwrap();
+        let model = parsed.model_config(Some(&configured)).unwrap();
+
+        assert_eq!(model.id, "mistral-small");
+        assert_eq!(model.temperature, 0.2);
+        assert_eq!(model.email_confidence_threshold, 0.5);
+        assert_eq!(model.prompt_version, PromptVersion::V2);
+
+        assert!(parsed.model_config(None).is_err());
+        let mock = EvalArgs::parse(args("--provider mock")).unwrap();
+        assert_eq!(mock.model_config(None).unwrap().id, "mock");
+    }
+}
@@ -0,0 +1,32 @@
+use std::{fs, path::Path};
+
+use serde::Deserialize;
+use server::server_config::ModelConfig;
+
+/// The parts of the server's config.toml a JSONL evaluation needs. Read directly so it runs
+/// without the server's secrets.
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct EvalConfig {
+    #[serde(default)]
+    pub categories: Vec<Category>,
+    #[serde(default)]
+    pub model: Option<ModelConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Category {
+    pub content: String,
+    pub mail_label: String,
+}
+
+impl EvalConfig {
+    /// A missing config file is allowed so the mock provider can run without one
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+}
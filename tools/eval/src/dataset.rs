@@ -0,0 +1,91 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use anyhow::Context;
+use sea_orm::{DatabaseConnection, DbBackend, FromQueryResult, Statement};
+use serde::Deserialize;
+
+/// An email with the mail label its user corrected it to. JSONL exports may leave out the
+/// user, they are replayed without any user's rules or history.
+#[derive(Debug, Clone, PartialEq, Deserialize, FromQueryResult)]
+pub struct LabeledEmail {
+    #[serde(default)]
+    pub user_email: String,
+    pub email_id: String,
+    #[serde(default)]
+    pub from: String,
+    #[serde(default)]
+    pub subject: String,
+    #[serde(default)]
+    pub body: String,
+    pub label: String,
+}
+
+pub fn from_jsonl(path: &Path) -> anyhow::Result<Vec<LabeledEmail>> {
+    let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+
+    let mut emails = vec![];
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let email = serde_json::from_str::<LabeledEmail>(&line)
+            .with_context(|| format!("Invalid line {} in {}", i + 1, path.display()))?;
+        emails.push(email);
+    }
+
+    Ok(emails)
+}
+
+/// Loads emails recorded in training mode that the user moved to another category. The model's
+/// own answers are never used as labels, and corrections that only removed our label are
+/// skipped since the right category is unknown.
+pub async fn from_email_training(
+    conn: &DatabaseConnection,
+    limit: Option<u64>,
+) -> anyhow::Result<Vec<LabeledEmail>> {
+    LabeledEmail::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"
+            SELECT
+                et.user_email,
+                et.email_id,
+                et.from,
+                et.subject,
+                et.body,
+                ec.corrected_category AS label
+            FROM email_training AS et
+            JOIN "user" AS u ON u.email = et.user_email
+            JOIN email_correction AS ec
+                ON ec.user_id = u.id AND ec.email_id = et.email_id
+            WHERE ec.corrected_category IS NOT NULL
+                -- Shadow-only rows keep no content outside training mode
+                AND (et.subject <> '' OR et.body <> '')
+            ORDER BY et.id DESC
+            LIMIT $1
+        "#,
+        [limit.map(|l| l as i64).into()],
+    ))
+    .all(conn)
+    .await
+    .context("Error loading email_training")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_jsonl_fixture() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/sample.jsonl");
+        let emails = from_jsonl(&path).unwrap();
+
+        assert_eq!(emails.len(), 4);
+        assert_eq!(emails[0].email_id, "sample-1");
+        assert_eq!(emails[0].label, "receipts");
+    }
+}
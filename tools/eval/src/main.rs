@@ -0,0 +1,269 @@
+mod args;
+mod config;
+mod dataset;
+mod metrics;
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    env, fs,
+    time::Instant,
+};
+
+use anyhow::{anyhow, Context};
+use dotenvy::dotenv;
+use entity::user;
+use reqwest::Certificate;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use server::{
+    email::{
+        classifier::{ClassifierSettings, EmailClassifier},
+        label_template,
+        parsed_message::ParsedMessage,
+        rules::{EmailRule, UserEmailRules},
+    },
+    rate_limiters::RateLimiters,
+    server_config::{
+        cfg, get_cert, ClassifierConfig, FewShotConfig, ModelConfig, PromptProvider,
+        UNKNOWN_CATEGORY,
+    },
+    HttpClient,
+};
+
+use crate::{
+    args::{DatasetSource, EvalArgs},
+    config::EvalConfig,
+    dataset::LabeledEmail,
+    metrics::{Outcome, Report},
+};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    dotenv().ok();
+    let args = EvalArgs::from_env()?;
+
+    let mut outcomes = vec![];
+    let mut errors = 0;
+    let model = match &args.dataset {
+        DatasetSource::Jsonl(path) => {
+            let emails = dataset::from_jsonl(path)?;
+            let emails = match args.limit {
+                Some(limit) => emails.into_iter().take(limit as usize).collect(),
+                None => emails,
+            };
+            let config = EvalConfig::load(&args.config)?;
+            let settings = ClassifierSettings {
+                model: args.model_config(config.model.as_ref())?,
+                few_shot: FewShotConfig::default(),
+                classifier: ClassifierConfig::default(),
+            };
+            eprintln!("Evaluating {} emails", emails.len());
+
+            let rate_limiters = rate_limiters(settings.model.provider);
+            let classifier = EmailClassifier::new(
+                http_client(settings.model.provider)?,
+                rate_limiters.clone(),
+                jsonl_rules(&config, &emails),
+                settings.clone(),
+            );
+            for email in &emails {
+                match replay(&classifier, &rate_limiters, &settings.model, email).await {
+                    Ok(outcome) => outcomes.push(outcome),
+                    Err(e) => {
+                        eprintln!("Error classifying {}: {:?}", email.email_id, e);
+                        errors += 1;
+                    }
+                }
+            }
+
+            settings.model
+        }
+        DatasetSource::Database => {
+            let database_url = env::var("DATABASE_URL").context("DATABASE_URL is required")?;
+            let conn = sea_orm::Database::connect(database_url).await?;
+            let emails = dataset::from_email_training(&conn, args.limit).await?;
+            let settings = ClassifierSettings {
+                model: args.model_config(Some(&cfg.model))?,
+                ..ClassifierSettings::from_config()
+            };
+            eprintln!("Evaluating {} emails", emails.len());
+
+            let http_client = http_client(settings.model.provider)?;
+            let rate_limiters = rate_limiters(settings.model.provider);
+            let mut emails_by_user = BTreeMap::<&str, Vec<&LabeledEmail>>::new();
+            for email in &emails {
+                emails_by_user
+                    .entry(&email.user_email)
+                    .or_default()
+                    .push(email);
+            }
+
+            for (user_email, emails) in emails_by_user {
+                let classifier = match load_user_classifier(
+                    &conn,
+                    &http_client,
+                    &rate_limiters,
+                    &settings,
+                    user_email,
+                    &emails,
+                )
+                .await
+                {
+                    Ok(classifier) => classifier,
+                    Err(e) => {
+                        eprintln!("Error loading classifier for {}: {:?}", user_email, e);
+                        errors += emails.len();
+                        continue;
+                    }
+                };
+
+                for email in emails {
+                    match replay(&classifier, &rate_limiters, &settings.model, email).await {
+                        Ok(outcome) => outcomes.push(outcome),
+                        Err(e) => {
+                            eprintln!("Error classifying {}: {:?}", email.email_id, e);
+                            errors += 1;
+                        }
+                    }
+                }
+            }
+
+            settings.model
+        }
+    };
+
+    let prompt = format!("{:?}", model.prompt_version).to_lowercase();
+    let model_name = match model.provider {
+        PromptProvider::Mistral => model.id.clone(),
+        PromptProvider::Mock => "mock".to_string(),
+    };
+    let report = Report::from_outcomes(&prompt, &model_name, &outcomes, errors);
+    let markdown = report.to_markdown();
+
+    if let Some(path) = &args.json {
+        fs::write(path, serde_json::to_string_pretty(&report)?)?;
+    }
+    if let Some(path) = &args.markdown {
+        fs::write(path, &markdown)?;
+    }
+    if args.json.is_none() && args.markdown.is_none() {
+        println!("{markdown}");
+    }
+
+    Ok(())
+}
+
+/// The mock provider makes no requests, so it doesn't need the server's certificate
+fn http_client(provider: PromptProvider) -> anyhow::Result<HttpClient> {
+    Ok(match provider {
+        PromptProvider::Mistral => reqwest::ClientBuilder::new()
+            .use_rustls_tls()
+            .add_root_certificate(Certificate::from_pem(&get_cert())?)
+            .build()?,
+        PromptProvider::Mock => HttpClient::new(),
+    })
+}
+
+/// Kept in this process, so an evaluation never draws from the server's shared prompt budget
+fn rate_limiters(provider: PromptProvider) -> RateLimiters {
+    match provider {
+        PromptProvider::Mistral => {
+            let limits = &cfg.api.prompt_limits;
+            RateLimiters::new(
+                limits.rate_limit_per_sec,
+                limits.refill_interval_ms,
+                limits.refill_amount,
+            )
+        }
+        PromptProvider::Mock => RateLimiters::new(1, 1_000, 1),
+    }
+}
+
+/// The configured categories, plus any the dataset was labeled with that the config lacks
+fn jsonl_rules(config: &EvalConfig, emails: &[LabeledEmail]) -> UserEmailRules {
+    let mut rules = config
+        .categories
+        .iter()
+        .map(|c| EmailRule {
+            prompt_content: c.content.clone(),
+            mail_label: c.mail_label.clone(),
+            associated_email_client_category: None,
+        })
+        .collect::<Vec<_>>();
+    for email in emails {
+        if email.label != UNKNOWN_CATEGORY.mail_label
+            && !rules.iter().any(|r| r.mail_label == email.label)
+        {
+            rules.push(EmailRule {
+                prompt_content: email.label.clone(),
+                mail_label: email.label.clone(),
+                associated_email_client_category: None,
+            });
+        }
+    }
+
+    UserEmailRules::new(rules)
+}
+
+/// Loads the user's rules, sender memory, examples and kNN index once, without anything
+/// learned from the emails being replayed
+async fn load_user_classifier(
+    conn: &DatabaseConnection,
+    http_client: &HttpClient,
+    rate_limiters: &RateLimiters,
+    settings: &ClassifierSettings,
+    user_email: &str,
+    emails: &[&LabeledEmail],
+) -> anyhow::Result<EmailClassifier> {
+    let user_id = user::Entity::find()
+        .filter(user::Column::Email.eq(user_email))
+        .one(conn)
+        .await?
+        .map(|u| u.id)
+        .ok_or(anyhow!("User {user_email} not found"))?;
+    let user_email_rules = UserEmailRules::from_user(conn, user_id).await?;
+    let held_out = emails
+        .iter()
+        .map(|e| e.email_id.clone())
+        .collect::<HashSet<_>>();
+
+    let classifier = EmailClassifier::load_without_emails(
+        conn,
+        http_client.clone(),
+        rate_limiters.clone(),
+        user_id,
+        user_email_rules,
+        &held_out,
+    )
+    .await?;
+
+    Ok(classifier.with_settings(settings.clone()))
+}
+
+/// Classifies an email the way the processor would
+async fn replay(
+    classifier: &EmailClassifier,
+    rate_limiters: &RateLimiters,
+    model: &ModelConfig,
+    email: &LabeledEmail,
+) -> anyhow::Result<Outcome> {
+    let message = ParsedMessage {
+        id: email.email_id.clone(),
+        from: Some(email.from.clone()),
+        subject: Some(email.subject.clone()),
+        body: Some(email.body.clone()),
+        ..Default::default()
+    };
+
+    if model.provider == PromptProvider::Mistral {
+        rate_limiters.acquire_one().await;
+    }
+    let start = Instant::now();
+    let result = classifier.classify(&message).await?;
+
+    Ok(Outcome {
+        expected: email.label.clone(),
+        predicted: label_template::resolve(&result.email_rule.mail_label, &message),
+        tokens: result.token_usage,
+        latency: start.elapsed(),
+    })
+}
@@ -0,0 +1,264 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use serde::Serialize;
+
+/// One evaluated email
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub expected: String,
+    pub predicted: String,
+    pub tokens: i64,
+    pub latency: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CategoryMetrics {
+    pub category: String,
+    /// Emails labeled with this category
+    pub support: usize,
+    pub predicted: usize,
+    pub true_positives: usize,
+    pub precision: f64,
+    pub recall: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatencyMetrics {
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub prompt: String,
+    pub model: String,
+    pub total: usize,
+    pub correct: usize,
+    pub errors: usize,
+    pub accuracy: f64,
+    pub categories: Vec<CategoryMetrics>,
+    /// Expected category -> predicted category -> count
+    pub confusion_matrix: BTreeMap<String, BTreeMap<String, usize>>,
+    pub total_tokens: i64,
+    pub mean_tokens: f64,
+    pub latency: LatencyMetrics,
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 {
+        return 0.0;
+    }
+    numerator as f64 / denominator as f64
+}
+
+fn percentile(sorted_ms: &[f64], pct: f64) -> f64 {
+    if sorted_ms.is_empty() {
+        return 0.0;
+    }
+    let idx = ((pct / 100.0) * (sorted_ms.len() - 1) as f64).round() as usize;
+    sorted_ms[idx]
+}
+
+impl Report {
+    pub fn from_outcomes(prompt: &str, model: &str, outcomes: &[Outcome], errors: usize) -> Self {
+        let mut confusion_matrix = BTreeMap::<String, BTreeMap<String, usize>>::new();
+        for outcome in outcomes {
+            *confusion_matrix
+                .entry(outcome.expected.clone())
+                .or_default()
+                .entry(outcome.predicted.clone())
+                .or_default() += 1;
+        }
+
+        let mut category_names = outcomes
+            .iter()
+            .flat_map(|o| [o.expected.clone(), o.predicted.clone()])
+            .collect::<Vec<_>>();
+        category_names.sort();
+        category_names.dedup();
+
+        let categories = category_names
+            .into_iter()
+            .map(|category| {
+                let support = outcomes.iter().filter(|o| o.expected == category).count();
+                let predicted = outcomes.iter().filter(|o| o.predicted == category).count();
+                let true_positives = outcomes
+                    .iter()
+                    .filter(|o| o.expected == category && o.predicted == category)
+                    .count();
+
+                CategoryMetrics {
+                    precision: ratio(true_positives, predicted),
+                    recall: ratio(true_positives, support),
+                    category,
+                    support,
+                    predicted,
+                    true_positives,
+                }
+            })
+            .collect();
+
+        let correct = outcomes
+            .iter()
+            .filter(|o| o.expected == o.predicted)
+            .count();
+        let total_tokens = outcomes.iter().map(|o| o.tokens).sum::<i64>();
+
+        let mut latencies_ms = outcomes
+            .iter()
+            .map(|o| o.latency.as_secs_f64() * 1000.0)
+            .collect::<Vec<_>>();
+        latencies_ms.sort_by(f64::total_cmp);
+        let latency = LatencyMetrics {
+            mean_ms: if latencies_ms.is_empty() {
+                0.0
+            } else {
+                latencies_ms.iter().sum::<f64>() / latencies_ms.len() as f64
+            },
+            p50_ms: percentile(&latencies_ms, 50.0),
+            p95_ms: percentile(&latencies_ms, 95.0),
+            max_ms: latencies_ms.last().copied().unwrap_or_default(),
+        };
+
+        Self {
+            prompt: prompt.to_string(),
+            model: model.to_string(),
+            total: outcomes.len(),
+            correct,
+            errors,
+            accuracy: ratio(correct, outcomes.len()),
+            categories,
+            confusion_matrix,
+            total_tokens,
+            mean_tokens: ratio(total_tokens as usize, outcomes.len()),
+            latency,
+        }
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = format!(
+            "# Evaluation: prompt `{}`, model `{}`\n\n\
+             | Emails | Correct | Errors | Accuracy | Total tokens | Mean tokens | Mean latency | p95 latency |\n\
+             |---|---|---|---|---|---|---|---|\n\
+             | {} | {} | {} | {:.1}% | {} | {:.1} | {:.0} ms | {:.0} ms |\n",
+            self.prompt,
+            self.model,
+            self.total,
+            self.correct,
+            self.errors,
+            self.accuracy * 100.0,
+            self.total_tokens,
+            self.mean_tokens,
+            self.latency.mean_ms,
+            self.latency.p95_ms,
+        );
+
+        md.push_str("\n## Per category\n\n| Category | Support | Predicted | Precision | Recall |\n|---|---|---|---|---|\n");
+        for c in &self.categories {
+            md.push_str(&format!(
+                "| {} | {} | {} | {:.2} | {:.2} |\n",
+                c.category, c.support, c.predicted, c.precision, c.recall
+            ));
+        }
+
+        let names = self
+            .categories
+            .iter()
+            .map(|c| c.category.as_str())
+            .collect::<Vec<_>>();
+        md.push_str("\n## Confusion matrix\n\nRows are expected, columns are predicted.\n\n");
+        md.push_str(&format!("| | {} |\n", names.join(" | ")));
+        md.push_str(&format!("|---|{}\n", "---|".repeat(names.len())));
+        for expected in &names {
+            let row = names
+                .iter()
+                .map(|predicted| {
+                    self.confusion_matrix
+                        .get(*expected)
+                        .and_then(|r| r.get(*predicted))
+                        .copied()
+                        .unwrap_or_default()
+                        .to_string()
+                })
+                .collect::<Vec<_>>();
+            md.push_str(&format!("| {} | {} |\n", expected, row.join(" | ")));
+        }
+
+        md
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(expected: &str, predicted: &str, latency_ms: u64) -> Outcome {
+        Outcome {
+            expected: expected.to_string(),
+            predicted: predicted.to_string(),
+            tokens: 100,
+            latency: Duration::from_millis(latency_ms),
+        }
+    }
+
+    fn report() -> Report {
+        Report::from_outcomes(
+            "v1",
+            "mock",
+            &[
+                outcome("Ads", "Ads", 10),
+                outcome("Ads", "Ads", 20),
+                outcome("Ads", "Receipts", 30),
+                outcome("Receipts", "Receipts", 40),
+            ],
+            1,
+        )
+    }
+
+    #[test]
+    fn test_report_metrics() {
+        let report = report();
+
+        assert_eq!(report.total, 4);
+        assert_eq!(report.correct, 3);
+        assert_eq!(report.errors, 1);
+        assert!((report.accuracy - 0.75).abs() < f64::EPSILON);
+        assert_eq!(report.total_tokens, 400);
+        assert!((report.latency.mean_ms - 25.0).abs() < 1e-9);
+        assert!((report.latency.max_ms - 40.0).abs() < 1e-9);
+
+        let ads = &report.categories[0];
+        assert_eq!(ads.category, "Ads");
+        assert_eq!((ads.support, ads.predicted, ads.true_positives), (3, 2, 2));
+        assert!((ads.precision - 1.0).abs() < f64::EPSILON);
+        assert!((ads.recall - 2.0 / 3.0).abs() < f64::EPSILON);
+
+        let receipts = &report.categories[1];
+        assert!((receipts.precision - 0.5).abs() < f64::EPSILON);
+        assert!((receipts.recall - 1.0).abs() < f64::EPSILON);
+
+        assert_eq!(report.confusion_matrix["Ads"]["Receipts"], 1);
+        assert_eq!(report.confusion_matrix["Ads"]["Ads"], 2);
+    }
+
+    #[test]
+    fn test_report_empty() {
+        let report = Report::from_outcomes("v1", "mock", &[], 0);
+
+        assert_eq!(report.accuracy, 0.0);
+        assert_eq!(report.latency.p95_ms, 0.0);
+        assert!(report.categories.is_empty());
+    }
+
+    #[test]
+    fn test_report_markdown() {
+        let md = report().to_markdown();
+
+        assert!(md.contains("| 4 | 3 | 1 | 75.0% | 400 | 100.0 | 25 ms | 40 ms |"));
+        assert!(md.contains("| Ads | 3 | 2 | 1.00 | 0.67 |"));
+        assert!(md.contains("| | Ads | Receipts |"));
+        assert!(md.contains("| Ads | 2 | 1 |"));
+    }
+}